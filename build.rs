@@ -0,0 +1,32 @@
+// captures build metadata for `GET /version` (see src/version.rs)
+
+use std::{
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    // not set when git (or the .git directory) is missing, eg: building from a tarball
+    let sha = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(sha) = sha {
+        println!("cargo:rustc-env=GIT_SHA={}", sha.trim());
+    }
+
+    // build.rs only runs again when a commit changes (below), so this is when the build script
+    // last ran for this commit, not when the binary was last compiled
+    let built_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={built_at}");
+
+    // a new commit means new metadata; anything else keeps the last build's
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    // `git gc` / `git pack-refs` move branch heads out of refs/ into this one file
+    println!("cargo:rerun-if-changed=.git/packed-refs");
+}
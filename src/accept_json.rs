@@ -0,0 +1,142 @@
+/*
+   /api ANSWERS JSON ONLY
+    every route under `/api` answers JSON and nothing else, so a request whose `Accept` header
+     rules JSON out is refused with `406 Not Acceptable` before it reaches a handler, rather than
+     answered with something the client said it can't use. accepted are:
+     - `application/json`, or any `application/<x>+json` (eg: `application/problem+json`)
+     - the wildcards `application/*` and `*/*`
+     - no `Accept` header at all, which means "anything" (RFC 9110, 12.5.1)
+
+    a media type with `q=0` is a "not this one" and doesn't count, so
+     `text/html, application/json;q=0` is `406`, as is an `Accept` that can't be parsed.
+     the `406` body says what to ask for, as JSON like every other /api error (in the envelope
+     under /api/v2, see api/response.rs).
+*/
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{self, Accept, Header, Quality},
+        StatusCode,
+    },
+    middleware::Next,
+    mime, Error,
+};
+
+use crate::api::response;
+
+fn accepts_json(media_type: &mime::Mime) -> bool {
+    match (media_type.type_(), media_type.subtype()) {
+        (mime::STAR, mime::STAR) | (mime::APPLICATION, mime::STAR | mime::JSON) => true,
+        (mime::APPLICATION, _) => media_type.suffix() == Some(mime::JSON),
+        _ => false,
+    }
+}
+
+pub async fn require_json_accept(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let acceptable = !req.headers().contains_key(header::ACCEPT)
+        || Accept::parse(&req).is_ok_and(|accept| {
+            accept
+                .iter()
+                .any(|item| item.quality > Quality::ZERO && accepts_json(&item.item))
+        });
+
+    if !acceptable {
+        let res = response::error_for(
+            req.request(),
+            StatusCode::NOT_ACCEPTABLE,
+            "this API only answers application/json, accept it (or */*)",
+        );
+        return Ok(req.into_response(res));
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        api_key::{ApiKeys, API_KEY_HEADER},
+        testing,
+    };
+
+    async fn users_with_accept(accept: Option<&str>) -> (StatusCode, Value) {
+        let builder = testing::builder()
+            .await
+            .with_api_keys(ApiKeys::new(&["accept-test-key"]));
+        let app = test::init_service(builder.build()).await;
+
+        let mut req = test::TestRequest::get()
+            .uri("/api/v1/users")
+            .insert_header((API_KEY_HEADER, "accept-test-key"));
+        if let Some(accept) = accept {
+            req = req.insert_header((header::ACCEPT, accept));
+        }
+        let res = test::call_service(&app, req.to_request()).await;
+        (res.status(), test::read_body_json(res).await)
+    }
+
+    #[actix_web::test]
+    async fn a_json_accept_header_gets_in() {
+        for accept in [
+            "application/json",
+            "application/problem+json",
+            "text/html, application/json;q=0.1",
+            "application/*",
+        ] {
+            let (status, body) = users_with_accept(Some(accept)).await;
+
+            assert_eq!(status, StatusCode::OK, "{accept}");
+            assert!(body.is_array());
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_wildcard_or_no_header_gets_in() {
+        for accept in [Some("*/*"), Some("text/html, */*;q=0.8"), None] {
+            let (status, _) = users_with_accept(accept).await;
+
+            assert_eq!(status, StatusCode::OK, "{accept:?}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn an_html_only_accept_header_is_406() {
+        for accept in [
+            "text/html",
+            "text/html, application/xhtml+xml",
+            "text/html, application/json;q=0",
+            "not a media type",
+        ] {
+            let (status, body) = users_with_accept(Some(accept)).await;
+
+            assert_eq!(status, StatusCode::NOT_ACCEPTABLE, "{accept}");
+            assert_eq!(
+                body["error"],
+                "this API only answers application/json, accept it (or */*)"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn routes_outside_the_api_are_not_checked() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/healthz")
+            .insert_header((header::ACCEPT, "text/html"))
+            .to_request();
+
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+}
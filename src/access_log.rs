@@ -0,0 +1,171 @@
+/*
+   ACCESS LOG
+    actix's Logger middleware writes one line per request (through the `log` crate, so it shows
+     up in the same log as everything else). the line has two formats, picked with
+     `log_format` (env APP_LOG_FORMAT, see config.rs):
+
+     - text: the common access log fields + the request id + the api key used (`-` for none,
+        see api_key.rs)
+
+        127.0.0.1 2026-01-01T12:00:00Z "GET /users HTTP/1.1" 200 512 1.234 3f9c... key#1
+
+     - json: the same fields as one JSON object per line, for log collectors
+
+        {"remote_addr":"127.0.0.1","time":"...","request":"GET /users HTTP/1.1","status":200,...}
+
+    `%a` is the address of the TCP peer. behind a load balancer that is the load balancer, so
+     with `trust_proxy` on the client address is taken from `Forwarded` / `X-Forwarded-For`
+     (`%{r}a`) instead. only turn it on behind a proxy: otherwise any client can claim any
+     address in those headers.
+
+    it is the OUTERMOST middleware, so `%D` (the time taken, in ms) covers all the others too.
+     a request that ends in an error instead of a response (a panic, a timeout) is not logged
+     here, panics.rs and timeout.rs log those themselves.
+
+    in the json format everything that comes from the client (request line, request id) goes
+     through serde_json, so quotes or backslashes in them can't break the JSON.
+*/
+
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Logger,
+    HttpMessage,
+};
+
+use crate::{api_key::ApiKeyId, request_id::REQUEST_ID_HEADER};
+
+const TEXT_FORMAT: &str = r#"%a %t "%r" %s %b %D %{request_id}xo %{api_key}xo"#;
+const TEXT_FORMAT_TRUSTING_PROXY: &str = r#"%{r}a %t "%r" %s %b %D %{request_id}xo %{api_key}xo"#;
+const JSON_FORMAT: &str = r#"{"remote_addr":%{remote_addr}xi,"time":"%t","request":%{request_line}xi,"status":%s,"bytes":%b,"duration_ms":%D,"request_id":%{request_id}xo,"api_key":%{api_key}xo}"#;
+
+fn json_string(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+fn request_id(res: &ServiceResponse) -> String {
+    res.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("-")
+        .to_owned()
+}
+
+// set by api_key.rs for requests to /api
+fn api_key(res: &ServiceResponse) -> Option<String> {
+    res.request()
+        .extensions()
+        .get::<ApiKeyId>()
+        .map(ApiKeyId::to_string)
+}
+
+pub fn logger(json: bool, trust_proxy: bool) -> Logger {
+    if !json {
+        let format = match trust_proxy {
+            true => TEXT_FORMAT_TRUSTING_PROXY,
+            false => TEXT_FORMAT,
+        };
+        return Logger::new(format)
+            .custom_response_replace("request_id", request_id)
+            .custom_response_replace("api_key", |res| {
+                api_key(res).unwrap_or_else(|| "-".to_owned())
+            });
+    }
+
+    Logger::new(JSON_FORMAT)
+        .custom_request_replace("remote_addr", move |req: &ServiceRequest| {
+            let addr = match trust_proxy {
+                true => req
+                    .connection_info()
+                    .realip_remote_addr()
+                    .map(str::to_owned),
+                false => req.peer_addr().map(|addr| addr.ip().to_string()),
+            };
+            json_string(addr.as_deref().unwrap_or("-"))
+        })
+        .custom_request_replace("request_line", |req: &ServiceRequest| {
+            json_string(&format!(
+                "{} {} {:?}",
+                req.method(),
+                req.uri(),
+                req.version()
+            ))
+        })
+        .custom_response_replace("request_id", |res| json_string(&request_id(res)))
+        .custom_response_replace("api_key", |res| {
+            api_key(res).map_or_else(|| "null".to_owned(), |id| json_string(&id))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{config::Config, testing};
+
+    // the one access log line of the request with this id
+    async fn access_line(config: Config, request_id: &str) -> String {
+        testing::capture_logs();
+        let app = test::init_service(testing::builder_with(config).await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/no/such/page")
+            .peer_addr("10.0.0.7:4000".parse().unwrap())
+            .insert_header((REQUEST_ID_HEADER, request_id))
+            .insert_header(("X-Forwarded-For", "203.0.113.9"))
+            .to_request();
+        test::call_service(&app, req).await;
+
+        // the request's tracing span is logged with its id too, without the request line
+        let mut lines: Vec<String> = testing::logged(request_id)
+            .into_iter()
+            .filter(|line| line.contains("HTTP/1.1"))
+            .collect();
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        lines.remove(0)
+    }
+
+    #[actix_web::test]
+    async fn a_text_line_has_the_request_and_its_status() {
+        let line = access_line(Config::default(), "access-text-1").await;
+
+        assert!(line.starts_with("INFO 10.0.0.7 "), "{line}");
+        assert!(
+            line.contains(r#""GET /no/such/page HTTP/1.1" 404 "#),
+            "{line}"
+        );
+        assert!(line.ends_with(" access-text-1 -"), "{line}");
+    }
+
+    #[actix_web::test]
+    async fn a_json_line_parses() {
+        let config = Config {
+            log_format: "json".to_owned(),
+            ..Config::default()
+        };
+        let line = access_line(config, "access-json-1").await;
+
+        let entry: Value = serde_json::from_str(line.strip_prefix("INFO ").unwrap()).unwrap();
+        assert_eq!(entry["request"], "GET /no/such/page HTTP/1.1");
+        assert_eq!(entry["status"], 404);
+        assert_eq!(entry["remote_addr"], "10.0.0.7");
+        assert_eq!(entry["request_id"], "access-json-1");
+        assert!(entry["api_key"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn a_trusted_proxy_gives_the_client_address() {
+        for log_format in ["text", "json"] {
+            let config = Config {
+                log_format: log_format.to_owned(),
+                trust_proxy: true,
+                ..Config::default()
+            };
+            let line = access_line(config, &format!("access-proxy-{log_format}")).await;
+
+            assert!(line.contains("203.0.113.9"), "{line}");
+            assert!(!line.contains("10.0.0.7"), "{line}");
+        }
+    }
+}
@@ -0,0 +1,101 @@
+/*
+   ADMIN AREA
+    everything registered here is mounted under `web::scope("/admin")`, which is wrapped in the
+     basic auth middleware (see auth.rs), so these handlers never run for anonymous requests.
+
+    `POST /admin/shutdown` starts a graceful stop (see supervisor.rs) and answers `202 Accepted`
+     right away: in-flight requests, this one included, still complete. calling it again while
+     the server is stopping is harmless.
+
+    `POST /admin/maintenance` switches maintenance mode on / off (see maintenance.rs).
+*/
+
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde_json::json;
+
+use crate::{maintenance::Maintenance, supervisor::ShutdownSwitch};
+
+#[get("/dashboard")]
+async fn dashboard() -> impl Responder {
+    HttpResponse::Ok().body("Welcome to the admin dashboard")
+}
+
+#[post("/shutdown")]
+async fn shutdown(switch: web::Data<ShutdownSwitch>) -> impl Responder {
+    if switch.trigger() {
+        log::info!("graceful shutdown requested through /admin/shutdown");
+    }
+    HttpResponse::Accepted().json(json!({ "status": "shutting down" }))
+}
+
+#[post("/maintenance")]
+async fn toggle_maintenance(maintenance: web::Data<Maintenance>) -> impl Responder {
+    let on = maintenance.toggle();
+    log::warn!("maintenance mode is now {}", if on { "ON" } else { "off" });
+    HttpResponse::Ok().json(json!({ "maintenance": on }))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(dashboard)
+        .service(shutdown)
+        .service(toggle_maintenance);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use actix_web::{http::StatusCode, rt, test, App, HttpServer};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    use crate::{auth::AdminCredentials, testing};
+
+    fn shutdown_request() -> test::TestRequest {
+        let credentials = STANDARD.encode("admin:s3cret");
+        test::TestRequest::post()
+            .uri("/admin/shutdown")
+            .insert_header(("Authorization", format!("Basic {credentials}")))
+    }
+
+    #[actix_web::test]
+    async fn shutdown_stops_the_server_once() {
+        testing::capture_logs();
+        let builder = testing::builder()
+            .await
+            .with_admin_credentials(AdminCredentials::new("admin", "s3cret"));
+
+        // a stand-in for the real server, only its handle matters
+        let server = HttpServer::new(App::new)
+            .workers(1)
+            .disable_signals()
+            .listen(TcpListener::bind("127.0.0.1:0").unwrap())
+            .unwrap()
+            .run();
+        builder.set_server_handle(server.handle());
+        let running = rt::spawn(server);
+
+        let app = test::init_service(builder.build()).await;
+        for _ in 0..3 {
+            let res = test::call_service(&app, shutdown_request().to_request()).await;
+            assert_eq!(res.status(), StatusCode::ACCEPTED);
+        }
+
+        running.await.unwrap().unwrap();
+        let stops = testing::logged("graceful shutdown requested through /admin/shutdown");
+        assert_eq!(stops.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn shutdown_needs_the_admin_credentials() {
+        let builder = testing::builder()
+            .await
+            .with_admin_credentials(AdminCredentials::new("admin", "other"));
+        let app = test::init_service(builder.build()).await;
+
+        let res = test::call_service(&app, shutdown_request().to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let req = test::TestRequest::get().uri("/readyz").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,146 @@
+/*
+   CONCURRENT AGGREGATION WITH tokio::join!
+    `GET /aggregate` needs data from three (simulated) sources and answers with all of it in one
+     JSON object. awaiting them one after the other would take the SUM of their latencies;
+     tokio::join! polls the three futures together on the same task, so the handler takes about
+     as long as the SLOWEST one (~300ms here instead of ~600ms).
+
+    nothing blocks: while the sources are "working" (sleeping), the worker is free to serve other
+     requests. a std::thread::sleep() in here would instead stall every request on this worker.
+
+    if any source fails the combined answer would be incomplete, so the whole response is
+     `502 Bad Gateway` naming the source that failed. `?fail=<source>` makes one fail on purpose.
+
+    the elapsed time is part of the response, so the concurrency can be seen from the outside.
+*/
+
+use std::time::{Duration, Instant};
+
+use actix_web::{get, rt::time::sleep, web, HttpResponse};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const PROFILE_LATENCY: Duration = Duration::from_millis(100);
+const ORDERS_LATENCY: Duration = Duration::from_millis(300);
+const RECOMMENDATIONS_LATENCY: Duration = Duration::from_millis(200);
+
+#[derive(Debug)]
+pub struct SourceError {
+    source: &'static str,
+    message: String,
+}
+
+#[derive(Deserialize)]
+pub struct AggregateQuery {
+    fail: Option<String>,
+}
+
+// one simulated upstream: waits `latency`, then answers `data` (or fails when asked to)
+async fn fetch(
+    source: &'static str,
+    latency: Duration,
+    fail: bool,
+    data: Value,
+) -> Result<Value, SourceError> {
+    sleep(latency).await;
+    if fail {
+        return Err(SourceError {
+            source,
+            message: format!("{source} is unavailable"),
+        });
+    }
+    Ok(data)
+}
+
+#[get("/aggregate")]
+pub async fn aggregate(query: web::Query<AggregateQuery>) -> HttpResponse {
+    let fails = |source: &str| query.fail.as_deref() == Some(source);
+    let started = Instant::now();
+
+    let (profile, orders, recommendations) = tokio::join!(
+        fetch(
+            "profile",
+            PROFILE_LATENCY,
+            fails("profile"),
+            json!({ "name": "Ann" })
+        ),
+        fetch(
+            "orders",
+            ORDERS_LATENCY,
+            fails("orders"),
+            json!([{ "id": 1 }, { "id": 2 }])
+        ),
+        fetch(
+            "recommendations",
+            RECOMMENDATIONS_LATENCY,
+            fails("recommendations"),
+            json!(["book", "lamp"])
+        ),
+    );
+    let elapsed_ms = started.elapsed().as_millis();
+
+    let combined = profile.and_then(|profile| {
+        Ok(json!({
+            "profile": profile,
+            "orders": orders?,
+            "recommendations": recommendations?,
+            "elapsed_ms": elapsed_ms,
+        }))
+    });
+
+    match combined {
+        Ok(body) => HttpResponse::Ok().json(body),
+        Err(err) => HttpResponse::BadGateway().json(json!({
+            "error": err.message,
+            "source": err.source,
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
+
+    use super::*;
+
+    const SUM: Duration = PROFILE_LATENCY
+        .saturating_add(ORDERS_LATENCY)
+        .saturating_add(RECOMMENDATIONS_LATENCY);
+
+    #[actix_web::test]
+    async fn the_sources_are_awaited_together() {
+        let app = test::init_service(App::new().service(aggregate)).await;
+
+        let started = Instant::now();
+        let req = test::TestRequest::get().uri("/aggregate").to_request();
+        let res = test::call_service(&app, req).await;
+        let elapsed = started.elapsed();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["profile"]["name"], "Ann");
+        assert_eq!(body["orders"].as_array().unwrap().len(), 2);
+        assert_eq!(body["recommendations"], json!(["book", "lamp"]));
+
+        // about the slowest source, well under all three one after the other
+        assert!(elapsed >= ORDERS_LATENCY);
+        assert!(elapsed < SUM, "took {elapsed:?}");
+        assert!(body["elapsed_ms"].as_u64().unwrap() < SUM.as_millis() as u64);
+    }
+
+    #[actix_web::test]
+    async fn a_failing_source_is_502_naming_it() {
+        let app = test::init_service(App::new().service(aggregate)).await;
+
+        for source in ["profile", "orders", "recommendations"] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/aggregate?fail={source}"))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+
+            assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["source"], source);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+/*
+   VERSIONED API
+    `/api/v1/...` and `/api/v2/...` are two scopes, each set up by its own configure function
+     (same pattern as the "Configure" section of the tutorial):
+
+        web::scope("/api")
+            .service(web::scope("/v1").configure(v1::configure))
+            .service(web::scope("/v2").configure(v2::configure))
+
+    a handler that didn't change between versions lives here and both versions register it;
+     a handler that did change is written again in the version module, so v1 clients keep the
+     old shape while v2 moves on:
+     - `/status`  -> shared, same in both versions
+     - `/users`   -> v1: a plain JSON array of the first page of users
+                     v2: `{ "data": [...], "meta": { "next_cursor": ... } }` with paging
+
+    v2 answers go through ApiResponse (see response.rs): `data` or `error`, plus a `meta` with
+     the request id, errors of the `/api` middleware and of the query string included. v1 stays as it was, its clients were promised that shape. `/status` is a
+     health check for load balancers and stays a plain `{ "status": "ok" }` in both.
+*/
+
+use actix_web::{get, web, HttpResponse, Responder};
+use serde_json::json;
+
+pub mod response;
+pub mod v1;
+pub mod v2;
+
+#[get("/status")]
+pub async fn status() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/v1").configure(v1::configure))
+        .service(web::scope("/v2").configure(v2::configure));
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::{header, StatusCode},
+        test, App,
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        api_key::{ApiKeys, API_KEY_HEADER},
+        db, testing,
+    };
+
+    fn api_get(uri: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header((API_KEY_HEADER, "test-key"))
+    }
+
+    #[actix_web::test]
+    async fn v1_and_v2_users_have_their_own_shapes() {
+        let builder = testing::builder().await;
+        let app =
+            test::init_service(builder.with_api_keys(ApiKeys::new(&["test-key"])).build()).await;
+        for n in 0..2 {
+            let req = test::TestRequest::post()
+                .uri("/users")
+                .set_json(json!({ "name": format!("u{n}"), "email": format!("u{n}@example.com") }))
+                .to_request();
+            test::call_service(&app, req).await;
+        }
+
+        let v1: Value =
+            test::call_and_read_body_json(&app, api_get("/api/v1/users").to_request()).await;
+        assert_eq!(v1.as_array().unwrap().len(), 2);
+        assert_eq!(v1[0]["name"], "u0");
+
+        let req = api_get("/api/v2/users?limit=1").to_request();
+        let v2: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(v2["data"].as_array().unwrap().len(), 1);
+        assert_eq!(v2["data"][0]["name"], "u0");
+        assert_eq!(v2["meta"]["next_cursor"], 1);
+        assert!(v2["meta"]["request_id"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn status_is_shared_by_both_versions() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for version in ["v1", "v2"] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/api/{version}/status"))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body, json!({ "status": "ok" }));
+        }
+    }
+
+    #[actix_web::test]
+    async fn v2_errors_come_in_the_envelope() {
+        let builder = testing::builder().await;
+        let app =
+            test::init_service(builder.with_api_keys(ApiKeys::new(&["test-key"])).build()).await;
+
+        let keyless = |version: &str| {
+            test::TestRequest::get()
+                .uri(&format!("/api/{version}/users"))
+                .to_request()
+        };
+        let res = test::call_service(&app, keyless("v2")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "a valid X-Api-Key header is required");
+        assert!(body["meta"]["request_id"].is_string());
+        // v1 keeps the shape its clients know
+        let body: Value = test::call_and_read_body_json(&app, keyless("v1")).await;
+        assert_eq!(
+            body,
+            json!({ "error": "a valid X-Api-Key header is required" })
+        );
+
+        let req = api_get("/api/v2/users")
+            .insert_header((header::ACCEPT, "text/html"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+        let body: Value = test::read_body_json(res).await;
+        assert!(body["meta"]["request_id"].is_string());
+
+        let req = api_get("/api/v2/users?limit=abc").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "invalid digit found in string");
+        assert!(body["meta"]["request_id"].is_string());
+        assert!(body.get("source").is_none());
+    }
+
+    #[actix_web::test]
+    async fn a_database_error_is_logged_not_sent() {
+        testing::capture_logs();
+        let url = format!(
+            "sqlite:file:{}?mode=memory&cache=shared",
+            uuid::Uuid::new_v4()
+        );
+        let pool = db::connect(&url).await.unwrap();
+        sqlx::query("DROP TABLE users")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(actix_web::web::Data::new(pool))
+                .service(web::scope("/api/v2").configure(v2::configure)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/api/v2/users").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "could not list the users");
+
+        let logged = testing::logged("listing users failed");
+        assert!(
+            logged.iter().any(|line| line.contains("no such table")),
+            "{logged:?}"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+/*
+   RESPONSE ENVELOPE
+    v2 handlers return an ApiResponse<T> instead of building the JSON themselves, and every
+     answer comes out in the same shape:
+
+        { "data": <T>, "meta": { "request_id": "3f9c..." } }
+        { "error": "what went wrong", "meta": { "request_id": "3f9c..." } }
+
+    - ApiResponse::ok(payload)             -> `200` with the payload under `data`
+    - ApiResponse::error(status, message)  -> that status with the message under `error`
+    - .meta(key, value)                    -> one more field in `meta` (eg: the paging cursor)
+
+    it is a Responder, so the request id (set by request_id.rs) is filled in when the response
+     is built, the handler never deals with it. the id is the same one as in `X-Request-Id`.
+
+    what answers before a handler is reached (the `/api` middleware: api_key.rs, accept_json.rs)
+     is shared by both versions, so it answers through error_for(): the `error` envelope under
+     `/api/v2`, the plain `{ "error": "..." }` v1 clients already know under `/api/v1`.
+*/
+
+use actix_web::{http::StatusCode, HttpMessage, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::request_id::RequestId;
+
+const V2_SCOPE: &str = "/api/v2";
+
+pub struct ApiResponse<T> {
+    status: StatusCode,
+    body: Result<T, String>,
+    meta: Map<String, Value>,
+}
+
+impl<T: Serialize> ApiResponse<T> {
+    pub fn ok(data: T) -> Self {
+        Self {
+            status: StatusCode::OK,
+            body: Ok(data),
+            meta: Map::new(),
+        }
+    }
+
+    pub fn error(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: Err(message.into()),
+            meta: Map::new(),
+        }
+    }
+
+    pub fn meta(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.meta.insert(key.to_owned(), value.into());
+        self
+    }
+}
+
+impl<T: Serialize> Responder for ApiResponse<T> {
+    type Body = actix_web::body::BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse {
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .map(|RequestId(id)| id.clone());
+
+        let mut meta = self.meta;
+        meta.insert("request_id".to_owned(), request_id.into());
+
+        let body = match self.body {
+            Ok(data) => json!({ "data": data, "meta": meta }),
+            Err(message) => json!({ "error": message, "meta": meta }),
+        };
+        HttpResponse::build(self.status).json(body)
+    }
+}
+
+// an error for a request to either API version, in that version's shape
+pub fn error_for(req: &HttpRequest, status: StatusCode, message: &str) -> HttpResponse {
+    let in_v2 = req
+        .path()
+        .strip_prefix(V2_SCOPE)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+    if in_v2 {
+        ApiResponse::<()>::error(status, message).respond_to(req)
+    } else {
+        HttpResponse::build(status).json(json!({ "error": message }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{middleware, test, web, App};
+
+    use super::*;
+    use crate::{
+        api_key::{ApiKeys, API_KEY_HEADER},
+        request_id::{self, REQUEST_ID_HEADER},
+        testing,
+    };
+
+    #[derive(Serialize)]
+    struct Payload {
+        name: &'static str,
+        tags: Vec<&'static str>,
+    }
+
+    async fn payload() -> ApiResponse<Payload> {
+        ApiResponse::ok(Payload {
+            name: "Abebe",
+            tags: vec!["a", "b"],
+        })
+        .meta("page", 1)
+    }
+
+    async fn failure() -> ApiResponse<Payload> {
+        ApiResponse::error(StatusCode::NOT_FOUND, "no such thing")
+    }
+
+    fn request(uri: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header((REQUEST_ID_HEADER, "envelope-1"))
+    }
+
+    #[actix_web::test]
+    async fn the_payload_is_under_data_with_the_request_id() {
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(request_id::request_id))
+                .route("/payload", web::get().to(payload))
+                .route("/failure", web::get().to(failure)),
+        )
+        .await;
+
+        let res = test::call_service(&app, request("/payload").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(
+            body,
+            json!({
+                "data": { "name": "Abebe", "tags": ["a", "b"] },
+                "meta": { "request_id": "envelope-1", "page": 1 },
+            })
+        );
+
+        let res = test::call_service(&app, request("/failure").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(
+            body,
+            json!({ "error": "no such thing", "meta": { "request_id": "envelope-1" } })
+        );
+    }
+
+    #[actix_web::test]
+    async fn the_request_id_is_the_one_in_the_header() {
+        let builder = testing::builder()
+            .await
+            .with_api_keys(ApiKeys::new(&["test-key"]));
+        let app = test::init_service(builder.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/v2/users")
+            .insert_header((API_KEY_HEADER, "test-key"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        let header = res.headers().get(REQUEST_ID_HEADER).unwrap();
+        let header = header.to_str().unwrap().to_owned();
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["meta"]["request_id"], header);
+        assert_eq!(body["data"], json!([]));
+    }
+}
@@ -0,0 +1,23 @@
+/*
+   API V1
+    the first version: `GET /api/v1/users` answers with a bare array of the first
+     users::DEFAULT_PAGE_SIZE users, there is no way to page further.
+*/
+
+use actix_web::{error, get, web, HttpResponse};
+use sqlx::SqlitePool;
+
+use crate::users::{self, DEFAULT_PAGE_SIZE};
+
+#[get("/users")]
+async fn list_users(pool: web::Data<SqlitePool>) -> actix_web::Result<HttpResponse> {
+    let page = users::page_after(&pool, None, DEFAULT_PAGE_SIZE)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(page.users))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(super::status).service(list_users);
+}
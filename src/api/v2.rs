@@ -0,0 +1,55 @@
+/*
+   API V2
+    `GET /api/v2/users?after=<id>&limit=<n>` wraps the users in the envelope of response.rs and
+     pages through them with a cursor (the keyset pagination of users.rs):
+
+        { "data": [ { "id": 1, "name": "...", "email": "..." } ],
+          "meta": { "request_id": "3f9c...", "next_cursor": 1 } }
+
+    the envelope leaves room for more fields later without breaking clients again.
+
+    errors come in the envelope too: a database error is logged and answered with a generic
+     `500` (its text is no business of the client's), and a bad query string (`?limit=abc`) is
+     a `400` with the same message as anywhere else (see extractor_errors.rs) but in the
+     envelope, through the QueryConfig registered on this scope only.
+*/
+
+use actix_web::{error::InternalError, get, http::StatusCode, web, Responder};
+use sqlx::SqlitePool;
+
+use super::response::ApiResponse;
+use crate::{
+    extractor_errors,
+    users::{self, PageParams, User},
+};
+
+#[get("/users")]
+async fn list_users(
+    pool: web::Data<SqlitePool>,
+    params: web::Query<PageParams>,
+) -> ApiResponse<Vec<User>> {
+    match users::page_after(&pool, params.after, params.limit()).await {
+        Ok(page) => ApiResponse::ok(page.users).meta("next_cursor", page.next_cursor),
+        Err(err) => {
+            log::error!("listing users failed: {err}");
+            ApiResponse::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "could not list the users",
+            )
+        }
+    }
+}
+
+fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err, req| {
+        let message = extractor_errors::query_message(&err);
+        let res = ApiResponse::<()>::error(StatusCode::BAD_REQUEST, message).respond_to(req);
+        InternalError::from_response(err, res).into()
+    })
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(query_config())
+        .service(super::status)
+        .service(list_users);
+}
@@ -0,0 +1,201 @@
+/*
+   API KEYS FOR /api
+    everything under `/api` needs an `X-Api-Key` header holding one of the keys in API_KEYS, a
+     comma separated list read once at startup:
+
+        API_KEYS="k3y-for-billing,k3y-for-reports"
+
+    a missing or unknown key is `401`, its body in the shape of the API version asked for (see
+     api/response.rs). if API_KEYS is not set (or empty) no key matches and the
+     scope is locked, like the admin area without ADMIN_USER / ADMIN_PASS (see auth.rs).
+
+    - the status routes (`/api/v1/status`, `/api/v2/status`) are health checks and stay open
+    - the key is compared with EVERY configured key, each with auth::constant_time_eq, so the
+       response time says neither how much of a key was right nor which key it was close to
+    - which key was used is stored in the request extensions as ApiKeyId (its position in
+       API_KEYS, never the key itself), and the access log prints it (see access_log.rs)
+
+    requests with `X-Api-Key` are never served from the response cache (see response_cache.rs),
+     which sits in front of this middleware.
+*/
+
+use std::{env, fmt};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header::HeaderName, StatusCode},
+    middleware::Next,
+    web, Error, HttpMessage,
+};
+
+use crate::{api::response, auth::constant_time_eq};
+
+pub const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+const EXEMPT_PATHS: [&str; 2] = ["/api/v1/status", "/api/v2/status"];
+
+pub struct ApiKeys {
+    keys: Vec<String>,
+}
+
+// the value stored in request extensions: the 1-based position of the key in API_KEYS
+#[derive(Clone, Copy, Debug)]
+pub struct ApiKeyId(pub usize);
+
+impl fmt::Display for ApiKeyId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key#{}", self.0)
+    }
+}
+
+impl ApiKeys {
+    pub fn from_env() -> Self {
+        let keys: Vec<String> = env::var("API_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(str::to_owned)
+            .collect();
+        if keys.is_empty() {
+            log::warn!("API_KEYS is not set, /api is locked");
+        }
+        Self { keys }
+    }
+
+    #[cfg(test)]
+    pub fn new(keys: &[&str]) -> Self {
+        Self {
+            keys: keys.iter().map(|&key| key.to_owned()).collect(),
+        }
+    }
+
+    // no early return: every key is compared, whichever one matches
+    fn find(&self, candidate: &str) -> Option<ApiKeyId> {
+        let mut found = None;
+        for (i, key) in self.keys.iter().enumerate() {
+            if constant_time_eq(key.as_bytes(), candidate.as_bytes()) && found.is_none() {
+                found = Some(ApiKeyId(i + 1));
+            }
+        }
+        found
+    }
+}
+
+pub async fn require_api_key(
+    keys: web::Data<ApiKeys>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if EXEMPT_PATHS.contains(&req.path()) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    }
+
+    let key_id = req
+        .headers()
+        .get(&API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|candidate| keys.find(candidate));
+
+    let Some(key_id) = key_id else {
+        let res = response::error_for(
+            req.request(),
+            StatusCode::UNAUTHORIZED,
+            "a valid X-Api-Key header is required",
+        );
+        return Ok(req.into_response(res));
+    };
+
+    req.extensions_mut().insert(key_id);
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{middleware, test, App, HttpResponse};
+
+    use super::*;
+    use crate::testing;
+
+    async fn users_status(keys: &[&str], key: Option<&str>) -> StatusCode {
+        let builder = testing::builder().await.with_api_keys(ApiKeys::new(keys));
+        let app = test::init_service(builder.build()).await;
+
+        let mut req = test::TestRequest::get().uri("/api/v1/users");
+        if let Some(key) = key {
+            req = req.insert_header((API_KEY_HEADER, key));
+        }
+        test::call_service(&app, req.to_request()).await.status()
+    }
+
+    #[actix_web::test]
+    async fn a_configured_key_gets_in() {
+        let keys = ["k3y-for-billing", "k3y-for-reports"];
+
+        for key in keys {
+            assert_eq!(users_status(&keys, Some(key)).await, StatusCode::OK);
+        }
+    }
+
+    #[actix_web::test]
+    async fn an_unknown_or_missing_key_is_401() {
+        let keys = ["k3y-for-billing"];
+
+        for key in [
+            Some("k3y-for-billin"),
+            Some("k3y-for-billing2"),
+            Some(""),
+            None,
+        ] {
+            assert_eq!(
+                users_status(&keys, key).await,
+                StatusCode::UNAUTHORIZED,
+                "{key:?}"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn without_keys_the_scope_is_locked_but_status_stays_open() {
+        let builder = testing::builder().await.with_api_keys(ApiKeys::new(&[]));
+        let app = test::init_service(builder.build()).await;
+
+        let req = test::TestRequest::get().uri("/api/v1/users").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        for uri in ["/api/v1/status", "/api/v2/status", "/healthz"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK, "{uri}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn the_key_used_is_in_the_request_extensions() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(ApiKeys::new(&["first", "second"])))
+                .wrap(middleware::from_fn(require_api_key))
+                .route(
+                    "/api/who",
+                    web::get().to(|key_id: web::ReqData<ApiKeyId>| async move {
+                        HttpResponse::Ok().body(key_id.to_string())
+                    }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/who")
+            .insert_header((API_KEY_HEADER, "second"))
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "key#2");
+    }
+}
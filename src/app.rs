@@ -0,0 +1,539 @@
+/*
+   APP BUILDER
+    HttpServer::new() takes an app FACTORY: a closure that is called once per worker thread to
+     build that worker's App. anything created inside the closure is therefore created N times,
+     while anything created before it (and moved in) can be shared by all workers.
+
+    AppBuilder keeps that split in one place:
+     - AppBuilder::new() builds the shared state ONCE (wrapped in web::Data, i.e. an Arc)
+     - build() wires state and middleware into an App, cloning only the Arcs,
+        so the factory is just `move || builder.build()`
+     - configure_app() registers the routes; it is a plain ServiceConfig function (see the
+        "Configure" section above), so it can also be mounted on a bare App or a scope
+
+    main only builds the app through it, so anything else that needs the real app
+     (eg: `test::init_service(builder.build())` in a test) gets exactly the same wiring.
+*/
+
+use std::time::Duration;
+
+use actix_web::{
+    body::MessageBody,
+    cookie::Key,
+    dev::{Extensions, ServerHandle, ServiceFactory, ServiceRequest, ServiceResponse},
+    middleware, web, App, Error,
+};
+use sqlx::SqlitePool;
+
+use crate::{
+    accept_json, access_log, admin, aggregate, api,
+    api_key::{self, ApiKeys},
+    auth::{self, AdminCredentials},
+    avatar, basics, bearer, body_limit, body_log,
+    config::{self, Config},
+    config_reload::{self, LiveConfig},
+    config_routes::ConfigRoutes,
+    contact, debug, decompress, disconnect, download,
+    events::{self, EventBus},
+    extractor_errors, files, greeting, hash, head, header_limit, https_redirect,
+    idempotency::{self, IdempotencyStore},
+    inflight::{self, InflightLimit},
+    ingest,
+    ip_allowlist::{IpAllowlist, IpAllowlistGuard},
+    items,
+    jobs::{self, JobStore},
+    keepalive,
+    kv::{self, KvStore},
+    maintenance::{self, Maintenance},
+    matched_route,
+    metrics::{self, Metrics},
+    multi_host, openapi, orders, panics, payments, prefs, pretty_json,
+    rate::{self, RateCache},
+    rate_limit::{self, RateLimiter},
+    report, repos, request_id,
+    request_log::{self, RequestLog},
+    response_cache::{self, ResponseCache},
+    reverse_proxy::{self, ReverseProxy},
+    search,
+    security_headers::security_headers,
+    session,
+    shorten::{self, ShortLinks},
+    sources, static_files, status, strip_prefix, submit,
+    supervisor::ShutdownSwitch,
+    tenant, text, timeout, trace, trailing_slash, tx,
+    uploads::{self, UploadStore},
+    users, version,
+    weather::{self, WeatherProxy},
+    webhook,
+    write_lock::{self, ResourceLocks},
+};
+
+// how long a response stays replayable for a given Idempotency-Key
+const IDEMPOTENCY_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone)]
+pub struct AppBuilder {
+    config: web::Data<Config>,
+    idempotency_store: web::Data<IdempotencyStore>,
+    job_store: web::Data<JobStore>,
+    admin_credentials: web::Data<Option<AdminCredentials>>,
+    event_bus: web::Data<EventBus>,
+    pool: web::Data<SqlitePool>,
+    metrics: web::Data<Metrics>,
+    upload_store: web::Data<UploadStore>,
+    rate_cache: web::Data<RateCache>,
+    resource_locks: web::Data<ResourceLocks>,
+    session_key: Key,
+    request_log: web::Data<RequestLog>,
+    weather_proxy: web::Data<WeatherProxy>,
+    shutdown_switch: web::Data<ShutdownSwitch>,
+    response_cache: web::Data<ResponseCache>,
+    reverse_proxy: web::Data<ReverseProxy>,
+    maintenance: web::Data<Maintenance>,
+    admin_allowlist: web::Data<IpAllowlist>,
+    short_links: web::Data<ShortLinks>,
+    api_keys: web::Data<ApiKeys>,
+    rate_limiter: web::Data<RateLimiter>,
+    kv_store: web::Data<KvStore>,
+    live_config: web::Data<LiveConfig>,
+    config_routes: web::Data<ConfigRoutes>,
+}
+
+impl AppBuilder {
+    pub fn new(config: Config, pool: SqlitePool, config_routes: ConfigRoutes) -> Self {
+        let response_cache_ttl = Duration::from_secs(config.response_cache_ttl_secs);
+        let shutdown_delay = Duration::from_secs(config.shutdown_delay_secs);
+        let upstream_max_attempts = config.upstream_max_attempts;
+        let config = web::Data::new(config);
+        Self {
+            live_config: web::Data::new(LiveConfig::new(config.clone())),
+            config,
+            idempotency_store: web::Data::new(IdempotencyStore::new(
+                IDEMPOTENCY_TTL,
+                idempotency::MAX_ENTRIES,
+            )),
+            job_store: web::Data::new(JobStore::default()),
+            admin_credentials: web::Data::new(AdminCredentials::from_env()),
+            event_bus: web::Data::new(EventBus::default()),
+            pool: web::Data::new(pool),
+            metrics: web::Data::new(Metrics::default()),
+            upload_store: web::Data::new(UploadStore::default()),
+            rate_cache: web::Data::new(RateCache::default()),
+            resource_locks: web::Data::new(ResourceLocks::new(write_lock::LOCK_WAIT)),
+            session_key: session::key_from_env(),
+            request_log: web::Data::new(RequestLog::new(request_log::CAPACITY)),
+            weather_proxy: web::Data::new(WeatherProxy::from_env(upstream_max_attempts)),
+            shutdown_switch: web::Data::new(ShutdownSwitch::new(shutdown_delay)),
+            response_cache: web::Data::new(ResponseCache::new(
+                response_cache_ttl,
+                response_cache::MAX_ENTRIES,
+            )),
+            reverse_proxy: web::Data::new(ReverseProxy::from_env(upstream_max_attempts)),
+            maintenance: web::Data::new(Maintenance::default()),
+            admin_allowlist: web::Data::new(IpAllowlist::from_env()),
+            short_links: web::Data::new(ShortLinks::default()),
+            api_keys: web::Data::new(ApiKeys::from_env()),
+            rate_limiter: web::Data::new(RateLimiter::default()),
+            kv_store: web::Data::new(KvStore::default()),
+            config_routes: web::Data::new(config_routes),
+        }
+    }
+
+    // tasks that keep the shared state fresh; call once, however many servers/workers follow
+    pub fn start_background_tasks(&self) {
+        reverse_proxy::spawn_health_checks(self.reverse_proxy.clone());
+        jobs::spawn_worker(self.job_store.clone());
+        jobs::spawn_sweeper(self.job_store.clone());
+        uploads::spawn_sweeper(self.upload_store.clone());
+        idempotency::spawn_sweeper(self.idempotency_store.clone());
+        config_reload::spawn_watcher(self.live_config.clone());
+        rate::spawn_refresh(
+            self.rate_cache.clone(),
+            rate::REFRESH_INTERVAL,
+            rate::fetch_rate,
+        );
+    }
+
+    pub fn shutdown_switch(&self) -> web::Data<ShutdownSwitch> {
+        self.shutdown_switch.clone()
+    }
+
+    // the server only exists after the app state, so its handle is handed in afterwards
+    pub fn set_server_handle(&self, handle: ServerHandle) {
+        self.shutdown_switch.set_handle(handle);
+    }
+
+    // the routes and the state without any middleware
+    pub fn bare_app(
+        &self,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = Error,
+            InitError = (),
+        >,
+    > {
+        App::new()
+            .app_data(self.config.clone())
+            .app_data(self.idempotency_store.clone())
+            .app_data(self.job_store.clone())
+            .app_data(self.admin_credentials.clone())
+            .app_data(self.event_bus.clone())
+            .app_data(self.pool.clone())
+            .app_data(self.metrics.clone())
+            .app_data(self.upload_store.clone())
+            .app_data(self.rate_cache.clone())
+            .app_data(self.resource_locks.clone())
+            .app_data(self.request_log.clone())
+            .app_data(self.weather_proxy.clone())
+            .app_data(self.shutdown_switch.clone())
+            .app_data(self.response_cache.clone())
+            .app_data(self.reverse_proxy.clone())
+            .app_data(self.maintenance.clone())
+            .app_data(self.admin_allowlist.clone())
+            .app_data(self.short_links.clone())
+            .app_data(self.api_keys.clone())
+            .app_data(self.rate_limiter.clone())
+            .app_data(self.kv_store.clone())
+            .app_data(self.live_config.clone())
+            .app_data(self.config_routes.clone())
+            // made here and not in new(): bare_app() runs once per worker, so is the semaphore
+            .app_data(web::Data::new(InflightLimit::new(self.config.max_inflight)))
+            .app_data(contact::form_config()) // size limit + error format for every web::Form
+            .app_data(extractor_errors::json_config()) // same error format for Json, Path, Query
+            .app_data(extractor_errors::path_config())
+            .app_data(extractor_errors::query_config())
+            .configure(configure_app)
+            .configure(|cfg| {
+                // debug-only routes don't exist at all unless asked for (see debug.rs)
+                if debug::enabled(&self.config) {
+                    debug::configure(cfg);
+                }
+            })
+            .configure(|cfg| self.config_routes.configure(cfg)) // routes.toml, after all of ours
+    }
+
+    // the state bare_app() registers, for state::check_app_data(): a field added there goes here too
+    pub fn registered_data(&self) -> Extensions {
+        let mut data = Extensions::new();
+        data.insert(self.config.clone());
+        data.insert(self.idempotency_store.clone());
+        data.insert(self.job_store.clone());
+        data.insert(self.admin_credentials.clone());
+        data.insert(self.event_bus.clone());
+        data.insert(self.pool.clone());
+        data.insert(self.metrics.clone());
+        data.insert(self.upload_store.clone());
+        data.insert(self.rate_cache.clone());
+        data.insert(self.resource_locks.clone());
+        data.insert(self.request_log.clone());
+        data.insert(self.weather_proxy.clone());
+        data.insert(self.shutdown_switch.clone());
+        data.insert(self.response_cache.clone());
+        data.insert(self.reverse_proxy.clone());
+        data.insert(self.maintenance.clone());
+        data.insert(self.admin_allowlist.clone());
+        data.insert(self.short_links.clone());
+        data.insert(self.api_keys.clone());
+        data.insert(self.rate_limiter.clone());
+        data.insert(self.kv_store.clone());
+        data.insert(self.live_config.clone());
+        data.insert(self.config_routes.clone());
+        data.insert(web::Data::new(InflightLimit::new(self.config.max_inflight)));
+        data
+    }
+
+    pub fn build(
+        &self,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = Error,
+            InitError = (),
+        >,
+    > {
+        self.bare_app()
+            .wrap(middleware::from_fn(pretty_json::pretty_print)) // ?pretty=1: indented JSON answers
+            .wrap(middleware::from_fn(tx::finish_transactions)) // commits a handler's Tx on 2xx, rolls back otherwise
+            .wrap(middleware::from_fn(head::head_as_get)) // HEAD answered like GET, minus the body
+            .wrap(middleware::from_fn(body_log::log_bodies)) // LOG_BODIES: redacted bodies in the log
+            .wrap(middleware::from_fn(response_cache::cache_responses)) // X-Cache: HIT/MISS for repeated GETs
+            .wrap(session::middleware(
+                self.session_key.clone(),
+                self.config.tls_enabled,
+            )) // cookie sessions for /login, /profile
+            .wrap(middleware::from_fn(idempotency::idempotency)) // replays responses for retried unsafe requests
+            .wrap(middleware::from_fn(write_lock::serialize_writes)) // one write at a time per /users/{id}-like resource
+            .wrap(middleware::from_fn(decompress::decompress_body)) // Content-Encoding: gzip bodies unpacked, 415 for others
+            .wrap(middleware::from_fn(body_limit::limit_body_size)) // 413 once a body passes max_body_bytes
+            .wrap(middleware::from_fn(timeout::request_timeout)) // 504 for handlers that take too long
+            .wrap(middleware::from_fn(keepalive::count_requests)) // counts requests per connection
+            .wrap(middleware::from_fn(matched_route::matched_route)) // X-Matched-Route for traces
+            .wrap(middleware::from_fn(metrics::record_metrics)) // request counts + durations for /metrics
+            .wrap(middleware::from_fn(request_log::record_requests)) // recent requests for /debug/requests
+            .wrap(middleware::from_fn(https_redirect::redirect_to_https)) // FORCE_HTTPS: http -> https
+            .wrap(middleware::from_fn(maintenance::maintenance_mode)) // 503 for everything while in maintenance
+            .wrap(middleware::from_fn(trailing_slash::normalize_path)) // /users/ -> /users, before anything reads the path
+            .wrap(middleware::from_fn(strip_prefix::strip_prefix)) // STRIP_PREFIX: /service-a/users -> /users
+            .wrap(middleware::from_fn(inflight::limit_inflight)) // MAX_INFLIGHT: 503 + Retry-After when the worker is full
+            .wrap(middleware::from_fn(rate_limit::limit_rate)) // RATE_LIMIT: 429 per client, X-RateLimit-* on every answer
+            .wrap(middleware::from_fn(header_limit::limit_url_and_headers)) // 414/431 for oversized urls and headers
+            .wrap(middleware::from_fn(config_reload::use_live_config)) // config.toml edits reach everything inside
+            .wrap(security_headers(&self.config.content_security_policy)) // nosniff, DENY, no-referrer, CSP
+            .wrap(middleware::from_fn(trace::trace_request)) // a tracing span per request, traceparent for upstreams
+            .wrap(middleware::from_fn(request_id::request_id)) // tags every request/response with X-Request-Id
+            .wrap(middleware::from_fn(panics::catch_panics)) // a panic anywhere inside becomes a 500
+            .wrap(access_log::logger(
+                self.config.log_format == "json",
+                self.config.trust_proxy,
+            )) // one line per request, outermost to time everything
+    }
+}
+
+// what new() reads from the environment, handed in directly instead (see testing.rs)
+#[cfg(test)]
+impl AppBuilder {
+    pub fn with_admin_credentials(mut self, credentials: AdminCredentials) -> Self {
+        self.admin_credentials = web::Data::new(Some(credentials));
+        self
+    }
+
+    pub fn with_api_keys(mut self, keys: ApiKeys) -> Self {
+        self.api_keys = web::Data::new(keys);
+        self
+    }
+
+    pub fn with_admin_allowlist(mut self, allowlist: IpAllowlist) -> Self {
+        self.admin_allowlist = web::Data::new(allowlist);
+        self
+    }
+
+    pub fn with_config_routes(mut self, config_routes: ConfigRoutes) -> Self {
+        self.config_routes = web::Data::new(config_routes);
+        self
+    }
+
+    // what the config.toml watcher swaps the reloaded config into
+    pub fn live_config(&self) -> web::Data<LiveConfig> {
+        self.live_config.clone()
+    }
+}
+
+// every route configure_app() registers, in the same order, for GET /debug/routes (see
+// debug.rs): a route added there belongs in here too. `*` is any method, and a pattern ending
+// in `/*` is a scope that answers everything under it
+pub const ROUTES: &[(&str, &str)] = &[
+    ("GET", "/"),
+    ("POST", "/echo"),
+    ("GET", "/healthz"),
+    ("GET", "/readyz"),
+    ("GET", "/whoami"),
+    ("POST", "/contact"),
+    ("GET", "/sources/stream"),
+    ("GET", "/debug/keepalive"),
+    ("GET", "/close"),
+    ("GET", "/keep"),
+    ("GET", "/config"),
+    ("GET", "/download/{name}"),
+    ("GET", "/files/{name}"),
+    ("POST", "/jobs/export"),
+    ("POST", "/jobs"),
+    ("GET", "/jobs/{id}"),
+    ("GET", "/events"),
+    ("POST", "/events"),
+    ("GET", "/boom"),
+    ("GET", "/users"),
+    ("GET", "/users/stream"),
+    ("POST", "/users"),
+    ("POST", "/users/bulk"),
+    ("POST", "/users/pair"),
+    ("GET", "/users/{id}"),
+    ("PUT", "/users/{id}"),
+    ("GET", "/metrics"),
+    ("POST", "/uploads"),
+    ("GET", "/uploads/{id}"),
+    ("GET", "/rate"),
+    ("POST", "/login"),
+    ("GET", "/profile"),
+    ("POST", "/logout"),
+    ("GET", "/debug/requests"),
+    ("GET", "/logs/stream"),
+    ("GET", "/aggregate"),
+    ("GET", "/tenant"),
+    ("GET", "/proxy/weather"),
+    ("GET", "/orgs/{org}/repos"),
+    ("GET", "/report.csv"),
+    ("POST", "/webhook"),
+    ("GET", "/version"),
+    ("GET", "/search"),
+    ("GET", "/hash"),
+    ("POST", "/prefs"),
+    ("GET", "/prefs"),
+    ("POST", "/ingest"),
+    ("GET", "/greeting"),
+    ("POST", "/shorten"),
+    ("GET", "/s/{code}"),
+    ("POST", "/payments"),
+    ("GET", "/status/{code}"),
+    ("POST", "/text"),
+    ("POST", "/submit"),
+    ("GET", "/items"),
+    ("GET", "/orders/{id}"),
+    ("POST", "/avatar"),
+    ("GET", "/rows/stream"),
+    ("GET", "/token"),
+    ("GET", "/site"),
+    ("PUT", "/kv/{key}"),
+    ("GET", "/kv/{key}"),
+    ("DELETE", "/kv/{key}"),
+    ("GET", "/api-docs/openapi.json"),
+    ("GET", "/swagger-ui"),
+    ("GET", "/swagger-ui/init.js"),
+    ("GET", "/slow"),
+    ("GET", "/static/*"),
+    ("GET", "/api/v1/status"),
+    ("GET", "/api/v1/users"),
+    ("GET", "/api/v2/status"),
+    ("GET", "/api/v2/users"),
+    ("*", "/proxy/*"),
+    ("GET", "/admin/dashboard"),
+    ("POST", "/admin/shutdown"),
+    ("POST", "/admin/maintenance"),
+];
+
+// the scopes configure_app() registers: each takes every path under it, if only to answer `404`
+pub const SCOPES: &[&str] = &[static_files::SCOPE, "/api", reverse_proxy::SCOPE, "/admin"];
+
+pub fn configure_app(cfg: &mut web::ServiceConfig) {
+    cfg.service(basics::hello)
+        .service(basics::echo)
+        .service(basics::healthz)
+        .service(basics::readyz)
+        .service(request_id::whoami)
+        .service(contact::contact)
+        .service(sources::stream_sources)
+        .service(keepalive::keepalive_report)
+        .service(keepalive::close)
+        .service(keepalive::keep)
+        .service(config::show_config)
+        .service(download::download)
+        .service(files::show_file)
+        .service(jobs::start_export)
+        .service(jobs::submit_job)
+        .service(jobs::job_status)
+        .service(events::subscribe)
+        .service(events::publish)
+        .service(panics::boom)
+        .service(users::list_users)
+        .service(users::stream_users) // before /users/{id}, which would take `stream` as an id
+        .service(users::create_user)
+        .service(users::create_users_bulk)
+        .service(tx::create_user_pair)
+        .service(users::show_user)
+        .service(users::update_user)
+        .service(metrics::scrape)
+        .service(uploads::create_upload)
+        .service(uploads::show_upload)
+        .service(rate::show_rate)
+        .service(session::login)
+        .service(session::profile)
+        .service(session::logout)
+        .service(request_log::recent_requests)
+        .service(request_log::stream_logs)
+        .service(aggregate::aggregate)
+        .service(tenant::show_tenant)
+        .service(weather::weather)
+        .service(repos::list_repos)
+        .service(report::report)
+        .configure(webhook::configure)
+        .service(version::version)
+        .service(search::search)
+        .service(hash::hash)
+        .service(prefs::set_prefs)
+        .service(prefs::show_prefs)
+        .service(ingest::ingest)
+        .service(greeting::greeting)
+        .service(shorten::shorten)
+        .service(shorten::follow)
+        .service(payments::create_payment)
+        .service(status::status_code)
+        .service(text::echo_text)
+        .service(submit::submit)
+        .service(items::list_items)
+        .service(orders::show_order)
+        .service(avatar::upload_avatar)
+        .service(disconnect::stream_rows)
+        .service(bearer::show_token)
+        .configure(multi_host::configure)
+        .service(kv::set_value)
+        .service(kv::get_value)
+        .service(kv::delete_value)
+        .service(openapi::openapi_json)
+        .service(openapi::swagger_ui)
+        .service(openapi::swagger_ui_init)
+        .service(timeout::slow)
+        .service(
+            web::scope(static_files::SCOPE)
+                .wrap(middleware::from_fn(static_files::cache_headers)) // immutable hashed assets, revalidated HTML
+                .configure(static_files::configure),
+        )
+        .service(
+            web::scope("/api")
+                .wrap(middleware::from_fn(accept_json::require_json_accept)) // 406 unless JSON is accepted
+                .wrap(middleware::from_fn(api_key::require_api_key)) // X-Api-Key, except the status routes
+                .configure(api::configure),
+        )
+        // after /proxy/weather, so that one is still answered locally
+        .service(web::scope(reverse_proxy::SCOPE).default_service(web::to(reverse_proxy::forward)))
+        .service(
+            web::scope("/admin")
+                .guard(IpAllowlistGuard) // ADMIN_ALLOWLIST: everybody else gets a plain 404
+                .wrap(middleware::from_fn(auth::basic_auth)) // only this scope needs credentials
+                .configure(admin::configure),
+        );
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use crate::{request_id::REQUEST_ID_HEADER, testing};
+
+    #[actix_web::test]
+    async fn apps_built_from_one_builder_share_its_state() {
+        let builder = testing::builder().await;
+        // two workers' apps
+        let first = test::init_service(builder.build()).await;
+        let second = test::init_service(builder.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/uploads")
+            .set_payload("shared")
+            .to_request();
+        let res = test::call_service(&first, req).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let location = res.headers().get("location").unwrap().to_str().unwrap();
+
+        let req = test::TestRequest::get().uri(location).to_request();
+        assert_eq!(test::call_and_read_body(&second, req).await, "shared");
+    }
+
+    #[actix_web::test]
+    async fn bare_app_has_the_routes_without_the_middleware() {
+        let builder = testing::builder().await;
+        let bare = test::init_service(builder.bare_app()).await;
+        let full = test::init_service(builder.build()).await;
+
+        let req = || test::TestRequest::get().uri("/healthz").to_request();
+        let bare_res = test::call_service(&bare, req()).await;
+        let full_res = test::call_service(&full, req()).await;
+
+        assert_eq!(bare_res.status(), StatusCode::OK);
+        assert!(!bare_res.headers().contains_key(REQUEST_ID_HEADER));
+        assert!(full_res.headers().contains_key(REQUEST_ID_HEADER));
+    }
+}
@@ -0,0 +1,174 @@
+/*
+   HTTP BASIC AUTHENTICATION
+    the client sends `Authorization: Basic <base64(user:password)>` on every request.
+     when it is missing or wrong we answer `401` with `WWW-Authenticate: Basic realm="admin"`,
+     which makes browsers show their login prompt.
+
+    the expected credentials come from the env vars ADMIN_USER / ADMIN_PASS, read once at
+     startup. if either is missing, nobody can log in (better locked than open).
+
+    comparing secrets with `==` stops at the first different byte, so the response time tells
+     an attacker how much of a guess was right. constant_time_eq always looks at every byte.
+*/
+
+use std::env;
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+pub struct AdminCredentials {
+    user: String,
+    pass: String,
+}
+
+impl AdminCredentials {
+    pub fn from_env() -> Option<Self> {
+        match (env::var("ADMIN_USER"), env::var("ADMIN_PASS")) {
+            (Ok(user), Ok(pass)) => Some(Self { user, pass }),
+            _ => {
+                log::warn!("ADMIN_USER / ADMIN_PASS are not set, the admin area is locked");
+                None
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn new(user: &str, pass: &str) -> Self {
+        Self {
+            user: user.to_owned(),
+            pass: pass.to_owned(),
+        }
+    }
+
+    fn matches(&self, user: &str, pass: &str) -> bool {
+        // `&` and not `&&`: the password is compared even when the user is already wrong
+        constant_time_eq(self.user.as_bytes(), user.as_bytes())
+            & constant_time_eq(self.pass.as_bytes(), pass.as_bytes())
+    }
+}
+
+// the time taken only depends on the lengths, never on where the inputs differ
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut diff = a.len() ^ b.len();
+    for i in 0..a.len().max(b.len()) {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        diff |= usize::from(x ^ y);
+    }
+    diff == 0
+}
+
+// "Basic dXNlcjpwYXNz" -> ("user", "pass")
+fn basic_credentials(req: &ServiceRequest) -> Option<(String, String)> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, pass) = decoded.split_once(':')?;
+    Some((user.to_owned(), pass.to_owned()))
+}
+
+pub async fn basic_auth(
+    credentials: web::Data<Option<AdminCredentials>>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let authorized = match (credentials.as_ref(), basic_credentials(&req)) {
+        (Some(expected), Some((user, pass))) => expected.matches(&user, &pass),
+        _ => false,
+    };
+
+    if !authorized {
+        let res = HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, r#"Basic realm="admin""#))
+            .finish();
+        return Ok(req.into_response(res));
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use super::*;
+    use crate::testing;
+
+    fn dashboard(authorization: Option<&str>) -> test::TestRequest {
+        let req = test::TestRequest::get().uri("/admin/dashboard");
+        match authorization {
+            Some(value) => req.insert_header((header::AUTHORIZATION, value)),
+            None => req,
+        }
+    }
+
+    fn basic(user: &str, pass: &str) -> String {
+        format!("Basic {}", STANDARD.encode(format!("{user}:{pass}")))
+    }
+
+    #[actix_web::test]
+    async fn the_right_credentials_get_in() {
+        let builder = testing::builder()
+            .await
+            .with_admin_credentials(AdminCredentials::new("admin", "s3cret"));
+        let app = test::init_service(builder.build()).await;
+
+        let req = dashboard(Some(&basic("admin", "s3cret"))).to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn anything_else_is_401_with_a_challenge() {
+        let builder = testing::builder()
+            .await
+            .with_admin_credentials(AdminCredentials::new("admin", "s3cret"));
+        let app = test::init_service(builder.build()).await;
+
+        for authorization in [
+            None,
+            Some(basic("admin", "wrong")),
+            Some(basic("root", "s3cret")),
+            Some("Bearer s3cret".to_owned()),
+            Some("Basic not-base64!".to_owned()),
+        ] {
+            let res =
+                test::call_service(&app, dashboard(authorization.as_deref()).to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{authorization:?}");
+            assert_eq!(
+                res.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+                r#"Basic realm="admin""#
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn without_credentials_configured_nobody_gets_in() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, dashboard(Some(&basic("", ""))).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn constant_time_eq_compares_whole_inputs() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret!"));
+        assert!(!constant_time_eq(b"", b"x"));
+    }
+}
@@ -0,0 +1,178 @@
+/*
+   IMAGE UPLOADS: POST /avatar
+    takes a PNG or JPEG as the raw request body and answers with what it is:
+
+        { "format": "png", "width": 256, "height": 256, "bytes": 18211 }
+
+    the Content-Type the client sent is not trusted, the bytes decide:
+     - the MAGIC BYTES at the start (`\x89PNG\r\n\x1a\n`, `FF D8 FF`) must be PNG or JPEG,
+        anything else is `415 Unsupported Media Type`
+     - the image crate then reads the dimensions from the image HEADER only; the pixels are
+        never decoded, so a small file claiming to be 100000x100000 costs nothing
+     - wider or higher than MAX_SIDE, or a header that can't be read, is
+        `422 Unprocessable Entity`: it is an image, just not one we take
+
+    the body is read up to MAX_BYTES (then `413`), nothing is stored: this is the validation a
+     real avatar upload would do before keeping the file.
+*/
+
+use std::io::Cursor;
+
+use actix_web::{error, post, web, HttpResponse};
+use futures_util::StreamExt;
+use image::{ImageFormat, ImageReader};
+use serde_json::json;
+
+const MAX_BYTES: usize = 1024 * 1024;
+const MAX_SIDE: u32 = 2048;
+
+#[post("/avatar")]
+pub async fn upload_avatar(mut body: web::Payload) -> actix_web::Result<HttpResponse> {
+    let mut bytes = web::BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > MAX_BYTES {
+            return Err(error::ErrorPayloadTooLarge(format!(
+                "an avatar may be at most {MAX_BYTES} bytes"
+            )));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    let format = match image::guess_format(&bytes) {
+        Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => format,
+        _ => {
+            return Ok(HttpResponse::UnsupportedMediaType()
+                .json(json!({ "error": "the body is not a PNG or JPEG image" })))
+        }
+    };
+
+    let dimensions = ImageReader::with_format(Cursor::new(&bytes[..]), format).into_dimensions();
+    let (width, height) = match dimensions {
+        Ok(dimensions) => dimensions,
+        Err(err) => {
+            return Ok(HttpResponse::UnprocessableEntity()
+                .json(json!({ "error": format!("the image can't be read: {err}") })))
+        }
+    };
+    if width > MAX_SIDE || height > MAX_SIDE {
+        return Ok(HttpResponse::UnprocessableEntity().json(json!({
+            "error": format!("{width}x{height} is too big, at most {MAX_SIDE}x{MAX_SIDE}"),
+        })));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "format": format.extensions_str()[0],
+        "width": width,
+        "height": height,
+        "bytes": bytes.len(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use image::RgbImage;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    fn encoded(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        RgbImage::new(width, height)
+            .write_to(&mut bytes, format)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    fn upload(body: Vec<u8>) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/avatar")
+            .insert_header(("content-type", "image/png"))
+            .set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn a_png_is_described() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let png = encoded(256, 128, ImageFormat::Png);
+        let size = png.len();
+
+        let res = test::call_service(&app, upload(png).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["format"], "png");
+        assert_eq!(body["width"], 256);
+        assert_eq!(body["height"], 128);
+        assert_eq!(body["bytes"], size);
+    }
+
+    #[actix_web::test]
+    async fn a_jpeg_is_described() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = upload(encoded(64, 48, ImageFormat::Jpeg)).to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["format"], "jpg");
+        assert_eq!(
+            (body["width"].as_u64(), body["height"].as_u64()),
+            (Some(64), Some(48))
+        );
+    }
+
+    #[actix_web::test]
+    async fn a_file_that_is_not_an_image_is_415_whatever_it_claims() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = upload(b"%PDF-1.7 not a picture".to_vec()).to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "the body is not a PNG or JPEG image");
+    }
+
+    #[actix_web::test]
+    async fn an_image_over_the_dimension_limit_is_422() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for (width, height) in [(MAX_SIDE + 1, 10), (10, MAX_SIDE + 1)] {
+            let req = upload(encoded(width, height, ImageFormat::Png)).to_request();
+            let res = test::call_service(&app, req).await;
+
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(
+                body["error"],
+                format!("{width}x{height} is too big, at most 2048x2048")
+            );
+        }
+
+        let req = upload(encoded(MAX_SIDE, MAX_SIDE, ImageFormat::Png)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn a_png_with_a_broken_header_is_422() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let png = encoded(16, 16, ImageFormat::Png);
+
+        let res = test::call_service(&app, upload(png[..12].to_vec()).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_web::test]
+    async fn a_body_over_the_size_limit_is_413() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let mut png = encoded(16, 16, ImageFormat::Png);
+        png.resize(MAX_BYTES + 1, 0);
+
+        let res = test::call_service(&app, upload(png).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,184 @@
+/*
+   WEIGHTED ROUND ROBIN OVER SEVERAL UPSTREAMS
+    the reverse proxy can spread its requests over more than one upstream, each with a weight:
+
+        UPSTREAMS="http://10.0.0.1:9000=3,http://10.0.0.2:9000=1"
+
+    out of every 4 requests 3 go to the first and 1 to the second. the order is the SMOOTH
+     weighted round robin nginx uses (a a b a, not a a a b): every pick adds each upstream's
+     weight to its score, takes the highest score and subtracts the total weight from it, so a
+     heavy upstream never gets a long burst in a row. a missing weight is 1.
+
+    a background task asks every upstream for HEALTH_PATH each HEALTH_INTERVAL; one that doesn't
+     answer 2xx within HEALTH_TIMEOUT is UNHEALTHY and skipped by the picks (its weight simply
+     doesn't count) until a later check succeeds. with every upstream unhealthy, pick() has
+     nothing to offer and the proxy answers `503`. upstreams start out healthy, so the first
+     requests don't have to wait for the first check.
+
+    the scores live behind one Mutex in the shared ReverseProxy, the health flags are atomics:
+     all workers pick from the same rotation, so the ratio holds across the whole server.
+*/
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+pub const HEALTH_PATH: &str = "/healthz";
+pub const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct Upstream {
+    pub url: String,
+    weight: i64,
+    healthy: AtomicBool,
+}
+
+pub struct Balancer {
+    upstreams: Vec<Upstream>,
+    // the smooth weighted round robin score of each upstream, same order
+    scores: Mutex<Vec<i64>>,
+}
+
+// "http://a:9000=3" -> ("http://a:9000", 3), "http://a:9000" -> ("http://a:9000", 1)
+fn parse_entry(entry: &str) -> Option<(String, i64)> {
+    let (url, weight) = match entry.rsplit_once('=') {
+        Some((url, weight)) => (url, weight.trim().parse::<u32>().ok().filter(|&w| w > 0)?),
+        None => (entry, 1),
+    };
+    let url = url.trim().trim_end_matches('/');
+    (url.starts_with("http://") || url.starts_with("https://"))
+        .then(|| (url.to_owned(), i64::from(weight)))
+}
+
+impl Balancer {
+    // "url=weight,url=weight,..."; broken entries are skipped with a warning
+    pub fn parse(spec: &str) -> Self {
+        let upstreams: Vec<Upstream> = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                let parsed = parse_entry(entry);
+                if parsed.is_none() {
+                    log::warn!(
+                        "skipping upstream `{entry}`, expected http(s)://host[:port][=weight]"
+                    );
+                }
+                parsed
+            })
+            .map(|(url, weight)| Upstream {
+                url,
+                weight,
+                healthy: AtomicBool::new(true),
+            })
+            .collect();
+
+        Self {
+            scores: Mutex::new(vec![0; upstreams.len()]),
+            upstreams,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.upstreams.is_empty()
+    }
+
+    // None when no upstream is healthy
+    pub fn pick(&self) -> Option<&Upstream> {
+        let mut scores = self.scores.lock().unwrap();
+        let mut total = 0;
+        let mut best: Option<usize> = None;
+
+        for (i, upstream) in self.upstreams.iter().enumerate() {
+            if !upstream.healthy.load(Ordering::Relaxed) {
+                continue;
+            }
+            scores[i] += upstream.weight;
+            total += upstream.weight;
+            if best.is_none_or(|best| scores[i] > scores[best]) {
+                best = Some(i);
+            }
+        }
+
+        let best = best?;
+        scores[best] -= total;
+        Some(&self.upstreams[best])
+    }
+
+    pub async fn check_health(&self) {
+        let client = awc::Client::builder().timeout(HEALTH_TIMEOUT).finish();
+
+        for upstream in &self.upstreams {
+            let healthy = client
+                .get(format!("{}{HEALTH_PATH}", upstream.url))
+                .send()
+                .await
+                .is_ok_and(|res| res.status().is_success());
+
+            let was_healthy = upstream.healthy.swap(healthy, Ordering::Relaxed);
+            match (was_healthy, healthy) {
+                (true, false) => log::warn!("upstream {} is unhealthy, skipping it", upstream.url),
+                (false, true) => log::info!("upstream {} is healthy again", upstream.url),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picks(balancer: &Balancer, n: usize) -> String {
+        (0..n)
+            .map(|_| match balancer.pick() {
+                Some(upstream) => &upstream.url[7..],
+                None => "-",
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn weights_are_parsed_and_broken_entries_skipped() {
+        let balancer = Balancer::parse("http://a/=3, http://b, ftp://c=2, http://d=0, http://e=x");
+
+        let parsed: Vec<_> = balancer
+            .upstreams
+            .iter()
+            .map(|upstream| (upstream.url.as_str(), upstream.weight))
+            .collect();
+        assert_eq!(parsed, [("http://a", 3), ("http://b", 1)]);
+        assert!(Balancer::parse("").is_empty());
+    }
+
+    #[actix_web::test]
+    async fn the_rotation_is_smooth_and_keeps_the_ratio() {
+        let balancer = Balancer::parse("http://a=3,http://b=1");
+
+        assert_eq!(picks(&balancer, 8), "aabaaaba");
+        let many = picks(&balancer, 400);
+        assert_eq!(many.matches('a').count(), 300);
+    }
+
+    #[actix_web::test]
+    async fn unhealthy_upstreams_dont_count() {
+        let balancer = Balancer::parse("http://a=3,http://b=1,http://c=1");
+
+        balancer.upstreams[0]
+            .healthy
+            .store(false, Ordering::Relaxed);
+        assert_eq!(picks(&balancer, 4), "bcbc");
+
+        balancer.upstreams[1]
+            .healthy
+            .store(false, Ordering::Relaxed);
+        balancer.upstreams[2]
+            .healthy
+            .store(false, Ordering::Relaxed);
+        assert!(balancer.pick().is_none());
+    }
+}
@@ -0,0 +1,113 @@
+/*
+   BASIC ROUTES
+    the hello / echo handlers from the first section above, wired into the live app, and the
+     probes for load balancers / orchestrators:
+     - `/healthz` -> the process is alive (restart it when this fails)
+     - `/readyz`  -> it wants traffic; `503` once a shutdown started (see supervisor.rs)
+*/
+
+use actix_web::{
+    get,
+    http::header::{CacheControl, CacheDirective},
+    post, web, HttpResponse, Responder,
+};
+
+use crate::supervisor::ShutdownSwitch;
+
+#[get("/")]
+pub async fn hello() -> impl Responder {
+    HttpResponse::Ok().body("Hello world!")
+}
+
+#[post("/echo")]
+pub async fn echo(req_body: String) -> impl Responder {
+    HttpResponse::Ok().body(req_body)
+}
+
+#[get("/healthz")]
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body("ok")
+}
+
+#[get("/readyz")]
+pub async fn readyz(switch: web::Data<ShutdownSwitch>) -> impl Responder {
+    let (mut res, body) = if switch.is_ready() {
+        (HttpResponse::Ok(), "ready")
+    } else {
+        (HttpResponse::ServiceUnavailable(), "shutting down")
+    };
+    res.insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use actix_web::{
+        http::{header, StatusCode},
+        rt, test, HttpServer,
+    };
+
+    use crate::{config::Config, testing};
+
+    #[actix_web::test]
+    async fn hello_echo_and_healthz() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "Hello world!");
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .set_payload("ping")
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "ping");
+
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-store"
+        );
+        assert_eq!(test::read_body(res).await, "ok");
+    }
+
+    #[actix_web::test]
+    async fn a_shutdown_turns_readyz_unready_while_healthz_stays_ok() {
+        let config = Config {
+            shutdown_delay_secs: 1,
+            ..Config::default()
+        };
+        let builder = testing::builder_with(config).await;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let factory = builder.clone();
+        let server = HttpServer::new(move || factory.build())
+            .workers(1)
+            .disable_signals()
+            .listen(listener)
+            .unwrap()
+            .run();
+        builder.set_server_handle(server.handle());
+        let running = rt::spawn(server);
+
+        let client = awc::Client::default();
+        let status = |path: &'static str| {
+            let req = client.get(format!("http://{addr}{path}"));
+            async move { req.send().await.unwrap().status() }
+        };
+        assert_eq!(status("/readyz").await, StatusCode::OK);
+
+        // what SIGTERM / SIGINT do in supervise()
+        assert!(builder.shutdown_switch().trigger());
+
+        // still accepting for shutdown_delay_secs, but no longer ready
+        assert_eq!(status("/readyz").await, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(status("/healthz").await, StatusCode::OK);
+
+        running.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+/*
+   BEARER TOKENS
+    a handler that only needs the raw token of `Authorization: Bearer <token>`, and not what it
+     means, asks for it with a BearerToken parameter:
+
+        async fn handler(BearerToken(token): BearerToken) -> ...
+
+    checking the token (a JWT's signature and claims, a lookup of an opaque token, ...) is up
+     to the handler or whatever it passes the token on to; this only takes the header apart:
+     - the scheme is matched case insensitively (`bearer` works too)
+     - the token is what follows the scheme, without surrounding whitespace, and has to be
+        token68 (letters, digits and `-._~+/`, `=` padding at the end, see RFC 6750)
+     - a missing header, another scheme (eg: `Basic`) or an empty or invalid token is `401`
+        with `WWW-Authenticate: Bearer`, before the handler runs
+
+    `GET /token` shows it in use: it answers with the length of the token, never the token.
+*/
+
+use std::future::{ready, Ready};
+
+use actix_web::{
+    dev::Payload, error::InternalError, get, http::header, Error, FromRequest, HttpRequest,
+    HttpResponse,
+};
+use serde_json::json;
+
+pub struct BearerToken(pub String);
+
+fn is_token68(token: &str) -> bool {
+    let token = token.trim_end_matches('=');
+    !token.is_empty()
+        && token.bytes().all(|b| {
+            b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~' | b'+' | b'/')
+        })
+}
+
+// "Bearer abc.def" -> "abc.def"
+fn bearer_token(req: &HttpRequest) -> Result<BearerToken, &'static str> {
+    let Some(value) = req.headers().get(header::AUTHORIZATION) else {
+        return Err("missing Authorization header");
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| "the Authorization header is not valid text")?;
+    let (scheme, token) = value.trim().split_once(' ').unwrap_or((value, ""));
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return Err("the Authorization scheme must be Bearer");
+    }
+
+    let token = token.trim();
+    if !is_token68(token) {
+        return Err("the bearer token is missing or malformed");
+    }
+    Ok(BearerToken(token.to_owned()))
+}
+
+impl FromRequest for BearerToken {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(bearer_token(req).map_err(|message| {
+            let res = HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .json(json!({ "error": message }));
+            InternalError::from_response(message, res).into()
+        }))
+    }
+}
+
+#[get("/token")]
+pub async fn show_token(BearerToken(token): BearerToken) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "token_length": token.len() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, web, App};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    async fn echo(BearerToken(token): BearerToken) -> String {
+        token
+    }
+
+    fn with_authorization(value: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri("/echo")
+            .insert_header((header::AUTHORIZATION, value))
+    }
+
+    #[actix_web::test]
+    async fn a_well_formed_header_gives_the_raw_token() {
+        let app = test::init_service(App::new().route("/echo", web::get().to(echo))).await;
+
+        for (value, token) in [
+            (
+                "Bearer eyJhbGciOiJIUzI1NiJ9.e30.sig_-~+/",
+                "eyJhbGciOiJIUzI1NiJ9.e30.sig_-~+/",
+            ),
+            ("bearer abc==", "abc=="),
+            ("  BEARER   padded  ", "padded"),
+        ] {
+            let body = test::call_and_read_body(&app, with_authorization(value).to_request()).await;
+            assert_eq!(body, token, "{value}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_missing_header_or_another_scheme_is_401() {
+        let app = test::init_service(App::new().route("/echo", web::get().to(echo))).await;
+        let cases = [
+            (
+                test::TestRequest::get().uri("/echo"),
+                "missing Authorization header",
+            ),
+            (
+                with_authorization("Basic YWRtaW46czNjcmV0"),
+                "the Authorization scheme must be Bearer",
+            ),
+            (
+                with_authorization("Bearer"),
+                "the bearer token is missing or malformed",
+            ),
+            (
+                with_authorization("Bearer a b"),
+                "the bearer token is missing or malformed",
+            ),
+            (
+                with_authorization("Bearer ==abc"),
+                "the bearer token is missing or malformed",
+            ),
+        ];
+
+        for (req, message) in cases {
+            let res = test::call_service(&app, req.to_request()).await;
+
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{message}");
+            assert_eq!(
+                res.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+                "Bearer"
+            );
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["error"], message);
+        }
+    }
+
+    #[actix_web::test]
+    async fn the_token_route_answers_with_the_length_only() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/token")
+            .insert_header((header::AUTHORIZATION, "Bearer secret-token"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = test::read_body(res).await;
+        assert_eq!(body, r#"{"token_length":12}"#);
+    }
+}
@@ -0,0 +1,143 @@
+/*
+   REQUEST BODY SIZE LIMIT
+    the extractors have their own limits (web::Bytes 256kB, web::Json 2MB, the contact form 4kB),
+     but a handler streaming web::Payload (eg: the reverse proxy) has none, and a chunked body
+     doesn't say up front how big it is going to be. this middleware puts ONE cap,
+     `max_body_bytes` (env APP_MAX_BODY_BYTES, default 1MB), on every request body:
+
+     - a declared Content-Length over the cap -> `413` right away, the body is never read
+     - otherwise (chunked, or no length at all) the payload is wrapped in a counting stream: the
+        chunk that crosses the cap ends the body with PayloadError::Overflow, which whoever reads
+        it turns into `413`. nothing past the cap is buffered, however much the client sends.
+
+    it sits outside the middleware that read bodies themselves (idempotency, body logging), so
+     they see the capped stream too.
+
+    UNCAPPED_PATHS are handlers that process the body as a stream and never hold more than a
+     chunk of it (eg: `/ingest`), so a size cap would only get in the way there.
+*/
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::PayloadError,
+    http::header,
+    middleware::Next,
+    web, Error, HttpMessage, HttpResponse,
+};
+use futures_util::StreamExt;
+
+use crate::config::Config;
+
+pub const UNCAPPED_PATHS: [&str; 1] = ["/ingest"];
+
+fn declared_length(req: &ServiceRequest) -> Option<u64> {
+    req.headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+pub async fn limit_body_size(
+    config: web::Data<Config>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if UNCAPPED_PATHS.contains(&req.path()) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    }
+    let max_bytes = config.max_body_bytes;
+
+    if declared_length(&req).is_some_and(|length| length > max_bytes as u64) {
+        let res = HttpResponse::PayloadTooLarge()
+            .body(format!("request body is larger than {max_bytes} bytes"));
+        return Ok(req.into_response(res));
+    }
+
+    let mut read = 0;
+    let capped = req.take_payload().map(move |chunk| {
+        let chunk = chunk?;
+        read += chunk.len();
+        if read > max_bytes {
+            return Err(PayloadError::Overflow);
+        }
+        Ok(chunk)
+    });
+    req.set_payload(Payload::Stream {
+        payload: Box::pin(capped),
+    });
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use super::*;
+    use crate::testing;
+
+    const MAX_BODY_BYTES: usize = 1000;
+
+    async fn capped_app_status(body: Vec<u8>, declare_length: bool) -> StatusCode {
+        let config = Config {
+            max_body_bytes: MAX_BODY_BYTES,
+            ..Config::default()
+        };
+        let app = test::init_service(testing::builder_with(config).await.build()).await;
+
+        let mut req = test::TestRequest::post()
+            .uri("/uploads")
+            .set_payload(body)
+            .to_request();
+        if !declare_length {
+            req.headers_mut().remove(header::CONTENT_LENGTH);
+        }
+        test::call_service(&app, req).await.status()
+    }
+
+    #[actix_web::test]
+    async fn a_declared_oversized_length_is_413() {
+        let status = capped_app_status(vec![b'a'; MAX_BODY_BYTES + 1], true).await;
+
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn an_undeclared_body_is_cut_off_at_the_cap() {
+        let status = capped_app_status(vec![b'a'; MAX_BODY_BYTES * 5], false).await;
+
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn a_body_within_the_cap_gets_through() {
+        for declare_length in [true, false] {
+            let status = capped_app_status(vec![b'a'; MAX_BODY_BYTES], declare_length).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_chunked_body_streaming_past_the_cap_is_413() {
+        let config = Config {
+            max_body_bytes: MAX_BODY_BYTES,
+            ..Config::default()
+        };
+        let addr = testing::serve(&testing::builder_with(config).await);
+
+        let chunks = (0..50).map(|_| Ok::<_, Error>(web::Bytes::from(vec![b'a'; 100])));
+        let res = awc::Client::default()
+            .post(format!("http://{addr}/uploads"))
+            .send_stream(futures_util::stream::iter(chunks))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
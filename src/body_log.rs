@@ -0,0 +1,244 @@
+/*
+   REQUEST / RESPONSE BODY LOGGING
+    with `log_bodies` on (env LOG_BODIES, see config.rs) every request body and response body is
+     written to the log, for debugging what clients actually send. it is off by default: bodies
+     are big, and they carry personal data.
+
+    before a body is logged:
+     - sensitive values are REDACTED: in JSON (at any depth) and in urlencoded forms, every
+        field whose name contains `password`, `token` or `secret` becomes "[REDACTED]"
+     - it is TRUNCATED to `log_body_max_bytes` (default 4096), the log says how much was cut
+
+    reading the request body consumes it, so (like idempotency.rs) it is read fully and then put
+     back for the handler, which sees exactly the same bytes. the request body limit of
+     web::Bytes applies (256kB) while logging is on.
+    a response body is only logged when its size is known and at most RESPONSE_BUFFER_LIMIT:
+     buffering an SSE stream would never end, and a large download would sit in memory. nor is
+     the answer to a HEAD: its body is empty and only CLAIMS the GET's size (see head.rs), which
+     buffering would turn into `Content-Length: 0`.
+*/
+
+use actix_web::{
+    body::{self, BodySize, BoxBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error,
+    http::{header, Method},
+    middleware::Next,
+    web::{self, Bytes},
+    Error,
+};
+use serde_json::Value;
+
+use crate::config::Config;
+
+const SENSITIVE: [&str; 3] = ["password", "token", "secret"];
+const REDACTED: &str = "[REDACTED]";
+const RESPONSE_BUFFER_LIMIT: u64 = 1024 * 1024;
+
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SENSITIVE.iter().any(|sensitive| name.contains(sensitive))
+}
+
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                if is_sensitive(name) {
+                    *value = Value::from(REDACTED);
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+// JSON and urlencoded forms are redacted, anything else is logged as (lossy) text
+fn redacted(content_type: Option<&str>, body: &[u8]) -> String {
+    let content_type = content_type.unwrap_or_default();
+
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        if let Ok(pairs) = serde_urlencoded::from_bytes::<Vec<(String, String)>>(body) {
+            let pairs: Vec<(String, String)> = pairs
+                .into_iter()
+                .map(|(name, value)| match is_sensitive(&name) {
+                    true => (name, REDACTED.to_owned()),
+                    false => (name, value),
+                })
+                .collect();
+            return serde_urlencoded::to_string(pairs).unwrap_or_default();
+        }
+    }
+
+    if let Ok(mut json) = serde_json::from_slice::<Value>(body) {
+        redact_json(&mut json);
+        return json.to_string();
+    }
+
+    String::from_utf8_lossy(body).into_owned()
+}
+
+fn truncated(mut text: String, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let total = text.len();
+    let mut cut = max_bytes;
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    text.truncate(cut);
+    format!("{text}... ({} more bytes)", total - cut)
+}
+
+fn content_type(headers: &header::HeaderMap) -> Option<&str> {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+}
+
+pub async fn log_bodies(
+    config: web::Data<Config>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if !config.log_bodies {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    }
+    let max_bytes = config.log_body_max_bytes;
+
+    let body = req.extract::<Bytes>().await?;
+    if !body.is_empty() {
+        let logged = redacted(content_type(req.headers()), &body);
+        log::info!(
+            "{} {} request body: {}",
+            req.method(),
+            req.path(),
+            truncated(logged, max_bytes)
+        );
+    }
+    req.set_payload(Payload::from(body)); // <- the handler still gets the whole body
+
+    // read now: head.rs turns the request into a GET on its way in
+    let is_head = req.method() == Method::HEAD;
+    let res = next.call(req).await?;
+    let loggable = !is_head
+        && matches!(res.response().body().size(), BodySize::Sized(size) if size <= RESPONSE_BUFFER_LIMIT);
+    if !loggable {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, res) = res.into_parts();
+    let (res, res_body) = res.into_parts();
+    let res_body = body::to_bytes(res_body)
+        .await
+        .map_err(|err| error::ErrorInternalServerError(err.into().to_string()))?;
+
+    if !res_body.is_empty() {
+        let logged = redacted(content_type(res.headers()), &res_body);
+        log::info!(
+            "{} {} response body ({}): {}",
+            req.method(),
+            req.path(),
+            res.status(),
+            truncated(logged, max_bytes)
+        );
+    }
+
+    Ok(ServiceResponse::new(
+        req,
+        res.set_body(res_body).map_into_boxed_body(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{middleware, test, App, HttpResponse};
+
+    use super::*;
+    use crate::testing;
+
+    // answers with exactly what it got
+    async fn echo(body: Bytes) -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(body)
+    }
+
+    async fn post_logged(on: bool, max_bytes: usize, path: &str, body: &str) -> Bytes {
+        testing::capture_logs();
+        let config = Config {
+            log_bodies: on,
+            log_body_max_bytes: max_bytes,
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .wrap(middleware::from_fn(log_bodies))
+                .route(path, web::post().to(echo)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri(path)
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload(body.to_owned())
+            .to_request();
+        test::call_and_read_body(&app, req).await
+    }
+
+    #[actix_web::test]
+    async fn passwords_are_redacted_and_the_handler_gets_everything() {
+        let body = r#"{"user":"abebe","password":"hunter2","nested":[{"api_token":"t0k"}]}"#;
+
+        let echoed = post_logged(true, 4096, "/body-log/redact", body).await;
+
+        assert_eq!(echoed, body);
+        let lines = testing::logged("/body-log/redact");
+        assert_eq!(lines.len(), 2, "{lines:?}");
+        for line in &lines {
+            assert!(line.contains(r#""password":"[REDACTED]""#), "{line}");
+            assert!(line.contains(r#""api_token":"[REDACTED]""#), "{line}");
+            assert!(line.contains(r#""user":"abebe""#), "{line}");
+            assert!(!line.contains("hunter2") && !line.contains("t0k"), "{line}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_long_body_is_truncated_in_the_log() {
+        let body = format!(r#"{{"text":"{}"}}"#, "x".repeat(100));
+
+        let echoed = post_logged(true, 20, "/body-log/truncate", &body).await;
+
+        assert_eq!(echoed, body);
+        let request_line = &testing::logged("/body-log/truncate request body")[0];
+        assert!(
+            request_line.ends_with(r#"{"text":"xxxxxxxxxxx... (91 more bytes)"#),
+            "{request_line}"
+        );
+    }
+
+    #[actix_web::test]
+    async fn nothing_is_logged_when_off() {
+        post_logged(false, 4096, "/body-log/off", r#"{"password":"hunter2"}"#).await;
+
+        assert!(testing::logged("/body-log/off").is_empty());
+    }
+
+    #[actix_web::test]
+    async fn form_fields_are_redacted_too() {
+        let logged = redacted(
+            Some("application/x-www-form-urlencoded"),
+            b"name=abebe&client_secret=s3cr3t",
+        );
+
+        assert_eq!(logged, "name=abebe&client_secret=%5BREDACTED%5D");
+    }
+}
@@ -0,0 +1,165 @@
+/*
+   CONDITIONAL GET (ETag / If-None-Match)
+    an ETag is a fingerprint of a response body. the client keeps it and sends it back in
+     `If-None-Match` next time:
+     - same fingerprint -> `304 Not Modified` with an empty body, the client reuses its copy
+     - different (or no) fingerprint -> `200` with the full body and the new `ETag`
+
+    the ETag here is a hash of the serialized body, so identical data always gives the same tag
+     and any change to the data gives a new one, without keeping version numbers around.
+
+   OPTIMISTIC LOCKING (If-Match)
+    the same tag works the other way round for updates (eg: `PUT /users/{id}`): the client
+     sends back the ETag it read in `If-Match`, meaning "only if it is still like this":
+     - the tag of the CURRENT data (or `*`)  -> the update goes ahead, the answer has the new tag
+     - any other tag                          -> `412 Precondition Failed`, somebody else changed
+                                                 it since; read it again and redo the change
+     - no `If-Match` at all                   -> `428 Precondition Required`, a blind update
+                                                 could overwrite a change it never saw
+    here the comparison is STRONG: a weak tag W/"x" never matches.
+
+   DATES (Last-Modified / If-Modified-Since)
+    for files a date is the natural validator: `Last-Modified` carries the file's mtime, the
+     client sends it back in `If-Modified-Since`, and a file not modified since is `304`.
+     HTTP dates have whole seconds, an mtime doesn't, and the clocks of the two sides may be
+     apart a little, so a file counts as unchanged up to CLOCK_SKEW after the client's date.
+*/
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    time::{Duration, SystemTime},
+};
+
+use actix_web::{
+    http::header::{ContentType, ETag, EntityTag, IfMatch, IfModifiedSince, IfNoneMatch},
+    HttpMessage, HttpRequest, HttpResponse,
+};
+use serde::Serialize;
+
+const CLOCK_SKEW: Duration = Duration::from_secs(1);
+
+pub fn etag_for(body: &[u8]) -> EntityTag {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    EntityTag::new_strong(format!("{:016x}", hasher.finish()))
+}
+
+// `If-None-Match` uses the weak comparison: W/"x" and "x" are the same representation
+fn none_match(req: &HttpRequest, etag: &EntityTag) -> bool {
+    match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => false,
+        Some(IfNoneMatch::Items(tags)) => !tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => true,
+    }
+}
+
+// `If-Modified-Since` against the time the data last changed: true when the client's copy is current
+pub fn not_modified_since(req: &HttpRequest, modified: SystemTime) -> bool {
+    match req.get_header::<IfModifiedSince>() {
+        Some(IfModifiedSince(since)) => modified <= SystemTime::from(since) + CLOCK_SKEW,
+        None => false,
+    }
+}
+
+pub enum Precondition {
+    Missing,
+    Matches,
+    Fails,
+}
+
+// `If-Match` against the tag of the current data
+pub fn if_match(req: &HttpRequest, current: &EntityTag) -> Precondition {
+    match req.get_header::<IfMatch>() {
+        Some(IfMatch::Any) => Precondition::Matches,
+        Some(IfMatch::Items(tags)) if tags.iter().any(|tag| tag.strong_eq(current)) => {
+            Precondition::Matches
+        }
+        Some(IfMatch::Items(_)) => Precondition::Fails,
+        None => Precondition::Missing,
+    }
+}
+
+// the ETag a value gets in json_with_etag()
+pub fn json_etag<T: Serialize>(value: &T) -> Result<EntityTag, serde_json::Error> {
+    serde_json::to_vec(value).map(|body| etag_for(&body))
+}
+
+// serializes `value` and answers with either a 304 or a 200 + ETag
+pub fn json_with_etag<T: Serialize>(req: &HttpRequest, value: &T) -> HttpResponse {
+    let body = match serde_json::to_vec(value) {
+        Ok(body) => body,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let etag = etag_for(&body);
+
+    if !none_match(req, &etag) {
+        return HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .insert_header(ETag(etag))
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::{header, StatusCode},
+        test,
+    };
+
+    use crate::testing;
+
+    fn get_config() -> test::TestRequest {
+        test::TestRequest::get().uri("/config")
+    }
+
+    #[actix_web::test]
+    async fn the_same_tag_again_is_304() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, get_config().to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res
+            .headers()
+            .get(header::ETAG)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        for tag in [etag.clone(), format!("W/{etag}"), "*".to_owned()] {
+            let req = get_config()
+                .insert_header((header::IF_NONE_MATCH, tag))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(res.headers().get(header::ETAG).unwrap(), etag.as_str());
+            assert!(test::read_body(res).await.is_empty());
+        }
+    }
+
+    #[actix_web::test]
+    async fn another_tag_gets_the_body() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = get_config()
+            .insert_header((header::IF_NONE_MATCH, "\"stale\""))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().contains_key(header::ETAG));
+        assert!(!test::read_body(res).await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn the_tag_follows_the_body() {
+        assert_eq!(super::etag_for(b"a"), super::etag_for(b"a"));
+        assert_ne!(super::etag_for(b"a"), super::etag_for(b"b"));
+    }
+}
@@ -0,0 +1,572 @@
+/*
+   CONFIGURATION
+    instead of hardcoding the address, port, workers, keep-alive and database, they are read from
+     1, a `config.toml` file in the working directory (optional)
+     2, environment variables, which take precedence over the file
+
+    | key                       | env var                       | default                                    |
+    | ------------------------- | ----------------------------- | ------------------------------------------ |
+    | `bind_addr`               | `APP_BIND_ADDR`               | 127.0.0.1                                  |
+    | `port`                    | `APP_PORT`                    | 8080                                       |
+    | `workers`                 | `APP_WORKERS`, `WORKERS`      | number of physical CPUs                    |
+    | `max_connections`         | `APP_MAX_CONNECTIONS`         | 25000 (per worker)                         |
+    | `max_connection_rate`     | `APP_MAX_CONNECTION_RATE`     | 256 (per worker)                           |
+    | `max_inflight`            | `MAX_INFLIGHT`                | 1024 (per worker)                          |
+    | `rate_limit`              | `RATE_LIMIT`                  | 0 (off), requests per client and window    |
+    | `rate_limit_window_secs`  | `RATE_LIMIT_WINDOW_SECS`      | 60                                         |
+    | `unix_socket`             | `UNIX_SOCKET`                 | none (eg: /tmp/app.sock)                   |
+    | `keep_alive_secs`         | `APP_KEEP_ALIVE_SECS`         | 5 (0 disables keep-alive)                  |
+    | `shutdown_delay_secs`     | `APP_SHUTDOWN_DELAY_SECS`     | 0 (see supervisor.rs)                      |
+    | `request_timeout_secs`    | `APP_REQUEST_TIMEOUT_SECS`    | 30                                         |
+    | `response_cache_ttl_secs` | `APP_RESPONSE_CACHE_TTL_SECS` | 10                                         |
+    | `slow_request_ms`         | `APP_SLOW_REQUEST_MS`         | 1000                                       |
+    | `database_url`            | `DATABASE_URL`                | sqlite://app.db                            |
+    | `enable_h2c`              | `ENABLE_H2C`                  | false                                      |
+    | `tls_enabled`             | `APP_TLS_ENABLED`             | false                                      |
+    | `force_https`             | `FORCE_HTTPS`                 | false                                      |
+    | `base_domain`             | `APP_BASE_DOMAIN`             | example.com                                |
+    | `log_format`              | `APP_LOG_FORMAT`              | text (or json)                             |
+    | `trust_proxy`             | `APP_TRUST_PROXY`             | false                                      |
+    | `max_url_bytes`           | `APP_MAX_URL_BYTES`           | 8192                                       |
+    | `max_header_bytes`        | `APP_MAX_HEADER_BYTES`        | 16384                                      |
+    | `max_body_bytes`          | `APP_MAX_BODY_BYTES`          | 1048576                                    |
+    | `max_expanded_body_bytes` | `APP_MAX_EXPANDED_BODY_BYTES` | 10485760                                   |
+    | `log_bodies`              | `LOG_BODIES`                  | false                                      |
+    | `log_body_max_bytes`      | `APP_LOG_BODY_MAX_BYTES`      | 4096                                       |
+    | `trailing_slash`          | `APP_TRAILING_SLASH`          | trim (or redirect)                         |
+    | `enable_debug`            | `ENABLE_DEBUG`                | false                                      |
+    | `upstream_max_attempts`   | `APP_UPSTREAM_MAX_ATTEMPTS`   | 3 (1 disables retries)                     |
+    | `strip_prefix`            | `STRIP_PREFIX`                | none (eg: /service-a)                      |
+    | `content_security_policy` | `CONTENT_SECURITY_POLICY`     | default-src 'self'; frame-ancestors 'none' |
+
+    a missing value falls back to its default (logged at debug level, leaving a setting out is
+     normal), an invalid one too but with a warning, EXCEPT the port: a port that can't be parsed
+     is a fatal startup error, since silently listening somewhere else than asked is worse than
+     not starting at all.
+
+    `max_connections` and `max_connection_rate` are limits PER WORKER: how many connections a
+     worker keeps open at once, and how many new TLS handshakes it runs at once. above them the
+     worker stops accepting until some finish, so the total is roughly the limit times the workers.
+     `max_inflight` is per worker too: how many requests it HANDLES at once, the next one gets
+     `503` (see inflight.rs). `rate_limit` is per client across ALL workers: more requests than
+     that within `rate_limit_window_secs` get `429` (see rate_limit.rs), it is off unless set.
+
+    while the server runs, edits to `config.toml` are picked up for the settings that can change
+     without a restart (see config_reload.rs).
+*/
+
+use std::{
+    env, fmt, fs, io,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+use actix_web::{
+    get,
+    http::{header::HeaderValue, KeepAlive},
+    web, HttpRequest, HttpResponse,
+};
+use serde::Serialize;
+use toml::{Table, Value};
+
+use crate::{conditional, security_headers};
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Config {
+    pub bind_addr: IpAddr,
+    pub port: u16,
+    pub workers: usize,
+    pub max_connections: usize,
+    pub max_connection_rate: usize,
+    // more requests at once get 503 instead of waiting (see inflight.rs)
+    pub max_inflight: usize,
+    // requests per client and window, more get 429 (see rate_limit.rs)
+    pub rate_limit: u32,
+    pub rate_limit_window_secs: u64,
+    // also listen on this unix socket path, next to bind_addr:port (see supervisor.rs)
+    pub unix_socket: String,
+    pub keep_alive_secs: u64,
+    // how long /readyz reports 503 before the server stops accepting (see supervisor.rs)
+    pub shutdown_delay_secs: u64,
+    // how long a handler may take to answer (see timeout.rs)
+    pub request_timeout_secs: u64,
+    // how long a GET answer is served from the response cache (see response_cache.rs)
+    pub response_cache_ttl_secs: u64,
+    // requests taking at least this long are logged as a warning (see request_log.rs)
+    pub slow_request_ms: u64,
+    // may contain credentials, so it is never sent to clients
+    #[serde(skip)]
+    pub database_url: String,
+    // also speak HTTP/2 over plain TCP (h2c) on the same port
+    pub enable_h2c: bool,
+    // clients reach the app over https (eg: through a TLS-terminating proxy)
+    pub tls_enabled: bool,
+    // redirect plain http requests to https (see https_redirect.rs)
+    pub force_https: bool,
+    // tenants are its subdomains (see tenant.rs)
+    pub base_domain: String,
+    // access log lines as `text` or `json` (see access_log.rs)
+    pub log_format: String,
+    // take the client address from Forwarded / X-Forwarded-For
+    pub trust_proxy: bool,
+    // bigger request bodies are refused with 413 (see body_limit.rs)
+    pub max_body_bytes: usize,
+    // a gzipped body unpacking to more gets 413 (see decompress.rs)
+    pub max_expanded_body_bytes: usize,
+    // longer urls get 414, bigger headers 431 (see header_limit.rs)
+    pub max_url_bytes: usize,
+    pub max_header_bytes: usize,
+    // log request/response bodies, redacted and truncated (see body_log.rs)
+    pub log_bodies: bool,
+    pub log_body_max_bytes: usize,
+    // `trim` or `redirect` paths with a trailing slash (see trailing_slash.rs)
+    pub trailing_slash: String,
+    // how often an upstream request is tried in all (see retry.rs)
+    pub upstream_max_attempts: u32,
+    // taken off the front of every path before routing, eg: /service-a (see strip_prefix.rs)
+    pub strip_prefix: String,
+    // sent on every response (see security_headers.rs)
+    pub content_security_policy: String,
+    // register /debug/state and /debug/routes (see debug.rs)
+    pub enable_debug: bool,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(io::Error),
+    Parse(toml::de::Error),
+    InvalidPort(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(err) => write!(f, "could not read {CONFIG_FILE}: {err}"),
+            ConfigError::Parse(err) => write!(f, "could not parse {CONFIG_FILE}: {err}"),
+            ConfigError::InvalidPort(value) => write!(f, "invalid port {value:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for io::Error {
+    fn from(err: ConfigError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8080,
+            // hyperthreads share a core, a worker per physical core avoids them fighting over it
+            workers: num_cpus::get_physical(),
+            // actix-server's own defaults
+            max_connections: 25_000,
+            max_connection_rate: 256,
+            max_inflight: 1024,
+            rate_limit: 0,
+            rate_limit_window_secs: 60,
+            unix_socket: String::new(),
+            keep_alive_secs: 5,
+            shutdown_delay_secs: 0,
+            request_timeout_secs: 30,
+            response_cache_ttl_secs: 10,
+            slow_request_ms: 1000,
+            database_url: "sqlite://app.db".to_owned(),
+            enable_h2c: false,
+            tls_enabled: false,
+            force_https: false,
+            base_domain: "example.com".to_owned(),
+            log_format: "text".to_owned(),
+            trust_proxy: false,
+            max_body_bytes: 1024 * 1024,
+            max_expanded_body_bytes: 10 * 1024 * 1024,
+            max_url_bytes: 8 * 1024,
+            max_header_bytes: 16 * 1024,
+            log_bodies: false,
+            log_body_max_bytes: 4096,
+            trailing_slash: "trim".to_owned(),
+            upstream_max_attempts: 3,
+            strip_prefix: String::new(),
+            content_security_policy: security_headers::DEFAULT_CSP.to_owned(),
+            enable_debug: false,
+        }
+    }
+}
+
+impl Config {
+    // reads `config.toml` (if present) and the process environment
+    pub fn load() -> Result<Self, ConfigError> {
+        let file = match fs::read_to_string(Path::new(CONFIG_FILE)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(ConfigError::Read(err)),
+        };
+
+        Self::from_sources(&file, |name| env::var(name).ok())
+    }
+
+    // the file contents and the env lookup are passed in so they can be swapped for fakes
+    pub fn from_sources(
+        file: &str,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let table: Table = file.parse().map_err(ConfigError::Parse)?;
+        // the first env var that is set wins, then the file
+        let lookup = |key: &str, env_keys: &[&str]| {
+            env_keys
+                .iter()
+                .find_map(|env_key| env(env_key))
+                .or_else(|| table.get(key).map(raw))
+        };
+        let defaults = Self::default();
+
+        let port = match lookup("port", &["APP_PORT"]) {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| ConfigError::InvalidPort(value))?,
+            None => defaults.port,
+        };
+
+        Ok(Self {
+            bind_addr: parse_or_default(
+                "bind_addr",
+                lookup("bind_addr", &["APP_BIND_ADDR"]),
+                defaults.bind_addr,
+                |_| true,
+            ),
+            port,
+            workers: parse_or_default(
+                "workers",
+                lookup("workers", &["APP_WORKERS", "WORKERS"]),
+                defaults.workers,
+                |&workers| workers > 0,
+            ),
+            max_connections: parse_or_default(
+                "max_connections",
+                lookup("max_connections", &["APP_MAX_CONNECTIONS"]),
+                defaults.max_connections,
+                |&max| max > 0,
+            ),
+            max_connection_rate: parse_or_default(
+                "max_connection_rate",
+                lookup("max_connection_rate", &["APP_MAX_CONNECTION_RATE"]),
+                defaults.max_connection_rate,
+                |&max| max > 0,
+            ),
+            max_inflight: parse_or_default(
+                "max_inflight",
+                lookup("max_inflight", &["MAX_INFLIGHT"]),
+                defaults.max_inflight,
+                |&max| max > 0,
+            ),
+            rate_limit: parse_or_default(
+                "rate_limit",
+                lookup("rate_limit", &["RATE_LIMIT"]),
+                defaults.rate_limit,
+                |_| true,
+            ),
+            rate_limit_window_secs: parse_or_default(
+                "rate_limit_window_secs",
+                lookup("rate_limit_window_secs", &["RATE_LIMIT_WINDOW_SECS"]),
+                defaults.rate_limit_window_secs,
+                |&secs| secs > 0,
+            ),
+            unix_socket: parse_or_default(
+                "unix_socket",
+                lookup("unix_socket", &["UNIX_SOCKET"]),
+                defaults.unix_socket,
+                |_| true,
+            ),
+            keep_alive_secs: parse_or_default(
+                "keep_alive_secs",
+                lookup("keep_alive_secs", &["APP_KEEP_ALIVE_SECS"]),
+                defaults.keep_alive_secs,
+                |_| true,
+            ),
+            shutdown_delay_secs: parse_or_default(
+                "shutdown_delay_secs",
+                lookup("shutdown_delay_secs", &["APP_SHUTDOWN_DELAY_SECS"]),
+                defaults.shutdown_delay_secs,
+                |_| true,
+            ),
+            request_timeout_secs: parse_or_default(
+                "request_timeout_secs",
+                lookup("request_timeout_secs", &["APP_REQUEST_TIMEOUT_SECS"]),
+                defaults.request_timeout_secs,
+                |&secs| secs > 0,
+            ),
+            response_cache_ttl_secs: parse_or_default(
+                "response_cache_ttl_secs",
+                lookup("response_cache_ttl_secs", &["APP_RESPONSE_CACHE_TTL_SECS"]),
+                defaults.response_cache_ttl_secs,
+                |_| true,
+            ),
+            slow_request_ms: parse_or_default(
+                "slow_request_ms",
+                lookup("slow_request_ms", &["APP_SLOW_REQUEST_MS"]),
+                defaults.slow_request_ms,
+                |&ms| ms > 0,
+            ),
+            database_url: parse_or_default(
+                "database_url",
+                lookup("database_url", &["DATABASE_URL"]),
+                defaults.database_url,
+                |url: &String| !url.is_empty(),
+            ),
+            enable_h2c: parse_or_default(
+                "enable_h2c",
+                lookup("enable_h2c", &["ENABLE_H2C"]),
+                defaults.enable_h2c,
+                |_| true,
+            ),
+            tls_enabled: parse_or_default(
+                "tls_enabled",
+                lookup("tls_enabled", &["APP_TLS_ENABLED"]),
+                defaults.tls_enabled,
+                |_| true,
+            ),
+            force_https: parse_or_default(
+                "force_https",
+                lookup("force_https", &["FORCE_HTTPS"]),
+                defaults.force_https,
+                |_| true,
+            ),
+            base_domain: parse_or_default(
+                "base_domain",
+                lookup("base_domain", &["APP_BASE_DOMAIN"]),
+                defaults.base_domain,
+                |domain: &String| !domain.is_empty(),
+            )
+            .to_ascii_lowercase(),
+            log_format: parse_or_default(
+                "log_format",
+                lookup("log_format", &["APP_LOG_FORMAT"]),
+                defaults.log_format,
+                |format: &String| format == "text" || format == "json",
+            ),
+            trust_proxy: parse_or_default(
+                "trust_proxy",
+                lookup("trust_proxy", &["APP_TRUST_PROXY"]),
+                defaults.trust_proxy,
+                |_| true,
+            ),
+            max_body_bytes: parse_or_default(
+                "max_body_bytes",
+                lookup("max_body_bytes", &["APP_MAX_BODY_BYTES"]),
+                defaults.max_body_bytes,
+                |&max| max > 0,
+            ),
+            max_expanded_body_bytes: parse_or_default(
+                "max_expanded_body_bytes",
+                lookup("max_expanded_body_bytes", &["APP_MAX_EXPANDED_BODY_BYTES"]),
+                defaults.max_expanded_body_bytes,
+                |&max| max > 0,
+            ),
+            max_url_bytes: parse_or_default(
+                "max_url_bytes",
+                lookup("max_url_bytes", &["APP_MAX_URL_BYTES"]),
+                defaults.max_url_bytes,
+                |&max| max > 0,
+            ),
+            max_header_bytes: parse_or_default(
+                "max_header_bytes",
+                lookup("max_header_bytes", &["APP_MAX_HEADER_BYTES"]),
+                defaults.max_header_bytes,
+                |&max| max > 0,
+            ),
+            log_bodies: parse_or_default(
+                "log_bodies",
+                lookup("log_bodies", &["LOG_BODIES"]),
+                defaults.log_bodies,
+                |_| true,
+            ),
+            log_body_max_bytes: parse_or_default(
+                "log_body_max_bytes",
+                lookup("log_body_max_bytes", &["APP_LOG_BODY_MAX_BYTES"]),
+                defaults.log_body_max_bytes,
+                |&max| max > 0,
+            ),
+            trailing_slash: parse_or_default(
+                "trailing_slash",
+                lookup("trailing_slash", &["APP_TRAILING_SLASH"]),
+                defaults.trailing_slash,
+                |mode: &String| mode == "trim" || mode == "redirect",
+            ),
+            upstream_max_attempts: parse_or_default(
+                "upstream_max_attempts",
+                lookup("upstream_max_attempts", &["APP_UPSTREAM_MAX_ATTEMPTS"]),
+                defaults.upstream_max_attempts,
+                |&attempts| attempts > 0,
+            ),
+            strip_prefix: parse_or_default(
+                "strip_prefix",
+                lookup("strip_prefix", &["STRIP_PREFIX"]),
+                defaults.strip_prefix,
+                |prefix: &String| {
+                    prefix.is_empty() || (prefix.starts_with('/') && !prefix.ends_with('/'))
+                },
+            ),
+            content_security_policy: parse_or_default(
+                "content_security_policy",
+                lookup("content_security_policy", &["CONTENT_SECURITY_POLICY"]),
+                defaults.content_security_policy,
+                |csp: &String| HeaderValue::from_str(csp).is_ok(),
+            ),
+            enable_debug: parse_or_default(
+                "enable_debug",
+                lookup("enable_debug", &["ENABLE_DEBUG"]),
+                defaults.enable_debug,
+                |_| true,
+            ),
+        })
+    }
+
+    pub fn keep_alive(&self) -> KeepAlive {
+        match self.keep_alive_secs {
+            0 => KeepAlive::Disabled,
+            secs => KeepAlive::Timeout(Duration::from_secs(secs)),
+        }
+    }
+}
+
+// toml values are typed (`port = 8080` vs `port = "8080"`), env vars are always strings,
+//  so both are turned into a string and parsed the same way
+fn raw(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn parse_or_default<T: FromStr + fmt::Debug>(
+    key: &str,
+    value: Option<String>,
+    default: T,
+    is_valid: impl Fn(&T) -> bool,
+) -> T {
+    let Some(value) = value else {
+        log::debug!("`{key}` is not set, using default {default:?}");
+        return default;
+    };
+
+    match value.trim().parse() {
+        Ok(parsed) if is_valid(&parsed) => parsed,
+        _ => {
+            log::warn!("invalid value {value:?} for `{key}`, using default {default:?}");
+            default
+        }
+    }
+}
+
+// cacheable view of the running configuration, clients revalidate it with `If-None-Match`
+#[get("/config")]
+pub async fn show_config(req: HttpRequest, config: web::Data<Config>) -> HttpResponse {
+    conditional::json_with_etag(&req, config.get_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[actix_web::test]
+    async fn nothing_set_is_the_defaults() {
+        assert_eq!(Config::from_sources("", no_env).unwrap(), Config::default());
+    }
+
+    #[actix_web::test]
+    async fn reads_the_file_and_lets_the_env_win() {
+        let file = "port = 9000\nbind_addr = \"0.0.0.0\"\nworkers = 3";
+        let config = Config::from_sources(file, |name| match name {
+            "WORKERS" => Some("5".to_owned()),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.bind_addr, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(config.workers, 5);
+    }
+
+    #[actix_web::test]
+    async fn an_invalid_value_falls_back_to_its_default() {
+        let config =
+            Config::from_sources("workers = 0\nkeep_alive_secs = \"soon\"", no_env).unwrap();
+
+        assert_eq!(config.workers, Config::default().workers);
+        assert_eq!(config.keep_alive_secs, 5);
+    }
+
+    #[actix_web::test]
+    async fn an_invalid_port_is_an_error() {
+        let err =
+            Config::from_sources("", |name| (name == "APP_PORT").then(|| "eighty".to_owned()))
+                .unwrap_err();
+
+        assert!(matches!(err, ConfigError::InvalidPort(port) if port == "eighty"));
+    }
+
+    #[actix_web::test]
+    async fn a_broken_file_is_an_error() {
+        assert!(matches!(
+            Config::from_sources("port = ", no_env),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn workers_and_connection_limits_come_from_the_env() {
+        assert_eq!(Config::default().workers, num_cpus::get_physical());
+
+        let config = Config::from_sources("", |name| match name {
+            "WORKERS" => Some("2".to_owned()),
+            "APP_MAX_CONNECTIONS" => Some("100".to_owned()),
+            "APP_MAX_CONNECTION_RATE" => Some("8".to_owned()),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.max_connections, 100);
+        assert_eq!(config.max_connection_rate, 8);
+    }
+
+    #[actix_web::test]
+    async fn app_workers_wins_over_workers() {
+        let config = Config::from_sources("", |name| match name {
+            "APP_WORKERS" => Some("3".to_owned()),
+            "WORKERS" => Some("2".to_owned()),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(config.workers, 3);
+    }
+
+    #[actix_web::test]
+    async fn get_config_leaves_the_database_url_out() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/config").to_request();
+        let config: Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(config["port"], 8080);
+        assert!(config.get("database_url").is_none());
+    }
+}
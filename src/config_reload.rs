@@ -0,0 +1,233 @@
+/*
+   HOT RELOAD OF config.toml
+    a background task watches `config.toml` (with the notify crate) and, when it changes, loads
+     the config again and swaps it in for the running server, no restart needed.
+
+    only the settings that are read ANEW for every request can change this way:
+
+        request_timeout_secs, slow_request_ms, force_https, base_domain, max_body_bytes,
+        max_expanded_body_bytes, max_url_bytes, max_header_bytes, log_bodies,
+        log_body_max_bytes, trailing_slash, strip_prefix, rate_limit, rate_limit_window_secs
+
+     everything else was used once to build the server or the shared state (the listener, the
+     workers, the pool, the caches, the middleware stack) and keeps its old value: a change to
+     it is logged as a warning and otherwise ignored. `workers` follows a SIGUSR2 (see
+     supervisor.rs), the rest a full restart.
+
+    - DEBOUNCED: an editor saving a file fires several events (truncate, write, rename, ...),
+       so the reload waits until the file has been quiet for DEBOUNCE
+    - the directory is watched, not the file: editors that save by writing a new file and
+       renaming it over the old one would otherwise end the watch after the first save
+    - a file that doesn't parse keeps the current config (see Config::load for what counts)
+    - env vars still win over the file, as at startup; a process can't see its env change
+
+    how the new values reach the handlers: they all ask for web::Data<Config>, which is fixed
+     when each worker builds its app. use_live_config(), the outermost middleware that reads it,
+     puts the CURRENT config in front of that one for every request, so every web::Data<Config>
+     extractor further in (handlers and middleware alike) gets the live value.
+*/
+
+use std::{path::Path, rc::Rc, sync::RwLock, time::Duration};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Extensions, ServiceRequest, ServiceResponse},
+    middleware::Next,
+    rt::{self, time::timeout},
+    web, Error,
+};
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::config::{Config, CONFIG_FILE};
+
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+pub struct LiveConfig {
+    current: RwLock<web::Data<Config>>,
+}
+
+// the loaded config, with every setting that can't be reloaded put back to its running value
+fn reloadable_part(running: &Config, mut loaded: Config) -> (Config, Vec<&'static str>) {
+    let mut ignored = Vec::new();
+    macro_rules! keep_running {
+        ($($field:ident),* $(,)?) => {$(
+            if running.$field != loaded.$field {
+                ignored.push(stringify!($field));
+                loaded.$field = running.$field.clone();
+            }
+        )*};
+    }
+    keep_running!(
+        bind_addr,
+        port,
+        workers,
+        max_connections,
+        max_connection_rate,
+        max_inflight,
+        unix_socket,
+        keep_alive_secs,
+        shutdown_delay_secs,
+        response_cache_ttl_secs,
+        database_url,
+        enable_h2c,
+        tls_enabled,
+        log_format,
+        trust_proxy,
+        upstream_max_attempts,
+        content_security_policy,
+        enable_debug,
+    );
+    (loaded, ignored)
+}
+
+impl LiveConfig {
+    pub fn new(config: web::Data<Config>) -> Self {
+        Self {
+            current: RwLock::new(config),
+        }
+    }
+
+    pub fn get(&self) -> web::Data<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn apply(&self, loaded: Config) {
+        let mut current = self.current.write().unwrap();
+        let (config, ignored) = reloadable_part(&current, loaded);
+        if !ignored.is_empty() {
+            log::warn!(
+                "{CONFIG_FILE}: ignoring changes to {} (they need a restart)",
+                ignored.join(", ")
+            );
+        }
+        if current.get_ref() == &config {
+            return;
+        }
+        *current = web::Data::new(config);
+        log::info!("{CONFIG_FILE}: reloaded");
+    }
+}
+
+// watches for as long as the server runs; without a working watcher there is just no reload
+pub fn spawn_watcher(live: web::Data<LiveConfig>) {
+    let (changed, mut changes) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        // reading the file (our own reload too) is an event as well, only changes count
+        let ours = event.is_ok_and(|event| {
+            !event.kind.is_access()
+                && event
+                    .paths
+                    .iter()
+                    .any(|path| path.file_name().is_some_and(|name| name == CONFIG_FILE))
+        });
+        if ours {
+            let _ = changed.send(());
+        }
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            log::warn!("can't watch {CONFIG_FILE}, it won't be reloaded: {err}");
+            return;
+        }
+    };
+    if let Err(err) = watcher.watch(Path::new("."), RecursiveMode::NonRecursive) {
+        log::warn!("can't watch {CONFIG_FILE}, it won't be reloaded: {err}");
+        return;
+    }
+
+    rt::spawn(async move {
+        let _watcher = watcher; // <- watching stops when it is dropped
+        while changes.recv().await.is_some() {
+            // wait for DEBOUNCE without another event
+            while let Ok(Some(())) = timeout(DEBOUNCE, changes.recv()).await {}
+
+            match Config::load() {
+                Ok(config) => live.apply(config),
+                Err(err) => log::error!("{CONFIG_FILE}: keeping the current config: {err}"),
+            }
+        }
+    });
+}
+
+pub async fn use_live_config(
+    live: web::Data<LiveConfig>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let mut data = Extensions::new();
+    data.insert(live.get());
+    req.add_data_container(Rc::new(data));
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{http::StatusCode, test};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[actix_web::test]
+    async fn a_reload_reaches_the_running_app() {
+        testing::capture_logs();
+        let builder = testing::builder().await;
+        let app = test::init_service(builder.build()).await;
+        let long_uri = format!("/healthz?pad={}", "x".repeat(200));
+
+        let req = test::TestRequest::get().uri(&long_uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let edited = "max_url_bytes = 100\nrate_limit = 7\nslow_request_ms = 1234\nport = 9999\n";
+        builder
+            .live_config()
+            .apply(Config::from_sources(edited, no_env).unwrap());
+
+        // the same app, not rebuilt, sees the new values
+        let req = test::TestRequest::get().uri(&long_uri).to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::URI_TOO_LONG
+        );
+        let req = test::TestRequest::get().uri("/config").to_request();
+        let shown: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(shown["max_url_bytes"], 100);
+        assert_eq!(shown["rate_limit"], 7);
+        assert_eq!(shown["slow_request_ms"], 1234);
+        assert_eq!(shown["port"], Config::default().port);
+    }
+
+    #[actix_web::test]
+    async fn settings_that_need_a_restart_are_kept_with_a_warning() {
+        testing::capture_logs();
+        let running = Config::default();
+        let live = LiveConfig::new(web::Data::new(running.clone()));
+
+        let edited = "port = 9999\nworkers = 17\nrequest_timeout_secs = 9\n";
+        live.apply(Config::from_sources(edited, no_env).unwrap());
+
+        let current = live.get();
+        assert_eq!(current.port, running.port);
+        assert_eq!(current.workers, running.workers);
+        assert_eq!(current.request_timeout_secs, 9);
+        assert!(!testing::logged("ignoring changes to port, workers").is_empty());
+    }
+
+    #[actix_web::test]
+    async fn an_unchanged_file_keeps_the_same_config() {
+        let live = LiveConfig::new(web::Data::new(Config::default()));
+        let before = live.get();
+
+        live.apply(Config::default());
+
+        assert!(Arc::ptr_eq(&before.into_inner(), &live.get().into_inner()));
+    }
+}
@@ -0,0 +1,427 @@
+/*
+   STATIC ROUTES FROM routes.toml
+    simple endpoints that always answer the same (robots.txt, a notice, a canned JSON) can be
+     added without touching the code: every `[[route]]` in `routes.toml` (working directory,
+     optional) becomes a route when the server starts.
+
+        [[route]]
+        method = "GET"                      # optional, GET by default
+        path = "/robots.txt"
+        status = 200                        # optional, 200 by default
+        content_type = "text/plain"         # optional, text/plain; charset=utf-8 by default
+        body = "User-agent: *\nDisallow:\n"
+
+    they are registered after every route of configure_app(), and a route that could never be
+     reached is not silently ignored, the server REFUSES TO START instead:
+     - the same method and path twice in the file
+     - a path that one of the app's own routes already matches, eg: `/users/7` is taken by
+        `/users/{id}` (whatever the methods: the file's routes are plain, the app's win)
+     - any path inside one of the app's scopes (`/api/...`, `/admin/...`, `/proxy/...`,
+        `/static/...`): a scope takes every path under it, if only to answer `404`
+    as are an unknown method, a status outside 100-599, a content type that isn't a valid header
+     value, and a path that doesn't start with `/` or has a pattern in it (`{id}`, `*`): these
+     routes are plain paths.
+
+    the first are checked while reading the file, the app's routes by check_reachable() at
+     startup: every path is matched against the patterns the app registers before the file's
+     routes (app::ROUTES and app::SCOPES, and debug::ROUTES when those are on), with the same
+     ResourceDef actix routes with.
+
+    the file is read once: a changed routes.toml needs a restart (it isn't part of the hot
+     reload of config.toml). `/debug/routes` lists these routes too.
+*/
+
+use std::{fmt, fs, future::ready, io, path::Path};
+
+use actix_web::{
+    dev::ResourceDef,
+    http::{
+        header::{self, HeaderValue},
+        Method, StatusCode,
+    },
+    web::{self, Bytes},
+    HttpResponse,
+};
+use serde::Deserialize;
+
+use crate::{app, config::Config, debug};
+
+pub const ROUTES_FILE: &str = "routes.toml";
+
+const DEFAULT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+#[derive(Deserialize)]
+struct RouteEntry {
+    #[serde(default = "default_method")]
+    method: String,
+    path: String,
+    #[serde(default = "default_status")]
+    status: u16,
+    content_type: Option<String>,
+    #[serde(default)]
+    body: String,
+}
+
+fn default_method() -> String {
+    "GET".to_owned()
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+#[derive(Deserialize)]
+struct RoutesFile {
+    #[serde(default)]
+    route: Vec<RouteEntry>,
+}
+
+#[derive(Clone)]
+pub struct ConfigRoute {
+    pub method: Method,
+    pub path: String,
+    status: StatusCode,
+    content_type: HeaderValue,
+    body: Bytes,
+}
+
+#[derive(Default)]
+pub struct ConfigRoutes {
+    pub routes: Vec<ConfigRoute>,
+}
+
+#[derive(Debug)]
+pub enum RoutesError {
+    Read(io::Error),
+    Parse(toml::de::Error),
+    // the route's place in the file (1-based) and what is wrong with it
+    Invalid(usize, String),
+}
+
+impl fmt::Display for RoutesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoutesError::Read(err) => write!(f, "could not read {ROUTES_FILE}: {err}"),
+            RoutesError::Parse(err) => write!(f, "could not parse {ROUTES_FILE}: {err}"),
+            RoutesError::Invalid(n, reason) => write!(f, "{ROUTES_FILE}, route {n}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for RoutesError {}
+
+impl From<RoutesError> for io::Error {
+    fn from(err: RoutesError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+impl ConfigRoute {
+    fn from_entry(entry: RouteEntry) -> Result<Self, String> {
+        let method = Method::from_bytes(entry.method.to_ascii_uppercase().as_bytes())
+            .map_err(|_| format!("invalid method {:?}", entry.method))?;
+        // StatusCode takes anything up to 999, HTTP only has 1xx to 5xx
+        let status = StatusCode::from_u16(entry.status)
+            .ok()
+            .filter(|status| status.as_u16() < 600)
+            .ok_or_else(|| format!("invalid status {}", entry.status))?;
+        if !entry.path.starts_with('/') || entry.path.contains(['{', '}', '*']) {
+            return Err(format!("{:?} is not a plain path", entry.path));
+        }
+        let content_type = match entry.content_type {
+            Some(value) => HeaderValue::from_str(&value)
+                .map_err(|_| format!("invalid content_type {value:?}"))?,
+            None => HeaderValue::from_static(DEFAULT_CONTENT_TYPE),
+        };
+
+        Ok(Self {
+            method,
+            path: entry.path,
+            status,
+            content_type,
+            body: Bytes::from(entry.body),
+        })
+    }
+
+    fn respond(&self) -> HttpResponse {
+        HttpResponse::build(self.status)
+            .insert_header((header::CONTENT_TYPE, self.content_type.clone()))
+            .body(self.body.clone())
+    }
+}
+
+impl ConfigRoutes {
+    // reads `routes.toml` if there is one; any route that can't be served is an error
+    pub fn load() -> Result<Self, RoutesError> {
+        let file = match fs::read_to_string(Path::new(ROUTES_FILE)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(RoutesError::Read(err)),
+        };
+
+        Self::from_toml(&file)
+    }
+
+    pub fn from_toml(file: &str) -> Result<Self, RoutesError> {
+        let file: RoutesFile = toml::from_str(file).map_err(RoutesError::Parse)?;
+
+        let mut routes: Vec<ConfigRoute> = Vec::with_capacity(file.route.len());
+        for (index, entry) in file.route.into_iter().enumerate() {
+            let n = index + 1;
+            let route =
+                ConfigRoute::from_entry(entry).map_err(|err| RoutesError::Invalid(n, err))?;
+
+            let duplicate = routes
+                .iter()
+                .position(|other| other.method == route.method && other.path == route.path);
+            if let Some(other) = duplicate {
+                return Err(RoutesError::Invalid(
+                    n,
+                    format!(
+                        "{} {} is already route {}",
+                        route.method,
+                        route.path,
+                        other + 1
+                    ),
+                ));
+            }
+            routes.push(route);
+        }
+
+        if !routes.is_empty() {
+            log::info!("{ROUTES_FILE}: {} routes", routes.len());
+        }
+        Ok(Self { routes })
+    }
+
+    // one resource per path, with a route for each of its methods (two resources for the same
+    // path would leave the second unreachable: the first answers 405 for the other methods)
+    pub fn configure(&self, cfg: &mut web::ServiceConfig) {
+        let mut paths: Vec<&str> = self
+            .routes
+            .iter()
+            .map(|route| route.path.as_str())
+            .collect();
+        paths.sort_unstable();
+        paths.dedup();
+
+        for path in paths {
+            let mut resource = web::resource(path);
+            for route in self.routes.iter().filter(|route| route.path == path) {
+                let answer = route.clone();
+                resource = resource
+                    .route(web::method(route.method.clone()).to(move || ready(answer.respond())));
+            }
+            cfg.service(resource);
+        }
+    }
+
+    // startup check that none of the app's own routes and scopes shadow a route from the file
+    pub fn check_reachable(&self, config: &Config) -> Result<(), RoutesError> {
+        let debug_routes = if debug::enabled(config) {
+            debug::ROUTES
+        } else {
+            &[]
+        };
+        // the scopes' `/*` entries are checked as scopes below
+        let patterns: Vec<&str> = app::ROUTES
+            .iter()
+            .chain(debug_routes)
+            .map(|(_, pattern)| *pattern)
+            .filter(|pattern| !pattern.ends_with("/*"))
+            .collect();
+
+        for (index, route) in self.routes.iter().enumerate() {
+            let taken_by = patterns
+                .iter()
+                .find(|pattern| ResourceDef::new(**pattern).is_match(&route.path));
+            if let Some(pattern) = taken_by {
+                return Err(RoutesError::Invalid(
+                    index + 1,
+                    format!(
+                        "{} is already matched by the app's route {pattern}",
+                        route.path
+                    ),
+                ));
+            }
+            let scope = app::SCOPES
+                .iter()
+                .find(|scope| ResourceDef::prefix(**scope).is_match(&route.path));
+            if let Some(scope) = scope {
+                return Err(RoutesError::Invalid(
+                    index + 1,
+                    format!("{} is inside the app's scope {scope}", route.path),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+
+    use super::*;
+    use crate::testing;
+
+    const SAMPLE: &str = r#"
+        [[route]]
+        path = "/robots.txt"
+        body = "User-agent: *\nDisallow: /admin\n"
+
+        [[route]]
+        path = "/motd"
+        content_type = "application/json"
+        body = '{"message":"welcome"}'
+
+        [[route]]
+        method = "post"
+        path = "/motd"
+        status = 405
+        body = "read only"
+
+        [[route]]
+        path = "/gone"
+        status = 410
+    "#;
+
+    fn invalid(result: Result<impl Sized, RoutesError>) -> (usize, String) {
+        match result {
+            Err(RoutesError::Invalid(n, reason)) => (n, reason),
+            Err(err) => panic!("not an invalid route: {err}"),
+            Ok(_) => panic!("accepted"),
+        }
+    }
+
+    #[actix_web::test]
+    async fn the_sample_routes_answer_as_defined() {
+        let routes = ConfigRoutes::from_toml(SAMPLE).unwrap();
+        routes.check_reachable(&Config::default()).unwrap();
+        let builder = testing::builder().await.with_config_routes(routes);
+        let app = test::init_service(builder.build()).await;
+
+        let res = test::call_service(
+            &app,
+            test::TestRequest::get().uri("/robots.txt").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            DEFAULT_CONTENT_TYPE
+        );
+        assert_eq!(
+            test::read_body(res).await,
+            "User-agent: *\nDisallow: /admin\n"
+        );
+
+        let res =
+            test::call_service(&app, test::TestRequest::get().uri("/motd").to_request()).await;
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        assert_eq!(test::read_body(res).await, r#"{"message":"welcome"}"#);
+
+        let res =
+            test::call_service(&app, test::TestRequest::post().uri("/motd").to_request()).await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(test::read_body(res).await, "read only");
+
+        let res =
+            test::call_service(&app, test::TestRequest::get().uri("/gone").to_request()).await;
+        assert_eq!(res.status(), StatusCode::GONE);
+        assert!(test::read_body(res).await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn the_shipped_routes_file_loads() {
+        let routes = ConfigRoutes::from_toml(&fs::read_to_string(ROUTES_FILE).unwrap()).unwrap();
+
+        routes.check_reachable(&Config::default()).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn a_duplicate_or_malformed_route_is_refused_while_reading() {
+        let duplicate = "[[route]]\npath = \"/a\"\n[[route]]\nmethod = \"get\"\npath = \"/a\"\n";
+        assert_eq!(
+            invalid(ConfigRoutes::from_toml(duplicate)),
+            (2, "GET /a is already route 1".to_owned())
+        );
+
+        for (entry, reason) in [
+            (
+                "path = \"/a\"\nmethod = \"G E T\"",
+                "invalid method \"G E T\"",
+            ),
+            ("path = \"/a\"\nstatus = 600", "invalid status 600"),
+            (
+                "path = \"/a\"\ncontent_type = \"text/plain\\nX-Injected: 1\"",
+                "invalid content_type \"text/plain\\nX-Injected: 1\"",
+            ),
+            ("path = \"a\"", "\"a\" is not a plain path"),
+            (
+                "path = \"/users/{id}\"",
+                "\"/users/{id}\" is not a plain path",
+            ),
+        ] {
+            let file = format!("[[route]]\n{entry}\n");
+            assert_eq!(
+                invalid(ConfigRoutes::from_toml(&file)),
+                (1, reason.to_owned())
+            );
+        }
+        assert!(matches!(
+            ConfigRoutes::from_toml("[[route]]\nbody = \"no path\"\n"),
+            Err(RoutesError::Parse(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn a_route_the_app_already_has_is_refused_at_startup() {
+        let check = |file: &str, config: &Config| {
+            ConfigRoutes::from_toml(file)
+                .unwrap()
+                .check_reachable(config)
+        };
+        let config = Config::default();
+
+        assert_eq!(
+            invalid(check("[[route]]\npath = \"/users/7\"\n", &config)),
+            (
+                1,
+                "/users/7 is already matched by the app's route /users/{id}".to_owned()
+            )
+        );
+        assert_eq!(
+            invalid(check(
+                "[[route]]\npath = \"/ok\"\n[[route]]\npath = \"/api/x\"\n",
+                &config
+            )),
+            (2, "/api/x is inside the app's scope /api".to_owned())
+        );
+        assert_eq!(
+            invalid(check("[[route]]\npath = \"/proxy\"\n", &config)),
+            (1, "/proxy is inside the app's scope /proxy".to_owned())
+        );
+        // only a prefix of whole segments is a scope's
+        check("[[route]]\npath = \"/apis\"\n", &config).unwrap();
+
+        // the debug routes only take their paths while they are registered
+        let debug_on = Config {
+            enable_debug: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            invalid(check("[[route]]\npath = \"/debug/state\"\n", &debug_on)),
+            (
+                1,
+                "/debug/state is already matched by the app's route /debug/state".to_owned()
+            )
+        );
+        if !cfg!(feature = "debug-endpoints") {
+            check("[[route]]\npath = \"/debug/state\"\n", &config).unwrap();
+        }
+    }
+}
@@ -0,0 +1,131 @@
+/*
+   FORMS (web::Form)
+    besides JSON, classic HTML forms post their fields as `application/x-www-form-urlencoded`
+     (eg: name=Abebe&message=hello). web::Form<T> deserializes that body into T with serde,
+     just like web::Json<T> does for JSON.
+
+    FormConfig controls the extractor:
+     - limit(): max body size in bytes, bigger bodies are rejected before being parsed
+     - error_handler(): turns extraction errors into the response we want the client to see
+        (eg: a JSON body sent to a form route gets a clear 415 instead of a vague 400), in the
+        format every extractor shares (see extractor_errors.rs)
+*/
+
+use actix_web::{http::header::ContentType, post, web, HttpResponse, Responder};
+use serde::Deserialize;
+
+use crate::extractor_errors;
+
+// 4kB is plenty for a name and a message
+const MAX_FORM_SIZE: usize = 4 * 1024;
+
+#[derive(Deserialize)]
+pub struct ContactForm {
+    name: String,
+    message: String,
+}
+
+pub fn form_config() -> web::FormConfig {
+    web::FormConfig::default()
+        .limit(MAX_FORM_SIZE)
+        .error_handler(|err, _req| extractor_errors::form_error(err))
+}
+
+#[post("/contact")]
+pub async fn contact(form: web::Form<ContactForm>) -> impl Responder {
+    let name = form.name.trim();
+    let message = form.message.trim();
+
+    // the extractor only checks that the fields exist, not that they have content
+    if name.is_empty() || message.is_empty() {
+        return HttpResponse::BadRequest().body("name and message must not be empty");
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            "<h1>Thanks, {}!</h1><p>We received your message:</p><blockquote>{}</blockquote>",
+            escape_html(name),
+            escape_html(message)
+        ))
+}
+
+// user input goes back into an HTML page, so it must not be able to inject markup
+fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use crate::testing;
+
+    fn contact(body: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/contact")
+            .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+            .set_payload(body.to_owned())
+    }
+
+    #[actix_web::test]
+    async fn thanks_with_the_fields_escaped() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = contact("name=Abebe&message=%3Cb%3Ehi%3C%2Fb%3E").to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = test::read_body(res).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("Thanks, Abebe!"));
+        assert!(body.contains("&lt;b&gt;hi&lt;/b&gt;"));
+    }
+
+    #[actix_web::test]
+    async fn blank_fields_are_400() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, contact("name=+&message=hi").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn json_is_415() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/contact")
+            .set_json(serde_json::json!({ "name": "a", "message": "b" }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[actix_web::test]
+    async fn a_form_over_the_limit_is_413() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let message = "a".repeat(super::MAX_FORM_SIZE);
+        let res = test::call_service(
+            &app,
+            contact(&format!("name=a&message={message}")).to_request(),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
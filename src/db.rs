@@ -0,0 +1,35 @@
+/*
+   DATABASE (sqlx + SQLite)
+    a connection pool is created once at startup and shared by all workers through web::Data,
+     like any other shared state. SqlitePool is already an Arc inside, so cloning it is cheap.
+
+    the schema is created at startup with plain `CREATE TABLE IF NOT EXISTS` queries, which
+     keeps the demo free of migration tooling. queries use the runtime `sqlx::query` API (not
+     the `query!` macros), so building doesn't need a live database.
+*/
+
+use std::str::FromStr;
+
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    SqlitePool,
+};
+
+// the pool as handlers name it: web::Data<DbPool>
+pub type DbPool = SqlitePool;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS users (
+        id    INTEGER PRIMARY KEY AUTOINCREMENT,
+        name  TEXT NOT NULL,
+        email TEXT NOT NULL
+    )
+";
+
+pub async fn connect(database_url: &str) -> Result<SqlitePool, sqlx::Error> {
+    let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+    let pool = SqlitePoolOptions::new().connect_with(options).await?;
+
+    sqlx::query(SCHEMA).execute(&pool).await?;
+    Ok(pool)
+}
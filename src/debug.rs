@@ -0,0 +1,193 @@
+/*
+   DEBUG-ONLY ROUTES
+    some routes are for looking inside a running server and have no business in production:
+     - `GET /debug/state`  -> the state types handed to the handlers (see state.rs)
+     - `GET /debug/routes` -> every route pattern the app serves with its method, in match order
+
+    they are only REGISTERED when the cargo feature `debug-endpoints` is on
+     (`cargo run --features debug-endpoints`) or `enable_debug` (env ENABLE_DEBUG, see config.rs)
+     is true. otherwise they don't exist at all: a plain `404` like any unknown path, not a route
+     that answers "forbidden". the branch is in AppBuilder::bare_app(), the app factory, so every
+     worker sees the same set of routes.
+
+    the route list is app::ROUTES, kept next to configure_app() (a route added there belongs in
+     there too), then ROUTES below while these are registered, then the routes from routes.toml
+     (see config_routes.rs), in the order they match. a test checks every entry against the
+     app's resource map, so a pattern that is no longer registered fails it.
+*/
+
+use actix_web::{
+    get,
+    http::header::{CacheControl, CacheDirective},
+    web, HttpResponse, Responder,
+};
+use serde_json::json;
+
+use crate::{app, config::Config, config_routes::ConfigRoutes, state};
+
+// the routes configure() below adds, in the same order
+pub const ROUTES: &[(&str, &str)] = &[("GET", "/debug/state"), ("GET", "/debug/routes")];
+
+pub fn enabled(config: &Config) -> bool {
+    cfg!(feature = "debug-endpoints") || config.enable_debug
+}
+
+// startup note, so nobody is surprised to find them in a deployment
+pub fn log_if_enabled(config: &Config) {
+    if enabled(config) {
+        log::warn!("debug endpoints are on: /debug/state, /debug/routes");
+    }
+}
+
+#[get("/debug/routes")]
+pub async fn list_routes(config_routes: web::Data<ConfigRoutes>) -> impl Responder {
+    let from_file = config_routes
+        .routes
+        .iter()
+        .map(|route| (route.method.as_str(), route.path.as_str()));
+    let routes: Vec<_> = app::ROUTES
+        .iter()
+        .chain(ROUTES)
+        .copied()
+        .chain(from_file)
+        .map(|(method, pattern)| json!({ "method": method, "pattern": pattern }))
+        .collect();
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(json!({ "routes": routes }))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(state::show_state).service(list_routes);
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App, HttpRequest};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    fn debug_on() -> Config {
+        Config {
+            enable_debug: true,
+            ..Config::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn debug_routes_lists_the_known_patterns() {
+        let routes = ConfigRoutes::from_toml("[[route]]\npath = \"/from-file\"\n").unwrap();
+        let builder = testing::builder_with(debug_on())
+            .await
+            .with_config_routes(routes);
+        let app = test::init_service(builder.build()).await;
+
+        let req = test::TestRequest::get().uri("/debug/routes").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(res).await;
+        let routes = body["routes"].as_array().unwrap();
+
+        for known in [
+            json!({ "method": "GET", "pattern": "/" }),
+            json!({ "method": "GET", "pattern": "/users/{id}" }),
+            json!({ "method": "GET", "pattern": "/api/v1/users" }),
+            json!({ "method": "POST", "pattern": "/admin/shutdown" }),
+            json!({ "method": "GET", "pattern": "/static/*" }),
+            json!({ "method": "*", "pattern": "/proxy/*" }),
+            json!({ "method": "GET", "pattern": "/debug/state" }),
+            json!({ "method": "GET", "pattern": "/debug/routes" }),
+        ] {
+            assert!(routes.contains(&known), "{known} in {routes:?}");
+        }
+        // `/users/{id}` would match `/users/bulk`, so the fixed one comes first
+        let position = |pattern: &str| {
+            routes
+                .iter()
+                .position(|route| route["pattern"] == pattern)
+                .unwrap()
+        };
+        assert!(position("/users/bulk") < position("/users/{id}"));
+        // routes.toml's come last, as they are registered last
+        assert_eq!(
+            routes.last().unwrap(),
+            &json!({ "method": "GET", "pattern": "/from-file" })
+        );
+    }
+
+    #[actix_web::test]
+    async fn every_listed_route_is_registered() {
+        // the resource map a request sees is the whole app's, so one extra route can check them all
+        let app = test::init_service(
+            App::new()
+                .configure(app::configure_app)
+                .configure(configure)
+                .route(
+                    "/unregistered",
+                    web::get().to(|req: HttpRequest| async move {
+                        let unregistered: Vec<_> = app::ROUTES
+                            .iter()
+                            .chain(ROUTES)
+                            .map(|(_, pattern)| *pattern)
+                            .filter(|pattern| !pattern.ends_with("/*"))
+                            .filter(|pattern| {
+                                let matched = req
+                                    .resource_map()
+                                    .match_pattern(&testing::sample_path(pattern));
+                                matched.as_deref() != Some(*pattern)
+                            })
+                            .collect();
+                        HttpResponse::Ok().json(unregistered)
+                    }),
+                )
+                .default_service(web::to(|| async {
+                    HttpResponse::NotFound().body("no route")
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/unregistered").to_request();
+        let unregistered: Vec<String> = test::call_and_read_body_json(&app, req).await;
+        assert!(unregistered.is_empty(), "{unregistered:?}");
+
+        // a scope with only a default service has no routes in the map, but it does answer
+        for (_, pattern) in app::ROUTES
+            .iter()
+            .filter(|(_, pattern)| pattern.ends_with("/*"))
+        {
+            let req = test::TestRequest::get()
+                .uri(&testing::sample_path(pattern))
+                .to_request();
+            assert_ne!(
+                test::call_and_read_body(&app, req).await,
+                "no route",
+                "{pattern}"
+            );
+        }
+    }
+
+    #[cfg(not(feature = "debug-endpoints"))]
+    #[actix_web::test]
+    async fn without_the_flag_the_routes_dont_exist() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for uri in ["/debug/state", "/debug/routes"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+
+    #[cfg(feature = "debug-endpoints")]
+    #[actix_web::test]
+    async fn the_feature_registers_them_without_the_env() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/debug/routes").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
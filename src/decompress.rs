@@ -0,0 +1,327 @@
+/*
+   COMPRESSED REQUEST BODIES
+    a client may send a large body gzipped, saying so with `Content-Encoding: gzip`. this
+     middleware unpacks it before anything else reads the body, so web::Json, web::Form,
+     web::Bytes, the idempotency check and the body log all get the plain body, as if it had
+     been sent uncompressed (Content-Encoding and Content-Length are removed: the unpacked size
+     is only known once it has all been read).
+
+    - `gzip` (and its old name `x-gzip`) is unpacked, `identity` or no header passes through
+    - any other encoding is `415 Unsupported Media Type` with `Accept-Encoding: gzip`, which
+       tells the client what it may use instead (RFC 7694)
+    - a body that isn't valid gzip ends with PayloadError::EncodingCorrupted, `400` for the
+       extractors
+
+    the body is unpacked AS IT IS READ, chunk by chunk: nothing is buffered here, so a handler
+     streaming web::Payload (eg: `/ingest`, json_array.rs) still gets its body a piece at a time,
+     only unpacked. a chunk of a few kB unpacks in microseconds, so it is done right on the worker.
+
+    a few kB of gzip can unpack to gigabytes (a "decompression bomb"). the unpacked body may be
+     at most `max_expanded_body_bytes` (env APP_MAX_EXPANDED_BODY_BYTES, see config.rs): the
+     output that crosses it ends the body with PayloadError::Overflow, `413` for the extractors,
+     so no more than that is ever unpacked. body_limit::UNCAPPED_PATHS are exempt, like they are
+     from max_body_bytes: they hold one chunk at a time, whatever the total.
+    the compressed body itself still counts against max_body_bytes: this sits inside
+     body_limit.rs and reads the stream that one caps.
+*/
+
+use std::io::{self, Write};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::PayloadError,
+    http::header,
+    middleware::Next,
+    web::{self, Bytes},
+    Error, HttpMessage, HttpResponse,
+};
+use flate2::write::GzDecoder;
+use futures_util::{stream, StreamExt};
+
+use crate::{body_limit, config::Config};
+
+// where the decoder writes: refuses to grow past `max_bytes`, so a bomb stops right there
+struct CappedBuffer {
+    unpacked: Vec<u8>,
+    total: usize,
+    max_bytes: Option<usize>,
+}
+
+impl Write for CappedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.total += buf.len();
+        if self
+            .max_bytes
+            .is_some_and(|max_bytes| self.total > max_bytes)
+        {
+            return Err(io::Error::other(PayloadError::Overflow));
+        }
+        self.unpacked.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Gunzip {
+    decoder: GzDecoder<CappedBuffer>,
+}
+
+impl Gunzip {
+    fn new(max_bytes: Option<usize>) -> Self {
+        Self {
+            decoder: GzDecoder::new(CappedBuffer {
+                unpacked: Vec::new(),
+                total: 0,
+                max_bytes,
+            }),
+        }
+    }
+
+    // what `compressed` unpacks to (possibly nothing yet)
+    fn feed(&mut self, compressed: &[u8]) -> Result<Bytes, PayloadError> {
+        let written = self
+            .decoder
+            .write_all(compressed)
+            .and_then(|()| self.decoder.flush());
+        self.unpacked(written)
+    }
+
+    // the end of the body: the gzip trailer must have arrived by now
+    fn finish(&mut self) -> Result<Bytes, PayloadError> {
+        let finished = self.decoder.try_finish();
+        self.unpacked(finished)
+    }
+
+    fn unpacked(&mut self, written: io::Result<()>) -> Result<Bytes, PayloadError> {
+        if let Err(err) = written {
+            return Err(match err.get_ref().and_then(|err| err.downcast_ref()) {
+                Some(PayloadError::Overflow) => PayloadError::Overflow,
+                _ => PayloadError::EncodingCorrupted,
+            });
+        }
+        Ok(Bytes::from(std::mem::take(
+            &mut self.decoder.get_mut().unpacked,
+        )))
+    }
+}
+
+// the compressed payload in, the unpacked chunks out; ends at the first error
+fn unpacked_stream(payload: Payload, gunzip: Gunzip) -> Payload {
+    let chunks = stream::unfold(Some((payload, gunzip)), |state| async move {
+        let (mut payload, mut gunzip) = state?;
+        loop {
+            let unpacked = match payload.next().await {
+                Some(Ok(chunk)) => gunzip.feed(&chunk),
+                Some(Err(err)) => Err(err),
+                None => {
+                    return match gunzip.finish() {
+                        Ok(rest) if rest.is_empty() => None,
+                        rest => Some((rest, None)),
+                    }
+                }
+            };
+            match unpacked {
+                Ok(chunk) if chunk.is_empty() => continue,
+                Ok(chunk) => return Some((Ok(chunk), Some((payload, gunzip)))),
+                Err(err) => return Some((Err(err), None)),
+            }
+        }
+    });
+    Payload::Stream {
+        payload: Box::pin(chunks),
+    }
+}
+
+pub async fn decompress_body(
+    config: web::Data<Config>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let encoding = req
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_ascii_lowercase());
+
+    match encoding.as_deref() {
+        None | Some("identity") => {
+            return next
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_boxed_body)
+        }
+        Some("gzip" | "x-gzip") => {}
+        Some(other) => {
+            let res = HttpResponse::UnsupportedMediaType()
+                .insert_header((header::ACCEPT_ENCODING, "gzip"))
+                .body(format!("unsupported Content-Encoding {other:?}, use gzip"));
+            return Ok(req.into_response(res));
+        }
+    }
+
+    let max_bytes = match body_limit::UNCAPPED_PATHS.contains(&req.path()) {
+        true => None,
+        false => Some(config.max_expanded_body_bytes),
+    };
+    let payload = unpacked_stream(req.take_payload(), Gunzip::new(max_bytes));
+    req.set_payload(payload);
+
+    let headers = req.headers_mut();
+    headers.remove(header::CONTENT_ENCODING);
+    headers.remove(header::CONTENT_LENGTH);
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        error::{self, PayloadError},
+        http::StatusCode,
+        middleware, test, App,
+    };
+    use flate2::{write::GzEncoder, Compression};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::testing;
+
+    fn gzip(plain: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(plain).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // how many unpacked bytes the handler got, and how the body ended
+    async fn count_bytes(mut payload: web::Payload) -> actix_web::Result<String> {
+        let mut total = 0;
+        while let Some(chunk) = payload.next().await {
+            match chunk {
+                Ok(chunk) => total += chunk.len(),
+                Err(PayloadError::Overflow) => {
+                    return Err(error::ErrorPayloadTooLarge(format!(
+                        "overflow after {total}"
+                    )))
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(total.to_string())
+    }
+
+    #[actix_web::test]
+    async fn a_gzipped_json_body_reaches_the_extractor_unpacked() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let user = json!({ "name": "Zipped", "email": "zip@example.com" });
+
+        for encoding in ["gzip", "x-gzip", " GZIP "] {
+            let req = test::TestRequest::post()
+                .uri("/users")
+                .insert_header((header::CONTENT_TYPE, "application/json"))
+                .insert_header((header::CONTENT_ENCODING, encoding))
+                .set_payload(gzip(user.to_string().as_bytes()))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+
+            assert_eq!(res.status(), StatusCode::CREATED, "{encoding}");
+            let created: Value = test::read_body_json(res).await;
+            assert_eq!(created["name"], "Zipped");
+        }
+    }
+
+    #[actix_web::test]
+    async fn an_unsupported_encoding_is_415() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .insert_header((header::CONTENT_ENCODING, "br"))
+            .set_payload("not really brotli")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(res.headers().get(header::ACCEPT_ENCODING).unwrap(), "gzip");
+        assert_eq!(
+            test::read_body(res).await,
+            "unsupported Content-Encoding \"br\", use gzip"
+        );
+    }
+
+    #[actix_web::test]
+    async fn a_bomb_stops_at_the_expanded_limit() {
+        let config = Config {
+            max_expanded_body_bytes: 64 * 1024,
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .wrap(middleware::from_fn(decompress_body))
+                .route("/count", web::post().to(count_bytes)),
+        )
+        .await;
+        let bomb = gzip(&vec![0; 100 * 1024 * 1024]);
+        assert!(bomb.len() < 200 * 1024);
+
+        let req = test::TestRequest::post()
+            .uri("/count")
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload(bomb)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = test::read_body(res).await;
+        let unpacked: usize = std::str::from_utf8(&body)
+            .unwrap()
+            .strip_prefix("overflow after ")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(unpacked <= 64 * 1024, "{unpacked}");
+
+        // just under the limit is fine
+        let req = test::TestRequest::post()
+            .uri("/count")
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload(gzip(&vec![0; 64 * 1024]))
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "65536");
+    }
+
+    #[actix_web::test]
+    async fn a_bomb_through_the_whole_app_is_413() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload(gzip(&vec![b' '; 50 * 1024 * 1024]))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn a_body_that_is_not_gzip_is_400() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload("plain text, not gzip")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,173 @@
+/*
+   STOPPING WORK WHEN THE CLIENT GOES AWAY
+    `GET /rows/stream?count=<n>` streams n rows (default 100, at most MAX_ROWS) as
+     newline-delimited JSON, one every ROW_DELAY, the way a slow database cursor would.
+
+    the rows are produced by a task of their own that hands them to the response through a
+     small channel. when the client disconnects, actix notices on the next write, drops the
+     response body and with it the channel's receiver. the producer finds out right away:
+     - send() fails once the receiver is gone, so no row is produced into a closed connection
+     - while it is busy with the next row it also waits on Sender::closed(), so it stops even
+        in the middle of a slow step instead of finishing it first
+    either way it logs how far it got and returns, which frees whatever it held (a cursor, a
+     connection from the pool, ...).
+
+    a handler that produces inside the stream itself (eg: `/users/stream`, `/sources/stream`)
+     needs none of this: its stream is the thing that gets dropped, and a dropped future simply
+     stops. the channel is for work that runs in a SPAWNED task, which nobody would stop.
+
+    idle streams (SSE) only learn about a disconnect when they write, see the heartbeat in
+     events.rs.
+*/
+
+use std::time::Duration;
+
+use actix_web::{
+    get,
+    rt::{self, time::sleep},
+    web::{self, Bytes},
+    Error, HttpResponse,
+};
+use futures_util::stream;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::mpsc;
+
+const MAX_ROWS: u32 = 100_000;
+const DEFAULT_ROWS: u32 = 100;
+const ROW_DELAY: Duration = Duration::from_millis(50);
+// rows produced ahead of what the client has taken
+const BUFFERED_ROWS: usize = 8;
+
+#[derive(Deserialize)]
+pub struct RowsQuery {
+    count: Option<u32>,
+}
+
+async fn produce_rows(count: u32, rows: mpsc::Sender<Bytes>) {
+    let mut produced = 0;
+    while produced < count {
+        tokio::select! {
+            _ = rows.closed() => break,
+            _ = sleep(ROW_DELAY) => {}
+        }
+
+        let id = u64::from(produced) + 1;
+        let row = Bytes::from(format!("{}\n", json!({ "id": id, "value": id * id })));
+        if rows.send(row).await.is_err() {
+            break;
+        }
+        produced += 1;
+    }
+
+    if produced < count {
+        log::info!("client disconnected, stopped producing after {produced} of {count} rows");
+    }
+}
+
+#[get("/rows/stream")]
+pub async fn stream_rows(query: web::Query<RowsQuery>) -> HttpResponse {
+    let count = query.count.unwrap_or(DEFAULT_ROWS).min(MAX_ROWS);
+    let (sender, receiver) = mpsc::channel(BUFFERED_ROWS);
+    rt::spawn(produce_rows(count, sender));
+
+    // ends when the producer is done (and drops its sender)
+    let body = stream::unfold(receiver, |mut receiver| async move {
+        let row = receiver.recv().await?;
+        Some((Ok::<_, Error>(row), receiver))
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use actix_web::test;
+    use futures_util::StreamExt;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    // the producer's last word for `count` rows, once it has stopped early
+    async fn stopped_early(count: u32) -> Vec<String> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let lines = testing::logged("stopped producing after");
+            let lines: Vec<String> = lines
+                .into_iter()
+                .filter(|line| line.ends_with(&format!(" of {count} rows")))
+                .collect();
+            if !lines.is_empty() || Instant::now() > deadline {
+                return lines;
+            }
+            sleep(ROW_DELAY).await;
+        }
+    }
+
+    #[actix_web::test]
+    async fn every_row_is_sent_when_the_client_stays() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/rows/stream?count=3")
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+
+        let rows: Vec<Value> = body
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2], json!({ "id": 3, "value": 9 }));
+    }
+
+    #[actix_web::test]
+    async fn dropping_the_body_stops_the_producer() {
+        testing::capture_logs();
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::get()
+            .uri("/rows/stream?count=4001")
+            .to_request();
+        let mut body = test::call_service(&app, req).await.into_body();
+        testing::next_chunk(&mut body).await.unwrap();
+        testing::next_chunk(&mut body).await.unwrap();
+
+        drop(body);
+
+        let lines = stopped_early(4001).await;
+        assert_eq!(lines.len(), 1, "{lines:?}");
+    }
+
+    #[actix_web::test]
+    async fn a_client_hanging_up_stops_the_producer() {
+        testing::capture_logs();
+        let addr = testing::serve(&testing::builder().await);
+        let mut res = awc::Client::default()
+            .get(format!("http://{addr}/rows/stream?count=4002"))
+            .send()
+            .await
+            .unwrap();
+        let first = res.next().await.unwrap().unwrap();
+        assert!(first.starts_with(b"{\"id\":1,"));
+
+        drop(res);
+
+        let lines = stopped_early(4002).await;
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        // stopped long before producing the whole count
+        let produced: u32 = lines[0]
+            .split("after ")
+            .nth(1)
+            .and_then(|rest| rest.split(' ').next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(produced < 100, "{produced}");
+    }
+}
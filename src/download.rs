@@ -0,0 +1,215 @@
+/*
+   STREAMING FILE DOWNLOADS WITH RANGE SUPPORT
+    `GET /download/{name}` sends a file from the `downloads/` directory.
+
+    the file is never loaded fully into memory: it is read CHUNK_SIZE bytes at a time and every
+     chunk is sent as soon as it is read (an async stream body). memory use stays the same for a
+     1kB file and a 10GB one, and since the next chunk is only read when the previous one was
+     taken, a slow client naturally slows the reading down too (backpressure).
+
+    the `Range` header lets a client ask for part of the file (eg: resuming a download):
+     - `Range: bytes=0-99`   -> `206 Partial Content` + `Content-Range: bytes 0-99/<size>`
+     - a range past the end  -> `416 Range Not Satisfiable` + a `Content-Range` with `*` as range
+     - no (or a malformed) Range header, or several ranges -> the whole file with `200`
+*/
+
+use std::{cmp, io, path::PathBuf};
+
+use actix_web::{
+    get,
+    http::{
+        header::{
+            self, ContentDisposition, ContentRange, ContentRangeSpec, DispositionParam,
+            DispositionType, Range,
+        },
+        StatusCode,
+    },
+    web::{self, Bytes},
+    HttpMessage, HttpRequest, HttpResponse,
+};
+use futures_util::{stream, Stream};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, SeekFrom},
+};
+
+pub const DOWNLOADS_DIR: &str = "downloads";
+
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+// only plain file names, so `..` or absolute paths can't escape the downloads directory
+pub fn download_path(name: &str) -> Option<PathBuf> {
+    let is_plain = !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\'])
+        && !name.contains("..");
+
+    is_plain.then(|| PathBuf::from(DOWNLOADS_DIR).join(name))
+}
+
+// reads `len` bytes from the file's current position, one chunk per stream item
+pub fn file_stream(file: File, len: u64) -> impl Stream<Item = Result<Bytes, io::Error>> {
+    stream::unfold((file, len), |(mut file, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+
+        let mut buf = vec![0; cmp::min(CHUNK_SIZE, remaining) as usize];
+        match file.read(&mut buf).await {
+            // the file got shorter while we were sending it, nothing more to send
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), (file, remaining - n as u64)))
+            }
+            Err(err) => Some((Err(err), (file, 0))),
+        }
+    })
+}
+
+// None means the whole file, Err(()) means the range can't be satisfied
+fn requested_range(req: &HttpRequest, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    match req.get_header::<Range>() {
+        Some(Range::Bytes(specs)) if specs.len() == 1 => {
+            specs[0].to_satisfiable_range(size).map(Some).ok_or(())
+        }
+        _ => Ok(None),
+    }
+}
+
+#[get("/download/{name}")]
+pub async fn download(
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let Some(path) = download_path(&name) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let mut file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(HttpResponse::NotFound().finish())
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let size = metadata.len();
+
+    let (status, start, len) = match requested_range(&req, size) {
+        Ok(None) => (StatusCode::OK, 0, size),
+        Ok(Some((start, end))) => (StatusCode::PARTIAL_CONTENT, start, end - start + 1),
+        Err(()) => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(size),
+                }))
+                .finish())
+        }
+    };
+
+    if start > 0 {
+        file.seek(SeekFrom::Start(start)).await?;
+    }
+
+    let mut res = HttpResponse::build(status);
+    res.insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(name.into_inner())],
+        })
+        .content_type("application/octet-stream")
+        .no_chunking(len); // <- the size is known, so send Content-Length instead of chunked encoding
+
+    if status == StatusCode::PARTIAL_CONTENT {
+        res.insert_header(ContentRange(ContentRangeSpec::Bytes {
+            range: Some((start, start + len - 1)),
+            instance_length: Some(size),
+        }));
+    }
+
+    Ok(res.streaming(file_stream(file, len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+
+    use super::*;
+    use crate::testing;
+
+    fn hello() -> Vec<u8> {
+        std::fs::read(download_path("hello.txt").unwrap()).unwrap()
+    }
+
+    fn get(range: Option<&str>) -> test::TestRequest {
+        let req = test::TestRequest::get().uri("/download/hello.txt");
+        match range {
+            Some(range) => req.insert_header((header::RANGE, range)),
+            None => req,
+        }
+    }
+
+    #[actix_web::test]
+    async fn sends_the_whole_file() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, get(None).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+        assert_eq!(
+            res.headers().get(header::CONTENT_LENGTH).unwrap(),
+            hello().len().to_string().as_str()
+        );
+        assert_eq!(test::read_body(res).await, hello());
+    }
+
+    #[actix_web::test]
+    async fn sends_a_range() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, get(Some("bytes=6-9")).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        let size = hello().len();
+        assert_eq!(
+            res.headers().get(header::CONTENT_RANGE).unwrap(),
+            format!("bytes 6-9/{size}").as_str()
+        );
+        assert_eq!(test::read_body(res).await, hello()[6..=9]);
+    }
+
+    #[actix_web::test]
+    async fn a_range_past_the_end_is_416() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, get(Some("bytes=100000-")).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        let size = hello().len();
+        assert_eq!(
+            res.headers().get(header::CONTENT_RANGE).unwrap(),
+            format!("bytes */{size}").as_str()
+        );
+    }
+
+    #[actix_web::test]
+    async fn names_leaving_the_directory_are_404() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for name in ["..%2FCargo.toml", ".hidden", "missing.txt"] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/download/{name}"))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "{name}");
+        }
+        assert_eq!(download_path("a/b"), None);
+    }
+}
@@ -0,0 +1,182 @@
+/*
+   SERVER-SENT EVENTS OVER A BROADCAST CHANNEL
+    `GET /events` keeps the response open and pushes every published message to the client as a
+     server-sent event (`data: ...` lines, Content-Type: text/event-stream).
+    `POST /events` publishes the request body to everyone currently listening.
+
+    the messages go through a tokio broadcast channel: one sender, one receiver per client.
+     the channel is bounded, so a client that reads too slowly can't make the server buffer
+     forever. instead its receiver LAGS: the oldest messages are dropped for it and recv() says
+     how many were skipped. two things can go wrong while receiving, and both get a defined outcome:
+     - RecvError::Lagged(n) -> the client gets a `{"type":"lagged","skipped":n}` event and
+                               carries on with the messages still in the channel
+     - RecvError::Closed    -> every sender is gone, nothing can ever arrive again, so the
+                               stream simply ends and the response completes cleanly
+
+    a client that goes away is only noticed when something is written to it. with nothing
+     published, an SSE stream could keep its receiver forever, so every HEARTBEAT of silence it
+     sends a `: ping` comment line (ignored by EventSource). a gone client fails that write, the
+     stream is dropped and its receiver unsubscribes (see disconnect.rs).
+*/
+
+use std::time::Duration;
+
+use actix_web::{
+    get,
+    http::header::{self, CacheControl, CacheDirective},
+    post,
+    rt::time::sleep,
+    web::{self, Bytes},
+    Error, HttpResponse, Responder,
+};
+use futures_util::{stream, Stream};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+// how many messages a slow client may fall behind before it starts skipping some
+const CHANNEL_CAPACITY: usize = 16;
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+pub struct EventBus {
+    sender: broadcast::Sender<String>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+// what a client can receive, serialized as the `data:` of an SSE event
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Event {
+    Message { data: String },
+    Lagged { skipped: u64 },
+}
+
+impl EventBus {
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.sender.subscribe()
+    }
+
+    // returns how many clients will get the message
+    pub fn publish(&self, message: String) -> usize {
+        // sending only fails when nobody is listening, which is fine for us
+        self.sender.send(message).unwrap_or(0)
+    }
+}
+
+// None once the channel is closed, so the stream (and the response) ends
+pub async fn next_event(receiver: &mut broadcast::Receiver<String>) -> Option<Event> {
+    match receiver.recv().await {
+        Ok(data) => Some(Event::Message { data }),
+        Err(RecvError::Lagged(skipped)) => Some(Event::Lagged { skipped }),
+        Err(RecvError::Closed) => None,
+    }
+}
+
+pub fn sse_stream(
+    receiver: broadcast::Receiver<String>,
+) -> impl Stream<Item = Result<Bytes, Error>> {
+    stream::unfold(receiver, |mut receiver| async move {
+        let frame = tokio::select! {
+            event = next_event(&mut receiver) => serde_json::to_string(&event?)
+                .map(|json| Bytes::from(format!("data: {json}\n\n")))
+                .map_err(Error::from),
+            _ = sleep(HEARTBEAT) => Ok(Bytes::from_static(b": ping\n\n")),
+        };
+        Some((frame, receiver))
+    })
+}
+
+#[get("/events")]
+pub async fn subscribe(bus: web::Data<EventBus>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/event-stream"))
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(sse_stream(bus.subscribe()))
+}
+
+#[post("/events")]
+pub async fn publish(bus: web::Data<EventBus>, message: String) -> impl Responder {
+    let receivers = bus.publish(message);
+    HttpResponse::Accepted().json(serde_json::json!({ "receivers": receivers }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    #[actix_web::test]
+    async fn published_messages_reach_subscribers() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/events").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+        let mut body = res.into_body();
+
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .set_payload("hello")
+            .to_request();
+        let published: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(published["receivers"], 1);
+
+        let frame = testing::next_chunk(&mut body).await.unwrap();
+        assert_eq!(
+            frame,
+            r#"data: {"type":"message","data":"hello"}"#.to_owned() + "\n\n"
+        );
+    }
+
+    #[actix_web::test]
+    async fn nobody_listening_is_zero_receivers() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .set_payload("hello")
+            .to_request();
+        let published: Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(published["receivers"], 0);
+    }
+
+    #[actix_web::test]
+    async fn a_slow_receiver_is_told_what_it_skipped() {
+        let bus = EventBus::default();
+        let mut receiver = bus.subscribe();
+        for n in 0..CHANNEL_CAPACITY + 3 {
+            bus.publish(n.to_string());
+        }
+
+        assert!(matches!(
+            next_event(&mut receiver).await,
+            Some(Event::Lagged { skipped: 3 })
+        ));
+        assert!(matches!(
+            next_event(&mut receiver).await,
+            Some(Event::Message { data }) if data == "3"
+        ));
+    }
+
+    #[actix_web::test]
+    async fn a_closed_channel_ends_the_stream() {
+        let bus = EventBus::default();
+        let mut events = Box::pin(sse_stream(bus.subscribe()));
+        drop(bus);
+
+        assert!(futures_util::StreamExt::next(&mut events).await.is_none());
+    }
+}
@@ -0,0 +1,309 @@
+/*
+   ONE ERROR FORMAT FOR ALL EXTRACTORS
+    when web::Path, web::Query, web::Json or web::Form can't turn the request into the handler's
+     type, each of them answers its own way by default (mostly a plain text `400`). the configs
+     here (registered once in AppBuilder, so they cover every route) make them all answer:
+
+        400 { "error": "missing field `email` at line 3 column 5", "source": "json" }
+
+     `source` says WHICH part of the request was wrong: path, query, json or form. a body of the
+     wrong Content-Type is `415` and a body over the limit `413`, with the same shape, be it the
+     extractor's own limit or the cap of body_limit.rs on a chunked body.
+
+    serde's messages name the RUST types (`expected struct NewUser`, `to a u32`), which mean
+     nothing to a client, so the message says what should have been sent instead: an object,
+     an integer, a number, one of the allowed values.
+
+    a malformed uuid (eg: `/orders/{id}` with a web::Path<Uuid>) says what a uuid looks like,
+     hyphenated or simple, instead of only where the uuid parser gave up.
+
+    the form config keeps the contact form's own size limit, see contact::form_config().
+*/
+
+use std::fmt;
+
+use actix_web::{
+    error::{
+        InternalError, JsonPayloadError, PathError, PayloadError, QueryPayloadError,
+        UrlencodedError,
+    },
+    http::StatusCode,
+    web, Error, HttpResponse,
+};
+use serde_json::json;
+
+// what a client should have sent, for a rust type in serde's message
+fn client_kind(rust_type: &str) -> &str {
+    match rust_type {
+        "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128"
+        | "usize" => "an integer",
+        "f32" | "f64" => "a number",
+        "bool" => "a boolean",
+        "char" => "a single character string",
+        _ if rust_type.starts_with("struct ")
+            || rust_type.starts_with("tuple struct ")
+            || rust_type.starts_with("a map") =>
+        {
+            "an object"
+        }
+        _ if rust_type.starts_with("enum ") || rust_type.starts_with("variant identifier") => {
+            "one of the allowed values"
+        }
+        _ => rust_type,
+    }
+}
+
+// "invalid type: string \"x\", expected u32 at line 1 column 9"
+//   -> "invalid type: string \"x\", expected an integer at line 1 column 9"
+// "can not parse \"abc\" to a i64" -> "can not parse \"abc\" to an integer"
+fn client_message(message: &str) -> String {
+    let Some((before, marker, rust_type)) = ["expected ", "to a "].into_iter().find_map(|marker| {
+        let (before, rust_type) = message.rsplit_once(marker)?;
+        Some((before, marker, rust_type))
+    }) else {
+        return message.to_owned();
+    };
+    let (rust_type, position) = match rust_type.rfind(" at line ") {
+        Some(at) => rust_type.split_at(at),
+        None => (rust_type, ""),
+    };
+    let marker = if marker == "to a " { "to " } else { marker };
+    format!("{before}{marker}{}{position}", client_kind(rust_type))
+}
+
+// also used by extractors of our own, eg: text::PlainText
+pub fn error_response(
+    err: impl fmt::Debug + fmt::Display + 'static,
+    status: StatusCode,
+    source: &str,
+    message: String,
+) -> Error {
+    let res = HttpResponse::build(status).json(json!({ "error": message, "source": source }));
+    InternalError::from_response(err, res).into()
+}
+
+// "UUID parsing failed: invalid character: found `x` at 3"
+//   -> "not a valid UUID (invalid character: found `x` at 3), expected 32 hex digits, ..."
+fn uuid_message(message: &str) -> Option<String> {
+    let reason = message.strip_prefix("UUID parsing failed: ")?;
+    Some(format!(
+        "not a valid UUID ({reason}), expected 32 hex digits with or without hyphens, \
+         eg: 67e55044-10b1-426f-9247-bb680e5fe0c8 or 67e5504410b1426f9247bb680e5fe0c8"
+    ))
+}
+
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|err, _req| {
+        let message = match &err {
+            PathError::Deserialize(err) => {
+                let message = err.to_string();
+                uuid_message(&message).unwrap_or_else(|| client_message(&message))
+            }
+            other => other.to_string(),
+        };
+        error_response(err, StatusCode::BAD_REQUEST, "path", message)
+    })
+}
+
+// also the message of the v2 API's query errors, see api::v2::query_config()
+pub fn query_message(err: &QueryPayloadError) -> String {
+    match err {
+        QueryPayloadError::Deserialize(err) => client_message(&err.to_string()),
+        other => other.to_string(),
+    }
+}
+
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err, _req| {
+        let message = query_message(&err);
+        error_response(err, StatusCode::BAD_REQUEST, "query", message)
+    })
+}
+
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|err, _req| {
+        let (status, message) = match &err {
+            JsonPayloadError::ContentType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "expected a JSON body (Content-Type: application/json)".to_owned(),
+            ),
+            JsonPayloadError::Overflow { limit }
+            | JsonPayloadError::OverflowKnownLength { limit, .. } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("JSON body is larger than {limit} bytes"),
+            ),
+            // the body cap of body_limit.rs, hit while a chunked body was being read
+            JsonPayloadError::Payload(PayloadError::Overflow) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "JSON body is larger than the server accepts".to_owned(),
+            ),
+            JsonPayloadError::Deserialize(err) => {
+                (StatusCode::BAD_REQUEST, client_message(&err.to_string()))
+            }
+            other => (StatusCode::BAD_REQUEST, other.to_string()),
+        };
+        error_response(err, status, "json", message)
+    })
+}
+
+// for FormConfig::error_handler(), see contact::form_config()
+pub fn form_error(err: UrlencodedError) -> Error {
+    let (status, message) = match &err {
+        UrlencodedError::ContentType => (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "expected a form body (Content-Type: application/x-www-form-urlencoded)".to_owned(),
+        ),
+        UrlencodedError::Overflow { limit, .. } => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("form is larger than {limit} bytes"),
+        ),
+        UrlencodedError::Payload(PayloadError::Overflow) => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "form is larger than the server accepts".to_owned(),
+        ),
+        UrlencodedError::Parse(err) => (StatusCode::BAD_REQUEST, client_message(&err.to_string())),
+        other => (StatusCode::BAD_REQUEST, other.to_string()),
+    };
+    error_response(err, status, "form", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::header, test};
+    use futures_util::stream;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{config::Config, testing};
+
+    async fn post_users(content_type: &str, body: &'static str) -> (StatusCode, Value) {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .set_payload(body)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        (res.status(), test::read_body_json(res).await)
+    }
+
+    #[actix_web::test]
+    async fn a_missing_field_is_named() {
+        let (status, body) = post_users("application/json", "{\n  \"name\": \"Abebe\"\n}").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["source"], "json");
+        assert_eq!(body["error"], "missing field `email` at line 3 column 1");
+    }
+
+    #[actix_web::test]
+    async fn a_wrong_type_says_what_to_send_without_rust_names() {
+        for (json, expected) in [
+            (r#"{"name": 7, "email": "a@b.c"}"#, "expected a string"),
+            (r#""Abebe""#, "expected an object"),
+        ] {
+            let (status, body) = post_users("application/json", json).await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let error = body["error"].as_str().unwrap();
+            assert!(error.contains(expected), "{error}");
+            assert!(!error.contains("NewUser"), "{error}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn malformed_json_is_400() {
+        let (status, body) = post_users("application/json", r#"{"name": "Abebe","#).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["source"], "json");
+        assert!(body["error"].as_str().unwrap().contains("EOF"));
+    }
+
+    #[actix_web::test]
+    async fn a_body_that_isnt_json_is_415() {
+        let (status, body) = post_users("text/plain", "name=Abebe").await;
+
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["source"], "json");
+    }
+
+    async fn get(uri: &str) -> (StatusCode, Value) {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let res = test::call_service(&app, req).await;
+        (res.status(), test::read_body_json(res).await)
+    }
+
+    #[actix_web::test]
+    async fn a_bad_path_segment_has_the_same_shape() {
+        let (status, body) = get("/users/abc").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["source"], "path");
+        assert_eq!(body["error"], "can not parse \"abc\" to an integer");
+    }
+
+    #[actix_web::test]
+    async fn a_malformed_uuid_says_what_one_looks_like() {
+        let (status, body) = get("/orders/not-a-uuid").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["source"], "path");
+        let error = body["error"].as_str().unwrap();
+        assert!(error.starts_with("not a valid UUID ("), "{error}");
+        assert!(
+            error.contains("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+            "{error}"
+        );
+    }
+
+    #[actix_web::test]
+    async fn a_bad_query_has_the_same_shape() {
+        let (status, body) = get("/users?limit=many").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["source"], "query");
+        assert_eq!(body["error"], "invalid digit found in string");
+    }
+
+    #[actix_web::test]
+    async fn a_bad_form_has_the_same_shape() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::post()
+            .uri("/contact")
+            .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+            .set_payload("name=Abebe")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["source"], "form");
+        assert_eq!(body["error"], "missing field `message`");
+    }
+
+    #[actix_web::test]
+    async fn a_chunked_json_body_over_the_cap_is_413_with_the_same_shape() {
+        let config = Config {
+            max_body_bytes: 1024,
+            ..Config::default()
+        };
+        let addr = testing::serve(&testing::builder_with(config).await);
+        let padding = format!(r#"{{"name": "{}", "#, "a".repeat(2048));
+        let chunks = [padding, r#""email": "a@b.c"}"#.to_owned()]
+            .map(|chunk| Ok::<_, Error>(web::Bytes::from(chunk)));
+
+        let mut res = awc::Client::default()
+            .post(format!("http://{addr}/users"))
+            .content_type("application/json")
+            .send_stream(stream::iter(chunks))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: Value = res.json().await.unwrap();
+        assert_eq!(body["source"], "json");
+        assert_eq!(body["error"], "JSON body is larger than the server accepts");
+    }
+}
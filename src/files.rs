@@ -0,0 +1,209 @@
+/*
+   FILES WITH DATE VALIDATION: GET /files/{name}
+    sends a file from the `downloads/` directory (the same names as `/download/{name}`), but
+     to be SHOWN rather than saved: no Content-Disposition, and a Content-Type guessed from the
+     extension. it answers with the file's mtime as
+
+        Last-Modified: Tue, 13 Oct 2026 09:12:45 GMT
+
+     and a client that sends that date back gets `304 Not Modified` for as long as the file
+     isn't touched (see conditional.rs for the comparison and its one second of slack):
+
+        If-Modified-Since: Tue, 13 Oct 2026 09:12:45 GMT   ->   304, no body
+        (file written again after that date)               ->   200 with the new file and date
+
+    the body is streamed like a download, see download.rs.
+*/
+
+use std::{io, path::Path};
+
+use actix_files::file_extension_to_mime;
+use actix_web::{
+    get,
+    http::header::{HttpDate, LastModified},
+    web, HttpRequest, HttpResponse,
+};
+use tokio::fs::File;
+
+use crate::{
+    conditional,
+    download::{download_path, file_stream},
+};
+
+#[get("/files/{name}")]
+pub async fn show_file(
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let Some(path) = download_path(&name) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(HttpResponse::NotFound().finish())
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let modified = metadata.modified()?;
+    let last_modified = LastModified(HttpDate::from(modified));
+
+    if conditional::not_modified_since(&req, modified) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(last_modified)
+            .finish());
+    }
+
+    let extension = Path::new(name.as_str())
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    let size = metadata.len();
+    Ok(HttpResponse::Ok()
+        .insert_header(last_modified)
+        .content_type(file_extension_to_mime(extension))
+        .no_chunking(size)
+        .streaming(file_stream(file, size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use actix_web::{
+        http::{header, StatusCode},
+        test,
+    };
+
+    use super::*;
+    use crate::{download::DOWNLOADS_DIR, testing};
+
+    // a file of its own in downloads/, with the mtime a test sets, gone when dropped
+    struct Written {
+        name: String,
+    }
+
+    impl Written {
+        fn new(contents: &str) -> Self {
+            let name = format!("files-test-{}.txt", uuid::Uuid::new_v4());
+            std::fs::write(Path::new(DOWNLOADS_DIR).join(&name), contents).unwrap();
+            Self { name }
+        }
+
+        fn touch(&self, modified: SystemTime) {
+            let file = std::fs::File::options()
+                .write(true)
+                .open(Path::new(DOWNLOADS_DIR).join(&self.name))
+                .unwrap();
+            file.set_modified(modified).unwrap();
+        }
+
+        fn get(&self) -> test::TestRequest {
+            test::TestRequest::get().uri(&format!("/files/{}", self.name))
+        }
+    }
+
+    impl Drop for Written {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(Path::new(DOWNLOADS_DIR).join(&self.name));
+        }
+    }
+
+    // a whole second plus `millis`, so the header (whole seconds) is behind the mtime
+    fn at(secs: u64, millis: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH
+            + Duration::from_secs(1_790_000_000 + secs)
+            + Duration::from_millis(millis)
+    }
+
+    fn date(time: SystemTime) -> String {
+        HttpDate::from(time).to_string()
+    }
+
+    #[actix_web::test]
+    async fn a_fresh_request_gets_the_file_and_its_date() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let file = Written::new("hello from disk\n");
+        file.touch(at(0, 700));
+
+        let res = test::call_service(&app, file.get().to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::LAST_MODIFIED).unwrap(),
+            date(at(0, 0)).as_str()
+        );
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/plain"
+        );
+        assert_eq!(test::read_body(res).await, "hello from disk\n");
+    }
+
+    #[actix_web::test]
+    async fn the_date_sent_back_is_304_despite_the_lost_fraction() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let file = Written::new("unchanged");
+        file.touch(at(0, 700));
+
+        let req = file
+            .get()
+            .insert_header((header::IF_MODIFIED_SINCE, date(at(0, 0))))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(
+            res.headers().get(header::LAST_MODIFIED).unwrap(),
+            date(at(0, 0)).as_str()
+        );
+        assert!(test::read_body(res).await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn a_file_modified_after_the_clients_date_is_sent_again() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let file = Written::new("first version");
+        file.touch(at(0, 0));
+        let since = date(at(0, 0));
+
+        std::fs::write(Path::new(DOWNLOADS_DIR).join(&file.name), "second version").unwrap();
+        file.touch(at(5, 0));
+        let req = file
+            .get()
+            .insert_header((header::IF_MODIFIED_SINCE, since))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::LAST_MODIFIED).unwrap(),
+            date(at(5, 0)).as_str()
+        );
+        assert_eq!(test::read_body(res).await, "second version");
+    }
+
+    #[actix_web::test]
+    async fn a_missing_or_unsafe_name_is_404() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for uri in [
+            "/files/no-such-file.txt",
+            "/files/..%2FCargo.toml",
+            "/files/.hidden",
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(
+                test::call_service(&app, req).await.status(),
+                StatusCode::NOT_FOUND,
+                "{uri}"
+            );
+        }
+    }
+}
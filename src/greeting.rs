@@ -0,0 +1,123 @@
+/*
+   LANGUAGE FROM Accept-Language
+    `GET /greeting` greets in the client's language. browsers send their preferences ranked by
+     QUALITY values, eg:
+
+        Accept-Language: fr-CH, fr;q=0.9, es;q=0.5, *;q=0.1
+
+     the languages are tried from the highest q to the lowest (the order of the header breaks
+     ties) and the first SUPPORTED one wins. only the primary language is compared, so `fr-CH`
+     and `es-MX` get `fr` and `es`. `q=0` means "not this one" and is skipped.
+
+    no header, an unparsable one, `*`, or nothing we support all end up in DEFAULT_LOCALE: a
+     greeting in the wrong language beats an error.
+
+    the answer depends on a request header, so it says so with `Vary: Accept-Language` (which
+     also keeps it out of the response cache) and names its language in `Content-Language`.
+*/
+
+use actix_web::{
+    get,
+    http::header::{self, AcceptLanguage, Header, Preference, Quality},
+    HttpRequest, HttpResponse,
+};
+use serde_json::json;
+
+// (locale, greeting), the first one is DEFAULT_LOCALE
+const GREETINGS: [(&str, &str); 3] = [("en", "Hello!"), ("es", "¡Hola!"), ("fr", "Bonjour !")];
+const DEFAULT_LOCALE: (&str, &str) = GREETINGS[0];
+
+fn best_match(accept: &AcceptLanguage) -> (&'static str, &'static str) {
+    let mut ranked: Vec<_> = accept
+        .0
+        .iter()
+        .filter(|item| item.quality > Quality::ZERO)
+        .collect();
+    // stable: equal q-values keep the order of the header
+    ranked.sort_by_key(|item| std::cmp::Reverse(item.quality));
+
+    for item in ranked {
+        let Preference::Specific(tag) = &item.item else {
+            break; // `*`: anything goes, so the default
+        };
+        let language = tag.primary_language().to_ascii_lowercase();
+        if let Some(&found) = GREETINGS.iter().find(|(locale, _)| *locale == language) {
+            return found;
+        }
+    }
+    DEFAULT_LOCALE
+}
+
+#[get("/greeting")]
+pub async fn greeting(req: HttpRequest) -> HttpResponse {
+    let (locale, text) =
+        AcceptLanguage::parse(&req).map_or(DEFAULT_LOCALE, |accept| best_match(&accept));
+
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_LANGUAGE, locale))
+        .insert_header((header::VARY, "Accept-Language"))
+        .json(json!({ "locale": locale, "greeting": text }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::Value;
+
+    use crate::testing;
+
+    async fn greet(accept_language: Option<&str>) -> (String, Value) {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let mut req = test::TestRequest::get().uri("/greeting");
+        if let Some(accept_language) = accept_language {
+            req = req.insert_header(("Accept-Language", accept_language));
+        }
+
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("Vary").unwrap(), "Accept-Language");
+        let language = res.headers().get("Content-Language").unwrap();
+        let language = language.to_str().unwrap().to_owned();
+        (language, test::read_body_json(res).await)
+    }
+
+    #[actix_web::test]
+    async fn each_supported_locale_is_greeted_in_its_language() {
+        for (accept, greeting) in [
+            ("en", "Hello!"),
+            ("es-MX", "¡Hola!"),
+            ("fr-CH", "Bonjour !"),
+        ] {
+            let (language, body) = greet(Some(accept)).await;
+
+            let locale = &accept[..2];
+            assert_eq!(language, locale);
+            assert_eq!(body["locale"], locale);
+            assert_eq!(body["greeting"], greeting);
+        }
+    }
+
+    #[actix_web::test]
+    async fn the_highest_quality_wins_whatever_the_order() {
+        let (language, _) = greet(Some("en;q=0.3, de, es;q=0.8, fr;q=0.5")).await;
+        assert_eq!(language, "es");
+
+        let (language, _) = greet(Some("fr;q=0, es;q=0.2")).await;
+        assert_eq!(language, "es");
+    }
+
+    #[actix_web::test]
+    async fn anything_unsupported_falls_back_to_english() {
+        for accept in [
+            Some("de, ja;q=0.5"),
+            Some("*"),
+            Some("not a language ;;"),
+            None,
+        ] {
+            let (language, body) = greet(accept).await;
+
+            assert_eq!(language, "en", "{accept:?}");
+            assert_eq!(body["greeting"], "Hello!");
+        }
+    }
+}
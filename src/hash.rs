@@ -0,0 +1,107 @@
+/*
+   CPU-BOUND WORK ON THE BLOCKING POOL
+    `GET /hash?password=...` hashes the password with argon2, which is SLOW ON PURPOSE (that is
+     what makes it hard to brute force): tens of milliseconds of pure CPU, no .await anywhere.
+
+    run directly in the handler it would hold the worker thread for the whole hash, and every
+     other request on that worker would wait behind it. web::block moves the closure to actix's
+     blocking thread pool and the handler only awaits the result, so the worker keeps serving
+     other requests meanwhile.
+
+    web::block fails when the blocking pool is gone or the closure panicked; that (and a failed
+     hash) is a `500`.
+*/
+
+use std::time::Instant;
+
+use actix_web::{
+    error, get,
+    http::header::{CacheControl, CacheDirective},
+    web, HttpResponse,
+};
+use argon2::{
+    password_hash::{PasswordHasher, SaltString},
+    Argon2,
+};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct HashQuery {
+    password: String,
+}
+
+fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    // 16 random bytes are all a salt needs to be
+    let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes())?;
+    let hashed = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hashed.to_string())
+}
+
+#[get("/hash")]
+pub async fn hash(query: web::Query<HashQuery>) -> actix_web::Result<HttpResponse> {
+    let started = Instant::now();
+    let password = query.into_inner().password;
+
+    let hashed = web::block(move || hash_password(&password))
+        .await? // <- BlockingError, the pool couldn't run the closure
+        .map_err(|err| error::ErrorInternalServerError(err.to_string()))?;
+
+    // a fresh salt every time, and a password in the url: nothing here belongs in a cache
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(json!({
+            "hash": hashed,
+            "elapsed_ms": started.elapsed().as_millis(),
+        })))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use argon2::{PasswordHash, PasswordVerifier};
+    use futures_util::future::join3;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    fn hash_request(password: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(&format!("/hash?password={password}"))
+    }
+
+    #[actix_web::test]
+    async fn hashes_run_side_by_side_and_the_worker_stays_free() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let started = Instant::now();
+
+        let timed = |req: test::TestRequest| {
+            let app = &app;
+            async move {
+                let res = test::call_service(app, req.to_request()).await;
+                (res, started.elapsed())
+            }
+        };
+        let ((first, first_took), (second, second_took), (health, health_took)) = join3(
+            timed(hash_request("one")),
+            timed(hash_request("two")),
+            timed(test::TestRequest::get().uri("/healthz")),
+        )
+        .await;
+
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(second.status(), StatusCode::OK);
+        assert_eq!(health.status(), StatusCode::OK);
+        // the health check didn't queue behind the hashes
+        assert!(health_took < first_took.min(second_took));
+
+        for (res, password) in [(first, "one"), (second, "two")] {
+            let body: Value = test::read_body_json(res).await;
+            let parsed = PasswordHash::new(body["hash"].as_str().unwrap()).unwrap();
+            assert!(Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok());
+        }
+    }
+}
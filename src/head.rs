@@ -0,0 +1,138 @@
+/*
+   HEAD FOR EVERY GET ROUTE
+    a HEAD request asks for exactly the headers a GET would get, without the body. `#[get]` only
+     matches GET, so without help every HEAD is a `404`, and the tutorial above adds a
+     `web::head()` route by hand to each resource.
+
+    this middleware does it for all of them: a HEAD is turned into a GET before routing, the
+     handler runs as usual, and on the way back the body is swapped for an empty one that still
+     reports the SIZE of the real body, so `Content-Length` is what the GET would have sent. the
+     real body is dropped unread, a HEAD on an endlessly streamed route (eg: `/events`) ends
+     right after the headers.
+
+    only pretty_json.rs and tx.rs sit inside it (they see a GET); every other middleware (logs,
+     metrics, the response cache) is around it and sees the HEAD request and the empty body.
+     the one of those that reads response bodies, body_log.rs, leaves HEAD answers alone. no
+     route of the app registers HEAD itself; one that did would never be reached.
+*/
+
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    body::{BodySize, EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::Method,
+    middleware::Next,
+    web::Bytes,
+    Error,
+};
+
+// a body without data that claims the size of the one it replaced
+pub struct HeadBody(BodySize);
+
+impl MessageBody for HeadBody {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        self.0
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Poll::Ready(None)
+    }
+}
+
+pub async fn head_as_get(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let is_head = req.method() == Method::HEAD;
+    if is_head {
+        req.head_mut().method = Method::GET;
+    }
+
+    let res = next.call(req).await?;
+    if !is_head {
+        return Ok(res.map_into_left_body());
+    }
+    Ok(res.map_body(|_, body| EitherBody::right(HeadBody(body.size()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::{header, StatusCode},
+        test,
+    };
+
+    use super::*;
+    use crate::testing;
+
+    #[actix_web::test]
+    async fn head_has_the_headers_of_get_and_no_body() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for uri in ["/", "/healthz", "/users", "/greeting"] {
+            let get = test::TestRequest::get().uri(uri).to_request();
+            let get = test::call_service(&app, get).await;
+            let head = test::TestRequest::default()
+                .method(Method::HEAD)
+                .uri(uri)
+                .to_request();
+            let head = test::call_service(&app, head).await;
+
+            assert_eq!(head.status(), get.status(), "{uri}");
+            for name in [header::CONTENT_TYPE, header::CACHE_CONTROL, header::VARY] {
+                assert_eq!(head.headers().get(&name), get.headers().get(&name), "{uri}");
+            }
+            let size = get.response().body().size();
+            assert_eq!(head.response().body().size(), size, "{uri}");
+            assert!(test::read_body(head).await.is_empty(), "{uri}");
+            assert!(!test::read_body(get).await.is_empty(), "{uri}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn content_length_on_the_wire_is_the_get_body_size() {
+        let addr = testing::serve(&testing::builder().await);
+        let client = awc::Client::default();
+
+        let mut res = client.head(format!("http://{addr}/")).send().await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_LENGTH).unwrap(),
+            "Hello world!".len().to_string().as_str()
+        );
+        assert!(res.body().await.unwrap().is_empty());
+
+        // an endless stream ends right after its headers
+        let res = client
+            .head(format!("http://{addr}/events"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn a_post_only_route_doesnt_answer_head() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::default()
+            .method(Method::HEAD)
+            .uri("/echo")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert!(res.status().is_client_error());
+        assert!(test::read_body(res).await.is_empty());
+    }
+}
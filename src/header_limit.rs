@@ -0,0 +1,135 @@
+/*
+   URL AND HEADER SIZE LIMITS
+    actix-http only refuses a request head once it passes 128kB (or 96 headers), far more than
+     any real client sends. a huge url or a pile of headers costs memory and time in everything
+     that looks at them (routing, logging, the cache key, ...), so this middleware answers them
+     before any of that runs:
+
+     - the url (path and query) longer than `max_url_bytes`      -> `414 URI Too Long`
+     - the headers together larger than `max_header_bytes`        -> `431 Request Header Fields Too Large`
+
+    (env APP_MAX_URL_BYTES and APP_MAX_HEADER_BYTES, see config.rs). a header counts as it is on
+     the wire: name, `: `, value and the line break. the defaults are well above what browsers
+     send (long cookies included), so normal requests never notice it.
+
+    it is one of the outermost middleware: outside strip_prefix.rs and trailing_slash.rs, so
+     routing never sees an oversized url, and outside inflight.rs, so these requests don't take
+     a permit.
+*/
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+
+use crate::config::Config;
+
+fn url_bytes(req: &ServiceRequest) -> usize {
+    req.uri().path_and_query().map_or(0, |pq| pq.as_str().len())
+}
+
+// "name: value\r\n" for every header
+fn header_bytes(req: &ServiceRequest) -> usize {
+    req.headers()
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum()
+}
+
+pub async fn limit_url_and_headers(
+    config: web::Data<Config>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let max_url = config.max_url_bytes;
+    if url_bytes(&req) > max_url {
+        let res = HttpResponse::UriTooLong().body(format!("url is longer than {max_url} bytes"));
+        return Ok(req.into_response(res));
+    }
+
+    let max_headers = config.max_header_bytes;
+    if header_bytes(&req) > max_headers {
+        let res = HttpResponse::RequestHeaderFieldsTooLarge()
+            .body(format!("headers are larger than {max_headers} bytes"));
+        return Ok(req.into_response(res));
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use super::*;
+    use crate::testing;
+
+    fn small_limits() -> Config {
+        Config {
+            max_url_bytes: 64,
+            max_header_bytes: 256,
+            ..Config::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn an_oversized_url_is_414() {
+        let app = test::init_service(testing::builder_with(small_limits()).await.build()).await;
+
+        let uri = format!("/users?filter={}", "x".repeat(64));
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::URI_TOO_LONG);
+        assert_eq!(test::read_body(res).await, "url is longer than 64 bytes");
+    }
+
+    #[actix_web::test]
+    async fn oversized_headers_are_431() {
+        let app = test::init_service(testing::builder_with(small_limits()).await.build()).await;
+
+        // many small headers add up just like one big one
+        let one_big = test::TestRequest::get()
+            .uri("/users")
+            .insert_header(("X-Big", "x".repeat(300)));
+        let many_small = (0..20).fold(test::TestRequest::get().uri("/users"), |req, n| {
+            req.insert_header((format!("x-small-{n}"), "0123456789"))
+        });
+
+        for req in [one_big, many_small] {
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        }
+    }
+
+    #[actix_web::test]
+    async fn normal_requests_are_unaffected() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/users?limit=5&after=1")
+            .insert_header(("Cookie", format!("id={}", "c".repeat(2000))))
+            .insert_header((
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64) Firefox/128.0",
+            ))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn a_header_counts_as_on_the_wire() {
+        let req = test::TestRequest::get()
+            .insert_header(("ab", "cde"))
+            .to_srv_request();
+
+        // "ab: cde\r\n"
+        assert_eq!(header_bytes(&req), 9);
+    }
+}
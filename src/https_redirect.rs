@@ -0,0 +1,112 @@
+/*
+   REDIRECT HTTP TO HTTPS
+    behind a TLS-terminating proxy the app itself only ever sees plain http, so the scheme the
+     CLIENT used has to come from the proxy: ConnectionInfo reads it from the `Forwarded` or
+     `X-Forwarded-Proto` header (and falls back to the connection's own scheme without them).
+
+    with `force_https` on (env FORCE_HTTPS, see config.rs) every request that came in over
+     http gets `301 Moved Permanently` to the same host + path + query on https. the health and
+     readiness checks are exempt: probes usually talk plain http straight to the app and don't
+     follow redirects.
+
+    only turn it on behind a proxy that sets these headers: anybody can send `X-Forwarded-Proto`,
+     the app can only trust it because the proxy overwrites it.
+*/
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+
+use crate::config::Config;
+
+const EXEMPT_PATHS: [&str; 2] = ["/healthz", "/readyz"];
+
+pub async fn redirect_to_https(
+    config: web::Data<Config>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let is_plain_http = req.connection_info().scheme() == "http";
+
+    if !config.force_https || !is_plain_http || EXEMPT_PATHS.contains(&req.path()) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    }
+
+    let path_and_query = req.uri().path_and_query().map_or("/", |pq| pq.as_str());
+    let location = format!("https://{}{path_and_query}", req.connection_info().host());
+
+    let res = HttpResponse::MovedPermanently()
+        .insert_header((header::LOCATION, location))
+        .finish();
+    Ok(req.into_response(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use super::*;
+    use crate::testing;
+
+    fn forced() -> Config {
+        Config {
+            force_https: true,
+            ..Config::default()
+        }
+    }
+
+    fn forwarded(proto: &str, uri: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header((header::HOST, "example.com"))
+            .insert_header(("X-Forwarded-Proto", proto))
+    }
+
+    #[actix_web::test]
+    async fn forwarded_http_is_redirected() {
+        let app = test::init_service(testing::builder_with(forced()).await.build()).await;
+
+        let res = test::call_service(&app, forwarded("http", "/users?limit=2").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            res.headers().get(header::LOCATION).unwrap(),
+            "https://example.com/users?limit=2"
+        );
+    }
+
+    #[actix_web::test]
+    async fn forwarded_https_passes_through() {
+        let app = test::init_service(testing::builder_with(forced()).await.build()).await;
+
+        let res = test::call_service(&app, forwarded("https", "/").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn health_checks_are_never_redirected() {
+        let app = test::init_service(testing::builder_with(forced()).await.build()).await;
+
+        for path in EXEMPT_PATHS {
+            let res = test::call_service(&app, forwarded("http", path).to_request()).await;
+            assert!(!res.status().is_redirection(), "{path}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn nothing_is_redirected_unless_forced() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, forwarded("http", "/").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,477 @@
+/*
+   IDEMPOTENCY KEYS
+    clients retrying an unsafe request (POST, PUT, PATCH, DELETE) send the same `Idempotency-Key`
+     header on every attempt. the first attempt is handled normally and its response is cached,
+     keyed by method + path + key. any repeat within the TTL gets the cached response back instead
+     of running the handler again.
+
+    reusing a key with a DIFFERENT body is a client bug, so it is answered with `409 Conflict`.
+
+    requests without the header, and safe methods (GET, HEAD, ...), are not touched.
+    5xx responses are not cached, so a retry after a server failure is processed again.
+
+    a key is RESERVED when its first request comes in, before the handler runs: a second request
+     with the same key arriving while the first is still being handled gets `409 Conflict`
+     ("still in progress") instead of running the handler a second time. the reservation is
+     dropped if the first one fails (5xx, an error, or the client going away), so it can be retried.
+
+    - the body is read up to `max_body_bytes` (see config.rs) to compare it, `413` past that
+    - a stored response never keeps its `Set-Cookie`: the session cookie set for the first
+       client must not be handed to whoever replays the key
+    - expired entries are swept every SWEEP_INTERVAL by a background task (see
+       AppBuilder::start_background_tasks()), not on every lookup; a lookup just ignores them
+
+    the store keeps at most MAX_ENTRIES responses; when it is full, expired entries are dropped
+     first and then the OLDEST one, so a flood of fresh keys can't grow it without bound.
+*/
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error,
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        Method, StatusCode,
+    },
+    middleware::Next,
+    rt,
+    web::{self, Bytes, BytesMut},
+    Error, HttpMessage, HttpResponse,
+};
+use futures_util::StreamExt;
+use tokio::time;
+
+use crate::config::Config;
+
+pub const IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+pub const IDEMPOTENT_REPLAY_HEADER: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+pub const MAX_ENTRIES: usize = 10_000;
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// method + path + key
+type CacheKey = (Method, String, String);
+
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+enum State {
+    // the first request is still being handled
+    Pending,
+    Done(CachedResponse),
+}
+
+struct Entry {
+    stored_at: Instant,
+    body_fingerprint: u64,
+    state: State,
+}
+
+enum Lookup {
+    // the key is now reserved for this request
+    Miss,
+    Hit(HttpResponse),
+    Conflict,
+    InProgress,
+}
+
+// shared between all workers through web::Data, so a retry can land on any worker
+pub struct IdempotencyStore {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<CacheKey, Entry>>,
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_live(&self, entry: &Entry) -> bool {
+        entry.stored_at.elapsed() < self.ttl
+    }
+
+    // a miss reserves the key, so a concurrent duplicate sees it in progress
+    fn lookup_or_reserve(&self, key: &CacheKey, body_fingerprint: u64) -> Lookup {
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get(key).filter(|entry| self.is_live(entry)) {
+            Some(entry) if entry.body_fingerprint != body_fingerprint => return Lookup::Conflict,
+            Some(Entry {
+                state: State::Pending,
+                ..
+            }) => return Lookup::InProgress,
+            Some(Entry {
+                state: State::Done(response),
+                ..
+            }) => return Lookup::Hit(response.replay()),
+            None => {}
+        }
+
+        if !entries.contains_key(key) && entries.len() >= self.max_entries {
+            entries.retain(|_, entry| self.is_live(entry));
+            if entries.len() >= self.max_entries {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.stored_at)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+
+        entries.insert(
+            key.clone(),
+            Entry {
+                stored_at: Instant::now(),
+                body_fingerprint,
+                state: State::Pending,
+            },
+        );
+        Lookup::Miss
+    }
+
+    fn complete(&self, key: CacheKey, body_fingerprint: u64, response: CachedResponse) {
+        self.entries.lock().unwrap().insert(
+            key,
+            Entry {
+                stored_at: Instant::now(),
+                body_fingerprint,
+                state: State::Done(response),
+            },
+        );
+    }
+
+    // drops a reservation that never got a response to keep
+    fn release(&self, key: &CacheKey) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries
+            .get(key)
+            .is_some_and(|entry| matches!(entry.state, State::Pending))
+        {
+            entries.remove(key);
+        }
+    }
+
+    fn sweep(&self) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|_, entry| entry.stored_at.elapsed() < self.ttl);
+    }
+}
+
+pub fn spawn_sweeper(store: web::Data<IdempotencyStore>) {
+    rt::spawn(async move {
+        let mut ticks = time::interval(SWEEP_INTERVAL);
+        loop {
+            ticks.tick().await;
+            store.sweep();
+        }
+    });
+}
+
+// releases the key when dropped before complete(): on an error, a 5xx, or a cancelled request
+struct Reservation<'a> {
+    store: &'a IdempotencyStore,
+    key: Option<CacheKey>,
+}
+
+impl Reservation<'_> {
+    fn complete(mut self, body_fingerprint: u64, response: CachedResponse) {
+        if let Some(key) = self.key.take() {
+            self.store.complete(key, body_fingerprint, response);
+        }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.store.release(&key);
+        }
+    }
+}
+
+impl CachedResponse {
+    fn replay(&self) -> HttpResponse {
+        let mut res = HttpResponse::build(self.status);
+        for (name, value) in &self.headers {
+            res.append_header((name.clone(), value.clone()));
+        }
+        res.insert_header((IDEMPOTENT_REPLAY_HEADER, HeaderValue::from_static("true")))
+            .body(self.body.clone())
+    }
+}
+
+fn is_unsafe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    )
+}
+
+fn fingerprint(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+// the whole body, None when it is larger than `max_bytes`
+async fn read_body(req: &mut ServiceRequest, max_bytes: usize) -> Result<Option<Bytes>, Error> {
+    let mut body = BytesMut::new();
+    let mut payload = req.take_payload();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > max_bytes {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Some(body.freeze()))
+}
+
+pub async fn idempotency(
+    store: web::Data<IdempotencyStore>,
+    config: web::Data<Config>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let key = req
+        .headers()
+        .get(&IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+
+    let key = match key {
+        Some(key) if is_unsafe(req.method()) => key,
+        _ => {
+            return next
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_boxed_body)
+        }
+    };
+
+    // the body has to be read to compare it with the first attempt...
+    let max_bytes = config.max_body_bytes;
+    let Some(body) = read_body(&mut req, max_bytes).await? else {
+        let res = HttpResponse::PayloadTooLarge()
+            .body(format!("request body is larger than {max_bytes} bytes"));
+        return Ok(req.into_response(res));
+    };
+    let body_fingerprint = fingerprint(&body);
+    let cache_key = (req.method().clone(), req.path().to_owned(), key);
+
+    match store.lookup_or_reserve(&cache_key, body_fingerprint) {
+        Lookup::Hit(res) => return Ok(req.into_response(res)),
+        Lookup::Conflict => {
+            return Ok(req.into_response(
+                HttpResponse::Conflict()
+                    .body("idempotency key was already used with a different request body"),
+            ))
+        }
+        Lookup::InProgress => {
+            return Ok(req.into_response(
+                HttpResponse::Conflict()
+                    .body("a request with this idempotency key is still in progress"),
+            ))
+        }
+        Lookup::Miss => {}
+    }
+    let reservation = Reservation {
+        store: &store,
+        key: Some(cache_key),
+    };
+
+    // ...and then put back so the handler can still extract it
+    req.set_payload(Payload::from(body));
+
+    let res = next.call(req).await?;
+    if res.status().is_server_error() {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    // buffer the response so it can be both cached and sent
+    let (req, res) = res.into_parts();
+    let (res, res_body) = res.into_parts();
+    let res_body = body::to_bytes(res_body)
+        .await
+        .map_err(|err| error::ErrorInternalServerError(err.into().to_string()))?;
+
+    let mut headers = res.headers().clone();
+    headers.remove(header::SET_COOKIE);
+    reservation.complete(
+        body_fingerprint,
+        CachedResponse {
+            status: res.status(),
+            headers,
+            body: res_body.clone(),
+        },
+    );
+
+    Ok(ServiceResponse::new(
+        req,
+        res.set_body(res_body).map_into_boxed_body(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{middleware, test, App};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::testing;
+
+    fn payment(key: &str, amount: u64) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/payments")
+            .insert_header((IDEMPOTENCY_KEY_HEADER, key))
+            .set_json(json!({ "amount": amount, "currency": "EUR" }))
+    }
+
+    #[actix_web::test]
+    async fn replays_the_first_response_for_the_same_key() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let first: Value =
+            test::call_and_read_body_json(&app, payment("k1", 100).to_request()).await;
+        let res = test::call_service(&app, payment("k1", 100).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get(IDEMPOTENT_REPLAY_HEADER).unwrap(), "true");
+        let replayed: Value = test::read_body_json(res).await;
+        assert_eq!(replayed["id"], first["id"]);
+    }
+
+    #[actix_web::test]
+    async fn refuses_the_same_key_with_another_body() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        test::call_service(&app, payment("k2", 100).to_request()).await;
+        let res = test::call_service(&app, payment("k2", 200).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn safe_methods_and_requests_without_a_key_are_not_stored() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for _ in 0..2 {
+            let req = test::TestRequest::post()
+                .uri("/echo")
+                .set_payload("hi")
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert!(!res.headers().contains_key(IDEMPOTENT_REPLAY_HEADER));
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_pending_key_is_in_progress_until_released() {
+        let store = IdempotencyStore::new(Duration::from_secs(60), 10);
+        let key = (Method::POST, "/payments".to_owned(), "k".to_owned());
+
+        assert!(matches!(store.lookup_or_reserve(&key, 1), Lookup::Miss));
+        let reservation = Reservation {
+            store: &store,
+            key: Some(key.clone()),
+        };
+        assert!(matches!(
+            store.lookup_or_reserve(&key, 1),
+            Lookup::InProgress
+        ));
+
+        drop(reservation);
+        assert!(matches!(store.lookup_or_reserve(&key, 1), Lookup::Miss));
+    }
+
+    #[actix_web::test]
+    async fn a_full_store_drops_the_oldest_key() {
+        let store = IdempotencyStore::new(Duration::from_secs(60), 2);
+        let key = |name: &str| (Method::POST, "/echo".to_owned(), name.to_owned());
+
+        for name in ["a", "b", "c"] {
+            store.lookup_or_reserve(&key(name), 1);
+        }
+
+        let entries = store.entries.lock().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(!entries.contains_key(&key("a")));
+    }
+
+    #[actix_web::test]
+    async fn reads_bodies_up_to_max_body_bytes() {
+        let config = Config {
+            max_body_bytes: 8,
+            ..Config::default()
+        };
+        let builder = testing::builder_with(config).await;
+        let app =
+            test::init_service(builder.bare_app().wrap(middleware::from_fn(idempotency))).await;
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .insert_header((IDEMPOTENCY_KEY_HEADER, "k"))
+            .set_payload("more than eight bytes")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn a_replay_never_carries_the_first_cookie() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(IdempotencyStore::new(
+                    Duration::from_secs(60),
+                    10,
+                )))
+                .app_data(web::Data::new(Config::default()))
+                .wrap(middleware::from_fn(idempotency))
+                .route(
+                    "/login",
+                    web::post().to(|| async {
+                        HttpResponse::Ok()
+                            .insert_header((header::SET_COOKIE, "id=secret"))
+                            .finish()
+                    }),
+                ),
+        )
+        .await;
+        let login = || {
+            test::TestRequest::post()
+                .uri("/login")
+                .insert_header((IDEMPOTENCY_KEY_HEADER, "k"))
+                .to_request()
+        };
+
+        let first = test::call_service(&app, login()).await;
+        let replay = test::call_service(&app, login()).await;
+
+        assert!(first.headers().contains_key(header::SET_COOKIE));
+        assert_eq!(
+            replay.headers().get(IDEMPOTENT_REPLAY_HEADER).unwrap(),
+            "true"
+        );
+        assert!(!replay.headers().contains_key(header::SET_COOKIE));
+    }
+}
@@ -0,0 +1,140 @@
+/*
+   IN-FLIGHT LIMIT PER WORKER
+    a worker takes any number of requests at once; under a flood they all wait on the same
+     database, the same upstreams, and every one of them gets slower. this middleware caps how
+     many requests a worker handles AT ONCE at `max_inflight` (env MAX_INFLIGHT, see config.rs):
+
+     - a request takes a permit of the worker's semaphore and gives it back when its response
+        is ready (a streamed body keeps going without it)
+     - when no permit is free it is NOT queued: it gets `503` with `Retry-After: 1` right away,
+        a client or load balancer can try again or go elsewhere, and the requests already in
+        flight finish at full speed
+
+    the semaphore is created in AppBuilder::bare_app(), which runs once per WORKER, so every
+     worker has its own (the total is about max_inflight times the workers).
+
+    `/healthz` and `/readyz` never take a permit: a busy instance is still alive, and an
+     orchestrator restarting it for being busy would only make things worse.
+*/
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use tokio::sync::Semaphore;
+
+const EXEMPT_PATHS: [&str; 2] = ["/healthz", "/readyz"];
+
+pub struct InflightLimit(Semaphore);
+
+impl InflightLimit {
+    pub fn new(max_inflight: usize) -> Self {
+        Self(Semaphore::new(max_inflight))
+    }
+}
+
+pub async fn limit_inflight(
+    limit: web::Data<InflightLimit>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if EXEMPT_PATHS.contains(&req.path()) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    }
+
+    let Ok(_permit) = limit.0.try_acquire() else {
+        let res = HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, "1"))
+            .body("too many requests in flight, try again");
+        return Ok(req.into_response(res));
+    };
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::{http::StatusCode, middleware, rt, test, App};
+    use futures_util::future::join_all;
+
+    use super::*;
+    use crate::{config::Config, testing};
+
+    async fn slow() -> HttpResponse {
+        rt::time::sleep(Duration::from_millis(200)).await;
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn the_request_over_the_limit_is_503_right_away() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(InflightLimit::new(2)))
+                .wrap(middleware::from_fn(limit_inflight))
+                .route("/slow", web::get().to(slow))
+                .route("/healthz", web::get().to(slow)),
+        )
+        .await;
+
+        let slow_requests = (0..3).map(|_| {
+            let req = test::TestRequest::get().uri("/slow").to_request();
+            test::call_service(&app, req)
+        });
+        let health =
+            test::call_service(&app, test::TestRequest::get().uri("/healthz").to_request());
+        let (responses, health) = futures_util::join!(join_all(slow_requests), health);
+
+        let mut statuses: Vec<_> = responses.iter().map(|res| res.status()).collect();
+        statuses.sort();
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::OK,
+                StatusCode::OK,
+                StatusCode::SERVICE_UNAVAILABLE
+            ]
+        );
+        let refused = responses
+            .iter()
+            .find(|res| res.status() == StatusCode::SERVICE_UNAVAILABLE)
+            .unwrap();
+        assert_eq!(refused.headers().get(header::RETRY_AFTER).unwrap(), "1");
+        assert_eq!(health.status(), StatusCode::OK);
+
+        // the permits came back
+        let req = test::TestRequest::get().uri("/slow").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn max_inflight_comes_from_the_config() {
+        let config = Config {
+            max_inflight: 1,
+            ..Config::default()
+        };
+        let app = test::init_service(testing::builder_with(config).await.build()).await;
+
+        let busy = test::call_service(
+            &app,
+            test::TestRequest::get().uri("/slow?secs=1").to_request(),
+        );
+        let other = async {
+            rt::time::sleep(Duration::from_millis(100)).await;
+            test::call_service(&app, test::TestRequest::get().uri("/users").to_request()).await
+        };
+        let (busy, other) = futures_util::join!(busy, other);
+
+        assert_eq!(busy.status(), StatusCode::OK);
+        assert_eq!(other.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,167 @@
+/*
+   STREAMING A REQUEST BODY
+    `POST /ingest` counts the lines of the body and answers `{ "lines": ..., "bytes": ... }`.
+     the body may be far bigger than memory, so it is never collected: web::Payload hands it
+     over chunk by chunk as it arrives, and each chunk is split into lines right away.
+
+    BACKPRESSURE comes for free: the payload only reads more from the socket when the handler
+     asks for the next chunk, so while a chunk is being processed nothing else is buffered, and
+     a fast client simply finds the connection's TCP window full until we catch up.
+
+    what is kept between chunks is only the unfinished last line. one line may be at most
+     MAX_LINE_LEN bytes, otherwise a body without any newline would pile up in memory after all.
+
+    every line must be valid UTF-8, else `400` naming the line. splitting on `\n` is safe for
+     this: that byte never appears inside a multi-byte UTF-8 character, so a character cut in
+     half by a chunk boundary always ends up whole in the carried-over line.
+
+    /ingest is exempt from `max_body_bytes` (see body_limit.rs): it never holds the body, so
+     there is nothing for that cap to protect.
+*/
+
+use actix_web::{error, post, web, HttpResponse};
+use futures_util::StreamExt;
+use serde_json::json;
+
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+#[derive(Default)]
+struct LineCounter {
+    lines: u64,
+    bytes: u64,
+    // the line the previous chunk ended in the middle of
+    partial: Vec<u8>,
+}
+
+impl LineCounter {
+    fn line(&mut self, line: &[u8]) -> Result<(), String> {
+        self.lines += 1;
+        std::str::from_utf8(line)
+            .map(|_| ())
+            .map_err(|_| format!("line {} is not valid UTF-8", self.lines))
+    }
+
+    fn feed(&mut self, chunk: &[u8]) -> Result<(), String> {
+        self.bytes += chunk.len() as u64;
+        let mut rest = chunk;
+
+        while let Some(newline) = rest.iter().position(|&byte| byte == b'\n') {
+            let (line, after) = rest.split_at(newline);
+            rest = &after[1..];
+
+            if self.partial.is_empty() {
+                self.line(line)?;
+            } else {
+                let mut whole = std::mem::take(&mut self.partial);
+                whole.extend_from_slice(line);
+                self.line(&whole)?;
+            }
+        }
+
+        if self.partial.len() + rest.len() > MAX_LINE_LEN {
+            return Err(format!(
+                "line {} is longer than {MAX_LINE_LEN} bytes",
+                self.lines + 1
+            ));
+        }
+        self.partial.extend_from_slice(rest);
+        Ok(())
+    }
+
+    // a last line without a trailing newline still counts
+    fn finish(mut self) -> Result<Self, String> {
+        if !self.partial.is_empty() {
+            let last = std::mem::take(&mut self.partial);
+            self.line(&last)?;
+        }
+        Ok(self)
+    }
+}
+
+#[post("/ingest")]
+pub async fn ingest(mut body: web::Payload) -> actix_web::Result<HttpResponse> {
+    let mut counter = LineCounter::default();
+
+    while let Some(chunk) = body.next().await {
+        counter.feed(&chunk?).map_err(error::ErrorBadRequest)?;
+    }
+    let counter = counter.finish().map_err(error::ErrorBadRequest)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "lines": counter.lines,
+        "bytes": counter.bytes,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, Error};
+    use futures_util::stream;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    const LINES: usize = 100_000;
+
+    #[actix_web::test]
+    async fn a_multi_megabyte_stream_is_counted_line_by_line() {
+        // 4MB, well over the default max_body_bytes of 1MB, in 64kB chunks
+        let line = format!("{}\n", "x".repeat(39));
+        let body = line.repeat(LINES).into_bytes();
+        let chunks: Vec<_> = body
+            .chunks(64 * 1024)
+            .map(|chunk| Ok::<_, Error>(web::Bytes::copy_from_slice(chunk)))
+            .collect();
+        let addr = testing::serve(&testing::builder().await);
+
+        let mut res = awc::Client::default()
+            .post(format!("http://{addr}/ingest"))
+            .send_stream(stream::iter(chunks))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let counted: Value = res.json().await.unwrap();
+        assert_eq!(counted["lines"], LINES);
+        assert_eq!(counted["bytes"], body.len());
+    }
+
+    #[actix_web::test]
+    async fn invalid_utf8_is_400_naming_the_line() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/ingest")
+            .set_payload(&b"one\ntwo\nth\xffree\nfour\n"[..])
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(test::read_body(res).await, "line 3 is not valid UTF-8");
+    }
+
+    #[actix_web::test]
+    async fn a_character_split_between_chunks_is_whole_again() {
+        let mut counter = LineCounter::default();
+        let text = "caf\u{e9}\nna\u{ef}ve".as_bytes();
+
+        counter.feed(&text[..4]).unwrap();
+        counter.feed(&text[4..9]).unwrap();
+        counter.feed(&text[9..]).unwrap();
+        let counter = counter.finish().unwrap();
+
+        assert_eq!(counter.lines, 2);
+        assert_eq!(counter.bytes, text.len() as u64);
+    }
+
+    #[actix_web::test]
+    async fn a_line_longer_than_the_cap_is_refused() {
+        let mut counter = LineCounter::default();
+
+        counter.feed(&vec![b'x'; MAX_LINE_LEN]).unwrap();
+        let err = counter.feed(b"x").unwrap_err();
+
+        assert_eq!(err, format!("line 1 is longer than {MAX_LINE_LEN} bytes"));
+    }
+}
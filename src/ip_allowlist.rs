@@ -0,0 +1,204 @@
+/*
+   IP ALLOWLIST GUARD
+    the admin scope is only routed for clients inside ADMIN_ALLOWLIST, a comma separated list of
+     CIDR ranges (IPv4 and IPv6 can be mixed, a bare address means just that one):
+
+        ADMIN_ALLOWLIST="10.0.0.0/8, 192.168.1.0/24, ::1, fd00::/8"
+
+    it is a GUARD, not a middleware: for anybody else the scope simply doesn't match, the
+     request falls through to the default `404`, and nothing tells them an admin area exists
+     (a middleware answering `403` would). the basic auth of the scope still applies inside.
+
+    - ADMIN_ALLOWLIST not set  -> no restriction, as before
+    - an unparsable entry      -> skipped with a warning; if none is left nobody gets in
+    - IPv4 clients on a dual stack socket show up as `::ffff:10.1.2.3`, they are compared as
+       the IPv4 address they are
+
+    the address checked is the PEER of the connection. behind a proxy that is the proxy, so
+     there the allowlist has to name the proxy (X-Forwarded-For is not trusted for this).
+
+    the ranges are parsed once at startup into the shared IpAllowlist; the guard itself is
+     stateless and reads it from the app data.
+*/
+
+use std::{env, net::IpAddr, str::FromStr};
+
+use actix_web::{
+    guard::{Guard, GuardContext},
+    web,
+};
+
+#[derive(Debug)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("`{addr}` is not an IP address"))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|&prefix| prefix <= max_prefix)
+                .ok_or_else(|| format!("`/{prefix}` is not a valid prefix length"))?,
+            None => max_prefix,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl Cidr {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+pub struct IpAllowlist {
+    // None: no allowlist configured, everybody passes
+    ranges: Option<Vec<Cidr>>,
+}
+
+impl IpAllowlist {
+    pub fn from_env() -> Self {
+        let Ok(list) = env::var("ADMIN_ALLOWLIST") else {
+            return Self { ranges: None };
+        };
+
+        let ranges = list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| match entry.parse::<Cidr>() {
+                Ok(cidr) => Some(cidr),
+                Err(err) => {
+                    log::warn!("ADMIN_ALLOWLIST: skipping `{entry}`: {err}");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        if ranges.is_empty() {
+            log::warn!("ADMIN_ALLOWLIST has no valid range, the admin area is unreachable");
+        }
+
+        Self {
+            ranges: Some(ranges),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn new(ranges: &[&str]) -> Self {
+        Self {
+            ranges: Some(ranges.iter().map(|range| range.parse().unwrap()).collect()),
+        }
+    }
+
+    fn allows(&self, ip: Option<IpAddr>) -> bool {
+        match (&self.ranges, ip) {
+            (None, _) => true,
+            (Some(ranges), Some(ip)) => ranges.iter().any(|range| range.contains(ip)),
+            // no peer address (eg: a unix socket), nothing to check against
+            (Some(_), None) => false,
+        }
+    }
+}
+
+pub struct IpAllowlistGuard;
+
+impl Guard for IpAllowlistGuard {
+    fn check(&self, ctx: &GuardContext<'_>) -> bool {
+        let Some(allowlist) = ctx.app_data::<web::Data<IpAllowlist>>() else {
+            return false;
+        };
+        allowlist.allows(ctx.head().peer_addr.map(|addr| addr.ip()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    use super::*;
+    use crate::{auth::AdminCredentials, testing};
+
+    async fn dashboard_status(peer: &str) -> StatusCode {
+        let builder = testing::builder()
+            .await
+            .with_admin_credentials(AdminCredentials::new("admin", "s3cret"))
+            .with_admin_allowlist(IpAllowlist::new(&["10.0.0.0/8", "fd00::/8"]));
+        let app = test::init_service(builder.build()).await;
+
+        let credentials = STANDARD.encode("admin:s3cret");
+        let req = test::TestRequest::get()
+            .uri("/admin/dashboard")
+            .peer_addr(peer.parse().unwrap())
+            .insert_header(("Authorization", format!("Basic {credentials}")))
+            .to_request();
+        test::call_service(&app, req).await.status()
+    }
+
+    #[actix_web::test]
+    async fn a_peer_in_range_reaches_the_admin_area() {
+        for peer in ["10.1.2.3:5000", "[fd00::1]:5000", "[::ffff:10.1.2.3]:5000"] {
+            assert_eq!(dashboard_status(peer).await, StatusCode::OK, "{peer}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_peer_out_of_range_gets_a_plain_404() {
+        for peer in [
+            "192.168.1.7:5000",
+            "[2001:db8::1]:5000",
+            "[::ffff:192.168.1.7]:5000",
+        ] {
+            assert_eq!(
+                dashboard_status(peer).await,
+                StatusCode::NOT_FOUND,
+                "{peer}"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn ranges_are_parsed_with_their_prefix() {
+        let cidr: Cidr = "192.168.1.0/24".parse().unwrap();
+        assert!(cidr.contains("192.168.1.255".parse().unwrap()));
+        assert!(!cidr.contains("192.168.2.0".parse().unwrap()));
+
+        let single: Cidr = "::1".parse().unwrap();
+        assert!(single.contains("::1".parse().unwrap()));
+        assert!(!single.contains("::2".parse().unwrap()));
+
+        let everything: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains("203.0.113.9".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com/8".parse::<Cidr>().is_err());
+    }
+}
@@ -0,0 +1,180 @@
+/*
+   PAGES WITH LINKS (HATEOAS)
+    `GET /items?page=<n>&per_page=<n>` pages through a (simulated) catalog with OFFSET pagination
+     and tells the client where to go next instead of leaving it to build urls itself:
+
+        {
+          "data":  [ { "id": 11, "name": "item 11" }, ... ],
+          "links": { "self": "http://host/items?page=2&per_page=10",
+                     "next": "http://host/items?page=3&per_page=10",
+                     "prev": "http://host/items?page=1&per_page=10" },
+          "meta":  { "total": 47, "page": 2, "per_page": 10, "total_pages": 5 }
+        }
+
+    - `prev` is null on the first page, `next` is null on the last one (and past it, where `prev`
+       points at the last page)
+    - the links come from url_for() with the ROUTE NAME `items`, so they carry the scheme and
+       host the client used (see trust_proxy in config.rs) and follow the route if it moves
+    - page starts at 1 (default 1), per_page is clamped to 1..=MAX_PER_PAGE (default 10); the
+       links always carry the values actually used
+
+    unlike `/users` (keyset, see users.rs) the catalog never changes, so page numbers are stable
+     and a client can jump straight to any page.
+*/
+
+use actix_web::{error, get, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const ITEM_COUNT: u32 = 47;
+const DEFAULT_PER_PAGE: u32 = 10;
+const MAX_PER_PAGE: u32 = 100;
+
+#[derive(Serialize)]
+pub struct Item {
+    id: u32,
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct ItemsQuery {
+    page: Option<u32>,
+    per_page: Option<u32>,
+}
+
+// absolute link to `page`, same page size
+fn page_link(req: &HttpRequest, page: u32, per_page: u32) -> actix_web::Result<String> {
+    let mut url = req
+        .url_for_static("items")
+        .map_err(error::ErrorInternalServerError)?;
+    url.query_pairs_mut()
+        .append_pair("page", &page.to_string())
+        .append_pair("per_page", &per_page.to_string());
+    Ok(url.into())
+}
+
+#[get("/items", name = "items")]
+pub async fn list_items(
+    req: HttpRequest,
+    query: web::Query<ItemsQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let total_pages = ITEM_COUNT.div_ceil(per_page);
+
+    let first = (page - 1).saturating_mul(per_page).saturating_add(1);
+    let last = page.saturating_mul(per_page).min(ITEM_COUNT);
+    let items: Vec<Item> = (first..=last)
+        .map(|id| Item {
+            id,
+            name: format!("item {id}"),
+        })
+        .collect();
+
+    // past the end, `prev` leads back to the last page that has items
+    let prev = match page > 1 {
+        true => Some(page_link(&req, (page - 1).min(total_pages), per_page)?),
+        false => None,
+    };
+    let next = match page < total_pages {
+        true => Some(page_link(&req, page + 1, per_page)?),
+        false => None,
+    };
+
+    Ok(HttpResponse::Ok().json(json!({
+        "data": items,
+        "links": {
+            "self": page_link(&req, page, per_page)?,
+            "next": next,
+            "prev": prev,
+        },
+        "meta": {
+            "total": ITEM_COUNT,
+            "page": page,
+            "per_page": per_page,
+            "total_pages": total_pages,
+        },
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::header, test};
+    use serde_json::Value;
+
+    use crate::testing;
+
+    async fn page(uri: &str) -> Value {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header((header::HOST, "shop.example.com"))
+            .to_request();
+        test::call_and_read_body_json(&app, req).await
+    }
+
+    fn link(page: u32, per_page: u32) -> String {
+        format!("http://shop.example.com/items?page={page}&per_page={per_page}")
+    }
+
+    #[actix_web::test]
+    async fn the_first_page_has_no_prev() {
+        let body = page("/items").await;
+
+        assert_eq!(body["links"]["self"], link(1, 10));
+        assert_eq!(body["links"]["next"], link(2, 10));
+        assert!(body["links"]["prev"].is_null());
+        assert_eq!(body["data"][0]["id"], 1);
+        assert_eq!(body["data"].as_array().unwrap().len(), 10);
+        assert_eq!(body["meta"]["total"], 47);
+        assert_eq!(body["meta"]["total_pages"], 5);
+    }
+
+    #[actix_web::test]
+    async fn a_middle_page_links_both_ways() {
+        let body = page("/items?page=3&per_page=10").await;
+
+        assert_eq!(body["links"]["self"], link(3, 10));
+        assert_eq!(body["links"]["next"], link(4, 10));
+        assert_eq!(body["links"]["prev"], link(2, 10));
+        assert_eq!(body["data"][0]["id"], 21);
+        assert_eq!(body["data"][0]["name"], "item 21");
+    }
+
+    #[actix_web::test]
+    async fn the_last_page_has_no_next() {
+        let body = page("/items?page=5").await;
+
+        assert!(body["links"]["next"].is_null());
+        assert_eq!(body["links"]["prev"], link(4, 10));
+        let ids: Vec<_> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["id"].as_u64().unwrap())
+            .collect();
+        assert_eq!(ids, [41, 42, 43, 44, 45, 46, 47]);
+    }
+
+    #[actix_web::test]
+    async fn past_the_end_prev_leads_back_to_the_last_page() {
+        let body = page("/items?page=9").await;
+
+        assert!(body["data"].as_array().unwrap().is_empty());
+        assert!(body["links"]["next"].is_null());
+        assert_eq!(body["links"]["prev"], link(5, 10));
+    }
+
+    #[actix_web::test]
+    async fn the_links_carry_the_page_size_actually_used() {
+        let body = page("/items?page=0&per_page=1000").await;
+
+        assert_eq!(body["links"]["self"], link(1, 100));
+        assert!(body["links"]["next"].is_null());
+        assert_eq!(body["meta"]["per_page"], 100);
+        assert_eq!(body["meta"]["total_pages"], 1);
+    }
+}
@@ -0,0 +1,720 @@
+/*
+   LONG-RUNNING JOBS: POLLING + WEBHOOKS
+    an export can take a while, much longer than a client wants to keep a request open. so:
+     1, `POST /jobs/export` only STARTS the export (in a background task) and answers right away
+         with `202 Accepted` and the job id
+     2, `GET /jobs/{id}` tells the client how the job is doing (polling)
+     3, if the client passed a `callback_url`, the server also POSTs the final job to it when the
+         job finishes (a webhook), so the client doesn't have to poll at all
+
+    webhook delivery can fail (the receiver is down, a network blip, ...), so it is retried a
+     bounded number of times with a growing delay. the delivery outcome is part of the job, so a
+     client polling the job can see whether its webhook got through.
+
+    the server makes that POST, so a `callback_url` pointing INTO its own network (127.0.0.1, the
+     cloud metadata address 169.254.169.254, 10.0.0.0/8, ...) would let any client reach what only
+     the server can (SSRF). the host is resolved when the export is started and EVERY address it
+     resolves to must be public, or it is `400`. the webhook is then sent to the address checked,
+     not resolved again (a name can't be switched to 127.0.0.1 in between), and redirects are not
+     followed.
+
+    a finished job (`done` or `failed`) can be polled for JOB_TTL, then it is swept (every
+     SWEEP_INTERVAL, see AppBuilder::start_background_tasks()) and its id is `404`.
+
+    at most MAX_EXPORTS exports run at once: a burst of `POST /jobs/export` past that is `503` with
+     `Retry-After`, rather than starting a background task for every request.
+
+    the job store lives in web::Data, shared by all workers: the export may be started on one
+     worker and polled on another.
+
+    A QUEUE OF JOBS
+     an export starts the moment it is asked for. `POST /jobs` instead only QUEUES a task and
+      answers `202` with its id; ONE background worker (spawned at startup, see
+      AppBuilder::start_background_tasks()) takes the tasks in order and runs them one at a time,
+      so a burst of submissions can't start a burst of work. the job goes
+      `queued` -> `running` -> `done` (with its result) or `failed`, and is polled with the same
+      `GET /jobs/{id}` as an export (`404` for an unknown id).
+
+         POST /jobs  { "duration_secs": 2, "fail": false }   (both optional)
+
+     the queue sits in the job store next to the jobs, behind its own lock; a Notify wakes the
+      worker when something is queued. at most MAX_QUEUED tasks wait, after that `503`.
+*/
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    get,
+    http::{
+        header::{self, CacheControl, CacheDirective},
+        Uri,
+    },
+    post,
+    rt::{self, time::sleep},
+    web, HttpResponse, Responder,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{
+    net,
+    sync::{Notify, Semaphore},
+    time,
+};
+use uuid::Uuid;
+
+// how long the simulated export takes
+const EXPORT_DURATION: Duration = Duration::from_secs(3);
+// exports running at once, see start_export()
+const MAX_EXPORTS: usize = 16;
+
+const MAX_WEBHOOK_ATTEMPTS: u32 = 3;
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(1);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const JOB_TTL: Duration = Duration::from_secs(60 * 60);
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+const MAX_QUEUED: usize = 1000;
+const DEFAULT_TASK_SECS: u64 = 2;
+const MAX_TASK_SECS: u64 = 60;
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done { result: serde_json::Value },
+    Failed { error: String },
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookState {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Webhook {
+    url: String,
+    attempts: u32,
+    state: WebhookState,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Job {
+    id: Uuid,
+    #[serde(flatten)]
+    status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook: Option<Webhook>,
+    #[serde(skip)]
+    finished_at: Option<Instant>,
+}
+
+// what a queued job does once the worker gets to it
+pub struct Task {
+    duration: Duration,
+    fail: bool,
+}
+
+pub struct JobStore {
+    jobs: Mutex<HashMap<Uuid, Job>>,
+    queue: Mutex<VecDeque<(Uuid, Task)>>,
+    queued: Notify,
+    // one permit per running export
+    exports: Arc<Semaphore>,
+}
+
+impl Default for JobStore {
+    fn default() -> Self {
+        Self {
+            jobs: Mutex::default(),
+            queue: Mutex::default(),
+            queued: Notify::new(),
+            exports: Arc::new(Semaphore::new(MAX_EXPORTS)),
+        }
+    }
+}
+
+impl JobStore {
+    fn insert(&self, job: Job) {
+        self.jobs.lock().unwrap().insert(job.id, job);
+    }
+
+    fn get(&self, id: &Uuid) -> Option<Job> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    fn update(&self, id: &Uuid, change: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            change(job);
+        }
+    }
+
+    // `done` or `failed`: from now on the job is only kept for JOB_TTL
+    fn finish(&self, id: &Uuid, status: JobStatus) {
+        self.update(id, |job| {
+            job.status = status;
+            job.finished_at = Some(Instant::now());
+        });
+    }
+
+    fn sweep(&self) {
+        self.jobs.lock().unwrap().retain(|_, job| {
+            job.finished_at
+                .is_none_or(|finished_at| finished_at.elapsed() < JOB_TTL)
+        });
+    }
+
+    // None when the queue is full
+    fn enqueue(&self, task: Task) -> Option<Uuid> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.len() >= MAX_QUEUED {
+            return None;
+        }
+        let id = Uuid::new_v4();
+        self.insert(Job {
+            id,
+            status: JobStatus::Queued,
+            webhook: None,
+            finished_at: None,
+        });
+        queue.push_back((id, task));
+        self.queued.notify_one();
+        Some(id)
+    }
+
+    async fn next_task(&self) -> (Uuid, Task) {
+        loop {
+            if let Some(next) = self.queue.lock().unwrap().pop_front() {
+                return next;
+            }
+            // notify_one() keeps a permit when nobody waits yet, so a task queued between the
+            // check above and this await still wakes us
+            self.queued.notified().await;
+        }
+    }
+}
+
+// stands in for real work: takes `duration`, then succeeds or fails as asked
+async fn run_task(task: Task) -> Result<serde_json::Value, String> {
+    sleep(task.duration).await;
+    if task.fail {
+        return Err("the task failed (as requested)".to_owned());
+    }
+    Ok(json!({ "waited_secs": task.duration.as_secs() }))
+}
+
+// the single queue worker; call once at startup
+pub fn spawn_worker(store: web::Data<JobStore>) {
+    rt::spawn(async move {
+        loop {
+            let (id, task) = store.next_task().await;
+            store.update(&id, |job| job.status = JobStatus::Running);
+
+            let status = match run_task(task).await {
+                Ok(result) => JobStatus::Done { result },
+                Err(error) => JobStatus::Failed { error },
+            };
+            store.finish(&id, status);
+        }
+    });
+}
+
+pub fn spawn_sweeper(store: web::Data<JobStore>) {
+    rt::spawn(async move {
+        let mut ticks = time::interval(SWEEP_INTERVAL);
+        loop {
+            ticks.tick().await;
+            store.sweep();
+        }
+    });
+}
+
+#[derive(Deserialize)]
+pub struct ExportRequest {
+    callback_url: Option<String>,
+}
+
+// stands in for the real export: it just takes a while and produces a summary
+async fn export() -> Result<serde_json::Value, String> {
+    sleep(EXPORT_DURATION).await;
+    Ok(json!({ "rows": 1000, "format": "csv" }))
+}
+
+// where a webhook goes: the URL as given, and the address it was checked to resolve to
+struct Callback {
+    url: Uri,
+    addr: SocketAddr,
+}
+
+async fn deliver_webhook(store: &JobStore, id: Uuid, callback: &Callback) {
+    // the receiver gets the finished job, its own delivery bookkeeping is left out
+    let Some(job) = store.get(&id).map(|job| Job {
+        webhook: None,
+        ..job
+    }) else {
+        return;
+    };
+    let client = awc::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .disable_redirects()
+        .finish();
+    let url = &callback.url;
+    // sent to the checked address, with the URL's own host in `Host`
+    let pinned = format!(
+        "http://{}{}",
+        callback.addr,
+        url.path_and_query().map_or("/", |path| path.as_str())
+    );
+    let host = url.authority().map_or("", |authority| authority.as_str());
+
+    for attempt in 1..=MAX_WEBHOOK_ATTEMPTS {
+        let request = client.post(&pinned).insert_header((header::HOST, host));
+        let delivered = match request.send_json(&job).await {
+            Ok(res) => res.status().is_success(),
+            Err(err) => {
+                log::warn!("webhook for job {id} to {url} failed: {err}");
+                false
+            }
+        };
+
+        store.update(&id, |job| {
+            if let Some(webhook) = &mut job.webhook {
+                webhook.attempts = attempt;
+                webhook.state = match delivered {
+                    true => WebhookState::Delivered,
+                    false if attempt == MAX_WEBHOOK_ATTEMPTS => WebhookState::Failed,
+                    false => WebhookState::Pending,
+                };
+            }
+        });
+
+        if delivered {
+            return;
+        }
+        if attempt < MAX_WEBHOOK_ATTEMPTS {
+            sleep(WEBHOOK_RETRY_DELAY * 2u32.pow(attempt - 1)).await; // 1s, 2s, ...
+        }
+    }
+
+    log::warn!("giving up on webhook for job {id} after {MAX_WEBHOOK_ATTEMPTS} attempts");
+}
+
+async fn run_export(store: web::Data<JobStore>, id: Uuid, callback: Option<Callback>) {
+    let status = match export().await {
+        Ok(result) => JobStatus::Done { result },
+        Err(error) => JobStatus::Failed { error },
+    };
+    store.finish(&id, status);
+
+    if let Some(callback) = callback {
+        deliver_webhook(&store, id, &callback).await;
+    }
+}
+
+// an address anyone on the internet could reach, so not one of the server's own networks
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || first == 0
+                || (first == 100 && second & 0xc0 == 64)) // 100.64.0.0/10, carrier-grade NAT
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+// the callback to use, or why `url` can't be one
+async fn check_callback(url: &str) -> Result<Callback, String> {
+    // awc is built without TLS here, so only plain http callbacks can be delivered
+    let url = url
+        .parse::<Uri>()
+        .ok()
+        .filter(|uri| uri.scheme_str() == Some("http"))
+        .ok_or("callback_url must be an absolute http:// URL")?;
+    let host = url
+        .host()
+        .ok_or("callback_url must be an absolute http:// URL")?;
+    // an IPv6 host keeps its brackets in the URI
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_u16().unwrap_or(80);
+
+    let addrs: Vec<SocketAddr> = net::lookup_host((host, port))
+        .await
+        .map_err(|_| format!("callback_url host {host:?} doesn't resolve"))?
+        .collect();
+    match addrs.first() {
+        Some(_) if addrs.iter().any(|addr| !is_public(addr.ip())) => Err(format!(
+            "callback_url host {host:?} is not a public address"
+        )),
+        Some(&addr) => Ok(Callback { url, addr }),
+        None => Err(format!("callback_url host {host:?} doesn't resolve")),
+    }
+}
+
+#[post("/jobs/export")]
+pub async fn start_export(
+    store: web::Data<JobStore>,
+    body: web::Json<ExportRequest>,
+) -> impl Responder {
+    // held by the export until it (and its webhook) is done
+    let Ok(permit) = store.exports.clone().try_acquire_owned() else {
+        return HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, "10"))
+            .body("too many exports are running, try again later");
+    };
+
+    let callback = match &body.callback_url {
+        Some(url) => match check_callback(url).await {
+            Ok(callback) => Some(callback),
+            Err(reason) => return HttpResponse::BadRequest().body(reason),
+        },
+        None => None,
+    };
+
+    let id = Uuid::new_v4();
+    store.insert(Job {
+        id,
+        status: JobStatus::Running,
+        webhook: body.into_inner().callback_url.map(|url| Webhook {
+            url,
+            attempts: 0,
+            state: WebhookState::Pending,
+        }),
+        finished_at: None,
+    });
+
+    // the export runs in the background, the response doesn't wait for it
+    rt::spawn(async move {
+        run_export(store, id, callback).await;
+        drop(permit);
+    });
+
+    let status_url = format!("/jobs/{id}");
+    HttpResponse::Accepted()
+        .insert_header((header::LOCATION, status_url.clone()))
+        .json(json!({ "id": id, "status_url": status_url }))
+}
+
+#[derive(Deserialize)]
+pub struct NewJob {
+    duration_secs: Option<u64>,
+    fail: Option<bool>,
+}
+
+#[post("/jobs")]
+pub async fn submit_job(store: web::Data<JobStore>, body: web::Json<NewJob>) -> impl Responder {
+    let duration_secs = body.duration_secs.unwrap_or(DEFAULT_TASK_SECS);
+    if duration_secs > MAX_TASK_SECS {
+        return HttpResponse::BadRequest()
+            .body(format!("duration_secs can be at most {MAX_TASK_SECS}"));
+    }
+    let task = Task {
+        duration: Duration::from_secs(duration_secs),
+        fail: body.fail.unwrap_or(false),
+    };
+
+    let Some(id) = store.enqueue(task) else {
+        return HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, "10"))
+            .body("the job queue is full, try again later");
+    };
+
+    let status_url = format!("/jobs/{id}");
+    HttpResponse::Accepted()
+        .insert_header((header::LOCATION, status_url.clone()))
+        .json(json!({ "id": id, "status": "queued", "status_url": status_url }))
+}
+
+#[get("/jobs/{id}")]
+pub async fn job_status(store: web::Data<JobStore>, id: web::Path<Uuid>) -> impl Responder {
+    match store.get(&id) {
+        // polled for changes, so never served from the response cache
+        Some(job) => HttpResponse::Ok()
+            .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+            .json(job),
+        None => HttpResponse::NotFound().body("no such job"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        body::MessageBody,
+        dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+        http::StatusCode,
+        test, App, Error,
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    fn export(body: Value) -> test::TestRequest {
+        test::TestRequest::post().uri("/jobs/export").set_json(body)
+    }
+
+    #[actix_web::test]
+    async fn an_export_is_accepted_and_polled() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, export(json!({})).to_request()).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let location = res.headers().get(header::LOCATION).unwrap().to_owned();
+        let started: Value = test::read_body_json(res).await;
+        assert_eq!(started["status_url"], location.to_str().unwrap());
+
+        let req = test::TestRequest::get()
+            .uri(location.to_str().unwrap())
+            .to_request();
+        let job: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(job["id"], started["id"]);
+        assert_eq!(job["status"], "running");
+    }
+
+    #[actix_web::test]
+    async fn an_unknown_job_is_404() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/jobs/{}", Uuid::new_v4()))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn exports_past_the_cap_are_503_until_one_finishes() {
+        let store = web::Data::new(JobStore::default());
+        let app =
+            test::init_service(App::new().app_data(store.clone()).service(start_export)).await;
+        // every permit taken, as if MAX_EXPORTS exports were running
+        let running = store
+            .exports
+            .clone()
+            .try_acquire_many_owned(MAX_EXPORTS as u32)
+            .unwrap();
+
+        let res = test::call_service(&app, export(json!({})).to_request()).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "10");
+        assert!(store.jobs.lock().unwrap().is_empty());
+
+        drop(running);
+        let res = test::call_service(&app, export(json!({})).to_request()).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+    }
+
+    #[actix_web::test]
+    async fn callbacks_into_the_servers_own_network_are_400() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.1/",
+            "http://[::1]/",
+            "http://[::ffff:192.168.0.1]/",
+            "https://example.com/hook",
+            "not a url",
+        ] {
+            let res =
+                test::call_service(&app, export(json!({ "callback_url": url })).to_request()).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{url}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn only_public_addresses_are_public() {
+        for ip in ["8.8.8.8", "1.1.1.1", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fc00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn finished_jobs_are_swept_after_the_ttl() {
+        let store = JobStore::default();
+        let job = |finished_at| Job {
+            id: Uuid::new_v4(),
+            status: JobStatus::Running,
+            webhook: None,
+            finished_at,
+        };
+        let (running, fresh, old) = (job(None), job(Some(Instant::now())), job(None));
+        let (running_id, fresh_id, old_id) = (running.id, fresh.id, old.id);
+        store.insert(running);
+        store.insert(fresh);
+        store.insert(old);
+        // long enough ago only if the clock has been running for JOB_TTL already
+        let Some(long_ago) = Instant::now().checked_sub(JOB_TTL) else {
+            return;
+        };
+        store.update(&old_id, |job| job.finished_at = Some(long_ago));
+
+        store.sweep();
+
+        assert!(store.get(&running_id).is_some());
+        assert!(store.get(&fresh_id).is_some());
+        assert!(store.get(&old_id).is_none());
+    }
+
+    // the queue and its worker, without the rest of the app
+    fn queue_app() -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = Error,
+            InitError = (),
+        >,
+    > {
+        let store = web::Data::new(JobStore::default());
+        spawn_worker(store.clone());
+        App::new()
+            .app_data(store)
+            .service(submit_job)
+            .service(job_status)
+    }
+
+    fn submit(body: Value) -> test::TestRequest {
+        test::TestRequest::post().uri("/jobs").set_json(body)
+    }
+
+    #[actix_web::test]
+    async fn a_submitted_job_is_polled_until_done() {
+        let app = test::init_service(queue_app()).await;
+
+        let res =
+            test::call_service(&app, submit(json!({ "duration_secs": 1 })).to_request()).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let submitted: Value = test::read_body_json(res).await;
+        assert_eq!(submitted["status"], "queued");
+        let status_url = submitted["status_url"].as_str().unwrap().to_owned();
+
+        let mut seen = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let job = loop {
+            let req = test::TestRequest::get().uri(&status_url).to_request();
+            let job: Value = test::call_and_read_body_json(&app, req).await;
+            let status = job["status"].as_str().unwrap().to_owned();
+            if seen.last() != Some(&status) {
+                seen.push(status.clone());
+            }
+            if status == "done" {
+                break job;
+            }
+            assert!(Instant::now() < deadline, "still not done: {seen:?}");
+            sleep(Duration::from_millis(50)).await;
+        };
+
+        assert_eq!(seen, ["queued", "running", "done"][3 - seen.len()..]);
+        assert!(seen.contains(&"running".to_owned()), "{seen:?}");
+        assert_eq!(job["id"], submitted["id"]);
+        assert_eq!(job["result"], json!({ "waited_secs": 1 }));
+    }
+
+    #[actix_web::test]
+    async fn a_failing_job_ends_failed() {
+        let app = test::init_service(queue_app()).await;
+
+        let req = submit(json!({ "duration_secs": 0, "fail": true })).to_request();
+        let submitted: Value = test::call_and_read_body_json(&app, req).await;
+        sleep(Duration::from_millis(50)).await;
+
+        let req = test::TestRequest::get()
+            .uri(submitted["status_url"].as_str().unwrap())
+            .to_request();
+        let job: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(job["status"], "failed");
+        assert_eq!(job["error"], "the task failed (as requested)");
+    }
+
+    #[actix_web::test]
+    async fn the_worker_runs_one_job_at_a_time_in_order() {
+        let app = test::init_service(queue_app()).await;
+
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let req = submit(json!({ "duration_secs": 1 })).to_request();
+            let submitted: Value = test::call_and_read_body_json(&app, req).await;
+            ids.push(submitted["id"].as_str().unwrap().to_owned());
+        }
+        sleep(Duration::from_millis(100)).await;
+
+        let mut statuses = Vec::new();
+        for id in &ids {
+            let req = test::TestRequest::get()
+                .uri(&format!("/jobs/{id}"))
+                .to_request();
+            let job: Value = test::call_and_read_body_json(&app, req).await;
+            statuses.push(job["status"].clone());
+        }
+        assert_eq!(statuses, ["running", "queued"]);
+    }
+
+    #[actix_web::test]
+    async fn a_task_longer_than_the_limit_is_400() {
+        let app = test::init_service(queue_app()).await;
+
+        let req = submit(json!({ "duration_secs": MAX_TASK_SECS + 1 })).to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn a_full_queue_takes_no_more() {
+        let store = JobStore::default();
+        let task = || Task {
+            duration: Duration::ZERO,
+            fail: false,
+        };
+
+        for _ in 0..MAX_QUEUED {
+            assert!(store.enqueue(task()).is_some());
+        }
+
+        assert!(store.enqueue(task()).is_none());
+    }
+}
@@ -0,0 +1,323 @@
+/*
+   READING A JSON ARRAY AS IT ARRIVES
+    web::Json reads the WHOLE body before parsing it, so a client posting a million-element
+     array to an endpoint that takes a thousand makes us hold all of it just to say no.
+     read_array() instead takes the body chunk by chunk (like ingest.rs) and cuts the array into
+     its elements as they complete, each parsed on its own:
+
+        [ {"name":"a"} , {"name":"b"} , ... ]
+          ^-- item 0 --^ ^-- item 1 --^
+
+     the element that goes past `max_items` ends the reading right there with `413`: the rest
+     of the body is never read, whatever its size. what is held at any time is the items so far
+     plus the one element in progress, itself at most MAX_ITEM_BYTES.
+
+    cutting only needs to know where an element ends: a `,` or the closing `]` at the top level
+     of the array, that is outside any string and any nested array or object. the element
+     itself is then checked by serde_json, which rejects anything malformed (`400`). so do
+     a body that isn't an array, empty elements (`[1,,2]`, `[1,]`) and anything but whitespace
+     after the closing `]`.
+*/
+
+use actix_web::{error, mime, web, Error, HttpMessage, HttpRequest};
+use futures_util::StreamExt;
+use serde_json::Value;
+
+const MAX_ITEM_BYTES: usize = 64 * 1024;
+
+// where in the body we are, relative to the array
+enum Position {
+    Before,
+    Inside,
+    After,
+}
+
+struct ArraySplitter {
+    max_items: usize,
+    position: Position,
+    items: Vec<Value>,
+    // the element in progress, and how deep inside it (nested arrays/objects) we are
+    current: Vec<u8>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl ArraySplitter {
+    fn new(max_items: usize) -> Self {
+        Self {
+            max_items,
+            position: Position::Before,
+            items: Vec::new(),
+            current: Vec::new(),
+            depth: 0,
+            in_string: false,
+            escaped: false,
+        }
+    }
+
+    // `last`: whether the element ended with the closing `]` (which allows `[]`)
+    fn end_item(&mut self, last: bool) -> Result<(), Error> {
+        let element = std::mem::take(&mut self.current);
+        if element.iter().all(u8::is_ascii_whitespace) {
+            if last && self.items.is_empty() {
+                return Ok(());
+            }
+            return Err(error::ErrorBadRequest(format!(
+                "item {} of the array is empty",
+                self.items.len()
+            )));
+        }
+
+        let item = serde_json::from_slice(&element).map_err(|err| {
+            error::ErrorBadRequest(format!(
+                "item {} is not valid JSON: {err}",
+                self.items.len()
+            ))
+        })?;
+        if self.items.len() == self.max_items {
+            return Err(error::ErrorPayloadTooLarge(format!(
+                "at most {} items per request",
+                self.max_items
+            )));
+        }
+        self.items.push(item);
+        Ok(())
+    }
+
+    fn feed(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        for &byte in chunk {
+            match self.position {
+                Position::Before if byte.is_ascii_whitespace() => continue,
+                Position::Before if byte == b'[' => {
+                    self.position = Position::Inside;
+                    continue;
+                }
+                Position::After if byte.is_ascii_whitespace() => continue,
+                Position::Before | Position::After => {
+                    return Err(error::ErrorBadRequest("the body must be one JSON array"))
+                }
+                Position::Inside => {}
+            }
+
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+            } else {
+                match byte {
+                    b',' | b']' if self.depth == 0 => {
+                        self.end_item(byte == b']')?;
+                        if byte == b']' {
+                            self.position = Position::After;
+                        }
+                        continue;
+                    }
+                    b'"' => self.in_string = true,
+                    b'[' | b'{' => self.depth += 1,
+                    b']' | b'}' => self.depth = self.depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+
+            if self.current.len() == MAX_ITEM_BYTES {
+                return Err(error::ErrorPayloadTooLarge(format!(
+                    "item {} is larger than {MAX_ITEM_BYTES} bytes",
+                    self.items.len()
+                )));
+            }
+            self.current.push(byte);
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<Value>, Error> {
+        match self.position {
+            Position::After => Ok(self.items),
+            _ => Err(error::ErrorBadRequest(
+                "the body ended before the JSON array did",
+            )),
+        }
+    }
+}
+
+fn is_json(req: &HttpRequest) -> bool {
+    req.mime_type()
+        .ok()
+        .flatten()
+        .is_some_and(|mime| mime.subtype() == mime::JSON || mime.suffix() == Some(mime::JSON))
+}
+
+// the elements of the JSON array in the body, `413` as soon as there are more than `max_items`
+pub async fn read_array(
+    req: &HttpRequest,
+    mut body: web::Payload,
+    max_items: usize,
+) -> Result<Vec<Value>, Error> {
+    if !is_json(req) {
+        return Err(error::ErrorUnsupportedMediaType(
+            "expected a JSON body (Content-Type: application/json)",
+        ));
+    }
+
+    let mut splitter = ArraySplitter::new(max_items);
+    while let Some(chunk) = body.next().await {
+        splitter.feed(&chunk?)?;
+    }
+    splitter.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use actix_web::{
+        body::MessageBody,
+        dev::{Payload, ServiceRequest, ServiceResponse},
+        error::PayloadError,
+        http::{header, StatusCode},
+        middleware::{self, Next},
+        test,
+        web::Bytes,
+        App, HttpResponse,
+    };
+    use futures_util::stream;
+    use serde_json::json;
+
+    use super::*;
+    use crate::users;
+
+    const ELEMENTS: usize = 1_000_000;
+
+    // replaces the body with `[` and then one element per chunk, a million of them, each made
+    // only when it is read; `pulled` counts the elements read so far
+    async fn send_huge_array(
+        pulled: web::Data<AtomicUsize>,
+        mut req: ServiceRequest,
+        next: Next<impl MessageBody>,
+    ) -> Result<ServiceResponse<impl MessageBody>, Error> {
+        let elements = stream::iter(0..ELEMENTS).map(move |n| {
+            pulled.fetch_add(1, Ordering::SeqCst);
+            let separator = if n == 0 { "" } else { "," };
+            let element =
+                json!({ "name": format!("user {n}"), "email": format!("u{n}@example.com") });
+            Ok::<_, PayloadError>(Bytes::from(format!("{separator}{element}")))
+        });
+        let chunks = stream::once(async { Ok(Bytes::from_static(b"[")) })
+            .chain(elements)
+            .chain(stream::once(async { Ok(Bytes::from_static(b"]")) }));
+        req.set_payload(Payload::Stream {
+            payload: Box::pin(chunks),
+        });
+        next.call(req).await
+    }
+
+    fn post_json(uri: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+    }
+
+    async fn count_items(req: HttpRequest, body: web::Payload) -> actix_web::Result<HttpResponse> {
+        let items = read_array(&req, body, 3).await?;
+        Ok(HttpResponse::Ok().body(items.len().to_string()))
+    }
+
+    #[actix_web::test]
+    async fn an_over_cap_array_is_refused_after_reading_just_past_the_cap() {
+        let pulled = web::Data::new(AtomicUsize::new(0));
+        let app = test::init_service(
+            App::new()
+                .app_data(pulled.clone())
+                .wrap(middleware::from_fn(send_huge_array))
+                .route("/items", web::post().to(count_items)),
+        )
+        .await;
+
+        let res = test::call_service(&app, post_json("/items").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(test::read_body(res).await, "at most 3 items per request");
+        // the 4th element only ends on the comma in front of the 5th
+        assert_eq!(pulled.load(Ordering::SeqCst), 5);
+    }
+
+    #[actix_web::test]
+    async fn the_bulk_endpoint_refuses_a_huge_array_early() {
+        let url = format!(
+            "sqlite:file:{}?mode=memory&cache=shared",
+            uuid::Uuid::new_v4()
+        );
+        let pool = crate::db::connect(&url).await.unwrap();
+        let pulled = web::Data::new(AtomicUsize::new(0));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(pulled.clone())
+                .wrap(middleware::from_fn(send_huge_array))
+                .service(users::create_users_bulk),
+        )
+        .await;
+
+        let res = test::call_service(&app, post_json("/users/bulk").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = test::read_body(res).await;
+        let max_items: usize = std::str::from_utf8(&body)
+            .unwrap()
+            .strip_prefix("at most ")
+            .and_then(|rest| rest.strip_suffix(" items per request"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(pulled.load(Ordering::SeqCst), max_items + 2);
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, 0);
+    }
+
+    #[actix_web::test]
+    async fn elements_are_split_at_the_top_level_only() {
+        let mut splitter = ArraySplitter::new(10);
+        for chunk in [
+            r#" [ {"a": [1, 2], "s": "x,]\"y"}"#,
+            r#", "two" , [3,[4]] ] "#,
+        ] {
+            splitter.feed(chunk.as_bytes()).unwrap();
+        }
+
+        let items = splitter.finish().unwrap();
+
+        assert_eq!(
+            items,
+            [
+                json!({ "a": [1, 2], "s": "x,]\"y" }),
+                json!("two"),
+                json!([3, [4]])
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn malformed_arrays_are_400() {
+        for body in ["{}", "[1,,2]", "[1,]", "[1] x", "[1, 2", "[nope]"] {
+            let mut splitter = ArraySplitter::new(10);
+            let result = splitter
+                .feed(body.as_bytes())
+                .and_then(|()| splitter.finish().map(drop));
+
+            let err = result.unwrap_err();
+            assert_eq!(
+                err.as_response_error().status_code(),
+                StatusCode::BAD_REQUEST,
+                "{body}"
+            );
+        }
+        assert!(ArraySplitter::new(10).feed(b" [ ] ").is_ok());
+    }
+}
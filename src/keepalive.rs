@@ -0,0 +1,220 @@
+/*
+   KEEP-ALIVE REUSE COUNTER
+    with keep-alive, several requests travel over the same TCP connection. to see it happening,
+     every connection gets its own counter:
+     - HttpServer::on_connect() runs once per new connection and can store "connection data"
+     - every request on that connection can read it back with req.conn_data::<T>()
+     - a small middleware bumps the counter for each request on the connection
+
+    `GET /debug/keepalive` reports the counter: 1 on a fresh connection, 2, 3, ... when the
+     client reuses it (eg: `curl localhost:8080/debug/keepalive localhost:8080/debug/keepalive`)
+
+    connection data is only touched by the worker owning the connection, so a Cell is enough
+
+   CLOSING FROM ONE RESPONSE
+    keep-alive is a server-wide setting (see main), but a single response can still end its
+     connection, eg: after an error that leaves the connection in doubt:
+     - `GET /close` -> `Connection: close`, the server closes the connection once it is sent.
+        it does it both ways: force_close() on the builder, and set_connection_type(Close) on
+        the head of a response that is already built
+     - `GET /keep`  -> set_connection_type(KeepAlive): the connection stays open for the next
+        request. HTTP/1.1 keeps connections alive by default, so no `Connection` header is sent
+        at all, the absence of `close` is what tells the client
+    both report the counter above, so `curl localhost:8080/keep localhost:8080/keep` shows 1
+     then 2 (one connection), and the same with `/close` shows 1 and 1 (a new one each time).
+*/
+
+use std::{any::Any, cell::Cell};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Extensions, ServiceRequest, ServiceResponse},
+    get,
+    http::{
+        header::{CacheControl, CacheDirective},
+        ConnectionType,
+    },
+    middleware::Next,
+    web, Error, HttpRequest, HttpResponse, Responder,
+};
+use serde::Serialize;
+
+#[derive(Default)]
+pub struct ConnectionRequests(Cell<u64>);
+
+pub fn on_connect(_conn: &dyn Any, data: &mut Extensions) {
+    data.insert(ConnectionRequests::default());
+}
+
+pub async fn count_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if let Some(counter) = req.conn_data::<ConnectionRequests>() {
+        counter.0.set(counter.0.get() + 1);
+    }
+    next.call(req).await
+}
+
+#[derive(Serialize)]
+struct KeepAliveReport {
+    requests_on_connection: u64,
+    reused: bool,
+}
+
+fn report(req: &HttpRequest) -> KeepAliveReport {
+    let requests_on_connection = req
+        .conn_data::<ConnectionRequests>()
+        .map_or(0, |counter| counter.0.get());
+
+    KeepAliveReport {
+        requests_on_connection,
+        reused: requests_on_connection > 1,
+    }
+}
+
+#[get("/debug/keepalive")]
+pub async fn keepalive_report(req: HttpRequest) -> impl Responder {
+    web::Json(report(&req))
+        .customize()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+}
+
+#[get("/close")]
+pub async fn close(req: HttpRequest) -> HttpResponse {
+    let mut res = HttpResponse::Ok()
+        .force_close() // <- close the connection, on the builder
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(report(&req));
+
+    // the same on a response that is already built
+    res.head_mut().set_connection_type(ConnectionType::Close);
+    res
+}
+
+#[get("/keep")]
+pub async fn keep(req: HttpRequest) -> HttpResponse {
+    let mut res = HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(report(&req));
+
+    res.head_mut()
+        .set_connection_type(ConnectionType::KeepAlive);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::rt::time::timeout;
+    use serde_json::Value;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::testing;
+
+    async fn report(client: &awc::Client, url: &str) -> (Value, bool) {
+        let mut res = client.get(url).send().await.unwrap();
+        let closed = res
+            .headers()
+            .get("connection")
+            .is_some_and(|value| value == "close");
+        (res.json().await.unwrap(), closed)
+    }
+
+    #[actix_web::test]
+    async fn counts_the_requests_on_a_reused_connection() {
+        let addr = testing::serve(&testing::builder().await);
+        let client = awc::Client::default();
+        let url = format!("http://{addr}/debug/keepalive");
+
+        let (first, _) = report(&client, &url).await;
+        let (second, _) = report(&client, &url).await;
+
+        assert_eq!(first["requests_on_connection"], 1);
+        assert_eq!(first["reused"], false);
+        assert_eq!(second["requests_on_connection"], 2);
+        assert_eq!(second["reused"], true);
+    }
+
+    #[actix_web::test]
+    async fn close_ends_the_connection_and_keep_does_not() {
+        let addr = testing::serve(&testing::builder().await);
+        let client = awc::Client::default();
+
+        let (first, closed) = report(&client, &format!("http://{addr}/close")).await;
+        let (second, _) = report(&client, &format!("http://{addr}/close")).await;
+        assert!(closed);
+        assert_eq!(first["requests_on_connection"], 1);
+        assert_eq!(second["requests_on_connection"], 1);
+
+        let (first, closed) = report(&client, &format!("http://{addr}/keep")).await;
+        let (second, _) = report(&client, &format!("http://{addr}/keep")).await;
+        assert!(!closed);
+        assert_eq!(first["requests_on_connection"], 1);
+        assert_eq!(second["requests_on_connection"], 2);
+    }
+
+    // one response off a raw connection: its head (lower case) and its body
+    async fn read_response(stream: &mut TcpStream) -> (String, Value) {
+        let mut received = Vec::new();
+        let mut buf = [0; 1024];
+        let head_end = loop {
+            if let Some(at) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+                break at + 4;
+            }
+            let read = stream.read(&mut buf).await.unwrap();
+            assert!(read > 0, "the connection closed before a response");
+            received.extend_from_slice(&buf[..read]);
+        };
+        let head = String::from_utf8(received[..head_end].to_vec())
+            .unwrap()
+            .to_ascii_lowercase();
+        let length: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        while received.len() < head_end + length {
+            let read = stream.read(&mut buf).await.unwrap();
+            assert!(read > 0, "the connection closed inside a body");
+            received.extend_from_slice(&buf[..read]);
+        }
+        let body = serde_json::from_slice(&received[head_end..head_end + length]).unwrap();
+        (head, body)
+    }
+
+    async fn send_get(stream: &mut TcpStream, path: &str) {
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn a_raw_client_sees_close_only_from_close() {
+        let addr = testing::serve(&testing::builder().await);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        send_get(&mut stream, "/keep").await;
+        let (head, first) = read_response(&mut stream).await;
+        assert!(head.starts_with("http/1.1 200"));
+        assert!(!head.contains("\r\nconnection:"), "{head}");
+        // the same connection takes another request
+        send_get(&mut stream, "/keep").await;
+        let (_, second) = read_response(&mut stream).await;
+        assert_eq!(first["requests_on_connection"], 1);
+        assert_eq!(second["requests_on_connection"], 2);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        send_get(&mut stream, "/close").await;
+        let (head, _) = read_response(&mut stream).await;
+        assert!(head.contains("\r\nconnection: close\r\n"), "{head}");
+        // and then the server hangs up
+        let mut rest = Vec::new();
+        let read = timeout(Duration::from_secs(5), stream.read_to_end(&mut rest)).await;
+        assert_eq!(read.unwrap().unwrap(), 0);
+    }
+}
@@ -0,0 +1,259 @@
+/*
+   IN-MEMORY KEY-VALUE STORE
+    `/kv/{key}` keeps raw bytes under a key, shared by all workers:
+     - `PUT /kv/{key}`            -> stores the body: `201` for a new key, `204` when it replaced one
+     - `PUT /kv/{key}?ttl=<secs>` -> the same, but the key expires after that many seconds
+     - `GET /kv/{key}`            -> the bytes as they were stored, `404` for a key that isn't there
+     - `DELETE /kv/{key}`         -> `204`, or `404` when there was nothing to delete
+
+    - the map sits behind an RwLock: GETs read side by side, only writes take it alone
+    - an expired key is not looked for by any timer: it is removed when it is next accessed,
+       and counts as missing (`404`) from the moment it expired
+    - a value over MAX_VALUE_BYTES is `413`, and at most MAX_KEYS keys are kept, after that a
+       new key is `503` (it all lives in memory); a PUT without `ttl` keeps its key forever
+    - GET answers are `no-store`: the response cache must not hand out a value after it expired
+*/
+
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    delete, error, get,
+    http::header::{CacheControl, CacheDirective},
+    put,
+    web::{self, Bytes},
+    HttpResponse,
+};
+use serde::Deserialize;
+
+const MAX_VALUE_BYTES: usize = 64 * 1024;
+const MAX_KEYS: usize = 100_000;
+
+struct Entry {
+    value: Bytes,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Default)]
+pub struct KvStore {
+    entries: RwLock<HashMap<String, Entry>>,
+}
+
+enum Stored {
+    Created,
+    Replaced,
+    Full,
+}
+
+impl KvStore {
+    fn get(&self, key: &str) -> Option<Bytes> {
+        let now = Instant::now();
+        {
+            let entries = self.entries.read().unwrap();
+            match entries.get(key) {
+                None => return None,
+                Some(entry) if !entry.is_expired(now) => return Some(entry.value.clone()),
+                Some(_) => {}
+            }
+        }
+
+        // expired: drop it, unless it was set again since the read lock was released
+        let mut entries = self.entries.write().unwrap();
+        if entries.get(key).is_some_and(|entry| entry.is_expired(now)) {
+            entries.remove(key);
+        }
+        None
+    }
+
+    fn set(&self, key: String, value: Bytes, ttl: Option<Duration>) -> Stored {
+        let now = Instant::now();
+        let mut entries = self.entries.write().unwrap();
+        let existed = match entries.get(&key) {
+            Some(entry) => !entry.is_expired(now),
+            None if entries.len() >= MAX_KEYS => return Stored::Full,
+            None => false,
+        };
+
+        let entry = Entry {
+            value,
+            expires_at: ttl.map(|ttl| now + ttl),
+        };
+        entries.insert(key, entry);
+        match existed {
+            true => Stored::Replaced,
+            false => Stored::Created,
+        }
+    }
+
+    // true when there was a (live) value to remove
+    fn remove(&self, key: &str) -> bool {
+        let removed = self.entries.write().unwrap().remove(key);
+        removed.is_some_and(|entry| !entry.is_expired(Instant::now()))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SetParams {
+    // seconds until the key expires, none means never
+    ttl: Option<u64>,
+}
+
+#[put("/kv/{key}")]
+pub async fn set_value(
+    store: web::Data<KvStore>,
+    key: web::Path<String>,
+    params: web::Query<SetParams>,
+    body: Bytes,
+) -> actix_web::Result<HttpResponse> {
+    if body.len() > MAX_VALUE_BYTES {
+        return Err(error::ErrorPayloadTooLarge(format!(
+            "a value may be at most {MAX_VALUE_BYTES} bytes"
+        )));
+    }
+    let ttl = match params.ttl {
+        Some(0) => return Err(error::ErrorBadRequest("ttl must be at least 1 second")),
+        ttl => ttl.map(Duration::from_secs),
+    };
+
+    Ok(match store.set(key.into_inner(), body, ttl) {
+        Stored::Created => HttpResponse::Created().finish(),
+        Stored::Replaced => HttpResponse::NoContent().finish(),
+        Stored::Full => {
+            HttpResponse::ServiceUnavailable().body(format!("the store is full ({MAX_KEYS} keys)"))
+        }
+    })
+}
+
+#[get("/kv/{key}")]
+pub async fn get_value(store: web::Data<KvStore>, key: web::Path<String>) -> HttpResponse {
+    match store.get(&key) {
+        Some(value) => HttpResponse::Ok()
+            .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+            .content_type("application/octet-stream")
+            .body(value),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[delete("/kv/{key}")]
+pub async fn delete_value(store: web::Data<KvStore>, key: web::Path<String>) -> HttpResponse {
+    match store.remove(&key) {
+        true => HttpResponse::NoContent().finish(),
+        false => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::{header, StatusCode},
+        rt::time::sleep,
+        test, App,
+    };
+
+    use super::*;
+    use crate::testing;
+
+    fn put(uri: &str, value: impl Into<Bytes>) -> test::TestRequest {
+        test::TestRequest::put().uri(uri).set_payload(value)
+    }
+
+    fn get(uri: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(uri)
+    }
+
+    fn delete(uri: &str) -> test::TestRequest {
+        test::TestRequest::delete().uri(uri)
+    }
+
+    #[actix_web::test]
+    async fn set_get_replace_and_delete() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let value = Bytes::from_static(b"\x00\x01binary\xff");
+
+        let res = test::call_service(&app, put("/kv/greeting", value.clone()).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = test::call_service(&app, get("/kv/greeting").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-store"
+        );
+        assert_eq!(test::read_body(res).await, value);
+
+        let res = test::call_service(&app, put("/kv/greeting", "again").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let body = test::call_and_read_body(&app, get("/kv/greeting").to_request()).await;
+        assert_eq!(body, "again");
+
+        let res = test::call_service(&app, delete("/kv/greeting").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let res = test::call_service(&app, get("/kv/greeting").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = test::call_service(&app, delete("/kv/greeting").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn an_expired_key_is_404_and_removed_on_access() {
+        let store = web::Data::new(KvStore::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(store.clone())
+                .service(set_value)
+                .service(get_value),
+        )
+        .await;
+
+        test::call_service(&app, put("/kv/short?ttl=1", "soon gone").to_request()).await;
+        test::call_service(&app, put("/kv/forever", "stays").to_request()).await;
+        let res = test::call_service(&app, get("/kv/short").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        sleep(Duration::from_millis(1100)).await;
+        // still there until someone asks for it
+        assert_eq!(store.entries.read().unwrap().len(), 2);
+        let res = test::call_service(&app, get("/kv/short").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(!store.entries.read().unwrap().contains_key("short"));
+        let res = test::call_service(&app, get("/kv/forever").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // an expired key set again is new
+        let res = test::call_service(&app, put("/kv/forever?ttl=1", "x").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        sleep(Duration::from_millis(1100)).await;
+        let res = test::call_service(&app, put("/kv/forever", "y").to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    #[actix_web::test]
+    async fn an_oversized_value_or_a_zero_ttl_is_refused() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(
+            &app,
+            put("/kv/big", vec![7; MAX_VALUE_BYTES + 1]).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let res = test::call_service(&app, get("/kv/big").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res =
+            test::call_service(&app, put("/kv/big", vec![7; MAX_VALUE_BYTES]).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let res = test::call_service(&app, put("/kv/zero?ttl=0", "x").to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
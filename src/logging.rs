@@ -0,0 +1,160 @@
+/*
+   LOGGING: STDERR AND A DAILY FILE
+    every line goes to stderr, filtered by RUST_LOG (default `info`). with LOG_DIR set, the same
+     lines also go to a file in that directory, a new one every day (UTC):
+
+        $LOG_DIR/app.2026-10-15.log, app.2026-10-16.log, ...
+
+    a line logged while a request is handled carries the request's span (see trace.rs), so its
+     request id is right there and grepping the file for one id gives everything that request
+     logged, its errors included. the access log line carries it too (see access_log.rs).
+
+    - the file is written by a background thread (tracing-appender's non_blocking), so a slow
+       disk never holds up a worker. it is NOT lossy: when the thread falls behind, loggers wait
+       for room instead of dropping lines
+    - rotating happens in that same thread, between two writes: a line goes either to the old
+       file or to the new one, none is lost in the switch
+    - the returned guard flushes what is still queued when it is dropped, so main keeps it
+       until the server has stopped
+    - a LOG_DIR that can't be created or written logs to stderr only, with a warning
+
+    LOG_DIR is an env var and not in config.toml: logging starts before the config is loaded,
+     so the config's own warnings are logged too.
+*/
+
+use std::{env, io::IsTerminal};
+
+use tracing_appender::{
+    non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard},
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+const FILE_PREFIX: &str = "app";
+const FILE_SUFFIX: &str = "log";
+
+// the file writer, if LOG_DIR asks for one and it can be opened
+fn log_file() -> Result<Option<RollingFileAppender>, String> {
+    let Ok(dir) = env::var("LOG_DIR") else {
+        return Ok(None);
+    };
+    log_file_in(&dir).map(Some)
+}
+
+fn log_file_in(dir: &str) -> Result<RollingFileAppender, String> {
+    RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(FILE_PREFIX)
+        .filename_suffix(FILE_SUFFIX)
+        .build(dir)
+        .map_err(|err| format!("can't log to LOG_DIR={dir}, logging to stderr only: {err}"))
+}
+
+// the appender behind a writer thread that waits for room rather than dropping lines
+fn file_writer(appender: RollingFileAppender) -> (NonBlocking, WorkerGuard) {
+    NonBlockingBuilder::default().lossy(false).finish(appender)
+}
+
+pub fn init() -> Option<WorkerGuard> {
+    let (file_layer, guard, file_error) = match log_file() {
+        Ok(Some(appender)) => {
+            let (writer, guard) = file_writer(appender);
+            let layer = fmt::layer().with_writer(writer).with_ansi(false);
+            (Some(layer), Some(guard), None)
+        }
+        Ok(None) => (None, None, None),
+        Err(err) => (None, None, Some(err)),
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(
+            fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_ansi(std::io::stderr().is_terminal()),
+        )
+        .with(file_layer)
+        .init();
+
+    // only now that there is a logger to say it
+    if let Some(err) = file_error {
+        log::warn!("{err}");
+    }
+    guard
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use actix_web::{middleware, test, web, App, HttpResponse};
+
+    use super::*;
+    use crate::{
+        request_id::{self, REQUEST_ID_HEADER},
+        trace,
+    };
+
+    async fn failing() -> HttpResponse {
+        tracing::error!("the upstream refused the order");
+        HttpResponse::BadGateway().finish()
+    }
+
+    #[actix_web::test]
+    async fn a_requests_lines_in_the_file_carry_its_id() {
+        let dir = env::temp_dir().join(format!("actix-web-logs-{}", uuid::Uuid::new_v4()));
+        let appender = log_file_in(dir.to_str().unwrap()).unwrap();
+        let (writer, guard) = file_writer(appender);
+        let subscriber =
+            tracing_subscriber::registry().with(fmt::layer().with_writer(writer).with_ansi(false));
+        let default = subscriber.set_default();
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(trace::trace_request))
+                .wrap(middleware::from_fn(request_id::request_id))
+                .route("/orders", web::post().to(failing)),
+        )
+        .await;
+
+        for id in ["order-req-1", "order-req-2"] {
+            let req = test::TestRequest::post()
+                .uri("/orders")
+                .insert_header((REQUEST_ID_HEADER, id))
+                .to_request();
+            test::call_service(&app, req).await;
+        }
+        drop(default);
+        // flushes what the writer thread still has
+        drop(guard);
+
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let name = files[0].file_name().unwrap().to_str().unwrap().to_owned();
+        assert!(name.starts_with("app.") && name.ends_with(".log"), "{name}");
+        let contents = fs::read_to_string(&files[0]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        for id in ["order-req-1", "order-req-2"] {
+            let lines: Vec<&str> = contents.lines().filter(|line| line.contains(id)).collect();
+            assert_eq!(lines.len(), 1, "{contents}");
+            assert!(lines[0].contains("ERROR"));
+            assert!(lines[0].contains("the upstream refused the order"));
+            assert!(lines[0].contains("path=/orders"));
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_dir_that_cant_be_created_is_an_error() {
+        let file = env::temp_dir().join(format!("actix-web-not-a-dir-{}", uuid::Uuid::new_v4()));
+        fs::write(&file, "a file, not a directory").unwrap();
+        let below = file.join("logs");
+
+        let err = log_file_in(below.to_str().unwrap()).unwrap_err();
+        fs::remove_file(&file).unwrap();
+
+        assert!(err.starts_with("can't log to LOG_DIR="), "{err}");
+    }
+}
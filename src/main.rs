@@ -1,9 +1,94 @@
-use std::{string, sync::Mutex, time::Duration};
+mod accept_json;
+mod access_log;
+mod admin;
+mod aggregate;
+mod api;
+mod api_key;
+mod app;
+mod auth;
+mod avatar;
+mod balancer;
+mod basics;
+mod bearer;
+mod body_limit;
+mod body_log;
+mod conditional;
+mod config;
+mod config_reload;
+mod config_routes;
+mod contact;
+mod db;
+mod debug;
+mod decompress;
+mod disconnect;
+mod download;
+mod events;
+mod extractor_errors;
+mod files;
+mod greeting;
+mod hash;
+mod head;
+mod header_limit;
+mod https_redirect;
+mod idempotency;
+mod inflight;
+mod ingest;
+mod ip_allowlist;
+mod items;
+mod jobs;
+mod json_array;
+mod keepalive;
+mod kv;
+mod logging;
+mod maintenance;
+mod matched_route;
+mod metrics;
+mod multi_host;
+mod openapi;
+mod orders;
+mod panics;
+mod payments;
+mod prefs;
+mod pretty_json;
+mod rate;
+mod rate_limit;
+mod report;
+mod repos;
+mod request_id;
+mod request_log;
+mod response_cache;
+mod retry;
+mod reverse_proxy;
+mod search;
+mod security_headers;
+mod session;
+mod shorten;
+mod sources;
+mod state;
+mod static_files;
+mod status;
+mod strip_prefix;
+mod submit;
+mod supervisor;
+mod tenant;
+#[cfg(test)]
+mod testing;
+mod text;
+mod timeout;
+mod trace;
+mod trailing_slash;
+mod tx;
+mod uploads;
+mod users;
+mod version;
+mod weather;
+mod webhook;
+mod write_lock;
+
+use std::net::SocketAddr;
+
+use actix_web::{dev::Server, HttpServer};
 
-use actix_web::{
-    get, guard, http::{self, KeepAlive}, post, web::{self, head, Json}, App, HttpRequest, HttpResponse, HttpServer, Responder, Result
-};
-use serde::Deserialize;
 /*
 /*
  Notice that some of these handlers have routing information attached directly using the
@@ -497,4 +582,273 @@ async fn main() -> std::io::Result<()> {
         .run()
         .await
 }
-        */
\ No newline at end of file
+        */
+
+///////////////////////////////////////////////////////////
+/*
+     THE LIVE APP
+       everything above is kept commented out so each topic can be explored on its own.
+        the app below is the one that actually runs with `cargo run` and wires the
+        features that live in their own modules
+*/
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    // RUST_LOG filters as before; log:: lines go through it too, inside their request's span.
+    // held until main returns, so the lines still queued for LOG_DIR are written out
+    let _log_guard = logging::init();
+
+    // an unparsable port stops the server right here
+    let config = config::Config::load()?;
+    session::warn_if_dev_auth();
+    debug::log_if_enabled(&config);
+    // a routes.toml route that could never be reached stops the server too
+    let config_routes = config_routes::ConfigRoutes::load()?;
+    config_routes.check_reachable(&config)?;
+
+    // shared state is created here, once; the factory only hands out clones of it to each worker
+    let pool = db::connect(&config.database_url)
+        .await
+        .map_err(std::io::Error::other)?;
+    let builder = app::AppBuilder::new(config.clone(), pool, config_routes);
+    builder.start_background_tasks();
+    state::check_app_data(&builder)?; // <- a missing web::Data is a startup error, not a 500
+
+    let server = serve(&builder, &config, config.workers, false)?;
+
+    // on SIGUSR2 the server is replaced by one with the reloaded worker count (see supervisor.rs)
+    supervisor::supervise(server, builder.shutdown_switch(), |reloaded| {
+        serve(&builder, &config, reloaded.workers, true)
+    })
+    .await
+}
+
+fn serve(
+    builder: &app::AppBuilder,
+    config: &config::Config,
+    workers: usize,
+    handover: bool,
+) -> std::io::Result<Server> {
+    let addr = SocketAddr::new(config.bind_addr, config.port);
+    let listener = supervisor::reusable_listener(addr, handover)?;
+
+    log::info!(
+        "serving on {addr} with {workers} workers, per worker at most {} connections and {} \
+         concurrent TLS handshakes",
+        config.max_connections,
+        config.max_connection_rate
+    );
+
+    let factory = builder.clone();
+    let server = HttpServer::new(move || factory.build())
+        .on_connect(keepalive::on_connect) // gives every connection its own request counter
+        .workers(workers)
+        .max_connections(config.max_connections)
+        .max_connection_rate(config.max_connection_rate)
+        .keep_alive(config.keep_alive());
+
+    // SIGTERM / SIGINT are handled by supervisor::supervise, which turns /readyz off first
+    #[cfg(unix)]
+    let server = server.disable_signals();
+
+    // h2c: the listener looks at the first bytes of each connection and serves HTTP/2 to clients
+    //  that start with the HTTP/2 preface (eg: `curl --http2-prior-knowledge`), HTTP/1.x otherwise
+    let server = if config.enable_h2c {
+        server.listen_auto_h2c(listener)?
+    } else {
+        server.listen(listener)?
+    };
+
+    // UNIX_SOCKET: the same app on a local socket too
+    let server = match config.unix_socket.as_str() {
+        "" => server,
+        #[cfg(unix)]
+        path => {
+            let listener = supervisor::unix_listener(path)?;
+            log::info!("also serving on unix socket {path}");
+            server.listen_uds(listener)?
+        }
+        #[cfg(not(unix))]
+        path => {
+            log::warn!("ignoring UNIX_SOCKET={path}, unix sockets need a unix system");
+            server
+        }
+    };
+
+    let server = server.run();
+    builder.set_server_handle(server.handle()); // <- lets /admin/shutdown stop this server
+    Ok(server)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, TcpListener};
+
+    use actix_web::{http::StatusCode, rt};
+
+    use super::*;
+    use crate::{config::Config, testing};
+
+    // serve() binds the port itself, so find one that is free first
+    fn free_port() -> u16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    fn local_config() -> Config {
+        Config {
+            bind_addr: Ipv4Addr::LOCALHOST.into(),
+            port: free_port(),
+            ..Config::default()
+        }
+    }
+
+    async fn serving(config: Config) -> SocketAddr {
+        let builder = testing::builder_with(config.clone()).await;
+        rt::spawn(serve(&builder, &config, 1, false).unwrap());
+        SocketAddr::new(config.bind_addr, config.port)
+    }
+
+    #[cfg(unix)]
+    fn socket_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("actix-web-{}.sock", uuid::Uuid::new_v4()))
+    }
+
+    #[cfg(unix)]
+    // a whole HTTP/1.1 exchange over the unix socket, the connection closed after it
+    async fn unix_get(path: &std::path::Path, uri: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::UnixStream::connect(path).await.unwrap();
+        let req = format!("GET {uri} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap();
+        res
+    }
+
+    async fn h2_status(addr: SocketAddr) -> Result<u16, h2::Error> {
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut client, connection) = h2::client::handshake(tcp).await?;
+        rt::spawn(connection);
+
+        let req = http::Request::get(format!("http://{addr}/healthz"))
+            .body(())
+            .unwrap();
+        let (res, _) = client.send_request(req, true)?;
+        Ok(res.await?.status().as_u16())
+    }
+
+    async fn http1_status(addr: SocketAddr) -> StatusCode {
+        let res = awc::Client::default()
+            .get(format!("http://{addr}/healthz"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.version(), actix_web::http::Version::HTTP_11);
+        res.status()
+    }
+
+    // `count` GET /slow?secs=1 at once, each on a connection of its own
+    async fn concurrent_slow_statuses(addr: SocketAddr, count: usize) -> Vec<u16> {
+        let requests = (0..count).map(|_| async move {
+            let res = awc::Client::default()
+                .get(format!("http://{addr}/slow?secs=1"))
+                .send()
+                .await
+                .unwrap();
+            res.status().as_u16()
+        });
+        futures_util::future::join_all(requests).await
+    }
+
+    #[actix_web::test]
+    async fn the_configured_worker_count_is_what_serves() {
+        // one request in flight per worker: N slow requests at once need N workers
+        let serving_with = |workers| async move {
+            let config = Config {
+                workers,
+                max_inflight: 1,
+                ..local_config()
+            };
+            let builder = testing::builder_with(config.clone()).await;
+            // as main does it
+            rt::spawn(serve(&builder, &config, config.workers, false).unwrap());
+            SocketAddr::new(config.bind_addr, config.port)
+        };
+
+        let three = serving_with(3).await;
+        assert_eq!(concurrent_slow_statuses(three, 3).await, [200, 200, 200]);
+
+        let one = serving_with(1).await;
+        let statuses = concurrent_slow_statuses(one, 3).await;
+        assert_eq!(statuses.iter().filter(|&&status| status == 200).count(), 1);
+        assert_eq!(statuses.iter().filter(|&&status| status == 503).count(), 2);
+    }
+
+    #[actix_web::test]
+    async fn with_h2c_both_http2_and_http1_clients_are_served() {
+        let addr = serving(Config {
+            enable_h2c: true,
+            ..local_config()
+        })
+        .await;
+
+        assert_eq!(h2_status(addr).await.unwrap(), 200);
+        assert_eq!(http1_status(addr).await, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn without_h2c_an_http2_client_is_refused() {
+        let addr = serving(local_config()).await;
+
+        assert!(h2_status(addr).await.is_err());
+        assert_eq!(http1_status(addr).await, StatusCode::OK);
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn the_unix_socket_serves_next_to_tcp() {
+        let path = socket_path();
+        let addr = serving(Config {
+            unix_socket: path.to_str().unwrap().to_owned(),
+            ..local_config()
+        })
+        .await;
+
+        let res = unix_get(&path, "/").await;
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+        assert!(res.ends_with("\r\n\r\nHello world!"), "{res}");
+        assert_eq!(http1_status(addr).await, StatusCode::OK);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn a_stale_socket_is_replaced_but_another_file_is_kept() {
+        let stale = socket_path();
+        drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+        assert!(stale.exists());
+
+        serving(Config {
+            unix_socket: stale.to_str().unwrap().to_owned(),
+            ..local_config()
+        })
+        .await;
+        assert!(unix_get(&stale, "/healthz").await.ends_with("ok"));
+        std::fs::remove_file(&stale).unwrap();
+
+        let file = socket_path();
+        std::fs::write(&file, "not a socket").unwrap();
+        let config = Config {
+            unix_socket: file.to_str().unwrap().to_owned(),
+            ..local_config()
+        };
+        let builder = testing::builder_with(config.clone()).await;
+
+        assert!(serve(&builder, &config, 1, false).is_err());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "not a socket");
+        std::fs::remove_file(&file).unwrap();
+    }
+}
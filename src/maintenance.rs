@@ -0,0 +1,145 @@
+/*
+   MAINTENANCE MODE
+    during a deploy (a migration, a data fix, ...) the app can be told to turn clients away for a
+     moment: `POST /admin/maintenance` switches maintenance mode on, the next call switches it
+     off again. the answer says which state it is in now.
+
+    while it is on, every request gets
+        503 { "error": "down for maintenance", ... } + `Retry-After: 60`
+     except EXEMPT_PATHS: the health check (the instance itself is fine, load balancers shouldn't
+     pull it) and the toggle itself, or there would be no way back.
+
+    the flag is an AtomicBool in the shared state, so a toggle handled by one worker is seen by
+     the next request on every other worker; no lock needed for a single bool.
+*/
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use serde_json::json;
+
+const EXEMPT_PATHS: [&str; 2] = ["/healthz", "/admin/maintenance"];
+const RETRY_AFTER_SECS: u64 = 60;
+
+#[derive(Default)]
+pub struct Maintenance {
+    on: AtomicBool,
+}
+
+impl Maintenance {
+    pub fn is_on(&self) -> bool {
+        self.on.load(Ordering::Relaxed)
+    }
+
+    // returns the new state
+    pub fn toggle(&self) -> bool {
+        !self.on.fetch_xor(true, Ordering::Relaxed)
+    }
+}
+
+pub async fn maintenance_mode(
+    maintenance: web::Data<Maintenance>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if !maintenance.is_on() || EXEMPT_PATHS.contains(&req.path()) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    }
+
+    let res = HttpResponse::ServiceUnavailable()
+        .insert_header((header::RETRY_AFTER, RETRY_AFTER_SECS))
+        .json(json!({
+            "error": "down for maintenance",
+            "retry_after_secs": RETRY_AFTER_SECS,
+        }));
+    Ok(req.into_response(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde_json::Value;
+
+    use crate::{auth::AdminCredentials, testing};
+
+    fn toggle_request() -> test::TestRequest {
+        let credentials = STANDARD.encode("admin:s3cret");
+        test::TestRequest::post()
+            .uri("/admin/maintenance")
+            .insert_header(("Authorization", format!("Basic {credentials}")))
+    }
+
+    fn get(uri: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(uri)
+    }
+
+    #[actix_web::test]
+    async fn maintenance_turns_clients_away_until_switched_off() {
+        let builder = testing::builder()
+            .await
+            .with_admin_credentials(AdminCredentials::new("admin", "s3cret"));
+        let app = test::init_service(builder.build()).await;
+
+        let toggled: Value =
+            test::call_and_read_body_json(&app, toggle_request().to_request()).await;
+        assert_eq!(toggled["maintenance"], true);
+
+        let res = test::call_service(&app, get("/users").to_request()).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers().get("Retry-After").unwrap(), "60");
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "down for maintenance");
+        assert_eq!(body["retry_after_secs"], 60);
+
+        let res = test::call_service(&app, get("/healthz").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let toggled: Value =
+            test::call_and_read_body_json(&app, toggle_request().to_request()).await;
+        assert_eq!(toggled["maintenance"], false);
+        let res = test::call_service(&app, get("/users").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn the_toggle_needs_the_admin_credentials() {
+        let builder = testing::builder()
+            .await
+            .with_admin_credentials(AdminCredentials::new("admin", "s3cret"));
+        let app = test::init_service(builder.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/admin/maintenance")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = test::call_service(&app, get("/users").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn every_worker_sees_the_toggle() {
+        // the workers share one Data<Maintenance>, built apps here stand in for workers
+        let builder = testing::builder()
+            .await
+            .with_admin_credentials(AdminCredentials::new("admin", "s3cret"));
+        let first = test::init_service(builder.build()).await;
+        let second = test::init_service(builder.build()).await;
+
+        test::call_service(&first, toggle_request().to_request()).await;
+
+        let res = test::call_service(&second, get("/users").to_request()).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
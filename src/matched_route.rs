@@ -0,0 +1,78 @@
+/*
+   MATCHED ROUTE HEADER
+    routing happens inside the App, after the outer middleware has run. but once the inner
+     service has answered, the request knows which resource it matched:
+     - match_name()    -> the resource name. the routing macros (#[get(...)] etc.) name the
+                          resource after the handler function, eg: `whoami`
+     - match_pattern() -> the pattern it was registered with, eg: `/jobs/{id}`
+
+    the response gets an `X-Matched-Route` header with the name (or the pattern for unnamed
+     resources), or `none` when nothing matched (the default 404). handy in traces, since it
+     tells which handler answered without guessing from the raw URL.
+*/
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    Error,
+};
+
+pub const MATCHED_ROUTE_HEADER: HeaderName = HeaderName::from_static("x-matched-route");
+
+pub async fn matched_route(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let mut res = next.call(req).await?;
+
+    let route = res
+        .request()
+        .match_name()
+        .map(str::to_owned)
+        .or_else(|| res.request().match_pattern())
+        .unwrap_or_else(|| "none".to_owned());
+
+    if let Ok(value) = HeaderValue::from_str(&route) {
+        res.headers_mut().insert(MATCHED_ROUTE_HEADER, value);
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+
+    use super::*;
+    use crate::testing;
+
+    async fn matched(uri: &str) -> String {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let res = test::call_service(&app, req).await;
+        res.headers()
+            .get(MATCHED_ROUTE_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[actix_web::test]
+    async fn names_the_handler_that_answered() {
+        assert_eq!(matched("/whoami").await, "whoami");
+        assert_eq!(matched("/jobs/not-a-uuid").await, "job_status");
+    }
+
+    #[actix_web::test]
+    async fn unnamed_resources_get_their_pattern() {
+        assert_eq!(matched("/webhook").await, "/webhook");
+    }
+
+    #[actix_web::test]
+    async fn nothing_matched_is_none() {
+        assert_eq!(matched("/no/such/route").await, "none");
+    }
+}
@@ -0,0 +1,434 @@
+/*
+   PROMETHEUS METRICS
+    `GET /metrics` exposes, in the Prometheus text format:
+     - http_requests_total{method, route, status}        -> a counter per combination
+     - http_request_duration_seconds{method, route}      -> a histogram of response times
+     - http_response_size_bytes{method, route}           -> a summary of response body sizes:
+                                                             `_sum` is the bytes sent, `_count`
+                                                             the responses, so `_sum / _count`
+                                                             is the average size of a route and
+                                                             sum(`_sum`) everything sent
+
+    a middleware records every request. the `route` label is the MATCHED PATTERN
+     (eg: `/jobs/{id}`), not the raw path: with raw paths every job id would create a new time
+     series and the number of series would grow without limit. requests that matched nothing
+     share the single `unmatched` route. the pattern is looked up BEFORE the request goes in, so
+     a request answered with an error (which takes the request down with it) still gets its route.
+    the `method` label is bounded the same way: the standard methods keep their name, any other
+     (a client can send `FOO /`) is `other`.
+
+    the duration is measured until the response head is ready; for streamed bodies the time
+     spent sending the body afterwards is not included. the body size on the other hand is
+     counted AS THE BODY IS SENT, chunk by chunk (CountedBody), so a stream adds up while it
+     runs and a client that hangs up halfway only counts what it got. HEAD responses send no
+     body and count 0.
+*/
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    get,
+    http::{
+        header::{CacheControl, CacheDirective},
+        Method, StatusCode,
+    },
+    middleware::Next,
+    web::{self, Bytes},
+    Error, HttpResponse, Responder,
+};
+
+// upper bounds in seconds, the usual Prometheus defaults
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    // bucket_counts[i] = observations <= BUCKETS[i] (non cumulative, summed up when rendered)
+    bucket_counts: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = BUCKETS.iter().position(|&le| seconds <= le) {
+            self.bucket_counts[i] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Sizes {
+    bytes: u64,
+    responses: u64,
+}
+
+#[derive(Default)]
+struct Series {
+    // (method, route, status) -> count
+    requests: BTreeMap<(String, String, u16), u64>,
+    // (method, route) -> histogram
+    durations: BTreeMap<(String, String), Histogram>,
+    // (method, route) -> body sizes
+    sizes: BTreeMap<(String, String), Sizes>,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    series: Mutex<Series>,
+}
+
+impl Metrics {
+    fn record(&self, method: &str, route: String, status: StatusCode, seconds: f64) {
+        let mut series = self.series.lock().unwrap();
+        *series
+            .requests
+            .entry((method.to_owned(), route.clone(), status.as_u16()))
+            .or_default() += 1;
+        series
+            .durations
+            .entry((method.to_owned(), route))
+            .or_default()
+            .observe(seconds);
+    }
+
+    fn start_body(&self, key: &(String, String)) {
+        let mut series = self.series.lock().unwrap();
+        series.sizes.entry(key.clone()).or_default().responses += 1;
+    }
+
+    fn add_body_bytes(&self, key: &(String, String), bytes: usize) {
+        let mut series = self.series.lock().unwrap();
+        series.sizes.entry(key.clone()).or_default().bytes += bytes as u64;
+    }
+
+    pub fn total_requests(&self) -> u64 {
+        self.series.lock().unwrap().requests.values().sum()
+    }
+
+    pub fn render(&self) -> String {
+        let series = self.series.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Total number of HTTP requests.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, route, status), count) in &series.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{method}\",route=\"{}\",status=\"{status}\"}} {count}",
+                escape_label(route)
+            );
+        }
+
+        out.push_str("# HELP http_request_duration_seconds HTTP request duration in seconds.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), histogram) in &series.durations {
+            let labels = format!("method=\"{method}\",route=\"{}\"", escape_label(route));
+
+            let mut cumulative = 0;
+            for (le, count) in BUCKETS.iter().zip(histogram.bucket_counts) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{labels},le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{labels}}} {}",
+                histogram.count
+            );
+        }
+
+        out.push_str("# HELP http_response_size_bytes Bytes of response bodies sent.\n");
+        out.push_str("# TYPE http_response_size_bytes summary\n");
+        for ((method, route), sizes) in &series.sizes {
+            let labels = format!("method=\"{method}\",route=\"{}\"", escape_label(route));
+            let _ = writeln!(
+                out,
+                "http_response_size_bytes_sum{{{labels}}} {}",
+                sizes.bytes
+            );
+            let _ = writeln!(
+                out,
+                "http_response_size_bytes_count{{{labels}}} {}",
+                sizes.responses
+            );
+        }
+
+        out
+    }
+}
+
+// a response body that adds every chunk to the metrics on its way out
+struct CountedBody {
+    body: BoxBody,
+    metrics: web::Data<Metrics>,
+    key: (String, String),
+}
+
+impl MessageBody for CountedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let polled = Pin::new(&mut self.body).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &polled {
+            self.metrics.add_body_bytes(&self.key, chunk.len());
+        }
+        polled
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::PATCH => "PATCH",
+        Method::OPTIONS => "OPTIONS",
+        Method::CONNECT => "CONNECT",
+        Method::TRACE => "TRACE",
+        _ => "other",
+    }
+}
+
+pub async fn record_metrics(
+    metrics: web::Data<Metrics>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let method = method_label(req.method());
+    let route = req
+        .resource_map()
+        .match_pattern(req.path())
+        .unwrap_or_else(|| "unmatched".to_owned());
+    let started = Instant::now();
+
+    let res = next.call(req).await;
+    let seconds = started.elapsed().as_secs_f64();
+
+    match res {
+        Ok(res) => {
+            metrics.record(method, route.clone(), res.status(), seconds);
+
+            let key = (method.to_owned(), route);
+            metrics.start_body(&key);
+            Ok(res.map_body(|_, body| CountedBody {
+                body: body.boxed(),
+                metrics,
+                key,
+            }))
+        }
+        Err(err) => {
+            let status = err.as_response_error().status_code();
+            metrics.record(method, route, status, seconds);
+            Err(err)
+        }
+    }
+}
+
+#[get("/metrics")]
+pub async fn scrape(metrics: web::Data<Metrics>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(metrics.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{error, http::StatusCode, middleware, test, App};
+    use futures_util::StreamExt;
+
+    use super::*;
+    use crate::testing;
+
+    fn scrape_request() -> test::TestRequest {
+        test::TestRequest::get().uri("/metrics")
+    }
+
+    #[actix_web::test]
+    async fn requests_are_counted_by_method_route_and_status() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "ok");
+        let req = test::TestRequest::get().uri("/no/such/route").to_request();
+        test::call_service(&app, req).await;
+
+        let res = test::call_service(&app, scrape_request().to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+
+        assert!(
+            body.contains(r#"http_requests_total{method="GET",route="/healthz",status="200"} 1"#)
+        );
+        assert!(
+            body.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#)
+        );
+        assert!(body
+            .contains(r#"http_request_duration_seconds_count{method="GET",route="/healthz"} 1"#));
+        assert!(body.contains(r#"http_response_size_bytes_sum{method="GET",route="/healthz"} 2"#));
+    }
+
+    #[actix_web::test]
+    async fn an_unknown_method_is_labelled_other() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let method = Method::from_bytes(b"BREW").unwrap();
+        let req = test::TestRequest::default()
+            .method(method)
+            .uri("/healthz")
+            .to_request();
+        test::call_service(&app, req).await;
+
+        let body = test::call_and_read_body(&app, scrape_request().to_request()).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains(r#"method="other",route="/healthz""#));
+        assert!(!body.contains("BREW"));
+    }
+
+    #[actix_web::test]
+    async fn a_request_ending_in_an_error_keeps_its_route() {
+        let metrics = web::Data::new(Metrics::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(metrics.clone())
+                .wrap(middleware::from_fn(
+                    |_req: ServiceRequest, _next: Next<BoxBody>| async {
+                        Err::<ServiceResponse<BoxBody>, _>(error::ErrorForbidden("no"))
+                    },
+                ))
+                .wrap(middleware::from_fn(record_metrics))
+                .route("/items/{id}", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/items/7").to_request();
+        assert!(test::try_call_service(&app, req).await.is_err());
+
+        assert!(metrics
+            .render()
+            .contains(r#"http_requests_total{method="GET",route="/items/{id}",status="403"} 1"#));
+        assert_eq!(metrics.total_requests(), 1);
+    }
+
+    async fn sized(path: web::Path<usize>) -> HttpResponse {
+        HttpResponse::Ok().body("x".repeat(path.into_inner()))
+    }
+
+    // "abc" then "defgh", the second chunk only when the receiver is set
+    async fn streamed(
+        second: web::Data<std::sync::Mutex<Option<tokio::sync::oneshot::Receiver<()>>>>,
+    ) -> HttpResponse {
+        let second = second.lock().unwrap().take().unwrap();
+        let body = futures_util::stream::once(async { Ok::<_, Error>(Bytes::from("abc")) }).chain(
+            futures_util::stream::once(async move {
+                second.await.unwrap();
+                Ok(Bytes::from("defgh"))
+            }),
+        );
+        HttpResponse::Ok().streaming(body)
+    }
+
+    fn size_line(metrics: &Metrics, part: &str, route: &str) -> Option<String> {
+        let prefix = format!(r#"http_response_size_bytes_{part}{{method="GET",route="{route}"}} "#);
+        metrics
+            .render()
+            .lines()
+            .find_map(|line| line.strip_prefix(&prefix).map(str::to_owned))
+    }
+
+    #[actix_web::test]
+    async fn body_sizes_add_up_per_route() {
+        let metrics = web::Data::new(Metrics::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(metrics.clone())
+                .wrap(middleware::from_fn(record_metrics))
+                .route("/sized/{n}", web::get().to(sized))
+                .route("/empty", web::get().to(HttpResponse::NoContent)),
+        )
+        .await;
+
+        for n in [10, 250, 4000] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/sized/{n}"))
+                .to_request();
+            assert_eq!(test::call_and_read_body(&app, req).await.len(), n);
+        }
+        let req = test::TestRequest::get().uri("/empty").to_request();
+        test::call_and_read_body(&app, req).await;
+
+        assert_eq!(size_line(&metrics, "sum", "/sized/{n}").unwrap(), "4260");
+        assert_eq!(size_line(&metrics, "count", "/sized/{n}").unwrap(), "3");
+        assert_eq!(size_line(&metrics, "sum", "/empty").unwrap(), "0");
+        assert_eq!(size_line(&metrics, "count", "/empty").unwrap(), "1");
+    }
+
+    #[actix_web::test]
+    async fn a_stream_is_counted_as_it_is_sent() {
+        let metrics = web::Data::new(Metrics::default());
+        let (send_second, second) = tokio::sync::oneshot::channel();
+        let app = test::init_service(
+            App::new()
+                .app_data(metrics.clone())
+                .app_data(web::Data::new(std::sync::Mutex::new(Some(second))))
+                .wrap(middleware::from_fn(record_metrics))
+                .route("/stream", web::get().to(streamed)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/stream").to_request();
+        let mut body = test::call_service(&app, req).await.into_body();
+        assert_eq!(size_line(&metrics, "sum", "/stream").unwrap(), "0");
+
+        assert_eq!(testing::next_chunk(&mut body).await.unwrap(), "abc");
+        assert_eq!(size_line(&metrics, "sum", "/stream").unwrap(), "3");
+
+        send_second.send(()).unwrap();
+        assert_eq!(testing::next_chunk(&mut body).await.unwrap(), "defgh");
+        assert!(testing::next_chunk(&mut body).await.is_none());
+        assert_eq!(size_line(&metrics, "sum", "/stream").unwrap(), "8");
+        assert_eq!(size_line(&metrics, "count", "/stream").unwrap(), "1");
+    }
+}
@@ -0,0 +1,82 @@
+/*
+   ONE ROUTE FOR SEVERAL HOSTS: GET /site
+    the virtual hosting example above gives every host a scope of its own. when several domains
+     should get the SAME answer, guard::Any combines Host guards, the route matches when ANY one
+     of them does:
+
+        guard::Any(guard::Host("a.com")).or(guard::Host("b.com"))
+
+    `GET /site` with `Host: a.com` or `Host: b.com` (any port) answers with the host it matched:
+
+        { "host": "a.com" }
+
+    any other host matches no route for `/site` and falls through to the app's default, a `404`.
+     (guard::All, see webhook.rs, is the opposite: EVERY guard has to match.)
+*/
+
+use actix_web::{guard, http::header, web, HttpRequest, HttpResponse, Responder};
+use serde_json::json;
+
+// "a.com:8080" -> "a.com", the way guard::Host compares it
+fn request_host(req: &HttpRequest) -> Option<&str> {
+    req.uri().host().or_else(|| {
+        let value = req.headers().get(header::HOST)?.to_str().ok()?;
+        Some(value.rsplit_once(':').map_or(value, |(host, _port)| host))
+    })
+}
+
+async fn site(req: HttpRequest) -> impl Responder {
+    HttpResponse::Ok().json(json!({ "host": request_host(&req) }))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(
+        "/site",
+        web::get()
+            .guard(guard::Any(guard::Host("a.com")).or(guard::Host("b.com")))
+            .to(site),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    fn site_for(host: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri("/site")
+            .insert_header((header::HOST, host))
+    }
+
+    #[actix_web::test]
+    async fn every_listed_host_matches() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for (host, matched) in [
+            ("a.com", "a.com"),
+            ("b.com", "b.com"),
+            ("b.com:8080", "b.com"),
+        ] {
+            let res = test::call_service(&app, site_for(host).to_request()).await;
+
+            assert_eq!(res.status(), StatusCode::OK, "{host}");
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["host"], matched);
+        }
+    }
+
+    #[actix_web::test]
+    async fn an_unlisted_host_falls_through() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for host in ["c.com", "a.com.evil.example", "sub.a.com"] {
+            let res = test::call_service(&app, site_for(host).to_request()).await;
+
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "{host}");
+        }
+    }
+}
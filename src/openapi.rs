@@ -0,0 +1,141 @@
+/*
+   OPENAPI DOCUMENT + SWAGGER UI
+    `GET /api-docs/openapi.json` -> the OpenAPI 3 document of the JSON API
+    `GET /swagger-ui`            -> Swagger UI, to read and try it in the browser
+
+    the document is generated by utoipa from what the handlers and types declare about
+     themselves (#[utoipa::path] on the handlers, ToSchema / IntoParams on the types, see
+     users.rs), so it changes together with the code instead of drifting away from it. ApiDoc
+     below only lists which handlers belong in it.
+
+    the Swagger UI page is a bit of HTML that loads swagger-ui from a CDN and points it at the
+     document, so nothing has to be downloaded or bundled at build time. it sets its own
+     Content-Security-Policy (allowing that CDN), which security_headers() leaves alone.
+*/
+
+use actix_web::{
+    get,
+    http::header::{self, ContentType},
+    HttpResponse, Responder,
+};
+use utoipa::OpenApi;
+
+use crate::users;
+
+const SWAGGER_UI_CDN: &str = "https://unpkg.com/swagger-ui-dist@5";
+// CSP sources with a path only match that exact file, and the CDN redirects to the full version
+const SWAGGER_UI_ORIGIN: &str = "https://unpkg.com";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "actix-web demo API"),
+    paths(
+        users::list_users,
+        users::create_user,
+        users::show_user,
+        users::update_user
+    )
+)]
+struct ApiDoc;
+
+#[get("/api-docs/openapi.json")]
+pub async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[get("/swagger-ui")]
+pub async fn swagger_ui() -> impl Responder {
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <title>API docs</title>
+  <link rel="stylesheet" href="{SWAGGER_UI_CDN}/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="{SWAGGER_UI_CDN}/swagger-ui-bundle.js"></script>
+  <script src="/swagger-ui/init.js"></script>
+</body>
+</html>"#
+    );
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .insert_header((
+            header::CONTENT_SECURITY_POLICY,
+            format!(
+                "default-src 'self'; script-src 'self' {SWAGGER_UI_ORIGIN}; \
+                 style-src 'self' {SWAGGER_UI_ORIGIN}; img-src 'self' data:"
+            ),
+        ))
+        .body(page)
+}
+
+// a script of our own instead of an inline one, so the CSP doesn't need 'unsafe-inline'
+#[get("/swagger-ui/init.js")]
+pub async fn swagger_ui_init() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/javascript")
+        .body(r##"SwaggerUIBundle({ url: "/api-docs/openapi.json", dom_id: "#swagger-ui" });"##)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::Value;
+
+    use crate::testing;
+
+    async fn spec() -> Value {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::get()
+            .uri("/api-docs/openapi.json")
+            .to_request();
+        test::call_and_read_body_json(&app, req).await
+    }
+
+    #[actix_web::test]
+    async fn the_spec_describes_the_users_routes() {
+        let spec = spec().await;
+
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+        let users = &spec["paths"]["/users"];
+        assert!(users["get"].is_object());
+        assert!(users["post"]["requestBody"].is_object());
+        assert!(users["post"]["responses"]["201"].is_object());
+        assert!(users["post"]["responses"]["400"].is_object());
+
+        let user = &spec["paths"]["/users/{id}"];
+        assert!(user["get"]["responses"]["404"].is_object());
+        assert!(user["put"].is_object());
+    }
+
+    #[actix_web::test]
+    async fn the_spec_has_the_schemas_of_the_bodies() {
+        let spec = spec().await;
+
+        let user = &spec["components"]["schemas"]["User"];
+        assert!(user["properties"]["email"].is_object());
+        assert!(spec["components"]["schemas"]["NewUser"].is_object());
+    }
+
+    #[actix_web::test]
+    async fn swagger_ui_points_at_the_spec() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/swagger-ui").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let csp = res.headers().get("Content-Security-Policy").unwrap();
+        assert!(csp.to_str().unwrap().contains("https://unpkg.com"));
+
+        let req = test::TestRequest::get()
+            .uri("/swagger-ui/init.js")
+            .to_request();
+        let init = test::call_and_read_body(&app, req).await;
+        assert!(std::str::from_utf8(&init)
+            .unwrap()
+            .contains("/api-docs/openapi.json"));
+    }
+}
@@ -0,0 +1,111 @@
+/*
+   UUID PATH PARAMETERS
+    `GET /orders/{id}` takes the id as a web::Path<Uuid>, so the handler only ever runs with a
+     real uuid and never parses a string itself. the uuid parser accepts the usual spellings:
+
+        /orders/67e55044-10b1-426f-9247-bb680e5fe0c8      hyphenated
+        /orders/67e5504410b1426f9247bb680e5fe0c8          simple, no hyphens
+        /orders/67E55044-10B1-426F-9247-BB680E5FE0C8      any case
+
+     and the answer always shows the id hyphenated and lowercase. anything else is `400` with
+     the source `path` and what a uuid should look like (see extractor_errors.rs); a well formed
+     id that isn't an order is `404`.
+*/
+
+use actix_web::{get, web, HttpResponse};
+use serde::Serialize;
+use serde_json::json;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+struct Order {
+    id: Uuid,
+    item: &'static str,
+    quantity: u32,
+    status: &'static str,
+}
+
+// stand-in data
+const ORDERS: [Order; 2] = [
+    Order {
+        id: Uuid::from_u128(0x67e55044_10b1_426f_9247_bb680e5fe0c8),
+        item: "keyboard",
+        quantity: 1,
+        status: "shipped",
+    },
+    Order {
+        id: Uuid::from_u128(0x9f1c2a7e_4b3d_4e8a_8c55_0d2f6a1b3c4d),
+        item: "cable",
+        quantity: 3,
+        status: "pending",
+    },
+];
+
+#[get("/orders/{id}")]
+pub async fn show_order(id: web::Path<Uuid>) -> HttpResponse {
+    let id = id.into_inner();
+    match ORDERS.iter().find(|order| order.id == id) {
+        Some(order) => HttpResponse::Ok().json(order),
+        None => HttpResponse::NotFound().json(json!({ "error": format!("no order {id}") })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::Value;
+
+    use crate::testing;
+
+    async fn order(id: &str) -> (StatusCode, Value) {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::get()
+            .uri(&format!("/orders/{id}"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        (res.status(), test::read_body_json(res).await)
+    }
+
+    #[actix_web::test]
+    async fn every_spelling_of_a_uuid_finds_the_order() {
+        for id in [
+            "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "67e5504410b1426f9247bb680e5fe0c8",
+            "67E55044-10B1-426F-9247-BB680E5FE0C8",
+        ] {
+            let (status, body) = order(id).await;
+
+            assert_eq!(status, StatusCode::OK, "{id}");
+            assert_eq!(body["id"], "67e55044-10b1-426f-9247-bb680e5fe0c8");
+            assert_eq!(body["item"], "keyboard");
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_uuid_that_isnt_an_order_is_404() {
+        let (status, body) = order("00000000-0000-4000-8000-000000000000").await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body["error"],
+            "no order 00000000-0000-4000-8000-000000000000"
+        );
+    }
+
+    #[actix_web::test]
+    async fn a_malformed_uuid_is_400_saying_what_a_uuid_looks_like() {
+        for id in [
+            "42",
+            "67e55044-10b1-426f-9247",
+            "67e55044-10b1-426f-9247-bb680e5fe0cz",
+        ] {
+            let (status, body) = order(id).await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{id}");
+            assert_eq!(body["source"], "path");
+            let error = body["error"].as_str().unwrap();
+            assert!(error.starts_with("not a valid UUID"), "{error}");
+            assert!(error.contains("with or without hyphens"), "{error}");
+        }
+    }
+}
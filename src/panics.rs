@@ -0,0 +1,88 @@
+/*
+   CATCHING PANICS IN HANDLERS
+    a panic inside a handler unwinds through the worker's task: the client's connection is
+     dropped without any response, and it is not obvious from the outside what happened.
+
+    this middleware runs the rest of the chain inside catch_unwind(), so a panic is caught for
+     THAT request only: it is logged together with the request path and turned into a clean
+     `500 Internal Server Error` with a JSON body. other requests, even on the same worker,
+     are not affected.
+
+    the ServiceRequest is gone once it was handed to the (panicked) chain, so there is nothing
+     left to build a ServiceResponse from. the 500 is returned as an Error that carries the
+     finished response instead, and actix sends that. it wraps all the other middleware (only
+     the access log sits outside it), so panics in them are caught too.
+*/
+
+use std::{any::Any, panic::AssertUnwindSafe};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    get,
+    middleware::Next,
+    Error, HttpResponse,
+};
+use futures_util::FutureExt;
+use serde_json::json;
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
+
+pub async fn catch_panics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    // the request is moved into the chain, keep what the log line needs
+    let method = req.method().clone();
+    let path = req.path().to_owned();
+
+    // AssertUnwindSafe: after a panic nothing of the request is used again
+    match AssertUnwindSafe(next.call(req)).catch_unwind().await {
+        Ok(res) => res,
+        Err(payload) => {
+            let message = panic_message(payload.as_ref()).to_owned();
+            log::error!("handler panicked on {method} {path}: {message}");
+
+            let res = HttpResponse::InternalServerError()
+                .json(json!({ "error": "internal server error" }));
+            Err(InternalError::from_response(message, res).into())
+        }
+    }
+}
+
+// deliberately panics, to see the middleware above at work
+#[get("/boom")]
+pub async fn boom() -> HttpResponse {
+    panic!("boom! this handler always panics")
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body, http::StatusCode, test};
+
+    use crate::testing;
+
+    #[actix_web::test]
+    async fn a_panic_is_a_json_500_and_the_app_carries_on() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/boom").to_request();
+        let err = test::try_call_service(&app, req).await.err().unwrap();
+        let res = err.error_response();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, r#"{"error":"internal server error"}"#);
+
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,176 @@
+/*
+   PAYMENTS WITH IDEMPOTENCY KEYS
+    `POST /payments` with `{ "amount": 1250, "currency": "EUR" }` charges (simulated) the amount,
+     in the smallest unit of the currency, and answers `201` with the new payment.
+
+    a charge must never happen twice because a client retried after a timeout, so the request
+     has to carry an `Idempotency-Key` header (`400` without it). the idempotency middleware (see
+     idempotency.rs) does the rest:
+     - the same key and body again within the TTL  -> the stored `201` comes back (same payment
+        id, `Idempotent-Replayed: true`), the handler doesn't run
+     - the same key with a different body           -> `409 Conflict`
+     - the same key while the first attempt is still being handled -> `409 Conflict` too (the
+        first one reserved the key), so two attempts arriving together can't both charge
+*/
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::idempotency::IDEMPOTENCY_KEY_HEADER;
+
+#[derive(Deserialize)]
+pub struct NewPayment {
+    amount: u64,
+    currency: String,
+}
+
+#[derive(Serialize)]
+pub struct Payment {
+    id: Uuid,
+    amount: u64,
+    currency: String,
+    status: &'static str,
+    // unix milliseconds
+    created_at: u128,
+}
+
+fn is_currency_code(code: &str) -> bool {
+    code.len() == 3 && code.bytes().all(|byte| byte.is_ascii_uppercase())
+}
+
+#[post("/payments")]
+pub async fn create_payment(req: HttpRequest, body: web::Json<NewPayment>) -> HttpResponse {
+    if !req.headers().contains_key(IDEMPOTENCY_KEY_HEADER) {
+        return HttpResponse::BadRequest()
+            .json(json!({ "error": "an Idempotency-Key header is required" }));
+    }
+    let NewPayment { amount, currency } = body.into_inner();
+    if amount == 0 {
+        return HttpResponse::BadRequest().json(json!({ "error": "amount must be positive" }));
+    }
+    if !is_currency_code(&currency) {
+        return HttpResponse::BadRequest()
+            .json(json!({ "error": "currency must be a 3 letter code, eg: EUR" }));
+    }
+
+    let payment = Payment {
+        id: Uuid::new_v4(),
+        amount,
+        currency,
+        status: "succeeded",
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis()),
+    };
+    log::info!(
+        "charged {} {} (payment {})",
+        payment.amount,
+        payment.currency,
+        payment.id
+    );
+
+    HttpResponse::Created().json(payment)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::{http::StatusCode, middleware, rt, test, App};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        idempotency::{self, IdempotencyStore, IDEMPOTENT_REPLAY_HEADER},
+        testing,
+    };
+
+    fn payment(key: Option<&str>, body: Value) -> test::TestRequest {
+        let req = test::TestRequest::post().uri("/payments").set_json(body);
+        match key {
+            Some(key) => req.insert_header((IDEMPOTENCY_KEY_HEADER, key)),
+            None => req,
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_first_request_charges() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let body = json!({ "amount": 1250, "currency": "EUR" });
+        let res = test::call_service(&app, payment(Some("p1"), body).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(!res.headers().contains_key(IDEMPOTENT_REPLAY_HEADER));
+        let created: Value = test::read_body_json(res).await;
+        assert_eq!(created["amount"], 1250);
+        assert_eq!(created["currency"], "EUR");
+        assert_eq!(created["status"], "succeeded");
+        assert!(created["id"].as_str().unwrap().parse::<Uuid>().is_ok());
+    }
+
+    #[actix_web::test]
+    async fn a_duplicate_gets_the_stored_payment_and_a_reuse_conflicts() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let body = json!({ "amount": 1250, "currency": "EUR" });
+
+        let first: Value =
+            test::call_and_read_body_json(&app, payment(Some("p2"), body.clone()).to_request())
+                .await;
+        let res = test::call_service(&app, payment(Some("p2"), body).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get(IDEMPOTENT_REPLAY_HEADER).unwrap(), "true");
+        let replayed: Value = test::read_body_json(res).await;
+        assert_eq!(replayed, first);
+
+        let other = json!({ "amount": 9999, "currency": "EUR" });
+        let res = test::call_service(&app, payment(Some("p2"), other).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn a_key_is_forgotten_after_the_ttl() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(IdempotencyStore::new(
+                    Duration::from_millis(100),
+                    10,
+                )))
+                .app_data(web::Data::new(Config::default()))
+                .wrap(middleware::from_fn(idempotency::idempotency))
+                .service(create_payment),
+        )
+        .await;
+        let body = json!({ "amount": 500, "currency": "USD" });
+
+        let first: Value =
+            test::call_and_read_body_json(&app, payment(Some("p3"), body.clone()).to_request())
+                .await;
+        rt::time::sleep(Duration::from_millis(150)).await;
+        let res = test::call_service(&app, payment(Some("p3"), body).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(!res.headers().contains_key(IDEMPOTENT_REPLAY_HEADER));
+        let second: Value = test::read_body_json(res).await;
+        assert_ne!(second["id"], first["id"]);
+    }
+
+    #[actix_web::test]
+    async fn a_payment_needs_a_key_an_amount_and_a_currency() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for (key, body) in [
+            (None, json!({ "amount": 100, "currency": "EUR" })),
+            (Some("p4"), json!({ "amount": 0, "currency": "EUR" })),
+            (Some("p5"), json!({ "amount": 100, "currency": "euro" })),
+        ] {
+            let res = test::call_service(&app, payment(key, body.clone()).to_request()).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{key:?} {body}");
+        }
+    }
+}
@@ -0,0 +1,130 @@
+/*
+   COOKIES
+    `POST /prefs` with `{ "theme": "dark" }` -> stores the theme in a `theme` cookie
+    `GET /prefs`                            -> `{ "theme": ... }`, `light` without the cookie
+
+    the cookie is set with the response builder's cookie() and read back with
+     HttpRequest::cookie(). it lives for a year (Max-Age) on the whole site (Path=/).
+
+    cookie values can't hold `;`, `,`, spaces and the like, so the value is percent-encoded
+     before it is set. cookie() writes the value as it is given, while HttpRequest::cookie()
+     decodes it again, so the handler reading it sees exactly what was posted.
+
+    unlike the session cookie this one is neither signed nor encrypted: fine for a theme,
+     never for anything that is trusted afterwards.
+*/
+
+use actix_web::{
+    cookie::{time::Duration, Cookie},
+    get, post, web, HttpRequest, HttpResponse,
+};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use serde_json::json;
+
+const THEME_COOKIE: &str = "theme";
+const DEFAULT_THEME: &str = "light";
+const MAX_THEME_LEN: usize = 64;
+
+#[derive(Deserialize)]
+pub struct Prefs {
+    theme: String,
+}
+
+#[post("/prefs")]
+pub async fn set_prefs(body: web::Json<Prefs>) -> HttpResponse {
+    let theme = body.theme.trim();
+    if theme.is_empty() || theme.len() > MAX_THEME_LEN {
+        return HttpResponse::BadRequest()
+            .body(format!("theme must be 1 to {MAX_THEME_LEN} characters"));
+    }
+
+    let cookie = Cookie::build(
+        THEME_COOKIE,
+        utf8_percent_encode(theme, NON_ALPHANUMERIC).to_string(),
+    )
+    .path("/")
+    .max_age(Duration::days(365))
+    .finish();
+
+    HttpResponse::Ok()
+        .cookie(cookie)
+        .json(json!({ "theme": theme }))
+}
+
+#[get("/prefs")]
+pub async fn show_prefs(req: HttpRequest) -> HttpResponse {
+    let theme = req.cookie(THEME_COOKIE).map_or_else(
+        || DEFAULT_THEME.to_owned(),
+        |cookie| cookie.value().to_owned(),
+    );
+
+    HttpResponse::Ok().json(json!({ "theme": theme }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::{header, StatusCode},
+        test,
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    #[actix_web::test]
+    async fn a_set_theme_is_read_back() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/prefs")
+            .set_json(json!({ "theme": "solarized; dark" }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let set_cookie = res
+            .headers()
+            .get(header::SET_COOKIE)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let cookie = Cookie::parse(set_cookie.to_owned()).unwrap();
+        assert_eq!(cookie.value(), "solarized%3B%20dark");
+        assert_eq!(cookie.path(), Some("/"));
+        assert_eq!(cookie.max_age(), Some(Duration::days(365)));
+
+        // sent back the way a browser does: name=value as it was set
+        let req = test::TestRequest::get()
+            .uri("/prefs")
+            .insert_header((header::COOKIE, format!("theme={}", cookie.value())))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["theme"], "solarized; dark");
+    }
+
+    #[actix_web::test]
+    async fn no_cookie_is_light() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/prefs").to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["theme"], DEFAULT_THEME);
+    }
+
+    #[actix_web::test]
+    async fn an_empty_theme_is_400() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/prefs")
+            .set_json(json!({ "theme": "  " }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(!res.headers().contains_key(header::SET_COOKIE));
+    }
+}
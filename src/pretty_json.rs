@@ -0,0 +1,170 @@
+/*
+   PRETTY JSON ON REQUEST
+    JSON answers are compact, which is what programs want and people can't read. with
+     `?pretty=1` (or `?pretty=true`, or a bare `?pretty`) on ANY route, this middleware parses a
+     JSON answer and writes it again indented, eg for looking at `/users?pretty=1` in a browser.
+
+    - only bodies with a JSON Content-Type (`application/json`, or a `+json` one such as
+       `application/problem+json`) change, anything else passes through as it is, as does
+       every answer without the flag
+    - only bodies of a known size up to MAX_BYTES: a stream (`/users/stream`, SSE) would have
+       to be buffered whole first, which is exactly what streaming avoids
+    - a body that claims to be JSON but doesn't parse is left alone
+    - the keys of an object come out sorted (serde_json's Value keeps them in a BTreeMap), the
+       values are the same
+
+    it is the innermost middleware, so the rest (HEAD, the cache, the body log) see the
+     indented body with its own Content-Length.
+*/
+
+use actix_web::{
+    body::{self, BodySize, BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    mime,
+    web::Bytes,
+    Error,
+};
+use serde_json::Value;
+
+const MAX_BYTES: u64 = 1024 * 1024;
+
+fn wants_pretty(query: &str) -> bool {
+    query
+        .split('&')
+        .any(|pair| matches!(pair, "pretty" | "pretty=1" | "pretty=true"))
+}
+
+fn is_json(res: &ServiceResponse<impl MessageBody>) -> bool {
+    res.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .is_some_and(|mime| {
+            mime.essence_str() == mime::APPLICATION_JSON.essence_str()
+                || (mime.type_() == mime::APPLICATION && mime.suffix() == Some(mime::JSON))
+        })
+}
+
+fn indented(compact: &[u8]) -> Option<Bytes> {
+    let value: Value = serde_json::from_slice(compact).ok()?;
+    serde_json::to_vec_pretty(&value).ok().map(Bytes::from)
+}
+
+pub async fn pretty_print(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let pretty = wants_pretty(req.query_string());
+    let res = next.call(req).await?;
+
+    let small_enough =
+        matches!(res.response().body().size(), BodySize::Sized(size) if size <= MAX_BYTES);
+    if !pretty || !is_json(&res) || !small_enough {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, res) = res.into_parts();
+    let (res, res_body) = res.into_parts();
+    let compact = body::to_bytes(res_body).await.map_err(Into::into)?;
+    let res_body = indented(&compact).unwrap_or(compact);
+
+    Ok(ServiceResponse::new(
+        req,
+        res.set_body(res_body).map_into_boxed_body(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::header::ContentType, middleware, test, web, App, HttpResponse};
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing;
+
+    fn get(uri: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(uri)
+    }
+
+    #[actix_web::test]
+    async fn the_flag_indents_the_same_json() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "name": "Ada", "email": "ada@example.com" }))
+            .to_request();
+        let created: Value = test::call_and_read_body_json(&app, req).await;
+        let uri = format!("/users/{}", created["id"]);
+
+        let compact = test::call_and_read_body(&app, get(&uri).to_request()).await;
+        let res = test::call_service(&app, get(&format!("{uri}?pretty=1")).to_request()).await;
+        let size = res.response().body().size();
+        let pretty = test::read_body(res).await;
+
+        assert!(!compact.contains(&b'\n'));
+        let value: Value = serde_json::from_slice(&compact).unwrap();
+        assert_eq!(pretty, serde_json::to_vec_pretty(&value).unwrap());
+        assert_eq!(size, BodySize::Sized(pretty.len() as u64));
+    }
+
+    #[actix_web::test]
+    async fn every_spelling_of_the_flag_counts() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for query in ["pretty", "pretty=1", "pretty=true", "limit=5&pretty"] {
+            let body =
+                test::call_and_read_body(&app, get(&format!("/users?{query}")).to_request()).await;
+            assert!(body.contains(&b'\n'), "{query}");
+        }
+        for query in ["pretty=0", "prettyish", ""] {
+            let body =
+                test::call_and_read_body(&app, get(&format!("/users?{query}")).to_request()).await;
+            assert!(!body.contains(&b'\n'), "{query}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn other_bodies_pass_through_untouched() {
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(pretty_print))
+                .route("/text", web::get().to(|| async { "{\"a\":1}" }))
+                .route(
+                    "/problem",
+                    web::get().to(|| async {
+                        HttpResponse::BadRequest()
+                            .content_type("application/problem+json")
+                            .body(r#"{"title":"bad"}"#)
+                    }),
+                )
+                .route(
+                    "/broken",
+                    web::get().to(|| async {
+                        HttpResponse::Ok()
+                            .content_type(ContentType::json())
+                            .body("{not json")
+                    }),
+                ),
+        )
+        .await;
+
+        let text = test::call_and_read_body(&app, get("/text?pretty=1").to_request()).await;
+        assert_eq!(text, "{\"a\":1}");
+        let broken = test::call_and_read_body(&app, get("/broken?pretty=1").to_request()).await;
+        assert_eq!(broken, "{not json");
+        // a +json type is JSON too
+        let problem = test::call_and_read_body(&app, get("/problem?pretty=1").to_request()).await;
+        assert_eq!(problem, "{\n  \"title\": \"bad\"\n}");
+    }
+
+    #[actix_web::test]
+    async fn a_stream_stays_compact() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let body = test::call_and_read_body(&app, get("/users/stream?pretty=1").to_request()).await;
+
+        assert_eq!(body, "[]");
+    }
+}
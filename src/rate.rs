@@ -0,0 +1,168 @@
+/*
+   BACKGROUND-REFRESHED CACHE
+    `GET /rate` returns an exchange rate that would be too slow (or too expensive) to fetch on
+     every request. instead ONE background task fetches it every REFRESH_INTERVAL and stores it in
+     the shared RateCache; the handler only reads what is there, it never waits on the fetch.
+
+    - the value sits behind an RwLock: any number of readers at once, the refresher only takes the
+       write lock for the moment it swaps the new value in (never across the fetch itself)
+    - the task is spawned once at startup (see AppBuilder::start_background_tasks()), not per
+       worker, so there is a single refresher however many workers run
+    - until the first fetch succeeds there is nothing to serve, so the handler answers `503`
+    - a failed refresh keeps the previous value: a slightly old rate beats no rate. `fetched_at`
+       lets the client see how old it is
+
+    the fetcher is passed in as a plain async closure, so a fake one (eg: counting up) can
+     replace the simulated upstream without touching the cache or the handler.
+*/
+
+use std::{
+    future::Future,
+    sync::RwLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{get, rt, web, HttpResponse, Responder};
+use serde::Serialize;
+use tokio::time::{self, MissedTickBehavior};
+
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Serialize)]
+pub struct Rate {
+    pub eur_usd: f64,
+    // unix seconds
+    pub fetched_at: u64,
+}
+
+#[derive(Default)]
+pub struct RateCache {
+    latest: RwLock<Option<Rate>>,
+}
+
+impl RateCache {
+    pub fn get(&self) -> Option<Rate> {
+        *self.latest.read().unwrap()
+    }
+
+    fn set(&self, rate: Rate) {
+        *self.latest.write().unwrap() = Some(rate);
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+// stands in for the real upstream: takes a moment and wobbles a little around 1.08
+pub async fn fetch_rate() -> Result<f64, String> {
+    time::sleep(Duration::from_millis(200)).await;
+    let wobble = (unix_now() % 100) as f64 / 10_000.0;
+    Ok(1.08 + wobble)
+}
+
+// fetches right away, then every `interval`; runs for as long as the server does
+pub fn spawn_refresh<F, Fut>(cache: web::Data<RateCache>, interval: Duration, fetch: F)
+where
+    F: Fn() -> Fut + 'static,
+    Fut: Future<Output = Result<f64, String>>,
+{
+    rt::spawn(async move {
+        let mut ticks = time::interval(interval);
+        // a slow fetch shouldn't be followed by a burst of catch-up fetches
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticks.tick().await; // <- the first tick completes immediately
+            match fetch().await {
+                Ok(eur_usd) => cache.set(Rate {
+                    eur_usd,
+                    fetched_at: unix_now(),
+                }),
+                Err(err) => {
+                    log::warn!("refreshing the exchange rate failed, keeping the old one: {err}")
+                }
+            }
+        }
+    });
+}
+
+#[get("/rate")]
+pub async fn show_rate(cache: web::Data<RateCache>) -> impl Responder {
+    match cache.get() {
+        Some(rate) => HttpResponse::Ok().json(rate),
+        None => HttpResponse::ServiceUnavailable()
+            .insert_header(("Retry-After", "1"))
+            .body("the exchange rate hasn't been fetched yet"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use actix_web::{http::StatusCode, test, App};
+    use serde_json::Value;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn the_cache_follows_the_fetcher() {
+        let cache = web::Data::new(RateCache::default());
+        let app = test::init_service(App::new().app_data(cache.clone()).service(show_rate)).await;
+
+        let req = test::TestRequest::get().uri("/rate").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers().get("Retry-After").unwrap(), "1");
+
+        // a fake upstream: 1.0, 2.0, 3.0, ...
+        let counter = Rc::new(Cell::new(0));
+        spawn_refresh(cache.clone(), Duration::from_millis(20), move || {
+            counter.set(counter.get() + 1);
+            let rate = counter.get() as f64;
+            async move { Ok(rate) }
+        });
+
+        let mut seen = Vec::new();
+        while seen.len() < 3 {
+            let req = test::TestRequest::get().uri("/rate").to_request();
+            let res = test::call_service(&app, req).await;
+            if res.status() == StatusCode::OK {
+                let rate: Value = test::read_body_json(res).await;
+                let eur_usd = rate["eur_usd"].as_f64().unwrap();
+                if seen.last() != Some(&eur_usd) {
+                    seen.push(eur_usd);
+                }
+            }
+            time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert_eq!(seen, [1.0, 2.0, 3.0]);
+    }
+
+    #[actix_web::test]
+    async fn a_failed_refresh_keeps_the_old_rate() {
+        let cache = web::Data::new(RateCache::default());
+        let fetches = Rc::new(Cell::new(0));
+        let counter = fetches.clone();
+        spawn_refresh(cache.clone(), Duration::from_millis(10), move || {
+            counter.set(counter.get() + 1);
+            let first = counter.get() == 1;
+            async move {
+                match first {
+                    true => Ok(1.5),
+                    false => Err("upstream down".to_owned()),
+                }
+            }
+        });
+
+        while fetches.get() < 3 {
+            time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert_eq!(cache.get().unwrap().eur_usd, 1.5);
+    }
+}
@@ -0,0 +1,270 @@
+/*
+   RATE LIMIT PER CLIENT
+    OFF unless `rate_limit` is set (env RATE_LIMIT, see config.rs). then every client (its
+     address, see below) may send `rate_limit` requests per `rate_limit_window_secs` (env
+     RATE_LIMIT_WINDOW_SECS, default 60). the window is FIXED: it starts with the client's first request and, once it is over, the next
+     request starts a new one with the full allowance again.
+
+    every answer says where the client stands, so a well-behaved client can slow down on its
+     own before it is refused:
+
+        X-RateLimit-Limit: 600        <- requests per window
+        X-RateLimit-Remaining: 597    <- left in the current window, after this request
+        X-RateLimit-Reset: 42         <- seconds until the window ends (rounded up)
+
+    past the limit the answer is `429 Too Many Requests` with `Retry-After` set to those same
+     seconds until the window ends, computed from when it started, so a client waiting exactly
+     that long is let in again (and not a fixed guess that is too short or needlessly long).
+
+    - the counts are shared by ALL workers (created once in AppBuilder::new()), unlike the
+       in-flight limit: a client's requests may land on any worker
+    - the client is its peer address, or with trust_proxy the address the proxy reports
+       (X-Forwarded-For / Forwarded), same as the access log
+    - a refused request doesn't count, so a client that keeps hammering isn't locked out for
+       longer than the window
+    - the limit and the window are read for every request, so config.toml edits apply at once;
+       a client already past a lowered limit is simply refused until its window ends
+    - `rate_limit = 0` (the default) switches it off, and the headers with it; `/healthz` and
+       `/readyz` are never limited, like in inflight.rs
+    - at most MAX_CLIENTS windows are kept: when a new client comes in and they are all taken,
+       the ended windows are dropped, and if none has ended the oldest one is
+*/
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+
+use crate::config::Config;
+
+pub const MAX_CLIENTS: usize = 100_000;
+
+const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+const EXEMPT_PATHS: [&str; 2] = ["/healthz", "/readyz"];
+
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+// where a client stands after one more request
+struct Quota {
+    allowed: bool,
+    limit: u32,
+    remaining: u32,
+    reset_secs: u64,
+}
+
+impl RateLimiter {
+    fn hit(&self, client: String, limit: u32, window: Duration) -> Quota {
+        let now = Instant::now();
+        // the counts stay valid even if a request panicked while holding the lock
+        let mut windows = self.windows.lock().unwrap_or_else(PoisonError::into_inner);
+        if windows.len() >= MAX_CLIENTS && !windows.contains_key(&client) {
+            windows.retain(|_, entry| now.duration_since(entry.started) < window);
+            if windows.len() >= MAX_CLIENTS {
+                let oldest = windows
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.started)
+                    .map(|(client, _)| client.clone());
+                if let Some(oldest) = oldest {
+                    windows.remove(&oldest);
+                }
+            }
+        }
+
+        let entry = windows.entry(client).or_insert(Window {
+            started: now,
+            count: 0,
+        });
+        if now.duration_since(entry.started) >= window {
+            *entry = Window {
+                started: now,
+                count: 0,
+            };
+        }
+        let allowed = entry.count < limit;
+        if allowed {
+            entry.count += 1;
+        }
+
+        let reset_in = window.saturating_sub(now.duration_since(entry.started));
+        Quota {
+            allowed,
+            limit,
+            // the limit may have been lowered (hot reload) below what the client already used
+            remaining: limit.saturating_sub(entry.count),
+            // rounded up: waiting 41 seconds when 41.3 are left would still be refused
+            reset_secs: reset_in.as_secs() + u64::from(reset_in.subsec_nanos() > 0),
+        }
+    }
+}
+
+fn client_addr(req: &ServiceRequest, trust_proxy: bool) -> String {
+    let addr = match trust_proxy {
+        true => req
+            .connection_info()
+            .realip_remote_addr()
+            .map(str::to_owned),
+        false => req.peer_addr().map(|addr| addr.ip().to_string()),
+    };
+    addr.unwrap_or_default()
+}
+
+fn insert_quota_headers(headers: &mut HeaderMap, quota: &Quota) {
+    headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(quota.limit));
+    headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from(quota.remaining));
+    headers.insert(X_RATELIMIT_RESET, HeaderValue::from(quota.reset_secs));
+}
+
+pub async fn limit_rate(
+    limiter: web::Data<RateLimiter>,
+    config: web::Data<Config>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if config.rate_limit == 0 || EXEMPT_PATHS.contains(&req.path()) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    }
+
+    let client = client_addr(&req, config.trust_proxy);
+    let window = Duration::from_secs(config.rate_limit_window_secs);
+    let quota = limiter.hit(client, config.rate_limit, window);
+
+    if !quota.allowed {
+        let mut res = HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, quota.reset_secs))
+            .body("too many requests, see Retry-After");
+        insert_quota_headers(res.headers_mut(), &quota);
+        return Ok(req.into_response(res));
+    }
+
+    let mut res = next
+        .call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body)?;
+    insert_quota_headers(res.headers_mut(), &quota);
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, rt::time::sleep, test};
+
+    use super::*;
+    use crate::testing;
+
+    fn limited(rate_limit: u32, window_secs: u64) -> Config {
+        Config {
+            rate_limit,
+            rate_limit_window_secs: window_secs,
+            ..Config::default()
+        }
+    }
+
+    fn from(client: &str, uri: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri(uri)
+            .peer_addr(format!("{client}:40000").parse().unwrap())
+    }
+
+    fn number<B>(res: &ServiceResponse<B>, name: impl header::AsHeaderName) -> u64 {
+        let value = res.headers().get(name).unwrap();
+        value.to_str().unwrap().parse().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn the_headers_count_down_to_a_429() {
+        let app = test::init_service(testing::builder_with(limited(3, 60)).await.build()).await;
+
+        for remaining in [2, 1, 0] {
+            let res = test::call_service(&app, from("10.0.0.1", "/").to_request()).await;
+
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(number(&res, X_RATELIMIT_LIMIT), 3);
+            assert_eq!(number(&res, X_RATELIMIT_REMAINING), remaining);
+            assert_eq!(number(&res, X_RATELIMIT_RESET), 60);
+        }
+
+        let res = test::call_service(&app, from("10.0.0.1", "/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(number(&res, X_RATELIMIT_REMAINING), 0);
+        assert_eq!(number(&res, header::RETRY_AFTER), 60);
+        assert_eq!(number(&res, X_RATELIMIT_RESET), 60);
+
+        // another client has a window of its own
+        let res = test::call_service(&app, from("10.0.0.2", "/").to_request()).await;
+        assert_eq!(number(&res, X_RATELIMIT_REMAINING), 2);
+    }
+
+    #[actix_web::test]
+    async fn retry_after_is_the_time_left_in_the_window() {
+        let app = test::init_service(testing::builder_with(limited(1, 2)).await.build()).await;
+        let res = test::call_service(&app, from("10.0.0.1", "/").to_request()).await;
+        assert_eq!(number(&res, X_RATELIMIT_RESET), 2);
+
+        sleep(Duration::from_millis(1200)).await;
+        let res = test::call_service(&app, from("10.0.0.1", "/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after = number(&res, header::RETRY_AFTER);
+        assert_eq!(retry_after, 1);
+
+        // waiting exactly that long is enough
+        sleep(Duration::from_secs(retry_after)).await;
+        let res = test::call_service(&app, from("10.0.0.1", "/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(number(&res, X_RATELIMIT_RESET), 2);
+    }
+
+    #[actix_web::test]
+    async fn probes_and_an_unset_limit_get_no_headers() {
+        let app = test::init_service(testing::builder_with(limited(1, 60)).await.build()).await;
+        for _ in 0..3 {
+            let res = test::call_service(&app, from("10.0.0.1", "/healthz").to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert!(res.headers().get(X_RATELIMIT_LIMIT).is_none());
+        }
+
+        let app = test::init_service(testing::builder_with(limited(0, 60)).await.build()).await;
+        for _ in 0..3 {
+            let res = test::call_service(&app, from("10.0.0.1", "/").to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert!(res.headers().get(X_RATELIMIT_REMAINING).is_none());
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_refused_request_does_not_count() {
+        let limiter = RateLimiter::default();
+        let window = Duration::from_secs(60);
+
+        assert!(limiter.hit("a".to_owned(), 1, window).allowed);
+        for _ in 0..5 {
+            assert!(!limiter.hit("a".to_owned(), 1, window).allowed);
+        }
+        // a raised limit (hot reload) lets the client in again in the same window
+        let quota = limiter.hit("a".to_owned(), 3, window);
+        assert!(quota.allowed);
+        assert_eq!(quota.remaining, 1);
+    }
+}
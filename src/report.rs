@@ -0,0 +1,145 @@
+/*
+   STREAMED CSV REPORT
+    `GET /report.csv?rows=<n>` sends a CSV file that is generated WHILE it is being sent: the
+     body is an async stream that produces the next row only when the previous one was taken, so
+     a report with millions of rows needs the memory of one row, not of the whole file. the size
+     isn't known upfront, so it goes out with chunked transfer encoding.
+
+    if generating a row fails half way, the stream yields an error. actix then closes the
+     connection WITHOUT the final empty chunk, so the client sees a broken download instead of
+     a short file that looks complete. `?fail_at=<row>` simulates such a failure.
+*/
+
+use std::io;
+
+use actix_web::{
+    get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web::{self, Bytes},
+    HttpResponse,
+};
+use futures_util::{stream, Stream, StreamExt};
+use serde::Deserialize;
+
+const DEFAULT_ROWS: u64 = 1000;
+const MAX_ROWS: u64 = 10_000_000;
+
+#[derive(Deserialize)]
+pub struct ReportParams {
+    rows: Option<u64>,
+    fail_at: Option<u64>,
+}
+
+// quotes a field when it contains a separator, a quote or a line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+// stands in for reading a row from somewhere
+fn generate_row(n: u64) -> Result<String, io::Error> {
+    let product = match n % 3 {
+        0 => "widget",
+        1 => "gadget, large",
+        _ => "the \"thing\"",
+    };
+    Ok(format!(
+        "{n},{},{}.{:02}\r\n",
+        csv_field(product),
+        n % 100,
+        n % 97
+    ))
+}
+
+fn report_stream(rows: u64, fail_at: Option<u64>) -> impl Stream<Item = Result<Bytes, io::Error>> {
+    let header = stream::once(async { Ok(Bytes::from_static(b"id,product,price\r\n")) });
+
+    // the unfold state is just the next row number
+    let body = stream::unfold(1, move |n| async move {
+        if n > rows {
+            return None;
+        }
+        let row = match fail_at {
+            Some(fail_at) if n == fail_at => Err(io::Error::other(format!("row {n} failed"))),
+            _ => generate_row(n),
+        };
+        match row {
+            Ok(row) => Some((Ok(Bytes::from(row)), n + 1)),
+            // the error ends the response; nothing comes after it
+            Err(err) => {
+                log::error!("report generation failed: {err}");
+                Some((Err(err), rows + 1))
+            }
+        }
+    });
+
+    header.chain(body)
+}
+
+#[get("/report.csv")]
+pub async fn report(params: web::Query<ReportParams>) -> HttpResponse {
+    let rows = params.rows.unwrap_or(DEFAULT_ROWS).min(MAX_ROWS);
+
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("report.csv".to_owned())],
+        })
+        .streaming(report_stream(rows, params.fail_at))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        body::{self, BodySize, MessageBody},
+        http::{header, StatusCode},
+        test,
+    };
+
+    use crate::testing;
+
+    #[actix_web::test]
+    async fn the_report_is_streamed_row_by_row() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/report.csv?rows=3")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/csv; charset=utf-8"
+        );
+        assert_eq!(
+            res.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=\"report.csv\""
+        );
+        assert_eq!(res.response().body().size(), BodySize::Stream);
+        assert_eq!(
+            test::read_body(res).await,
+            "id,product,price\r\n\
+             1,\"gadget, large\",1.01\r\n\
+             2,\"the \"\"thing\"\"\",2.02\r\n\
+             3,widget,3.03\r\n"
+        );
+    }
+
+    #[actix_web::test]
+    async fn a_failing_row_breaks_the_body() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/report.csv?rows=10&fail_at=4")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(body::to_bytes(res.into_body()).await.is_err());
+    }
+}
@@ -0,0 +1,143 @@
+/*
+   PATH + QUERY IN ONE HANDLER
+    `GET /orgs/{org}/repos?sort=name` needs a value from the path AND one from the query string.
+     every extractor is just another handler argument, so both are simply listed:
+
+        async fn list_repos(org: web::Path<String>, params: web::Query<RepoParams>)
+
+    actix runs them one after the other before the handler; if either fails, the handler never
+     runs and the client gets that extractor's error:
+     - `sort` missing         -> `#[serde(default)]` fills in `updated`
+     - `sort=stars` (unknown) -> web::Query fails, `400 Bad Request`
+     - an org name that isn't letters, digits or `-` -> `400 Bad Request` from the handler (a
+        String path segment always extracts, so that check has to happen here)
+*/
+
+use actix_web::{get, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepoSort {
+    Created,
+    #[default]
+    Updated,
+    Name,
+}
+
+#[derive(Deserialize)]
+pub struct RepoParams {
+    #[serde(default)]
+    sort: RepoSort,
+}
+
+#[derive(Serialize)]
+struct Repo {
+    name: &'static str,
+    created: u32,
+    updated: u32,
+}
+
+// stand-in data: (name, created, updated) as days since some epoch
+const REPOS: [Repo; 3] = [
+    Repo {
+        name: "website",
+        created: 10,
+        updated: 300,
+    },
+    Repo {
+        name: "api",
+        created: 50,
+        updated: 320,
+    },
+    Repo {
+        name: "cli",
+        created: 120,
+        updated: 200,
+    },
+];
+
+fn is_valid_org(org: &str) -> bool {
+    !org.is_empty() && org.len() <= 39 && org.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+#[get("/orgs/{org}/repos")]
+pub async fn list_repos(org: web::Path<String>, params: web::Query<RepoParams>) -> HttpResponse {
+    let org = org.into_inner();
+    if !is_valid_org(&org) {
+        return HttpResponse::BadRequest().body("org must be letters, digits or `-`");
+    }
+
+    let mut repos: Vec<&Repo> = REPOS.iter().collect();
+    match params.sort {
+        RepoSort::Created => repos.sort_by_key(|repo| std::cmp::Reverse(repo.created)),
+        RepoSort::Updated => repos.sort_by_key(|repo| std::cmp::Reverse(repo.updated)),
+        RepoSort::Name => repos.sort_by_key(|repo| repo.name),
+    }
+
+    HttpResponse::Ok().json(json!({
+        "org": org,
+        "sort": params.sort,
+        "repos": repos,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::Value;
+
+    use crate::testing;
+
+    fn repo_names(body: &Value) -> Vec<&str> {
+        body["repos"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|repo| repo["name"].as_str().unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn the_path_and_the_query_both_reach_the_handler() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/orgs/acme/repos?sort=name")
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["org"], "acme");
+        assert_eq!(body["sort"], "name");
+        assert_eq!(repo_names(&body), ["api", "cli", "website"]);
+    }
+
+    #[actix_web::test]
+    async fn a_missing_sort_is_updated() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/orgs/acme/repos")
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["sort"], "updated");
+        assert_eq!(repo_names(&body), ["api", "website", "cli"]);
+    }
+
+    #[actix_web::test]
+    async fn an_invalid_org_or_sort_is_400() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for uri in [
+            "/orgs/ac_me/repos",
+            "/orgs/ac_me/repos?sort=name",
+            "/orgs/acme/repos?sort=stars",
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+}
@@ -0,0 +1,118 @@
+/*
+   REQUEST ID MIDDLEWARE
+    every response carries an `X-Request-Id` header so a request can be followed through logs.
+     - if the client already sent one (eg: a load balancer or another service), it is echoed back
+     - otherwise a new UUID v4 is generated
+
+    the id is also stored in the request extensions, so any handler (or later middleware)
+     can read it with `req.extensions().get::<RequestId>()`
+
+    `middleware::from_fn` is the function form of a Transform middleware: it gets the request,
+     can do work before calling `next`, and can modify the response after it.
+*/
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    get,
+    http::header::{CacheControl, CacheDirective, HeaderName, HeaderValue},
+    middleware::Next,
+    Error, HttpMessage, HttpRequest, HttpResponse, Responder,
+};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+// the value stored in request extensions
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    let mut res = next.call(req).await?;
+
+    // the incoming value was already a valid header value and a UUID always is, so this can't fail
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    Ok(res)
+}
+
+#[get("/whoami")]
+pub async fn whoami(req: HttpRequest) -> impl Responder {
+    match req.extensions().get::<RequestId>() {
+        // different for every request, must never come from the response cache
+        Some(RequestId(id)) => HttpResponse::Ok()
+            .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+            .body(id.clone()),
+        // only happens if the route is mounted without the middleware
+        None => HttpResponse::InternalServerError().body("request id middleware is not registered"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+
+    use super::*;
+    use crate::testing;
+
+    #[actix_web::test]
+    async fn generates_an_id_and_hands_it_to_handlers() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/whoami").to_request();
+        let res = test::call_service(&app, req).await;
+        let header = res.headers().get(REQUEST_ID_HEADER).cloned().unwrap();
+        let body = test::read_body(res).await;
+
+        assert!(Uuid::parse_str(header.to_str().unwrap()).is_ok());
+        assert_eq!(body, header.as_bytes());
+    }
+
+    #[actix_web::test]
+    async fn echoes_the_client_id() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/whoami")
+            .insert_header((REQUEST_ID_HEADER, "lb-1234"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "lb-1234");
+        assert_eq!(test::read_body(res).await, "lb-1234");
+    }
+
+    #[actix_web::test]
+    async fn replaces_an_empty_id() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/healthz")
+            .insert_header((REQUEST_ID_HEADER, ""))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        let id = res
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(Uuid::parse_str(id).is_ok());
+    }
+}
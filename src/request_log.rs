@@ -0,0 +1,437 @@
+/*
+   RECENT REQUESTS LOG
+    `GET /debug/requests` shows the last requests the server handled (method, path, status,
+     duration, time), newest first, for a quick look at what is going on right now.
+
+    a middleware appends a summary of every request to a RING BUFFER: a VecDeque capped at
+     CAPACITY entries where the oldest entry is dropped once it is full, so memory use is fixed
+     however long the server runs. it sits behind a Mutex since every worker writes to it.
+
+    query parameters, all optional:
+     - status=2xx|3xx|4xx|5xx  -> only that status class
+     - path_prefix=/users      -> only paths starting with it
+     - limit=<n>               -> page size (default 50, max 200)
+     - before=<seq>            -> the page after the one whose `next_cursor` was <seq>
+
+    every entry has a growing `seq` number, which is used as the cursor (like the users
+     pagination): new requests coming in between two pages don't shift what the next page shows.
+
+    LIVE STREAM
+     `GET /logs/stream` sends each entry as it is recorded instead, as newline-delimited JSON
+      (`application/x-ndjson`), one entry per line, for as long as the client stays connected:
+
+        {"seq":41,"method":"GET","path":"/users","status":200,"duration_ms":3.2,"timestamp":...}
+        {"seq":42,"method":"POST","path":"/users","status":201,"duration_ms":8.9,"timestamp":...}
+
+     every client has its own receiver on a tokio broadcast channel, bounded at LIVE_CAPACITY
+      entries. a client that falls further behind than that is DISCONNECTED: its stream ends, so
+      it can't make the server keep entries for it (it can reconnect, and /debug/requests still
+      has what it missed). a gone client is noticed at the next entry written to it.
+
+    SLOW REQUESTS
+     the same timing also logs a WARN for every request that took at least `slow_request_ms`
+     (env APP_SLOW_REQUEST_MS, default 1000), with method, path and duration. fast requests add
+     nothing to the log.
+*/
+
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    get,
+    http::header::{CacheControl, CacheDirective},
+    middleware::Next,
+    web::{self, Bytes},
+    Error, HttpResponse, Responder,
+};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::config::Config;
+
+pub const CAPACITY: usize = 1000;
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+// how many entries a /logs/stream client may fall behind before it is dropped
+const LIVE_CAPACITY: usize = 256;
+
+#[derive(Clone, Serialize)]
+pub struct Entry {
+    seq: u64,
+    method: String,
+    path: String,
+    status: u16,
+    duration_ms: f64,
+    // unix milliseconds
+    timestamp: u128,
+}
+
+struct Ring {
+    entries: VecDeque<Entry>,
+    next_seq: u64,
+}
+
+pub struct RequestLog {
+    capacity: usize,
+    ring: Mutex<Ring>,
+    live: broadcast::Sender<Entry>,
+}
+
+impl RequestLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ring: Mutex::new(Ring {
+                entries: VecDeque::with_capacity(capacity),
+                next_seq: 1,
+            }),
+            live: broadcast::channel(LIVE_CAPACITY).0,
+        }
+    }
+
+    fn push(&self, method: String, path: String, status: u16, duration_ms: f64) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis());
+
+        let mut ring = self.ring.lock().unwrap();
+        if ring.entries.len() == self.capacity {
+            ring.entries.pop_front();
+        }
+        let seq = ring.next_seq;
+        ring.next_seq += 1;
+        let entry = Entry {
+            seq,
+            method,
+            path,
+            status,
+            duration_ms,
+            timestamp,
+        };
+        // still under the lock, so the streams get the entries in `seq` order;
+        // sending only fails when nobody is listening
+        let _ = self.live.send(entry.clone());
+        ring.entries.push_back(entry);
+    }
+
+    // newest first; one extra entry is taken to know whether there is a next page
+    fn page(&self, query: &LogQuery, limit: usize) -> LogPage {
+        let ring = self.ring.lock().unwrap();
+        let mut entries: Vec<Entry> = ring
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| query.before.is_none_or(|before| entry.seq < before))
+            .filter(|entry| query.matches(entry))
+            .take(limit + 1)
+            .cloned()
+            .collect();
+
+        let has_more = entries.len() > limit;
+        entries.truncate(limit);
+        let next_cursor = if has_more {
+            entries.last().map(|entry| entry.seq)
+        } else {
+            None
+        };
+
+        LogPage {
+            entries,
+            next_cursor,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LogQuery {
+    status: Option<String>,
+    path_prefix: Option<String>,
+    limit: Option<usize>,
+    before: Option<u64>,
+}
+
+impl LogQuery {
+    fn matches(&self, entry: &Entry) -> bool {
+        // "4xx" -> 4
+        let class_matches = match &self.status {
+            Some(class) => class
+                .strip_suffix("xx")
+                .and_then(|digit| digit.parse::<u16>().ok())
+                .is_some_and(|digit| entry.status / 100 == digit),
+            None => true,
+        };
+        let path_matches = self
+            .path_prefix
+            .as_deref()
+            .is_none_or(|prefix| entry.path.starts_with(prefix));
+
+        class_matches && path_matches
+    }
+}
+
+#[derive(Serialize)]
+pub struct LogPage {
+    entries: Vec<Entry>,
+    next_cursor: Option<u64>,
+}
+
+pub async fn record_requests(
+    log: web::Data<RequestLog>,
+    config: web::Data<Config>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let method = req.method().to_string();
+    let path = req.path().to_owned();
+    let started = Instant::now();
+
+    let res = next.call(req).await;
+    let elapsed = started.elapsed();
+    let duration_ms = elapsed.as_secs_f64() * 1000.0;
+
+    let status = match &res {
+        Ok(res) => res.status(),
+        Err(err) => err.as_response_error().status_code(),
+    };
+    if elapsed >= Duration::from_millis(config.slow_request_ms) {
+        log::warn!("slow request: {method} {path} took {duration_ms:.0}ms ({status})");
+    }
+    log.push(method, path, status.as_u16(), duration_ms);
+
+    res
+}
+
+#[get("/debug/requests")]
+pub async fn recent_requests(
+    log: web::Data<RequestLog>,
+    query: web::Query<LogQuery>,
+) -> impl Responder {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(log.page(&query, limit))
+}
+
+#[get("/logs/stream")]
+pub async fn stream_logs(log: web::Data<RequestLog>) -> HttpResponse {
+    // ends at the first error: Lagged (too slow, see LIVE STREAM) or Closed
+    let lines = stream::unfold(log.live.subscribe(), |mut receiver| async move {
+        let entry = receiver.recv().await.ok()?;
+        Some((entry, receiver))
+    })
+    .map(|entry| {
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        Ok::<_, Error>(Bytes::from(line))
+    });
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .content_type("application/x-ndjson")
+        .streaming(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body::MessageBody, http::StatusCode, middleware, test, App};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    fn paths(page: &Value) -> Vec<&str> {
+        page["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["path"].as_str().unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn requests_show_up_filtered_and_paged() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for uri in [
+            "/healthz",
+            "/users",
+            "/users/404",
+            "/nowhere",
+            "/users?limit=1",
+        ] {
+            test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        }
+
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        let page: Value =
+            test::call_and_read_body_json(&app, get("/debug/requests?status=4xx")).await;
+        assert_eq!(paths(&page), ["/nowhere", "/users/404"]);
+        assert!(page["entries"][0]["duration_ms"].is_number());
+        assert_eq!(page["entries"][0]["status"], 404);
+        assert_eq!(page["entries"][0]["method"], "GET");
+
+        let page: Value = test::call_and_read_body_json(
+            &app,
+            get("/debug/requests?path_prefix=/users&status=2xx"),
+        )
+        .await;
+        assert_eq!(paths(&page), ["/users", "/users"]);
+
+        let page: Value =
+            test::call_and_read_body_json(&app, get("/debug/requests?path_prefix=/users&limit=2"))
+                .await;
+        assert_eq!(paths(&page), ["/users", "/users/404"]);
+        let cursor = page["next_cursor"].as_u64().unwrap();
+
+        let uri = format!("/debug/requests?path_prefix=/users&limit=2&before={cursor}");
+        let page: Value = test::call_and_read_body_json(&app, get(&uri)).await;
+        assert_eq!(paths(&page), ["/users"]);
+        assert!(page["next_cursor"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn the_ring_keeps_only_the_newest() {
+        let log = RequestLog::new(3);
+        for n in 0..5 {
+            log.push("GET".to_owned(), format!("/{n}"), 200, 1.0);
+        }
+
+        let query = LogQuery {
+            status: None,
+            path_prefix: None,
+            limit: None,
+            before: None,
+        };
+        let page = log.page(&query, 10);
+        let paths: Vec<&str> = page
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect();
+
+        assert_eq!(paths, ["/4", "/3", "/2"]);
+        assert_eq!(page.entries[0].seq, 5);
+    }
+
+    #[actix_web::test]
+    async fn the_log_is_served_without_the_debug_flag() {
+        let config = Config {
+            enable_debug: false,
+            ..Config::default()
+        };
+        let app = test::init_service(testing::builder_with(config).await.build()).await;
+
+        for uri in ["/debug/requests", "/logs/stream"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(
+                test::call_service(&app, req).await.status(),
+                StatusCode::OK,
+                "{uri}"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn only_slow_requests_are_warned_about() {
+        testing::capture_logs();
+        let config = Config {
+            slow_request_ms: 50,
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(RequestLog::new(CAPACITY)))
+                .wrap(middleware::from_fn(record_requests))
+                .route("/slow-log/fast", web::get().to(HttpResponse::Ok))
+                .route(
+                    "/slow-log/slow",
+                    web::get().to(|| async {
+                        tokio::time::sleep(Duration::from_millis(80)).await;
+                        HttpResponse::Ok().finish()
+                    }),
+                ),
+        )
+        .await;
+
+        for uri in ["/slow-log/fast", "/slow-log/slow"] {
+            test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        }
+
+        let warnings = testing::logged("slow request: GET /slow-log/");
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(warnings[0].starts_with("WARN slow request: GET /slow-log/slow took "));
+        assert!(warnings[0].ends_with("ms (200 OK)"));
+    }
+
+    async fn next_line<B: MessageBody + Unpin>(body: &mut B) -> Value {
+        let line = testing::next_chunk(body).await.expect("another line");
+        assert_eq!(line.last(), Some(&b'\n'));
+        serde_json::from_slice(&line).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn the_stream_sends_a_line_per_request() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::get().uri("/logs/stream").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "application/x-ndjson"
+        );
+        let mut body = res.into_body();
+
+        for uri in ["/healthz", "/users/404"] {
+            test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        }
+
+        // the stream's own request is recorded once its answer has started
+        let own = next_line(&mut body).await;
+        assert_eq!(own["path"], "/logs/stream");
+        let first = next_line(&mut body).await;
+        assert_eq!(
+            (&first["method"], &first["path"]),
+            (&"GET".into(), &"/healthz".into())
+        );
+        assert_eq!(first["status"], 200);
+        let second = next_line(&mut body).await;
+        assert_eq!(second["path"], "/users/404");
+        assert_eq!(second["status"], 404);
+        assert_eq!(
+            second["seq"].as_u64(),
+            first["seq"].as_u64().map(|seq| seq + 1)
+        );
+    }
+
+    #[actix_web::test]
+    async fn a_client_too_far_behind_is_dropped() {
+        let log = web::Data::new(RequestLog::new(CAPACITY));
+        let app = test::init_service(App::new().app_data(log.clone()).service(stream_logs)).await;
+        let req = test::TestRequest::get().uri("/logs/stream").to_request();
+        let mut slow = test::call_service(&app, req).await.into_body();
+        let req = test::TestRequest::get().uri("/logs/stream").to_request();
+        let mut keeping_up = test::call_service(&app, req).await.into_body();
+
+        for n in 0..=LIVE_CAPACITY {
+            log.push("GET".to_owned(), format!("/{n}"), 200, 1.0);
+            assert_eq!(next_line(&mut keeping_up).await["path"], format!("/{n}"));
+        }
+
+        assert!(testing::next_chunk(&mut slow).await.is_none());
+        log.push("GET".to_owned(), "/after".to_owned(), 200, 1.0);
+        assert_eq!(next_line(&mut keeping_up).await["path"], "/after");
+    }
+}
@@ -0,0 +1,394 @@
+/*
+   RESPONSE CACHE FOR GET
+    a `200` answer to a GET is kept in memory for `response_cache_ttl_secs` (default 10, see
+     config.rs) and served again to the next identical request without running the handler:
+     `X-Cache: HIT` on a cached answer, `X-Cache: MISS` when the handler ran.
+
+    the key is host + path + query (the Tenant extractor makes the answer depend on the host).
+    at most MAX_ENTRIES answers are kept; when it is full the LEAST RECENTLY USED one goes.
+
+    a shared cache must never hand one client's answer to another, or serve something that
+     can't be replayed, so these are passed through untouched:
+     - requests with `Cookie`, `Authorization` or `X-Api-Key` (sessions, the admin area, /api)
+     - conditional and range requests (`If-None-Match`, `If-Modified-Since`, `Range`): their
+        answer depends on more than the URL
+     - answers with `Cache-Control: no-store` or `private`, with `Set-Cookie`, or with `Vary`
+        (they depend on request headers, which aren't part of the key, eg: `Vary: Cookie`)
+     - answers whose handler changed the session: session.rs sits OUTSIDE this and only adds
+        its `Set-Cookie` afterwards, so the header isn't there yet to be seen
+     - streamed answers and anything above MAX_BODY_SIZE (SSE would never finish buffering,
+        a large download would sit in memory)
+    handlers that must always be live (eg: `/metrics`, `/healthz`, `/jobs/{id}`) opt out with
+     no-store.
+
+    a successful write (any method but GET/HEAD/OPTIONS/TRACE answered 2xx) drops what is cached
+     for its path, with any query: after `PUT /users/7` the next `GET /users/7` shows the change
+     (and its new ETag) instead of the old answer. other paths showing the same data (eg: the
+     `/users` list) still age out with the ttl.
+*/
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_session::{SessionExt, SessionStatus};
+use actix_web::{
+    body::{self, BodySize, BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    error,
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        Method, StatusCode,
+    },
+    middleware::Next,
+    web::{self, Bytes},
+    Error, HttpResponse,
+};
+
+use crate::api_key::API_KEY_HEADER;
+
+pub const X_CACHE_HEADER: HeaderName = HeaderName::from_static("x-cache");
+
+pub const MAX_ENTRIES: usize = 256;
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+struct Entry {
+    stored_at: Instant,
+    // a counter instead of an Instant, so two uses in the same instant still have an order
+    last_used: u64,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+struct Entries {
+    map: HashMap<String, Entry>,
+    uses: u64,
+}
+
+pub struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<Entries>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::new(Entries {
+                map: HashMap::new(),
+                uses: 0,
+            }),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<HttpResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.uses += 1;
+        let now = entries.uses;
+
+        let entry = entries.map.get_mut(key)?;
+        if entry.stored_at.elapsed() >= self.ttl {
+            entries.map.remove(key);
+            return None;
+        }
+        entry.last_used = now;
+
+        let mut res = HttpResponse::build(StatusCode::OK);
+        for (name, value) in &entry.headers {
+            res.append_header((name.clone(), value.clone()));
+        }
+        Some(res.body(entry.body.clone()))
+    }
+
+    fn put(&self, key: String, headers: HeaderMap, body: Bytes) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.uses += 1;
+        let now = entries.uses;
+
+        if !entries.map.contains_key(&key) && entries.map.len() >= self.max_entries {
+            // expired entries go first, otherwise the least recently used one
+            let ttl = self.ttl;
+            entries
+                .map
+                .retain(|_, entry| entry.stored_at.elapsed() < ttl);
+            if entries.map.len() >= self.max_entries {
+                let oldest = entries
+                    .map
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.map.remove(&oldest);
+                }
+            }
+        }
+
+        entries.map.insert(
+            key,
+            Entry {
+                stored_at: Instant::now(),
+                last_used: now,
+                headers,
+                body,
+            },
+        );
+    }
+
+    // everything cached for `host_path`, whatever its query
+    fn invalidate(&self, host_path: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.map.retain(|key, _| {
+            key.strip_prefix(host_path)
+                .is_none_or(|rest| !rest.is_empty() && !rest.starts_with('?'))
+        });
+    }
+}
+
+fn is_cacheable_request(req: &ServiceRequest) -> bool {
+    req.method() == Method::GET
+        && ![
+            header::COOKIE,
+            header::AUTHORIZATION,
+            API_KEY_HEADER,
+            header::IF_NONE_MATCH,
+            header::IF_MODIFIED_SINCE,
+            header::RANGE,
+        ]
+        .iter()
+        .any(|name| req.headers().contains_key(name))
+}
+
+fn is_cacheable_response(res: &ServiceResponse<impl MessageBody>) -> bool {
+    let cache_control = res
+        .headers()
+        .get_all(header::CACHE_CONTROL)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .any(|directive| directive == "no-store" || directive == "private");
+    let small_enough =
+        matches!(res.response().body().size(), BodySize::Sized(size) if size <= MAX_BODY_SIZE);
+
+    res.status() == StatusCode::OK
+        && !cache_control
+        && !res.headers().contains_key(header::SET_COOKIE)
+        && !res.headers().contains_key(header::VARY)
+        && res.request().get_session().status() == SessionStatus::Unchanged
+        && small_enough
+}
+
+fn cache_key(req: &ServiceRequest) -> String {
+    let path_and_query = req.uri().path_and_query().map_or("/", |pq| pq.as_str());
+    format!("{}{path_and_query}", req.connection_info().host())
+}
+
+pub async fn cache_responses(
+    cache: web::Data<ResponseCache>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if !req.method().is_safe() {
+        let host_path = format!("{}{}", req.connection_info().host(), req.path());
+        let res = next.call(req).await?;
+        if res.status().is_success() {
+            cache.invalidate(&host_path);
+        }
+        return Ok(res.map_into_boxed_body());
+    }
+    if !is_cacheable_request(&req) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    }
+
+    let key = cache_key(&req);
+    if let Some(mut res) = cache.get(&key) {
+        res.headers_mut()
+            .insert(X_CACHE_HEADER, HeaderValue::from_static("HIT"));
+        return Ok(req.into_response(res));
+    }
+
+    let res = next.call(req).await?;
+    if !is_cacheable_response(&res) {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    // buffer the (small) body so it can be both stored and sent
+    let (req, res) = res.into_parts();
+    let (mut res, res_body) = res.into_parts();
+    let res_body = body::to_bytes(res_body)
+        .await
+        .map_err(|err| error::ErrorInternalServerError(err.into().to_string()))?;
+
+    cache.put(key, res.headers().clone(), res_body.clone());
+    res.headers_mut()
+        .insert(X_CACHE_HEADER, HeaderValue::from_static("MISS"));
+
+    Ok(ServiceResponse::new(
+        req,
+        res.set_body(res_body).map_into_boxed_body(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use actix_session::Session;
+    use actix_web::{
+        cookie::Key, dev::ServiceFactory, http::header::CacheDirective, middleware, test, App,
+    };
+
+    use super::*;
+    use crate::session;
+
+    // how often the handler really ran
+    #[derive(Default)]
+    struct Runs(AtomicUsize);
+
+    async fn counted(runs: web::Data<Runs>) -> HttpResponse {
+        let n = runs.0.fetch_add(1, Ordering::SeqCst) + 1;
+        HttpResponse::Ok().body(format!("run {n}"))
+    }
+
+    async fn never_stored(runs: web::Data<Runs>) -> HttpResponse {
+        runs.0.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::Ok()
+            .insert_header(header::CacheControl(vec![CacheDirective::NoStore]))
+            .finish()
+    }
+
+    async fn writes_the_session(session: Session) -> actix_web::Result<HttpResponse> {
+        session.insert("seen", true)?;
+        Ok(HttpResponse::Ok().body("hello"))
+    }
+
+    fn cached_app(
+        ttl: Duration,
+        max_entries: usize,
+        runs: &web::Data<Runs>,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = Error,
+            InitError = (),
+        >,
+    > {
+        App::new()
+            .app_data(web::Data::new(ResponseCache::new(ttl, max_entries)))
+            .app_data(runs.clone())
+            .wrap(middleware::from_fn(cache_responses))
+            .wrap(session::middleware(Key::generate(), false))
+            .route("/counted/{name}", web::get().to(counted))
+            .route("/counted/{name}", web::put().to(HttpResponse::NoContent))
+            .route("/never-stored", web::get().to(never_stored))
+            .route("/session", web::get().to(writes_the_session))
+    }
+
+    fn get(uri: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(uri)
+    }
+
+    fn x_cache<B>(res: &ServiceResponse<B>) -> Option<&str> {
+        res.headers()
+            .get(X_CACHE_HEADER)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    #[actix_web::test]
+    async fn a_repeated_get_is_a_hit() {
+        let runs = web::Data::new(Runs::default());
+        let app = test::init_service(cached_app(Duration::from_secs(10), MAX_ENTRIES, &runs)).await;
+
+        let res = test::call_service(&app, get("/counted/a").to_request()).await;
+        assert_eq!(x_cache(&res), Some("MISS"));
+        let res = test::call_service(&app, get("/counted/a").to_request()).await;
+        assert_eq!(x_cache(&res), Some("HIT"));
+        assert_eq!(test::read_body(res).await, "run 1");
+
+        let res = test::call_service(&app, get("/counted/a?other=query").to_request()).await;
+        assert_eq!(x_cache(&res), Some("MISS"));
+        assert_eq!(runs.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    async fn an_entry_expires_after_the_ttl() {
+        let runs = web::Data::new(Runs::default());
+        let app =
+            test::init_service(cached_app(Duration::from_millis(50), MAX_ENTRIES, &runs)).await;
+
+        test::call_service(&app, get("/counted/a").to_request()).await;
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        let res = test::call_service(&app, get("/counted/a").to_request()).await;
+
+        assert_eq!(x_cache(&res), Some("MISS"));
+        assert_eq!(runs.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    async fn the_least_recently_used_entry_goes_first() {
+        let runs = web::Data::new(Runs::default());
+        let app = test::init_service(cached_app(Duration::from_secs(10), 2, &runs)).await;
+
+        for uri in ["/counted/a", "/counted/b", "/counted/a", "/counted/c"] {
+            test::call_service(&app, get(uri).to_request()).await;
+        }
+
+        let res = test::call_service(&app, get("/counted/a").to_request()).await;
+        assert_eq!(x_cache(&res), Some("HIT"));
+        let res = test::call_service(&app, get("/counted/b").to_request()).await;
+        assert_eq!(x_cache(&res), Some("MISS"));
+    }
+
+    #[actix_web::test]
+    async fn no_store_private_requests_and_session_changes_pass_through() {
+        let runs = web::Data::new(Runs::default());
+        let app = test::init_service(cached_app(Duration::from_secs(10), MAX_ENTRIES, &runs)).await;
+
+        for _ in 0..2 {
+            let res = test::call_service(&app, get("/never-stored").to_request()).await;
+            assert_eq!(x_cache(&res), None);
+        }
+        assert_eq!(runs.0.load(Ordering::SeqCst), 2);
+
+        for _ in 0..2 {
+            let req = get("/counted/a").insert_header((header::AUTHORIZATION, "Basic eDp5"));
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(x_cache(&res), None);
+        }
+
+        for _ in 0..2 {
+            let res = test::call_service(&app, get("/session").to_request()).await;
+            assert_eq!(x_cache(&res), None);
+            assert!(res.headers().contains_key(header::SET_COOKIE));
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_successful_write_drops_the_path() {
+        let runs = web::Data::new(Runs::default());
+        let app = test::init_service(cached_app(Duration::from_secs(10), MAX_ENTRIES, &runs)).await;
+        test::call_service(&app, get("/counted/a").to_request()).await;
+        test::call_service(&app, get("/counted/a?x=1").to_request()).await;
+        test::call_service(&app, get("/counted/ab").to_request()).await;
+
+        let req = test::TestRequest::put().uri("/counted/a").to_request();
+        test::call_service(&app, req).await;
+
+        for (uri, expected) in [
+            ("/counted/a", "MISS"),
+            ("/counted/a?x=1", "MISS"),
+            ("/counted/ab", "HIT"),
+        ] {
+            let res = test::call_service(&app, get(uri).to_request()).await;
+            assert_eq!(x_cache(&res), Some(expected), "{uri}");
+        }
+    }
+}
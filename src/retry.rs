@@ -0,0 +1,275 @@
+/*
+   RETRIES FOR UPSTREAM CALLS
+    an upstream that is restarting or briefly overloaded fails a request that would work a moment
+     later. send_with_retry() sends a request again when the failure looks TRANSIENT:
+     - the request never got an answer: connection refused/reset, timeout
+     - the upstream answered `502`, `503` or `504`
+    anything else (a `4xx`, a bad url, ...) would fail the same way again and is returned at once.
+
+    between attempts it waits with EXPONENTIAL BACKOFF and JITTER: BASE_DELAY, then twice that,
+     ... (at most MAX_DELAY), each time a random amount between half and all of it, so clients
+     that failed together don't all come back at the same instant.
+
+    - at most `upstream_max_attempts` attempts (env APP_UPSTREAM_MAX_ATTEMPTS, see config.rs)
+    - all of them together within the DEADLINE of the policy: an attempt only gets the time that
+       is left, and there is no retry that couldn't start before it
+    - only IDEMPOTENT methods (GET, HEAD, PUT, DELETE, ...) are sent again. a POST that timed out
+       may well have been processed, sending it again could do it twice, so it gets one attempt
+
+    the result says how many attempts it took, the callers log it.
+*/
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    http::{Method, StatusCode},
+    rt::time::{sleep, timeout},
+};
+use awc::{error::SendRequestError, ClientResponse};
+use uuid::Uuid;
+
+const BASE_DELAY: Duration = Duration::from_millis(100);
+const MAX_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub deadline: Duration,
+}
+
+pub struct Retried<T> {
+    pub result: Result<T, SendRequestError>,
+    pub attempts: u32,
+}
+
+fn is_transient_error(err: &SendRequestError) -> bool {
+    matches!(
+        err,
+        SendRequestError::Connect(_)
+            | SendRequestError::Send(_)
+            | SendRequestError::Timeout
+            | SendRequestError::H2(_)
+    )
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+// BASE_DELAY * 2^(attempt - 1), capped, then somewhere in its upper half
+fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(MAX_DELAY);
+    let random = u32::from(Uuid::new_v4().as_bytes()[0]);
+    delay / 2 + delay / 2 * random / 255
+}
+
+// `send` builds and sends the request once per call
+pub async fn send_with_retry<S, F, Fut>(
+    policy: RetryPolicy,
+    method: &Method,
+    mut send: F,
+) -> Retried<ClientResponse<S>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<ClientResponse<S>, SendRequestError>>,
+{
+    let started = Instant::now();
+    let max_attempts = match method.is_idempotent() {
+        true => policy.max_attempts.max(1),
+        false => 1,
+    };
+
+    let mut attempt = 1;
+    loop {
+        let left = policy.deadline.saturating_sub(started.elapsed());
+        let result = match timeout(left, send()).await {
+            Ok(result) => result,
+            Err(_) => Err(SendRequestError::Timeout),
+        };
+
+        let transient = match &result {
+            Ok(res) => is_transient_status(res.status()),
+            Err(err) => is_transient_error(err),
+        };
+        let delay = backoff(attempt);
+        if !transient || attempt >= max_attempts || started.elapsed() + delay >= policy.deadline {
+            return Retried {
+                result,
+                attempts: attempt,
+            };
+        }
+
+        match &result {
+            Ok(res) => log::info!("upstream answered {}, retrying in {delay:?}", res.status()),
+            Err(err) => log::info!("upstream request failed ({err}), retrying in {delay:?}"),
+        }
+        sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, TcpListener},
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+    };
+
+    use actix_web::{rt, web, App, HttpResponse, HttpServer};
+
+    use super::*;
+
+    // answers `status` for the first `failures` requests, then `200`; counts every request
+    fn flaky_upstream(failures: u32, status: StatusCode) -> (SocketAddr, Arc<AtomicU32>) {
+        let seen = Arc::new(AtomicU32::new(0));
+        let counter = seen.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = HttpServer::new(move || {
+            let counter = counter.clone();
+            App::new().default_service(web::to(move || {
+                let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    match n <= failures {
+                        true => HttpResponse::build(status).finish(),
+                        false => HttpResponse::Ok().body("finally"),
+                    }
+                }
+            }))
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .unwrap()
+        .run();
+        rt::spawn(server);
+        (addr, seen)
+    }
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            deadline: Duration::from_secs(10),
+        }
+    }
+
+    #[actix_web::test]
+    async fn two_failures_then_success_takes_three_attempts() {
+        let (addr, seen) = flaky_upstream(2, StatusCode::SERVICE_UNAVAILABLE);
+        let client = awc::Client::default();
+
+        let retried = send_with_retry(policy(5), &Method::GET, || {
+            client.get(format!("http://{addr}/")).send()
+        })
+        .await;
+
+        let mut res = retried.result.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body().await.unwrap(), "finally");
+        assert_eq!(retried.attempts, 3);
+        assert_eq!(seen.load(Ordering::SeqCst), 3);
+    }
+
+    #[actix_web::test]
+    async fn max_attempts_bounds_the_retries() {
+        let (addr, seen) = flaky_upstream(10, StatusCode::BAD_GATEWAY);
+        let client = awc::Client::default();
+
+        let retried = send_with_retry(policy(2), &Method::GET, || {
+            client.get(format!("http://{addr}/")).send()
+        })
+        .await;
+
+        assert_eq!(retried.result.unwrap().status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(retried.attempts, 2);
+        assert_eq!(seen.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    async fn a_post_and_a_4xx_are_not_retried() {
+        let (addr, seen) = flaky_upstream(1, StatusCode::SERVICE_UNAVAILABLE);
+        let client = awc::Client::default();
+        let retried = send_with_retry(policy(5), &Method::POST, || {
+            client.post(format!("http://{addr}/")).send()
+        })
+        .await;
+        assert_eq!(
+            retried.result.unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(retried.attempts, 1);
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+
+        let (addr, seen) = flaky_upstream(1, StatusCode::NOT_FOUND);
+        let retried = send_with_retry(policy(5), &Method::GET, || {
+            client.get(format!("http://{addr}/")).send()
+        })
+        .await;
+        assert_eq!(retried.result.unwrap().status(), StatusCode::NOT_FOUND);
+        assert_eq!(retried.attempts, 1);
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn a_refused_connection_is_retried() {
+        // a port nobody listens on any more
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = awc::Client::default();
+
+        let retried = send_with_retry(policy(3), &Method::GET, || {
+            client.get(format!("http://{addr}/")).send()
+        })
+        .await;
+
+        assert!(matches!(retried.result, Err(SendRequestError::Connect(_))));
+        assert_eq!(retried.attempts, 3);
+    }
+
+    #[actix_web::test]
+    async fn the_deadline_bounds_the_whole_budget() {
+        let (addr, _) = flaky_upstream(u32::MAX, StatusCode::SERVICE_UNAVAILABLE);
+        let client = awc::Client::default();
+        let policy = RetryPolicy {
+            max_attempts: 100,
+            deadline: Duration::from_millis(300),
+        };
+
+        let started = Instant::now();
+        let retried = send_with_retry(policy, &Method::GET, || {
+            client.get(format!("http://{addr}/")).send()
+        })
+        .await;
+
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert!(retried.attempts < 100);
+    }
+
+    #[actix_web::test]
+    async fn the_backoff_grows_with_jitter_up_to_the_cap() {
+        for _ in 0..100 {
+            let first = backoff(1);
+            assert!(first >= BASE_DELAY / 2 && first <= BASE_DELAY, "{first:?}");
+            let third = backoff(3);
+            assert!(
+                third >= BASE_DELAY * 2 && third <= BASE_DELAY * 4,
+                "{third:?}"
+            );
+            let late = backoff(30);
+            assert!(late >= MAX_DELAY / 2 && late <= MAX_DELAY, "{late:?}");
+        }
+    }
+}
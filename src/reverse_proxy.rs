@@ -0,0 +1,423 @@
+/*
+   REVERSE PROXY
+    everything under `/proxy/...` (except `/proxy/weather`, which is registered before the scope
+     and so matches first) is forwarded to the upstream in UPSTREAM_URL:
+
+        GET /proxy/repos/1?page=2   ->   GET $UPSTREAM_URL/repos/1?page=2
+
+    UPSTREAMS (eg: `http://a:9000=3,http://b:9000=1`) names several instead, it wins over
+     UPSTREAM_URL. each request goes to one of them by weight, unhealthy ones are skipped, and
+     with none healthy the answer is `503` (see balancer.rs). a single UPSTREAM_URL is the same
+     thing with one upstream.
+
+    method, path, query, headers and body go to the upstream, and its status, headers and body
+     come back. both bodies are STREAMED through chunk by chunk, never buffered whole, so a
+     large upload or download costs no more memory than a small one.
+
+    hop-by-hop headers describe ONE connection (ours with the client, or ours with the
+     upstream), not the message, so they are dropped in both directions: Connection, Keep-Alive,
+     Transfer-Encoding, TE, Trailer, Upgrade, Proxy-*, plus any header named in `Connection`.
+     Host is set for the upstream by awc. Content-Length is set again from the body size, so a
+     body of known size keeps it while anything else goes chunked.
+    the upstream learns about the original request from X-Forwarded-For / -Proto / -Host, and
+     gets a `traceparent` with this request as its parent (see trace.rs).
+
+    a request WITHOUT a body and with an idempotent method (GET, HEAD, ...) is retried with
+     backoff when the upstream fails or answers 502/503/504 (see retry.rs), all attempts within
+     UPSTREAM_TIMEOUT. one with a body is streamed to the upstream as it comes in, so there is
+     nothing to send a second time: it gets one attempt.
+
+    the upstream not answering (refused, timed out, ...) is `502 Bad Gateway`. awc would
+     decompress bodies by default, that is turned off: the client gets exactly the bytes (and
+     the Content-Encoding) the upstream sent.
+*/
+
+use std::{env, time::Duration};
+
+use actix_web::{
+    body::SizedStream,
+    http::header::{self, HeaderMap, HeaderName},
+    rt::{self, time},
+    web, HttpRequest, HttpResponse,
+};
+
+use crate::{
+    balancer::{self, Balancer},
+    retry::{self, RetryPolicy},
+    trace::{TraceContext, TRACEPARENT},
+};
+
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+pub const SCOPE: &str = "/proxy";
+
+const HOP_BY_HOP: [HeaderName; 9] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    header::TRANSFER_ENCODING,
+    header::TE,
+    header::TRAILER,
+    header::UPGRADE,
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::CONTENT_LENGTH,
+];
+
+pub struct ReverseProxy {
+    upstreams: Balancer,
+    retry: RetryPolicy,
+}
+
+impl ReverseProxy {
+    pub fn from_env(max_attempts: u32) -> Self {
+        let spec = env::var("UPSTREAMS")
+            .or_else(|_| env::var("UPSTREAM_URL"))
+            .unwrap_or_default();
+        let proxy = Self::new(&spec, max_attempts);
+        if proxy.upstreams.is_empty() {
+            log::warn!("neither UPSTREAMS nor UPSTREAM_URL is set, /proxy/... answers 502");
+        }
+        proxy
+    }
+
+    // `spec` as in UPSTREAMS
+    pub fn new(spec: &str, max_attempts: u32) -> Self {
+        Self {
+            upstreams: Balancer::parse(spec),
+            retry: RetryPolicy {
+                max_attempts,
+                deadline: UPSTREAM_TIMEOUT,
+            },
+        }
+    }
+}
+
+// hop-by-hop headers, and the ones the `Connection` header lists
+fn is_forwardable(name: &HeaderName, headers: &HeaderMap) -> bool {
+    let listed_in_connection = headers
+        .get_all(header::CONNECTION)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|listed| listed.trim().eq_ignore_ascii_case(name.as_str()));
+
+    !HOP_BY_HOP.contains(name) && name != header::HOST && !listed_in_connection
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+// health checks for the upstreams; call once at startup
+pub fn spawn_health_checks(proxy: web::Data<ReverseProxy>) {
+    if proxy.upstreams.is_empty() {
+        return;
+    }
+    rt::spawn(async move {
+        let mut ticks = time::interval(balancer::HEALTH_INTERVAL);
+        loop {
+            ticks.tick().await;
+            proxy.upstreams.check_health().await;
+        }
+    });
+}
+
+pub async fn forward(
+    req: HttpRequest,
+    body: web::Payload,
+    proxy: web::Data<ReverseProxy>,
+    trace: TraceContext,
+) -> HttpResponse {
+    if proxy.upstreams.is_empty() {
+        return HttpResponse::BadGateway().body("no upstream configured");
+    }
+    let Some(upstream) = proxy.upstreams.pick() else {
+        return HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, "5"))
+            .body("no upstream is healthy right now");
+    };
+    let upstream_url = &upstream.url;
+
+    let path = req.path().strip_prefix(SCOPE).unwrap_or("");
+    let url = match req.query_string() {
+        "" => format!("{upstream_url}{path}"),
+        query => format!("{upstream_url}{path}?{query}"),
+    };
+
+    let client = awc::Client::builder().timeout(UPSTREAM_TIMEOUT).finish();
+    let mut upstream_req = client.request(req.method().clone(), &url).no_decompress();
+    for (name, value) in req.headers() {
+        if is_forwardable(name, req.headers()) {
+            upstream_req = upstream_req.append_header((name.clone(), value.clone()));
+        }
+    }
+
+    let conn = req.connection_info().clone();
+    if let Some(client_addr) = conn.peer_addr() {
+        // proxies in front of us already listed theirs, ours goes at the end of the chain
+        let forwarded_for = match req.headers().get("X-Forwarded-For").map(|v| v.to_str()) {
+            Some(Ok(chain)) => format!("{chain}, {client_addr}"),
+            _ => client_addr.to_owned(),
+        };
+        upstream_req = upstream_req.insert_header(("X-Forwarded-For", forwarded_for));
+    }
+    upstream_req = upstream_req
+        .insert_header(("X-Forwarded-Proto", conn.scheme()))
+        .insert_header(("X-Forwarded-Host", conn.host()))
+        // the client's traceparent is replaced by ours: we are the upstream's parent now
+        .insert_header((TRACEPARENT, trace.traceparent()));
+
+    let has_body = content_length(req.headers()).is_some_and(|len| len > 0)
+        || req.headers().contains_key(header::TRANSFER_ENCODING);
+    let sent = if !has_body {
+        // nothing to stream, so the same request can be sent again
+        let frozen = match upstream_req.freeze() {
+            Ok(frozen) => frozen,
+            Err(err) => {
+                log::warn!("proxying {} {url} failed: {err}", req.method());
+                return HttpResponse::BadGateway().body("the request can't be forwarded");
+            }
+        };
+        let retried = retry::send_with_retry(proxy.retry, req.method(), || frozen.send()).await;
+        if retried.attempts > 1 {
+            log::info!(
+                "proxying {} {url}: {} attempts",
+                req.method(),
+                retried.attempts
+            );
+        }
+        retried.result
+    } else {
+        // a body of known size is sent with Content-Length again, anything else chunked
+        match content_length(req.headers()) {
+            Some(len) => upstream_req.send_body(SizedStream::new(len, body)).await,
+            None => upstream_req.send_stream(body).await,
+        }
+    };
+    let upstream_res = match sent {
+        Ok(res) => res,
+        Err(err) => {
+            log::warn!("proxying {} {url} failed: {err}", req.method());
+            return HttpResponse::BadGateway().body("the upstream is not reachable");
+        }
+    };
+
+    let mut res = HttpResponse::build(upstream_res.status());
+    for (name, value) in upstream_res.headers() {
+        if is_forwardable(name, upstream_res.headers()) {
+            res.append_header((name.clone(), value.clone()));
+        }
+    }
+    if let Some(len) = content_length(upstream_res.headers()) {
+        res.no_chunking(len);
+    }
+    res.streaming(upstream_res)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener};
+
+    use actix_web::{
+        body::MessageBody,
+        dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+        http::StatusCode,
+        test, App, Error, HttpServer,
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    // answers with what it received, plus a header of its own and a hop-by-hop one
+    async fn echo(req: HttpRequest, body: web::Bytes) -> HttpResponse {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_owned())
+        };
+        HttpResponse::Created()
+            .insert_header(("X-Upstream", "echo"))
+            .insert_header(("Keep-Alive", "timeout=5"))
+            .json(json!({
+                "method": req.method().as_str(),
+                "path": req.path(),
+                "query": req.query_string(),
+                "body": String::from_utf8_lossy(&body),
+                "x_custom": header("x-custom"),
+                "x_hop": header("x-hop"),
+                "x_forwarded_host": header("x-forwarded-host"),
+                "has_traceparent": header("traceparent").is_some(),
+            }))
+    }
+
+    fn echo_upstream() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = HttpServer::new(|| App::new().default_service(web::to(echo)))
+            .workers(1)
+            .disable_signals()
+            .listen(listener)
+            .unwrap()
+            .run();
+        rt::spawn(server);
+        addr
+    }
+
+    // answers every path, /healthz too, with its name
+    fn named_upstream(name: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = HttpServer::new(move || {
+            App::new().default_service(web::to(
+                move || async move { HttpResponse::Ok().body(name) },
+            ))
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .unwrap()
+        .run();
+        rt::spawn(server);
+        addr
+    }
+
+    fn proxy_app(
+        spec: &str,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = Error,
+            InitError = (),
+        >,
+    > {
+        proxy_app_with(web::Data::new(ReverseProxy::new(spec, 1)))
+    }
+
+    fn proxy_app_with(
+        proxy: web::Data<ReverseProxy>,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = Error,
+            InitError = (),
+        >,
+    > {
+        App::new()
+            .app_data(proxy)
+            .service(web::scope(SCOPE).default_service(web::to(forward)))
+    }
+
+    #[actix_web::test]
+    async fn method_path_query_headers_and_body_are_forwarded() {
+        let addr = echo_upstream();
+        let app = test::init_service(proxy_app(&format!("http://{addr}"))).await;
+
+        let req = test::TestRequest::post()
+            .uri("/proxy/repos/1?page=2")
+            .insert_header((header::HOST, "shop.example.com"))
+            .insert_header(("X-Custom", "kept"))
+            .insert_header((header::CONNECTION, "x-hop"))
+            .insert_header(("X-Hop", "dropped"))
+            .set_payload("hello upstream")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get("X-Upstream").unwrap(), "echo");
+        assert!(!res.headers().contains_key("keep-alive"));
+        let echoed: Value = test::read_body_json(res).await;
+        assert_eq!(
+            echoed,
+            json!({
+                "method": "POST",
+                "path": "/repos/1",
+                "query": "page=2",
+                "body": "hello upstream",
+                "x_custom": "kept",
+                "x_hop": null,
+                "x_forwarded_host": "shop.example.com",
+                "has_traceparent": true,
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn an_unreachable_upstream_is_502() {
+        // a port nothing listens on any more
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let app = test::init_service(proxy_app(&format!("http://{addr}"))).await;
+
+        let req = test::TestRequest::get().uri("/proxy/anything").to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[actix_web::test]
+    async fn no_upstream_is_502() {
+        let app = test::init_service(proxy_app("")).await;
+
+        let req = test::TestRequest::get().uri("/proxy/anything").to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[actix_web::test]
+    async fn requests_are_spread_by_weight() {
+        let (heavy, light) = (named_upstream("heavy"), named_upstream("light"));
+        let app =
+            test::init_service(proxy_app(&format!("http://{heavy}=3,http://{light}=1"))).await;
+
+        let mut heavy_count = 0;
+        for _ in 0..40 {
+            let req = test::TestRequest::get().uri("/proxy/who").to_request();
+            if test::call_and_read_body(&app, req).await == "heavy" {
+                heavy_count += 1;
+            }
+        }
+
+        assert_eq!(heavy_count, 30);
+    }
+
+    #[actix_web::test]
+    async fn an_unhealthy_upstream_is_skipped_and_all_down_is_503() {
+        let up = named_upstream("up");
+        let down = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let proxy = web::Data::new(ReverseProxy::new(
+            &format!("http://{down}=5,http://{up}=1"),
+            1,
+        ));
+        let app = test::init_service(proxy_app_with(proxy.clone())).await;
+
+        proxy.upstreams.check_health().await;
+        for _ in 0..10 {
+            let req = test::TestRequest::get().uri("/proxy/who").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(test::read_body(res).await, "up");
+        }
+
+        let all_down = web::Data::new(ReverseProxy::new(&format!("http://{down}"), 1));
+        let app = test::init_service(proxy_app_with(all_down.clone())).await;
+        all_down.upstreams.check_health().await;
+
+        let req = test::TestRequest::get().uri("/proxy/who").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "5");
+    }
+}
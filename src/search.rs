@@ -0,0 +1,136 @@
+/*
+   REPEATED QUERY PARAMETERS
+    `GET /search?tag=rust&tag=web` returns the articles carrying ALL the given tags; without any
+     `tag` every article is returned.
+
+    web::Query<T> deserializes with serde_urlencoded, which can't collect a key that appears
+     several times into a `Vec<String>` field (it complains about a duplicate field). it CAN
+     produce the raw list of pairs in order, though, so the SearchParams extractor asks for
+     `web::Query<Vec<(String, String)>>` and gathers the tags from that:
+
+        ?tag=rust&tag=web&tag=async  ->  [("tag","rust"), ("tag","web"), ("tag","async")]
+                                     ->  SearchParams { tags: ["rust", "web", "async"] }
+*/
+
+use std::{future::Future, pin::Pin};
+
+use actix_web::{dev::Payload, get, web, FromRequest, HttpRequest, HttpResponse};
+use serde::Serialize;
+
+pub struct SearchParams {
+    pub tags: Vec<String>,
+}
+
+impl FromRequest for SearchParams {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let pairs = web::Query::<Vec<(String, String)>>::from_request(req, payload);
+        Box::pin(async move {
+            let tags = pairs
+                .await?
+                .into_inner()
+                .into_iter()
+                .filter(|(key, _)| key == "tag")
+                .map(|(_, value)| value)
+                .collect();
+            Ok(SearchParams { tags })
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Article {
+    title: &'static str,
+    tags: &'static [&'static str],
+}
+
+const ARTICLES: [Article; 4] = [
+    Article {
+        title: "Getting started with actix-web",
+        tags: &["rust", "web"],
+    },
+    Article {
+        title: "Async Rust in depth",
+        tags: &["rust", "async"],
+    },
+    Article {
+        title: "Streaming responses",
+        tags: &["rust", "web", "async"],
+    },
+    Article {
+        title: "HTTP caching explained",
+        tags: &["web"],
+    },
+];
+
+#[get("/search")]
+pub async fn search(params: SearchParams) -> HttpResponse {
+    let results: Vec<&Article> = ARTICLES
+        .iter()
+        .filter(|article| {
+            params
+                .tags
+                .iter()
+                .all(|tag| article.tags.contains(&tag.as_str()))
+        })
+        .collect();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "tags": params.tags,
+        "results": results,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+    use serde_json::{json, Value};
+
+    use crate::testing;
+
+    async fn search(query: &str) -> Value {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::get()
+            .uri(&format!("/search{query}"))
+            .to_request();
+        test::call_and_read_body_json(&app, req).await
+    }
+
+    fn titles(body: &Value) -> Vec<&str> {
+        body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|article| article["title"].as_str().unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn no_tag_is_every_article() {
+        let body = search("").await;
+
+        assert_eq!(body["tags"], json!([]));
+        assert_eq!(titles(&body).len(), 4);
+    }
+
+    #[actix_web::test]
+    async fn one_tag() {
+        let body = search("?tag=async").await;
+
+        assert_eq!(body["tags"], json!(["async"]));
+        assert_eq!(
+            titles(&body),
+            ["Async Rust in depth", "Streaming responses"]
+        );
+    }
+
+    #[actix_web::test]
+    async fn repeated_tags_all_have_to_match() {
+        let body = search("?tag=rust&tag=web&page=2&tag=async").await;
+
+        assert_eq!(body["tags"], json!(["rust", "web", "async"]));
+        assert_eq!(titles(&body), ["Streaming responses"]);
+    }
+}
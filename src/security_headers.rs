@@ -0,0 +1,98 @@
+/*
+   SECURITY HEADERS
+    every response gets headers that switch off browser behaviour we never want:
+     - X-Content-Type-Options: nosniff     -> trust the Content-Type, don't guess from the body
+     - X-Frame-Options: DENY               -> no embedding in frames (clickjacking)
+     - Referrer-Policy: no-referrer        -> don't leak our URLs to other sites
+     - Content-Security-Policy: <csp>      -> where scripts, styles, ... may come from; from
+                                              `content_security_policy` (see config.rs)
+
+    actix's DefaultHeaders middleware only adds a header when the response doesn't already have
+     it, so a handler that needs a different policy (eg: a page that must be embeddable) sets
+     its own and that one is kept.
+*/
+
+use actix_web::{http::header, middleware::DefaultHeaders};
+
+pub const DEFAULT_CSP: &str = "default-src 'self'; frame-ancestors 'none'";
+
+pub fn security_headers(content_security_policy: &str) -> DefaultHeaders {
+    DefaultHeaders::new()
+        .add((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .add((header::X_FRAME_OPTIONS, "DENY"))
+        .add((header::REFERRER_POLICY, "no-referrer"))
+        .add((header::CONTENT_SECURITY_POLICY, content_security_policy))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App, HttpResponse};
+
+    use super::*;
+    use crate::{config::Config, testing};
+
+    #[actix_web::test]
+    async fn every_response_gets_the_headers() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for uri in ["/healthz", "/no/such/page"] {
+            let res =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            let headers = res.headers();
+            assert_eq!(
+                headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+                "nosniff"
+            );
+            assert_eq!(headers.get(header::X_FRAME_OPTIONS).unwrap(), "DENY");
+            assert_eq!(headers.get(header::REFERRER_POLICY).unwrap(), "no-referrer");
+            assert_eq!(
+                headers.get(header::CONTENT_SECURITY_POLICY).unwrap(),
+                DEFAULT_CSP
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn the_policy_comes_from_the_config() {
+        let config = Config {
+            content_security_policy: "default-src 'none'".to_owned(),
+            ..Config::default()
+        };
+        let app = test::init_service(testing::builder_with(config).await.build()).await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+
+        assert_eq!(
+            res.headers().get(header::CONTENT_SECURITY_POLICY).unwrap(),
+            "default-src 'none'"
+        );
+    }
+
+    #[actix_web::test]
+    async fn a_handler_set_header_is_kept() {
+        let app = test::init_service(App::new().wrap(security_headers(DEFAULT_CSP)).route(
+            "/embeddable",
+            web::get().to(|| async {
+                HttpResponse::Ok()
+                    .insert_header((header::CONTENT_SECURITY_POLICY, "frame-ancestors *"))
+                    .insert_header((header::X_FRAME_OPTIONS, "SAMEORIGIN"))
+                    .finish()
+            }),
+        ))
+        .await;
+
+        let req = test::TestRequest::get().uri("/embeddable").to_request();
+        let res = test::call_service(&app, req).await;
+
+        let headers = res.headers();
+        assert_eq!(
+            headers.get(header::CONTENT_SECURITY_POLICY).unwrap(),
+            "frame-ancestors *"
+        );
+        assert_eq!(headers.get(header::X_FRAME_OPTIONS).unwrap(), "SAMEORIGIN");
+        assert_eq!(
+            headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+            "nosniff"
+        );
+    }
+}
@@ -0,0 +1,294 @@
+/*
+   COOKIE SESSIONS
+    `POST /login`   -> looks the user up and stores its id in the session
+    `GET /profile`  -> the logged in user, `401` without a session
+    `POST /logout`  -> clears the session (the cookie is removed)
+
+    with actix-session's CookieSessionStore the session data lives IN the cookie itself, no
+     server side storage. the cookie is encrypted and authenticated with a Key, so the client can
+     neither read nor forge it. the cookie is:
+     - HttpOnly      -> not readable from javascript
+     - SameSite=Lax  -> not sent on cross site POSTs (basic CSRF protection)
+     - Secure        -> only when the app is served over https (`tls_enabled`), otherwise
+                         browsers would never send it back over plain http
+
+    the key comes from SESSION_KEY (at least 64 bytes). without it a random key is generated at
+     startup, which logs everybody out on every restart. either way it is created ONCE and shared
+     by all workers: with a key per worker a cookie would only work on the worker that set it.
+
+    there are no passwords in the users table, so logging in by email stands in for a real
+     credential check.
+
+    handlers that need the logged in user take an AuthenticatedUser: it answers `401` itself
+     when there is no (valid) session, so the handler only ever runs with a user.
+
+    DEV-AUTH (cargo feature `dev-auth`, eg: `cargo run --features dev-auth`)
+     for local development without logging in: any request with an `X-Dev-User` header is taken
+     as a fixed fake user (id 0, named after the header), the session isn't even looked at.
+     the code for it is behind #[cfg(feature = "dev-auth")], so a build without the feature
+     doesn't contain it at all and the header is just ignored. startup logs a warning when it is in.
+*/
+
+use std::env;
+
+use actix_session::{storage::CookieSessionStore, Session, SessionExt, SessionMiddleware};
+use actix_web::{
+    cookie::{Key, SameSite},
+    dev::Payload,
+    error, get, post, web, FromRequest, HttpRequest, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::users::{self, User};
+
+const USER_ID_KEY: &str = "user_id";
+
+pub fn key_from_env() -> Key {
+    match env::var("SESSION_KEY").map(|key| Key::try_from(key.as_bytes())) {
+        Ok(Ok(key)) => key,
+        Ok(Err(_)) => {
+            log::warn!("SESSION_KEY is shorter than 64 bytes, using a random key");
+            Key::generate()
+        }
+        Err(_) => {
+            log::warn!("SESSION_KEY is not set, using a random key (sessions end on restart)");
+            Key::generate()
+        }
+    }
+}
+
+pub fn middleware(key: Key, secure: bool) -> SessionMiddleware<CookieSessionStore> {
+    SessionMiddleware::builder(CookieSessionStore::default(), key)
+        .cookie_http_only(true)
+        .cookie_same_site(SameSite::Lax)
+        .cookie_secure(secure)
+        .build()
+}
+
+#[cfg(feature = "dev-auth")]
+const DEV_USER_HEADER: &str = "X-Dev-User";
+
+pub struct AuthenticatedUser(pub User);
+
+#[cfg(feature = "dev-auth")]
+fn dev_user(req: &HttpRequest) -> Option<AuthenticatedUser> {
+    let name = req.headers().get(DEV_USER_HEADER)?.to_str().ok()?;
+    Some(AuthenticatedUser(User {
+        id: 0,
+        name: name.to_owned(),
+        email: "dev@localhost".to_owned(),
+    }))
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        #[cfg(feature = "dev-auth")]
+        if let Some(user) = dev_user(req) {
+            return Box::pin(async { Ok(user) });
+        }
+
+        let session = req.get_session();
+        let pool = req.app_data::<web::Data<SqlitePool>>().cloned();
+
+        Box::pin(async move {
+            let pool = pool.ok_or_else(|| {
+                error::ErrorInternalServerError("SqlitePool is not registered as app data")
+            })?;
+            let Some(id) = session.get::<i64>(USER_ID_KEY)? else {
+                return Err(error::ErrorUnauthorized("not logged in"));
+            };
+
+            match users::find(&pool, id)
+                .await
+                .map_err(error::ErrorInternalServerError)?
+            {
+                Some(user) => Ok(AuthenticatedUser(user)),
+                // the user was deleted since logging in
+                None => {
+                    session.purge();
+                    Err(error::ErrorUnauthorized("not logged in"))
+                }
+            }
+        })
+    }
+}
+
+// startup note, so a dev-auth build is never mistaken for a real one
+pub fn warn_if_dev_auth() {
+    #[cfg(feature = "dev-auth")]
+    log::warn!("built with dev-auth: any request with {DEV_USER_HEADER} is logged in");
+}
+
+#[derive(Deserialize)]
+pub struct Login {
+    email: String,
+}
+
+#[post("/login")]
+pub async fn login(
+    pool: web::Data<SqlitePool>,
+    session: Session,
+    body: web::Json<Login>,
+) -> actix_web::Result<HttpResponse> {
+    let user = users::find_by_email(&pool, &body.email)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let Some(user) = user else {
+        return Ok(HttpResponse::Unauthorized().body("unknown user"));
+    };
+
+    // a new session id on login, so a session planted before it can't be taken over
+    session.renew();
+    session.insert(USER_ID_KEY, user.id)?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[get("/profile")]
+pub async fn profile(user: AuthenticatedUser) -> HttpResponse {
+    HttpResponse::Ok().json(user.0)
+}
+
+#[post("/logout")]
+pub async fn logout(session: Session) -> HttpResponse {
+    session.purge();
+    HttpResponse::NoContent().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        cookie::Cookie,
+        dev::ServiceResponse,
+        http::{header, StatusCode},
+        test,
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{config::Config, testing};
+
+    fn sign_up() -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "name": "Abebe", "email": "abebe@example.com" }))
+    }
+
+    fn log_in(email: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/login")
+            .set_json(json!({ "email": email }))
+    }
+
+    fn profile_with(cookie: Option<Cookie<'static>>) -> test::TestRequest {
+        let req = test::TestRequest::get().uri("/profile");
+        match cookie {
+            Some(cookie) => req.cookie(cookie),
+            None => req,
+        }
+    }
+
+    fn session_cookie<B>(res: &ServiceResponse<B>) -> Cookie<'static> {
+        res.response()
+            .cookies()
+            .find(|cookie| cookie.name() == "id")
+            .expect("a session cookie")
+            .into_owned()
+    }
+
+    #[actix_web::test]
+    async fn login_then_profile_then_logout() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        test::call_service(&app, sign_up().to_request()).await;
+
+        let res = test::call_service(&app, log_in("abebe@example.com").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let cookie = session_cookie(&res);
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_ne!(cookie.secure(), Some(true));
+
+        let res = test::call_service(&app, profile_with(Some(cookie.clone())).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let user: Value = test::read_body_json(res).await;
+        assert_eq!(user["email"], "abebe@example.com");
+
+        let req = test::TestRequest::post()
+            .uri("/logout")
+            .cookie(cookie)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let removal = res.headers().get(header::SET_COOKIE).unwrap();
+        assert!(removal.to_str().unwrap().contains("Max-Age=0"));
+    }
+
+    #[actix_web::test]
+    async fn no_session_no_profile() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, profile_with(None).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let forged = Cookie::new("id", "not-a-real-session");
+        let res = test::call_service(&app, profile_with(Some(forged)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn an_unknown_email_is_not_logged_in() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, log_in("nobody@example.com").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert!(!res.headers().contains_key(header::SET_COOKIE));
+    }
+
+    #[actix_web::test]
+    async fn the_cookie_is_secure_with_tls() {
+        let config = Config {
+            tls_enabled: true,
+            ..Config::default()
+        };
+        let app = test::init_service(testing::builder_with(config).await.build()).await;
+        test::call_service(&app, sign_up().to_request()).await;
+
+        let res = test::call_service(&app, log_in("abebe@example.com").to_request()).await;
+
+        assert_eq!(session_cookie(&res).secure(), Some(true));
+    }
+
+    #[cfg(feature = "dev-auth")]
+    #[actix_web::test]
+    async fn the_dev_header_is_a_fake_user() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = profile_with(None)
+            .insert_header((DEV_USER_HEADER, "tester"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let user: Value = test::read_body_json(res).await;
+        assert_eq!(user["id"], 0);
+        assert_eq!(user["name"], "tester");
+    }
+
+    #[cfg(not(feature = "dev-auth"))]
+    #[actix_web::test]
+    async fn without_dev_auth_the_header_is_ignored() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = profile_with(None)
+            .insert_header(("X-Dev-User", "tester"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
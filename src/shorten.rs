@@ -0,0 +1,205 @@
+/*
+   REDIRECTS AND A URL SHORTENER
+    `POST /shorten` with `{ "url": "https://..." }` stores the url under a new short code and
+     answers `303 See Other` pointing at the short link, `GET /s/{code}` then answers
+     `301 Moved Permanently` to the original url (`404` for a code that doesn't exist).
+
+    - 303 after a POST: the client follows it with a GET, so a reload of the page it lands on
+       doesn't post the form again. redirect_to() builds it for any handler
+    - 301 for the short link: it never changes, browsers and proxies may remember it
+    - the Location of the 303 comes from url_for() with the ROUTE NAME `short_link` (the `name`
+       of the GET route) instead of a hand written "/s/{code}", so moving the route (eg: into a
+       scope) can't leave a broken link behind
+
+    codes are CODE_LEN random base62 characters, drawn again while the code is taken; the check
+     and the insert happen under the same lock, so two posts can't get the same code. only http
+     and https urls are accepted: a short link to `javascript:` or `file:` would turn the
+     shortener into a tool for attacks on whoever clicks it. at most MAX_LINKS are kept (it all
+     lives in memory), after that `503`.
+*/
+
+use std::{collections::HashMap, sync::Mutex};
+
+use actix_web::{
+    error, get,
+    http::{header, Uri},
+    post, web, HttpRequest, HttpResponse,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+const CODE_LEN: usize = 7;
+const MAX_LINKS: usize = 100_000;
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+#[derive(Default)]
+pub struct ShortLinks {
+    links: Mutex<HashMap<String, String>>,
+}
+
+impl ShortLinks {
+    fn get(&self, code: &str) -> Option<String> {
+        self.links.lock().unwrap().get(code).cloned()
+    }
+
+    // None when the store is full
+    fn insert(&self, url: String) -> Option<String> {
+        let mut links = self.links.lock().unwrap();
+        if links.len() >= MAX_LINKS {
+            return None;
+        }
+        let code = loop {
+            let code = random_code();
+            if !links.contains_key(&code) {
+                break code;
+            }
+        };
+        links.insert(code.clone(), url);
+        Some(code)
+    }
+}
+
+// a uuid v4 is 122 random bits, plenty for 7 characters
+fn random_code() -> String {
+    Uuid::new_v4()
+        .as_bytes()
+        .iter()
+        .take(CODE_LEN)
+        .map(|&byte| char::from(ALPHABET[usize::from(byte) % ALPHABET.len()]))
+        .collect()
+}
+
+fn is_web_url(url: &str) -> bool {
+    url.parse::<Uri>()
+        .is_ok_and(|uri| matches!(uri.scheme_str(), Some("http" | "https")) && uri.host().is_some())
+}
+
+pub fn redirect_to(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+#[derive(Deserialize)]
+pub struct ShortenRequest {
+    url: String,
+}
+
+#[post("/shorten")]
+pub async fn shorten(
+    req: HttpRequest,
+    links: web::Data<ShortLinks>,
+    body: web::Json<ShortenRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let url = body.into_inner().url;
+    if !is_web_url(&url) {
+        return Ok(HttpResponse::BadRequest().body("url must be an absolute http(s) url"));
+    }
+
+    let Some(code) = links.insert(url) else {
+        return Ok(HttpResponse::ServiceUnavailable().body("no room for more short links"));
+    };
+    let short_link = req
+        .url_for("short_link", [&code])
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(redirect_to(short_link.as_str()))
+}
+
+#[get("/s/{code}", name = "short_link")]
+pub async fn follow(links: web::Data<ShortLinks>, code: web::Path<String>) -> HttpResponse {
+    match links.get(&code) {
+        Some(url) => HttpResponse::MovedPermanently()
+            .insert_header((header::LOCATION, url))
+            .finish(),
+        None => HttpResponse::NotFound().body("unknown short link"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use actix_web::{http::StatusCode, test};
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing;
+
+    fn shorten_request(url: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/shorten")
+            .set_json(json!({ "url": url }))
+    }
+
+    #[actix_web::test]
+    async fn a_short_link_redirects_to_the_url() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let url = "https://example.com/a/long/path?with=query";
+
+        let res = test::call_service(&app, shorten_request(url).to_request()).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let short_link: Uri = res
+            .headers()
+            .get(header::LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let code = short_link.path().strip_prefix("/s/").unwrap();
+        assert_eq!(code.len(), CODE_LEN);
+
+        let req = test::TestRequest::get().uri(short_link.path()).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), url);
+    }
+
+    #[actix_web::test]
+    async fn an_unknown_code_is_404() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/s/nothere").to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn only_web_urls_are_shortened() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for url in [
+            "javascript:alert(1)",
+            "file:///etc/passwd",
+            "/relative",
+            "not a url",
+        ] {
+            let res = test::call_service(&app, shorten_request(url).to_request()).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{url}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn every_link_gets_its_own_code() {
+        let links = ShortLinks::default();
+
+        let codes: HashSet<_> = (0..10_000)
+            .map(|n| links.insert(format!("https://example.com/{n}")).unwrap())
+            .collect();
+
+        assert_eq!(codes.len(), 10_000);
+        assert!(codes
+            .iter()
+            .all(|code| code.bytes().all(|b| ALPHABET.contains(&b))));
+    }
+
+    #[actix_web::test]
+    async fn redirect_to_is_a_303() {
+        let res = redirect_to("/somewhere");
+
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/somewhere");
+    }
+}
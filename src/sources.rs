@@ -0,0 +1,116 @@
+/*
+   STREAMING AGGREGATION FROM MULTIPLE SOURCES
+    `GET /sources/stream` asks several (simulated) internal sources at the same time and streams
+     their items into ONE JSON array, as soon as each source answers.
+
+    - FuturesUnordered polls all the source futures concurrently and yields each result the moment
+       it is ready, so a slow source doesn't hold back the fast ones (the order is not fixed)
+    - HttpResponse::streaming() sends every chunk as it is produced (chunked transfer encoding),
+       so the client gets data progressively instead of waiting for the slowest source
+
+    the array framing is written by us: `[` first, a `,` before every element except the first
+     one, and `]` at the end. each element is serialized as a whole chunk, so the output stays
+     valid JSON no matter how the sources interleave.
+*/
+
+use std::{future::ready, time::Duration};
+
+use actix_web::{get, http::header::ContentType, rt::time::sleep, web::Bytes, Error, HttpResponse};
+use futures_util::stream::{self, FuturesUnordered, StreamExt};
+use serde::Serialize;
+
+pub struct Source {
+    pub name: &'static str,
+    pub latency: Duration,
+    pub item_count: u32,
+}
+
+// stand-ins for other services / databases, each with its own response time
+pub const SOURCES: [Source; 3] = [
+    Source {
+        name: "inventory",
+        latency: Duration::from_millis(300),
+        item_count: 2,
+    },
+    Source {
+        name: "orders",
+        latency: Duration::from_millis(100),
+        item_count: 3,
+    },
+    Source {
+        name: "users",
+        latency: Duration::from_millis(200),
+        item_count: 1,
+    },
+];
+
+#[derive(Serialize)]
+pub struct SourceItem {
+    source: &'static str,
+    id: u32,
+}
+
+impl Source {
+    pub async fn fetch(&self) -> Vec<SourceItem> {
+        sleep(self.latency).await; // <- async wait, the worker keeps serving other requests
+        (1..=self.item_count)
+            .map(|id| SourceItem {
+                source: self.name,
+                id,
+            })
+            .collect()
+    }
+}
+
+#[get("/sources/stream")]
+pub async fn stream_sources() -> HttpResponse {
+    let items = SOURCES
+        .iter()
+        .map(Source::fetch)
+        .collect::<FuturesUnordered<_>>()
+        .flat_map(stream::iter);
+
+    let mut first = true;
+    let elements = items.map(move |item| {
+        let mut chunk = if first { Vec::new() } else { b",".to_vec() };
+        first = false;
+        serde_json::to_writer(&mut chunk, &item)?;
+        Ok::<_, Error>(Bytes::from(chunk))
+    });
+
+    let body = stream::once(ready(Ok(Bytes::from_static(b"["))))
+        .chain(elements)
+        .chain(stream::once(ready(Ok(Bytes::from_static(b"]")))));
+
+    HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .streaming(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::header, test};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    #[actix_web::test]
+    async fn streams_every_source_as_one_array_fastest_first() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/sources/stream").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        let items: Vec<Value> = test::read_body_json(res).await;
+
+        let total: u32 = SOURCES.iter().map(|source| source.item_count).sum();
+        assert_eq!(items.len(), total as usize);
+        // orders answers first (100ms), inventory last (300ms)
+        assert_eq!(items.first().unwrap()["source"], "orders");
+        assert_eq!(items.last().unwrap()["source"], "inventory");
+    }
+}
@@ -0,0 +1,188 @@
+/*
+   SEVERAL STATE TYPES, AND CHECKING THEY ARE THERE
+    app_data() can be called any number of times; each call registers one value under its TYPE,
+     and a handler gets it back by asking for web::Data<ThatType>. `GET /debug/state` asks for
+     three at once (the database pool, the config and the metrics), no wrapping struct needed.
+     it is a debug-only route (see debug.rs).
+
+    what is registered where:
+     - AppBuilder::build() -> every web::Data the handlers use: Config, DbPool, Metrics, the
+        job / upload / idempotency stores, the caches, ... (one field per type on AppBuilder)
+     - the "/admin" scope   -> nothing extra, it reuses the app's Option<AdminCredentials>
+     - contact::form_config() and the extractor_errors configs -> Form/Json/Path/QueryConfig,
+        not state but looked up the same way by their extractors
+
+    the catch: a handler asking for a type that was never registered still COMPILES, and only
+     answers `500` once it is called. so:
+     - at startup, check_app_data() looks up every type the handlers and middleware extract
+        (the list in missing_data() below) in AppBuilder::registered_data(), and main refuses to
+        start if one isn't there. a handler asking for a new type adds it to that list.
+     - the tests call every route of the route table (app::ROUTES) once on the real app, and
+        none may answer `500`: a handler asking for a type that isn't registered fails them even
+        if nobody added it to the list.
+*/
+
+use std::{any::type_name, io};
+
+use actix_web::{
+    dev::Extensions,
+    get,
+    http::header::{CacheControl, CacheDirective},
+    web, HttpResponse, Responder,
+};
+use serde_json::json;
+
+use crate::{
+    api_key::ApiKeys, app::AppBuilder, auth::AdminCredentials, config::Config,
+    config_reload::LiveConfig, config_routes::ConfigRoutes, db::DbPool, events::EventBus,
+    idempotency::IdempotencyStore, inflight::InflightLimit, ip_allowlist::IpAllowlist,
+    jobs::JobStore, kv::KvStore, maintenance::Maintenance, metrics::Metrics, rate::RateCache,
+    rate_limit::RateLimiter, request_log::RequestLog, response_cache::ResponseCache,
+    reverse_proxy::ReverseProxy, shorten::ShortLinks, supervisor::ShutdownSwitch,
+    uploads::UploadStore, weather::WeatherProxy, write_lock::ResourceLocks,
+};
+
+#[get("/debug/state")]
+pub async fn show_state(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(json!({
+            "db_connections": pool.size(),
+            "bind_addr": format!("{}:{}", config.bind_addr, config.port),
+            "requests_served": metrics.total_requests(),
+        }))
+}
+
+// the name of T if there is no web::Data<T> in `data`
+fn missing<T: 'static>(data: &Extensions) -> Option<&'static str> {
+    (!data.contains::<web::Data<T>>()).then(type_name::<T>)
+}
+
+// every state type the handlers and middleware ask for, that isn't in `data`
+fn missing_data(data: &Extensions) -> Vec<&'static str> {
+    [
+        missing::<Config>(data),
+        missing::<DbPool>(data),
+        missing::<Metrics>(data),
+        missing::<IdempotencyStore>(data),
+        missing::<JobStore>(data),
+        missing::<Option<AdminCredentials>>(data),
+        missing::<EventBus>(data),
+        missing::<UploadStore>(data),
+        missing::<RateCache>(data),
+        missing::<ResourceLocks>(data),
+        missing::<RequestLog>(data),
+        missing::<WeatherProxy>(data),
+        missing::<ShutdownSwitch>(data),
+        missing::<ResponseCache>(data),
+        missing::<ReverseProxy>(data),
+        missing::<Maintenance>(data),
+        missing::<IpAllowlist>(data),
+        missing::<ShortLinks>(data),
+        missing::<ApiKeys>(data),
+        missing::<RateLimiter>(data),
+        missing::<KvStore>(data),
+        missing::<LiveConfig>(data),
+        missing::<ConfigRoutes>(data),
+        missing::<InflightLimit>(data),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+pub fn check_app_data(builder: &AppBuilder) -> io::Result<()> {
+    let missing = missing_data(&builder.registered_data());
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(io::Error::other(format!(
+        "app data the handlers ask for is never registered: {}",
+        missing.join(", ")
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::{Method, StatusCode},
+        test, App,
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{app, debug, testing};
+
+    #[actix_web::test]
+    async fn the_real_app_has_all_its_data() {
+        let config = Config {
+            enable_debug: true,
+            ..Config::default()
+        };
+        let builder = testing::builder_with(config).await;
+
+        check_app_data(&builder).unwrap();
+
+        let app = test::init_service(builder.build()).await;
+        let req = test::TestRequest::get().uri("/debug/state").to_request();
+        let state: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(state["bind_addr"], "127.0.0.1:8080");
+        assert!(state["db_connections"].as_u64().unwrap() >= 1);
+        assert!(state["requests_served"].is_u64());
+    }
+
+    #[actix_web::test]
+    async fn no_route_is_missing_its_data() {
+        let config = Config {
+            enable_debug: true,
+            ..Config::default()
+        };
+        let app = test::init_service(testing::builder_with(config).await.build()).await;
+
+        for &(method, pattern) in app::ROUTES.iter().chain(debug::ROUTES) {
+            // a 500 is all it answers
+            if pattern == "/boom" {
+                continue;
+            }
+            let mut uri = testing::sample_path(pattern);
+            if pattern == "/slow" {
+                uri.push_str("?secs=0");
+            }
+            let method = if method == "*" { "GET" } else { method };
+            let req = test::TestRequest::default()
+                .method(Method::from_bytes(method.as_bytes()).unwrap())
+                .uri(&uri)
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_ne!(
+                res.status(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "{method} {uri}"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_missing_data_type_is_caught() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Config::default()))
+                .service(show_state),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/debug/state").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let mut data = Extensions::new();
+        data.insert(web::Data::new(Config::default()));
+        let missing = missing_data(&data);
+        assert!(missing.contains(&type_name::<DbPool>()), "{missing:?}");
+        assert!(!missing.contains(&type_name::<Config>()), "{missing:?}");
+    }
+}
@@ -0,0 +1,172 @@
+/*
+   STATIC FILES WITH LONG-LIVED CACHING
+    `GET /static/...` serves the files in the `static/` directory (actix-files), `/static` alone
+     is its index.html.
+
+    the Cache-Control each file gets depends on its name:
+     - FINGERPRINTED: a dot- or dash-separated part of at least MIN_HASH_LEN hex digits after
+        the first, eg `app.3f9a1c2b.css` or `chunk-8d2e4f6a.js`. the hash changes whenever the
+        content does, so a given name never changes and browsers may keep it for a year without
+        asking again:
+            Cache-Control: public, max-age=31536000, immutable
+     - anything else (index.html, favicon.ico, ...) keeps its name across deploys, so it may be
+        stored but has to be revalidated every time (the ETag / Last-Modified from actix-files
+        make that a cheap `304`):
+            Cache-Control: no-cache
+
+    only successful answers (2xx, 304) get one: a 404 for a file that isn't deployed YET must
+     not be remembered for a year. the header is the same for every origin, so a CORS request
+     and a plain one share the cached copy.
+*/
+
+use actix_files::Files;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{self, CacheControl, CacheDirective, TryIntoHeaderValue},
+        StatusCode,
+    },
+    middleware::Next,
+    web, Error,
+};
+
+pub const SCOPE: &str = "/static";
+pub const STATIC_DIR: &str = "static";
+
+const MIN_HASH_LEN: usize = 8;
+const ONE_YEAR_SECS: u32 = 365 * 24 * 60 * 60;
+
+// `app.3f9a1c2b.css` yes, `index.html` / `3f9a1c2b.css` (no name part in front) no
+fn is_fingerprinted(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let Some((stem, _extension)) = name.rsplit_once('.') else {
+        return false;
+    };
+    stem.split(['.', '-'])
+        .skip(1)
+        .any(|part| part.len() >= MIN_HASH_LEN && part.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+fn cache_control(path: &str) -> CacheControl {
+    if is_fingerprinted(path) {
+        CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(ONE_YEAR_SECS),
+            CacheDirective::Extension("immutable".to_owned(), None),
+        ])
+    } else {
+        CacheControl(vec![CacheDirective::NoCache])
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(Files::new("", STATIC_DIR).index_file("index.html"));
+}
+
+pub async fn cache_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let directives = cache_control(req.path());
+    let mut res = next.call(req).await?;
+
+    let status = res.status();
+    if status.is_success() || status == StatusCode::NOT_MODIFIED {
+        res.headers_mut()
+            .insert(header::CACHE_CONTROL, directives.try_into_value()?);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+
+    use super::*;
+    use crate::testing;
+
+    const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+    fn get(uri: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(uri)
+    }
+
+    #[actix_web::test]
+    async fn a_hashed_asset_is_cached_for_a_year() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, get("/static/app.3f9a1c2b.css").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CACHE_CONTROL).unwrap(), IMMUTABLE);
+    }
+
+    #[actix_web::test]
+    async fn html_is_always_revalidated() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for uri in ["/static/index.html", "/static/"] {
+            let res = test::call_service(&app, get(uri).to_request()).await;
+
+            assert_eq!(res.status(), StatusCode::OK, "{uri}");
+            assert_eq!(
+                res.headers().get(header::CACHE_CONTROL).unwrap(),
+                "no-cache"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_revalidation_keeps_the_header_and_a_cors_request_gets_the_same() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let res = test::call_service(&app, get("/static/index.html").to_request()).await;
+        let etag = res.headers().get(header::ETAG).unwrap().clone();
+
+        let req = get("/static/index.html")
+            .insert_header((header::IF_NONE_MATCH, etag))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(
+            res.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-cache"
+        );
+
+        let req = get("/static/app.3f9a1c2b.css")
+            .insert_header((header::ORIGIN, "https://elsewhere.example"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(header::CACHE_CONTROL).unwrap(), IMMUTABLE);
+    }
+
+    #[actix_web::test]
+    async fn a_missing_asset_is_not_cached() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, get("/static/app.00000000.js").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(res.headers().get(header::CACHE_CONTROL).is_none());
+    }
+
+    #[actix_web::test]
+    async fn fingerprints_are_recognised_by_name() {
+        for path in [
+            "/static/app.3f9a1c2b.css",
+            "chunk-8d2e4f6a.js",
+            "a.b.0123456789ab.map",
+        ] {
+            assert!(is_fingerprinted(path), "{path}");
+        }
+        for path in [
+            "index.html",
+            "3f9a1c2b.css",
+            "app.3f9a1c2.css",
+            "app.3f9a1c2g.css",
+            "app",
+        ] {
+            assert!(!is_fingerprinted(path), "{path}");
+        }
+    }
+}
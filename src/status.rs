@@ -0,0 +1,147 @@
+/*
+   STATUS CODES ON DEMAND
+    `GET /status/{code}` answers with exactly that status, for trying out how a client handles
+     it (like httpbin's /status). the body is the code and its reason, eg: `404 Not Found`.
+
+    - only 100-599 are status codes, anything else is `400`
+    - redirects (301, 302, 303, 307, 308) come with a `Location`: `/` or the local path given in
+       `?location=`; an absolute url isn't taken, that would make this an open redirect
+    - 1xx, 204 and 304 have no body by definition, so none is sent
+*/
+
+use actix_web::{
+    get,
+    http::{
+        header::{self, CacheControl, CacheDirective},
+        StatusCode,
+    },
+    web, HttpResponse,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Deserialize)]
+pub struct StatusQuery {
+    location: Option<String>,
+}
+
+fn is_redirect(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308)
+}
+
+fn has_body(status: StatusCode) -> bool {
+    !(status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED)
+}
+
+// "/x" but not "//host/x", which a browser reads as another host
+fn is_local_path(location: &str) -> bool {
+    location.starts_with('/') && !location.starts_with("//")
+}
+
+#[get("/status/{code}")]
+pub async fn status_code(code: web::Path<u16>, query: web::Query<StatusQuery>) -> HttpResponse {
+    let code = code.into_inner();
+    let status = match StatusCode::from_u16(code) {
+        Ok(status) if (100..=599).contains(&code) => status,
+        _ => {
+            return HttpResponse::BadRequest()
+                .json(json!({ "error": format!("{code} is not a status code (100-599)") }))
+        }
+    };
+
+    let location = query.location.as_deref().unwrap_or("/");
+    if !is_local_path(location) {
+        return HttpResponse::BadRequest()
+            .json(json!({ "error": "location must be a local path, eg: /users" }));
+    }
+
+    let mut res = HttpResponse::build(status);
+    res.insert_header(CacheControl(vec![CacheDirective::NoStore]));
+    if is_redirect(status) {
+        res.insert_header((header::LOCATION, location));
+    }
+
+    if has_body(status) {
+        res.body(format!(
+            "{code} {}",
+            status.canonical_reason().unwrap_or("Unknown")
+        ))
+    } else {
+        res.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body::MessageBody, test};
+
+    use super::*;
+    use crate::testing;
+
+    async fn fetch(uri: &str) -> (StatusCode, Option<String>, web::Bytes) {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let res = test::call_service(&app, req).await;
+        let location = res
+            .headers()
+            .get(header::LOCATION)
+            .map(|location| location.to_str().unwrap().to_owned());
+        (res.status(), location, test::read_body(res).await)
+    }
+
+    #[actix_web::test]
+    async fn answers_the_requested_status_with_its_reason() {
+        for (code, body) in [
+            (200, "200 OK"),
+            (404, "404 Not Found"),
+            (418, "418 I'm a teapot"),
+        ] {
+            let (status, location, read) = fetch(&format!("/status/{code}")).await;
+
+            assert_eq!(status.as_u16(), code);
+            assert_eq!(location, None);
+            assert_eq!(read, body);
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_redirect_has_a_location() {
+        let (status, location, _) = fetch("/status/302").await;
+        assert_eq!(status, StatusCode::FOUND);
+        assert_eq!(location.as_deref(), Some("/"));
+
+        let (status, location, _) = fetch("/status/307?location=/users").await;
+        assert_eq!(status, StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(location.as_deref(), Some("/users"));
+    }
+
+    #[actix_web::test]
+    async fn no_content_and_not_modified_have_no_body() {
+        for code in [204, 304] {
+            let app = test::init_service(testing::builder().await.build()).await;
+            let req = test::TestRequest::get()
+                .uri(&format!("/status/{code}"))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+
+            assert_eq!(res.status().as_u16(), code);
+            assert!(res.response().body().size().is_eof(), "{code}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn anything_else_is_400() {
+        for uri in [
+            "/status/99",
+            "/status/600",
+            "/status/301?location=https://evil.example",
+            "/status/301?location=//evil.example",
+        ] {
+            let (status, location, _) = fetch(uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+            assert_eq!(location, None);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+/*
+   SERVING BEHIND A PATH PREFIX
+    a proxy that publishes the app as `https://example.com/service-a/...` forwards
+     `/service-a/users` as is, and no route matches it. with `strip_prefix` (env STRIP_PREFIX,
+     see config.rs) set to `/service-a`, this middleware takes the prefix off BEFORE routing, so
+     the handlers only ever see `/users`.
+
+    - only a whole leading segment is a match: `/service-a` and `/service-a/x`, not
+       `/service-ab`; a request without the prefix passes through unchanged
+    - on the way back a `Location` pointing into the app gets the prefix put back in front, so
+       redirects built with url_for() (or any `/path`) still lead the client through the proxy
+    - unset or empty -> the middleware does nothing
+
+    it is outside trailing_slash.rs and everything else that looks at the path; the access log
+     (further out) still shows the path as the client sent it.
+*/
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{self, HeaderValue},
+        uri::PathAndQuery,
+        Uri,
+    },
+    middleware::Next,
+    web, Error,
+};
+
+use crate::config::Config;
+
+// "/service-a/users" -> "/users", "/service-a" -> "/", "/users" -> None
+fn without_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    match path.strip_prefix(prefix)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+// a Location is ours when it is a plain path or an absolute url to the host of the request
+fn with_prefix(location: &str, prefix: &str, host: &str) -> Option<String> {
+    if location.starts_with('/') && !location.starts_with("//") {
+        return Some(format!("{prefix}{location}"));
+    }
+    let uri = location.parse::<Uri>().ok()?;
+    let authority = uri.authority()?;
+    if authority.as_str() != host {
+        return None;
+    }
+    let path_and_query = uri.path_and_query().map_or("/", PathAndQuery::as_str);
+    Some(format!(
+        "{}://{authority}{prefix}{path_and_query}",
+        uri.scheme_str()?
+    ))
+}
+
+pub async fn strip_prefix(
+    config: web::Data<Config>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let prefix = config.strip_prefix.as_str();
+    let stripped = match without_prefix(req.path(), prefix) {
+        Some(path) if !prefix.is_empty() => path.to_owned(),
+        _ => {
+            return next
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_boxed_body)
+        }
+    };
+
+    let path_and_query = match req.query_string() {
+        "" => stripped,
+        query => format!("{stripped}?{query}"),
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = PathAndQuery::try_from(path_and_query).ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        req.match_info_mut().get_mut().update(&uri);
+        req.head_mut().uri = uri;
+    }
+    let host = req.connection_info().host().to_owned();
+
+    let mut res = next.call(req).await?;
+    let location = res
+        .headers()
+        .get(header::LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|location| with_prefix(location, prefix, &host))
+        .and_then(|location| HeaderValue::from_str(&location).ok());
+    if let Some(location) = location {
+        res.headers_mut().insert(header::LOCATION, location);
+    }
+
+    Ok(res.map_into_boxed_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing;
+
+    fn prefixed(trailing_slash: &str) -> Config {
+        Config {
+            strip_prefix: "/service-a".to_owned(),
+            trailing_slash: trailing_slash.to_owned(),
+            ..Config::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_prefixed_request_reaches_the_handler_and_others_pass() {
+        let app = test::init_service(testing::builder_with(prefixed("trim")).await.build()).await;
+
+        for (uri, status) in [
+            ("/service-a/users?limit=1", StatusCode::OK),
+            ("/users", StatusCode::OK),
+            ("/service-ab/users", StatusCode::NOT_FOUND),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), status, "{uri}");
+        }
+
+        let req = test::TestRequest::get().uri("/service-a").to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "Hello world!");
+    }
+
+    #[actix_web::test]
+    async fn generated_urls_get_the_prefix_back() {
+        let app = test::init_service(testing::builder_with(prefixed("trim")).await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/service-a/shorten")
+            .set_json(json!({ "url": "https://example.com/" }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let location = res
+            .headers()
+            .get(header::LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let short_link: Uri = location.parse().unwrap();
+        assert!(short_link.path().starts_with("/service-a/s/"), "{location}");
+
+        let req = test::TestRequest::get().uri(short_link.path()).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+    }
+
+    #[actix_web::test]
+    async fn a_redirect_to_a_path_keeps_the_prefix() {
+        let app =
+            test::init_service(testing::builder_with(prefixed("redirect")).await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/service-a/users/")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            res.headers().get(header::LOCATION).unwrap(),
+            "/service-a/users"
+        );
+    }
+
+    #[actix_web::test]
+    async fn only_our_own_locations_are_rewritten() {
+        let host = "example.com";
+
+        assert_eq!(
+            with_prefix("/users", "/p", host).as_deref(),
+            Some("/p/users")
+        );
+        assert_eq!(
+            with_prefix("https://example.com/s/x?y=1", "/p", host).as_deref(),
+            Some("https://example.com/p/s/x?y=1")
+        );
+        assert_eq!(with_prefix("https://other.org/x", "/p", host), None);
+        assert_eq!(with_prefix("//other.org/x", "/p", host), None);
+    }
+}
@@ -0,0 +1,178 @@
+/*
+   ONE ROUTE, JSON OR FORM
+    `POST /submit` takes the same data either way, an API client sends JSON and an HTML form
+     posts urlencoded fields:
+
+        Content-Type: application/json                   {"name":"Abebe","quantity":2}
+        Content-Type: application/x-www-form-urlencoded  name=Abebe&quantity=2
+
+    the JsonOrForm<T> extractor looks at the Content-Type and hands the request to web::Json<T>
+     or web::Form<T>, so both end up as the SAME T (and use the same configs: size limits and the
+     error format of extractor_errors.rs). any other Content-Type is `415`.
+
+    web::Either<Json<T>, Form<T>> would do it too, but it tries JSON first and falls back to the
+     form on ANY error, so a broken JSON body would be reported as a form error.
+*/
+
+use actix_web::{
+    dev::Payload, http::StatusCode, mime, post, web, Error, FromRequest, HttpMessage, HttpRequest,
+    HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+use crate::extractor_errors;
+
+pub struct JsonOrForm<T> {
+    pub value: T,
+    // "json" or "form", what the client sent
+    pub format: &'static str,
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for JsonOrForm<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let essence = req
+            .mime_type()
+            .ok()
+            .flatten()
+            .map(|mime| mime.essence_str().to_owned());
+
+        match essence.as_deref() {
+            Some(essence) if essence == mime::APPLICATION_JSON.essence_str() => {
+                let json = web::Json::<T>::from_request(req, payload);
+                Box::pin(async move {
+                    Ok(JsonOrForm {
+                        value: json.await?.into_inner(),
+                        format: "json",
+                    })
+                })
+            }
+            Some(essence) if essence == mime::APPLICATION_WWW_FORM_URLENCODED.essence_str() => {
+                let form = web::Form::<T>::from_request(req, payload);
+                Box::pin(async move {
+                    Ok(JsonOrForm {
+                        value: form.await?.into_inner(),
+                        format: "form",
+                    })
+                })
+            }
+            _ => {
+                let message = "expected a JSON or form body (Content-Type: application/json or \
+                               application/x-www-form-urlencoded)"
+                    .to_owned();
+                let err = extractor_errors::error_response(
+                    message.clone(),
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "body",
+                    message,
+                );
+                Box::pin(async move { Err(err) })
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Submission {
+    name: String,
+    quantity: u32,
+    #[serde(default)]
+    gift_wrap: bool,
+}
+
+#[post("/submit")]
+pub async fn submit(body: JsonOrForm<Submission>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "submission": body.value,
+        "received_as": body.format,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::header, test};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    async fn post_submit(content_type: &str, body: &'static str) -> (StatusCode, Value) {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::post()
+            .uri("/submit")
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .set_payload(body)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        (res.status(), test::read_body_json(res).await)
+    }
+
+    #[actix_web::test]
+    async fn json_and_form_give_the_same_submission() {
+        let (json_status, from_json) = post_submit(
+            "application/json",
+            r#"{"name": "Abebe", "quantity": 2, "gift_wrap": true}"#,
+        )
+        .await;
+        let (form_status, from_form) = post_submit(
+            "application/x-www-form-urlencoded",
+            "name=Abebe&quantity=2&gift_wrap=true",
+        )
+        .await;
+
+        assert_eq!(json_status, StatusCode::OK);
+        assert_eq!(form_status, StatusCode::OK);
+        assert_eq!(from_json["submission"], from_form["submission"]);
+        assert_eq!(
+            from_json["submission"],
+            json!({ "name": "Abebe", "quantity": 2, "gift_wrap": true })
+        );
+        assert_eq!(from_json["received_as"], "json");
+        assert_eq!(from_form["received_as"], "form");
+    }
+
+    #[actix_web::test]
+    async fn a_charset_parameter_doesnt_matter() {
+        let (status, body) = post_submit(
+            "application/x-www-form-urlencoded; charset=utf-8",
+            "name=Abebe&quantity=1",
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["submission"]["gift_wrap"], false);
+    }
+
+    #[actix_web::test]
+    async fn a_broken_body_is_reported_in_its_own_format() {
+        let (status, body) = post_submit("application/json", r#"{"name": "Abebe""#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["source"], "json");
+
+        let (status, body) = post_submit(
+            "application/x-www-form-urlencoded",
+            "name=Abebe&quantity=two",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["source"], "form");
+    }
+
+    #[actix_web::test]
+    async fn any_other_content_type_is_415() {
+        for content_type in ["text/plain", "multipart/form-data; boundary=x", ""] {
+            let (status, body) = post_submit(content_type, "name=Abebe&quantity=2").await;
+
+            assert_eq!(
+                status,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "{content_type:?}"
+            );
+            assert_eq!(body["source"], "body");
+        }
+    }
+}
@@ -0,0 +1,299 @@
+/*
+   CHANGING THE WORKER COUNT WITHOUT DOWNTIME
+    actix can't resize a running server's worker pool. instead, on SIGUSR2 a whole new server is
+     started next to the old one, and only then is the old one told to stop:
+     1, the config is reloaded and a NEW server is bound to the SAME address. this is allowed
+         because the listeners are created with SO_REUSEADDR + SO_REUSEPORT: the kernel keeps
+         both sockets open and spreads new connections over them
+     2, the new server starts accepting
+     3, the old server is stopped GRACEFULLY: it stops accepting, finishes its in-flight
+         requests, then exits
+     at every moment at least one server is accepting, so clients never see a refused connection.
+
+    the shared state (AppBuilder) is reused, so caches, jobs, ... survive the restart.
+
+    SO_REUSEPORT would just as well let a SECOND PROCESS bind the port, and the kernel would
+     quietly split the traffic between two unrelated servers. so at startup (not on SIGUSR2) the
+     address is first bound once WITHOUT it: if anything listens there already, that fails with
+     "address in use" and the server doesn't start. only then is the real listener created.
+    a SIGUSR2 that comes after a shutdown was triggered is ignored: the server is going away,
+     and a new one would never be stopped.
+
+    note: a running process can't see changes to its own environment, so an APP_WORKERS set at
+     launch keeps winning. to change the workers at runtime, edit `workers` in config.toml.
+
+    try it:
+        cargo run &
+        while true; do curl -s localhost:8080/ > /dev/null || echo FAILED; done &
+        sed -i 's/^# workers = 4/workers = 2/' config.toml
+        kill -USR2 <pid of the server>       # log: "starting 2 workers", no FAILED lines
+
+    `POST /admin/shutdown` stops the server from the inside. a handler can only reach the server
+     through the app state, but the app state exists BEFORE the server (and its ServerHandle)
+     does, so the state holds a ShutdownSwitch that main fills in once the server runs (and
+     refills after every SIGUSR2 restart). the first trigger stops the current server
+     gracefully, later ones do nothing.
+
+    READINESS WHILE DRAINING
+     SIGTERM / SIGINT pull the same switch (actix's own signal handling is turned off for it).
+     the FIRST thing a trigger does is mark the instance not ready: `GET /readyz` answers `503`
+     from then on while `/healthz` stays `200` (the process is fine, it just wants no new work).
+     the server keeps accepting for `shutdown_delay_secs` more (default 0, see config.rs), long
+     enough for the load balancer's next readiness probe to take it out of rotation, and only
+     then stops accepting and drains its in-flight requests.
+
+    UNIX SOCKET
+     with `unix_socket` set (env UNIX_SOCKET, see config.rs) the server also listens on that
+     path, next to the TCP address, so local tooling can use `curl --unix-socket`. a socket file
+     can't be bound twice and isn't removed when a process dies, so an existing SOCKET at the path
+     is deleted first: left over from a crash, or the old server's during a SIGUSR2 restart (it
+     keeps serving the connections it already has). anything else at the path is left alone and
+     binding fails.
+*/
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use actix_web::{
+    dev::{Server, ServerHandle},
+    rt, web,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::config::Config;
+
+// `handover`: for a SIGUSR2 restart, where the old server of this process still holds `addr`
+pub fn reusable_listener(addr: SocketAddr, handover: bool) -> io::Result<std::net::TcpListener> {
+    if !handover {
+        // dropped right away, it only proves that nobody else listens on `addr`
+        let probe = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        probe.set_reuse_address(true)?;
+        probe.bind(&addr.into()).map_err(|err| {
+            io::Error::new(err.kind(), format!("{addr} is already in use: {err}"))
+        })?;
+    }
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+#[cfg(unix)]
+pub fn unix_listener(path: &str) -> io::Result<std::os::unix::net::UnixListener> {
+    use std::{fs, os::unix::fs::FileTypeExt};
+
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        log::info!("removing stale socket {path}");
+        fs::remove_file(path)?;
+    }
+    std::os::unix::net::UnixListener::bind(path)
+}
+
+pub struct ShutdownSwitch {
+    handle: Mutex<Option<ServerHandle>>,
+    triggered: AtomicBool,
+    // how long to keep accepting after turning unready
+    delay: Duration,
+}
+
+impl ShutdownSwitch {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            handle: Mutex::new(None),
+            triggered: AtomicBool::new(false),
+            delay,
+        }
+    }
+
+    // false from the first trigger on
+    pub fn is_ready(&self) -> bool {
+        !self.triggered.load(Ordering::SeqCst)
+    }
+
+    pub fn set_handle(&self, handle: ServerHandle) {
+        *self.handle.lock().unwrap() = Some(handle);
+    }
+
+    // true only for the call that actually started the shutdown
+    pub fn trigger(&self) -> bool {
+        let Some(handle) = self.handle.lock().unwrap().clone() else {
+            return false;
+        };
+        if self.triggered.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+
+        // not awaited here: a graceful stop waits for in-flight requests, including this one
+        let delay = self.delay;
+        rt::spawn(async move {
+            rt::time::sleep(delay).await;
+            handle.stop(true).await
+        });
+        true
+    }
+}
+
+// runs `server` until it stops; on SIGUSR2 swaps it for a fresh one built by `restart`,
+//  on SIGTERM / SIGINT pulls the shutdown switch
+#[cfg(unix)]
+pub async fn supervise(
+    server: Server,
+    switch: web::Data<ShutdownSwitch>,
+    mut restart: impl FnMut(&Config) -> io::Result<Server>,
+) -> io::Result<()> {
+    use std::mem;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut reload = signal(SignalKind::user_defined2())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut handle = server.handle();
+    let mut running = rt::spawn(server); // <- a Server only runs while something polls it
+
+    loop {
+        tokio::select! {
+            res = &mut running => return res.map_err(io::Error::other)?,
+            _ = terminate.recv() => {
+                if switch.trigger() {
+                    log::info!("SIGTERM: unready, stopping in {:?}", switch.delay);
+                }
+            }
+            _ = interrupt.recv() => {
+                if switch.trigger() {
+                    log::info!("SIGINT: unready, stopping in {:?}", switch.delay);
+                }
+            }
+            _ = reload.recv() => {
+                if !switch.is_ready() {
+                    log::warn!("SIGUSR2: ignored, the server is shutting down");
+                    continue;
+                }
+                let config = match Config::load() {
+                    Ok(config) => config,
+                    Err(err) => {
+                        log::error!("SIGUSR2: keeping the current server, config is invalid: {err}");
+                        continue;
+                    }
+                };
+
+                let next = match restart(&config) {
+                    Ok(next) => next,
+                    Err(err) => {
+                        log::error!("SIGUSR2: keeping the current server, restart failed: {err}");
+                        continue;
+                    }
+                };
+                log::info!("SIGUSR2: new server started with {} workers", config.workers);
+
+                let old_handle = mem::replace(&mut handle, next.handle());
+                let old_running = mem::replace(&mut running, rt::spawn(next));
+
+                // the new listener is already accepting, now the old server can drain
+                rt::spawn(async move {
+                    old_handle.stop(true).await;
+                    let _ = old_running.await;
+                    log::info!("SIGUSR2: old server drained and stopped");
+                });
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn supervise(
+    server: Server,
+    _switch: web::Data<ShutdownSwitch>,
+    _restart: impl FnMut(&Config) -> io::Result<Server>,
+) -> io::Result<()> {
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, App, HttpServer};
+
+    use super::*;
+
+    fn server_on(listener: std::net::TcpListener, name: &'static str) -> Server {
+        HttpServer::new(move || App::new().route("/", web::get().to(move || async move { name })))
+            .workers(1)
+            .disable_signals()
+            .listen(listener)
+            .unwrap()
+            .run()
+    }
+
+    // a fresh client each time: no pooled connection is kept to the old server
+    async fn get(addr: SocketAddr) -> String {
+        let mut res = awc::Client::default()
+            .get(format!("http://{addr}/"))
+            .send()
+            .await
+            .expect("a connection");
+        assert_eq!(res.status(), StatusCode::OK);
+        String::from_utf8(res.body().await.unwrap().to_vec()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn startup_refuses_an_address_in_use() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+
+        let err = reusable_listener(addr, false).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(err.to_string().contains("already in use"));
+    }
+
+    #[actix_web::test]
+    async fn a_handover_serves_without_a_gap() {
+        let listener = reusable_listener("127.0.0.1:0".parse().unwrap(), false).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let old = server_on(listener, "old");
+        let old_handle = old.handle();
+        let old_running = rt::spawn(old);
+        assert_eq!(get(addr).await, "old");
+
+        // what supervise() does on SIGUSR2: the new server first, only then the old one stops
+        let new = server_on(reusable_listener(addr, true).unwrap(), "new");
+        let new_handle = new.handle();
+        rt::spawn(new);
+        get(addr).await;
+
+        old_handle.stop(true).await;
+        old_running.await.unwrap().unwrap();
+
+        for _ in 0..5 {
+            assert_eq!(get(addr).await, "new");
+        }
+        new_handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn the_switch_turns_unready_and_triggers_once() {
+        let switch = ShutdownSwitch::new(Duration::ZERO);
+        assert!(!switch.trigger(), "no server to stop yet");
+        assert!(switch.is_ready());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = server_on(listener, "ok");
+        switch.set_handle(server.handle());
+        let running = rt::spawn(server);
+
+        assert!(switch.trigger());
+        assert!(!switch.is_ready());
+        assert!(!switch.trigger());
+        running.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+/*
+   TENANT FROM THE SUBDOMAIN
+    every customer (tenant) gets its own subdomain of `base_domain` (config.rs, default
+     example.com): `acme.example.com` -> tenant `acme`. where the Host guard of the virtual
+     hosting section picks a handler by host, the Tenant extractor hands the subdomain to
+     any handler that asks for it:
+
+        async fn handler(tenant: Tenant) -> ...
+
+    the tenant is lowercased (hostnames are case insensitive) and must be a single DNS label:
+     letters, digits and `-`, not starting or ending with `-`, at most 63 characters.
+     a request to the bare domain, to another domain, or with an invalid subdomain gets `400`
+     before the handler runs.
+
+    the host comes from ConnectionInfo, so behind a proxy `X-Forwarded-Host` is used too.
+*/
+
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, error, get, web, FromRequest, HttpRequest, HttpResponse};
+use serde_json::json;
+
+use crate::config::Config;
+
+pub struct Tenant(pub String);
+
+fn is_valid_label(label: &str) -> bool {
+    (1..=63).contains(&label.len())
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+fn tenant_from_host(host: &str, base_domain: &str) -> Result<Tenant, &'static str> {
+    // "Acme.Example.com:8080" -> "acme.example.com"
+    let host = host.rsplit_once(':').map_or(host, |(host, _port)| host);
+    let host = host.to_ascii_lowercase();
+
+    if host == base_domain {
+        return Err("no tenant: use a subdomain, eg: acme.<domain>");
+    }
+    let Some(subdomain) = host.strip_suffix(&format!(".{base_domain}")) else {
+        return Err("unknown domain");
+    };
+    if !is_valid_label(subdomain) {
+        return Err("invalid tenant name");
+    }
+
+    Ok(Tenant(subdomain.to_owned()))
+}
+
+impl FromRequest for Tenant {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let Some(config) = req.app_data::<web::Data<Config>>() else {
+            return ready(Err(error::ErrorInternalServerError(
+                "Config is not registered as app data",
+            )));
+        };
+
+        let tenant = tenant_from_host(req.connection_info().host(), &config.base_domain)
+            .map_err(error::ErrorBadRequest);
+        ready(tenant)
+    }
+}
+
+#[get("/tenant")]
+pub async fn show_tenant(tenant: Tenant) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "tenant": tenant.0 }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::Value;
+
+    use crate::testing;
+
+    fn tenant_of(host: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri("/tenant")
+            .insert_header(("Host", host))
+    }
+
+    #[actix_web::test]
+    async fn a_subdomain_is_the_tenant_lowercased() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for host in ["acme.example.com", "ACME.Example.com:8080"] {
+            let body: Value =
+                test::call_and_read_body_json(&app, tenant_of(host).to_request()).await;
+            assert_eq!(body["tenant"], "acme");
+        }
+    }
+
+    #[actix_web::test]
+    async fn no_or_an_invalid_subdomain_is_400() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for host in [
+            "example.com",
+            "acme.other.org",
+            "ac_me.example.com",
+            "-acme.example.com",
+            "a.b.example.com",
+        ] {
+            let res = test::call_service(&app, tenant_of(host).to_request()).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{host}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_forwarded_host_is_used_too() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = tenant_of("internal:8080")
+            .insert_header(("X-Forwarded-Host", "globex.example.com"))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["tenant"], "globex");
+    }
+}
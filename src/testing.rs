@@ -0,0 +1,190 @@
+/*
+   TEST APP FACTORY
+    tests build the app the way main does, through AppBuilder, so they exercise the same routes,
+     state and middleware as the running server:
+
+        let app = test::init_service(testing::builder().await.build()).await;
+
+    - builder() uses Config::default(), builder_with() takes any other config
+    - every builder gets its OWN in-memory SQLite database (a named one with `cache=shared`, so
+       all connections of the pool see it), tests don't see each other's rows
+    - routes.toml is not read, there are no config routes unless handed in (with_config_routes())
+    - background tasks are not started, a test that needs one starts it itself
+    - what AppBuilder::new() reads from the environment (ADMIN_USER, ...) is handed in with its
+       test-only with_*() methods instead, never through env vars shared by every test
+
+    build() is the whole stack, bare_app() the routes and state without the middleware, for a
+     test that wraps only the middleware it is about.
+
+    a streamed body that never ends (SSE, /logs/stream, ...) can't be read whole, next_chunk()
+     reads it a chunk at a time.
+
+    a route of the route table (app::ROUTES) is requested at sample_path(), a path its pattern
+     matches.
+
+    log lines are checked with capture_logs() (once, before the requests) and logged(): every
+     record of the whole test binary is kept, so a test picks its own lines out by something
+     only its requests have (eg: its X-Request-Id).
+
+    what only shows on a real connection (keep-alive, the connection closing, ...) needs a
+     server: serve() runs the app on a free port of 127.0.0.1, with one worker, until the test
+     ends, and tells where.
+*/
+
+use std::{
+    future::poll_fn,
+    net::{SocketAddr, TcpListener},
+    pin::Pin,
+    sync::{Mutex, Once},
+};
+
+use actix_web::{body::MessageBody, rt, web::Bytes, HttpServer};
+use uuid::Uuid;
+
+use crate::{app::AppBuilder, config::Config, config_routes::ConfigRoutes, db, keepalive};
+
+pub async fn builder() -> AppBuilder {
+    builder_with(Config::default()).await
+}
+
+pub async fn builder_with(config: Config) -> AppBuilder {
+    let database_url = format!("sqlite:file:{}?mode=memory&cache=shared", Uuid::new_v4());
+    let pool = db::connect(&database_url)
+        .await
+        .expect("in-memory database");
+    AppBuilder::new(config, pool, ConfigRoutes::default())
+}
+
+// None at the end of the body
+pub async fn next_chunk<B: MessageBody + Unpin>(body: &mut B) -> Option<Bytes> {
+    poll_fn(|cx| Pin::new(&mut *body).poll_next(cx))
+        .await
+        .map(|chunk| chunk.map_err(Into::into).expect("a body chunk"))
+}
+
+// a path the pattern matches: `x` for every `{segment}`, a scope's `*` left empty
+pub fn sample_path(pattern: &str) -> String {
+    pattern
+        .split('/')
+        .map(|segment| match segment {
+            "*" => "",
+            segment if segment.starts_with('{') => "x",
+            segment => segment,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+struct CapturedLogs(Mutex<Vec<String>>);
+
+impl log::Log for CapturedLogs {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let line = format!("{} {}", record.level(), record.args());
+        self.0.lock().unwrap().push(line);
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURED_LOGS: CapturedLogs = CapturedLogs(Mutex::new(Vec::new()));
+
+pub fn capture_logs() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&CAPTURED_LOGS).expect("no other logger in tests");
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+}
+
+// "LEVEL message" for every line logged so far that contains `needle`
+pub fn logged(needle: &str) -> Vec<String> {
+    CAPTURED_LOGS
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|line| line.contains(needle))
+        .cloned()
+        .collect()
+}
+
+pub fn serve(builder: &AppBuilder) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("a free port");
+    let addr = listener.local_addr().expect("a bound address");
+
+    let factory = builder.clone();
+    let server = HttpServer::new(move || factory.build())
+        .on_connect(keepalive::on_connect)
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .expect("listening")
+        .run();
+    rt::spawn(server);
+    addr
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    async fn user_count(builder: &AppBuilder) -> usize {
+        let app = test::init_service(builder.build()).await;
+        let req = test::TestRequest::get().uri("/users").to_request();
+        let page: Value = test::call_and_read_body_json(&app, req).await;
+        page["users"].as_array().unwrap().len()
+    }
+
+    #[actix_web::test]
+    async fn every_builder_has_its_own_database() {
+        let (first, second) = (builder().await, builder().await);
+        let app = test::init_service(first.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "name": "Abebe", "email": "abebe@example.com" }))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::CREATED
+        );
+
+        assert_eq!(user_count(&first).await, 1);
+        assert_eq!(user_count(&second).await, 0);
+    }
+
+    #[actix_web::test]
+    async fn builder_with_uses_the_given_config() {
+        let config = Config {
+            base_domain: "test.local".to_owned(),
+            ..Config::default()
+        };
+        let app = test::init_service(builder_with(config).await.build()).await;
+
+        let req = test::TestRequest::get().uri("/config").to_request();
+        let config: Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(config["base_domain"], "test.local");
+    }
+
+    #[actix_web::test]
+    async fn serve_answers_on_a_real_socket() {
+        let addr = serve(&builder().await);
+
+        let mut res = awc::Client::default()
+            .get(format!("http://{addr}/healthz"))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body().await.unwrap(), "ok");
+    }
+}
@@ -0,0 +1,172 @@
+/*
+   TEXT BODIES IN OTHER CHARSETS
+    `POST /text` takes a `text/plain` body and answers with it decoded (as JSON, so always UTF-8):
+
+        Content-Type: text/plain; charset=iso-8859-1      body: 63 61 66 e9
+        -> { "text": "café", "charset": "iso-8859-1", "chars": 4 }
+
+    the body is read by the PlainText extractor, which looks at the `charset` parameter of the
+     Content-Type instead of assuming UTF-8:
+     - utf-8 (also the default without a charset)  -> must be valid UTF-8
+     - us-ascii                                    -> must be 7 bit only
+     - iso-8859-1 / latin1                         -> every byte is the code point of that value
+    any other charset is `415`, so is a Content-Type other than text/plain; bytes that aren't
+     valid in the declared charset are `400`. errors have the shape of extractor_errors.rs, with
+     `"source": "text"`.
+*/
+
+use actix_web::{
+    dev::Payload, http::StatusCode, mime, post, web::Bytes, Error, FromRequest, HttpMessage,
+    HttpRequest, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use serde_json::json;
+
+use crate::extractor_errors;
+
+pub struct PlainText {
+    pub text: String,
+    // the canonical name of the charset it was decoded from
+    pub charset: &'static str,
+}
+
+fn text_error(status: StatusCode, message: String) -> Error {
+    extractor_errors::error_response(message.clone(), status, "text", message)
+}
+
+fn charset_of(req: &HttpRequest) -> Result<&'static str, Error> {
+    let mime = match req.mime_type() {
+        Ok(Some(mime)) if mime.essence_str() == mime::TEXT_PLAIN.essence_str() => mime,
+        _ => {
+            return Err(text_error(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "expected a text body (Content-Type: text/plain)".to_owned(),
+            ))
+        }
+    };
+    let Some(charset) = mime.get_param(mime::CHARSET) else {
+        return Ok("utf-8");
+    };
+
+    match charset.as_str().to_ascii_lowercase().as_str() {
+        "utf-8" | "utf8" => Ok("utf-8"),
+        "us-ascii" | "ascii" => Ok("us-ascii"),
+        "iso-8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1" => Ok("iso-8859-1"),
+        other => Err(text_error(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("unsupported charset `{other}`, use utf-8, us-ascii or iso-8859-1"),
+        )),
+    }
+}
+
+fn decode(bytes: &[u8], charset: &str) -> Option<String> {
+    match charset {
+        "utf-8" => String::from_utf8(bytes.to_vec()).ok(),
+        "us-ascii" => bytes
+            .is_ascii()
+            .then(|| bytes.iter().copied().map(char::from).collect()),
+        // latin-1 is the first 256 code points of unicode, byte for byte
+        _ => Some(bytes.iter().copied().map(char::from).collect()),
+    }
+}
+
+impl FromRequest for PlainText {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        // checked before reading, so a wrong charset doesn't cost the whole body
+        let charset = charset_of(req);
+        let body = Bytes::from_request(req, payload);
+
+        Box::pin(async move {
+            let charset = charset?;
+            let bytes = body.await?;
+            let text = decode(&bytes, charset).ok_or_else(|| {
+                text_error(
+                    StatusCode::BAD_REQUEST,
+                    format!("the body is not valid {charset}"),
+                )
+            })?;
+            Ok(PlainText { text, charset })
+        })
+    }
+}
+
+#[post("/text")]
+pub async fn echo_text(body: PlainText) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "chars": body.text.chars().count(),
+        "text": body.text,
+        "charset": body.charset,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::header, test};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    async fn post_text(content_type: &str, body: &'static [u8]) -> (StatusCode, Value) {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let req = test::TestRequest::post()
+            .uri("/text")
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .set_payload(body)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        (res.status(), test::read_body_json(res).await)
+    }
+
+    #[actix_web::test]
+    async fn utf8_is_the_default() {
+        for content_type in ["text/plain", "text/plain; charset=UTF-8"] {
+            let (status, body) = post_text(content_type, "café ☕".as_bytes()).await;
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["text"], "café ☕");
+            assert_eq!(body["charset"], "utf-8");
+            assert_eq!(body["chars"], 6);
+        }
+    }
+
+    #[actix_web::test]
+    async fn latin1_is_decoded_byte_by_byte() {
+        let (status, body) = post_text("text/plain; charset=iso-8859-1", b"caf\xe9 \xa3").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["text"], "café £");
+        assert_eq!(body["charset"], "iso-8859-1");
+        assert_eq!(body["chars"], 6);
+    }
+
+    #[actix_web::test]
+    async fn bytes_invalid_in_the_charset_are_400() {
+        for (content_type, bytes) in [
+            ("text/plain; charset=utf-8", &b"caf\xe9"[..]),
+            ("text/plain; charset=us-ascii", "café".as_bytes()),
+        ] {
+            let (status, body) = post_text(content_type, bytes).await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{content_type}");
+            assert_eq!(body["source"], "text");
+        }
+    }
+
+    #[actix_web::test]
+    async fn an_unsupported_charset_or_type_is_415() {
+        let (status, body) = post_text("text/plain; charset=shift_jis", b"x").await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            body["error"],
+            "unsupported charset `shift_jis`, use utf-8, us-ascii or iso-8859-1"
+        );
+
+        let (status, body) = post_text("application/json", b"\"x\"").await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["source"], "text");
+    }
+}
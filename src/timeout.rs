@@ -0,0 +1,313 @@
+/*
+   REQUEST TIMEOUT
+    a handler stuck on a slow upstream (or in a loop around an await) would hold its connection
+     forever. this middleware gives every request `request_timeout_secs` (default 30) to produce
+     its response, after that the client gets `504 Gateway Timeout`.
+
+    tokio::time::timeout() races the handler future against a timer. when the timer wins, the
+     handler future is DROPPED: it is never polled again, so the handler really stops at the
+     await it was waiting on (its locals are dropped, a db query in flight is abandoned, ...).
+     it doesn't keep running in the background after we answered.
+
+    the timer only covers producing the response HEAD: once the handler has answered, sending
+     the body takes as long as it takes. the routes in STREAMING_ROUTES are not timed at all, as
+     their head may itself wait on something this timer knows nothing about: the SSE streams
+     (`/events`, `/logs/stream`), `/sources/stream`, and everything under `/proxy` (the
+     upstream, bounded by reverse_proxy.rs's own UPSTREAM_TIMEOUT). they are found by ROUTE
+     PATTERN: this runs before routing, but ResourceMap::match_pattern() already tells which
+     route a path will reach (the proxy's catch-all has no pattern, so its scope is checked on
+     the path).
+
+    PER-ROUTE TIMEOUTS
+     a route that needs a different limit wraps itself in route_timeout, with the limit in
+      seconds as a const parameter:
+
+        #[get("/slow", wrap = "middleware::from_fn(timeout::route_timeout::<60>)")]
+
+     `GET /slow?secs=<n>` (default 10, at most 120) is such a route: it just takes n seconds,
+      and may take up to a minute whatever the global limit is.
+
+     a SHORTER limit than the global one is simply enforced by that wrapper. a LONGER one needs
+      the global middleware to step back, but it runs before routing and can't know about the
+      route. so it leaves an empty RouteLimit in the request extensions, route_timeout fills it
+      in once routing got there, and when the global timer runs out it looks there first: with a
+      longer limit for this route it re-arms itself to that instead of answering `504`.
+*/
+
+use std::{cell::Cell, rc::Rc, time::Duration};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    get, middleware,
+    middleware::Next,
+    web, Error, HttpMessage, HttpResponse,
+};
+use serde::Deserialize;
+use tokio::time::{self, Instant};
+
+use crate::{config::Config, reverse_proxy};
+
+const SLOW_DEFAULT_SECS: u64 = 10;
+const SLOW_MAX_SECS: u64 = 120;
+
+// route patterns, plus everything in reverse_proxy::SCOPE
+const STREAMING_ROUTES: [&str; 3] = ["/events", "/logs/stream", "/sources/stream"];
+
+fn is_streaming(req: &ServiceRequest) -> bool {
+    match req.resource_map().match_pattern(req.path()) {
+        Some(pattern) => {
+            STREAMING_ROUTES.contains(&pattern.as_str())
+                || pattern.starts_with(reverse_proxy::SCOPE)
+        }
+        // the proxy forwards from its scope's default service, which has no pattern of its own
+        None => req
+            .path()
+            .strip_prefix(reverse_proxy::SCOPE)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/')),
+    }
+}
+
+// the limit route_timeout set for the matched route, if any (Rc: one request, one thread)
+#[derive(Clone, Default)]
+struct RouteLimit(Rc<Cell<Option<Duration>>>);
+
+// the request went down with the handler future, so (like panics.rs) the response travels as
+//  an error and is rendered by the layers outside
+fn timed_out(method: &str, path: &str, limit: Duration) -> Error {
+    log::warn!("{method} {path} timed out after {limit:?}");
+    let res = HttpResponse::GatewayTimeout().body("the request took too long");
+    InternalError::from_response("request timed out", res).into()
+}
+
+pub async fn request_timeout(
+    config: web::Data<Config>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if is_streaming(&req) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    }
+
+    let limit = Duration::from_secs(config.request_timeout_secs);
+    let method = req.method().to_string();
+    let path = req.path().to_owned();
+    let route_limit = RouteLimit::default();
+    req.extensions_mut().insert(route_limit.clone());
+
+    let started = Instant::now();
+    let res = next.call(req);
+    let deadline = time::sleep(limit);
+    tokio::pin!(res, deadline);
+
+    loop {
+        tokio::select! {
+            res = &mut res => return res.map(ServiceResponse::map_into_boxed_body),
+            () = &mut deadline => match route_limit.0.get() {
+                // this route allows longer: wait until its own limit instead
+                Some(route) if started.elapsed() < route => deadline.as_mut().reset(started + route),
+                _ => return Err(timed_out(&method, &path, started.elapsed())),
+            },
+        }
+    }
+}
+
+pub async fn route_timeout<const SECS: u64>(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let limit = Duration::from_secs(SECS);
+    if let Some(route_limit) = req.extensions().get::<RouteLimit>() {
+        route_limit.0.set(Some(limit));
+    }
+    let method = req.method().to_string();
+    let path = req.path().to_owned();
+
+    match time::timeout(limit, next.call(req)).await {
+        Ok(res) => res.map(ServiceResponse::map_into_boxed_body),
+        Err(_elapsed) => Err(timed_out(&method, &path, limit)),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SlowQuery {
+    secs: Option<u64>,
+}
+
+#[get("/slow", wrap = "middleware::from_fn(route_timeout::<60>)")]
+pub async fn slow(query: web::Query<SlowQuery>) -> HttpResponse {
+    let secs = query.secs.unwrap_or(SLOW_DEFAULT_SECS).min(SLOW_MAX_SECS);
+    time::sleep(Duration::from_secs(secs)).await;
+    HttpResponse::Ok().body(format!("done after {secs}s"))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
+
+    use super::*;
+    use crate::testing;
+
+    // flips `dropped` when the handler future is dropped before it finished
+    struct DropFlag(Rc<Cell<bool>>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    fn one_second() -> web::Data<Config> {
+        web::Data::new(Config {
+            request_timeout_secs: 1,
+            ..Config::default()
+        })
+    }
+
+    #[actix_web::test]
+    async fn a_handler_too_slow_is_504_and_cancelled() {
+        let dropped = Rc::new(Cell::new(false));
+        let flag = dropped.clone();
+        let app = test::init_service(
+            App::new()
+                .app_data(one_second())
+                .wrap(middleware::from_fn(request_timeout))
+                .route(
+                    "/stuck",
+                    web::get().to(move || {
+                        let guard = DropFlag(flag.clone());
+                        async move {
+                            time::sleep(Duration::from_secs(30)).await;
+                            drop(guard);
+                            HttpResponse::Ok().finish()
+                        }
+                    }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/stuck").to_request();
+        let err = test::try_call_service(&app, req).await.unwrap_err();
+
+        assert_eq!(err.error_response().status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(dropped.get(), "the handler future was dropped");
+    }
+
+    #[actix_web::test]
+    async fn a_handler_in_time_answers() {
+        let app = test::init_service(
+            App::new()
+                .app_data(one_second())
+                .wrap(middleware::from_fn(request_timeout))
+                .route("/quick", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/quick").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn streaming_routes_are_not_timed() {
+        let app = test::init_service(
+            App::new()
+                .app_data(one_second())
+                .wrap(middleware::from_fn(request_timeout))
+                .route(
+                    "/events",
+                    web::get().to(|| async {
+                        time::sleep(Duration::from_millis(1200)).await;
+                        HttpResponse::Ok().finish()
+                    }),
+                )
+                // an ordinary short route under /debug, timed like any other
+                .route("/debug/keepalive", web::get().to(|| sleeping(1200)))
+                .service(
+                    web::scope(reverse_proxy::SCOPE).default_service(web::to(|| async {
+                        time::sleep(Duration::from_millis(1200)).await;
+                        HttpResponse::Ok().finish()
+                    })),
+                ),
+        )
+        .await;
+
+        for uri in ["/events", "/proxy/anything"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        }
+        let req = test::TestRequest::get()
+            .uri("/debug/keepalive")
+            .to_request();
+        let err = test::try_call_service(&app, req).await.unwrap_err();
+        assert_eq!(err.error_response().status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    async fn sleeping(millis: u64) -> HttpResponse {
+        time::sleep(Duration::from_millis(millis)).await;
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn a_longer_route_limit_outlasts_the_global_one() {
+        let config = Config {
+            request_timeout_secs: 1,
+            ..Config::default()
+        };
+        let app = test::init_service(testing::builder_with(config).await.build()).await;
+
+        let req = test::TestRequest::get().uri("/slow?secs=2").to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body(res).await, "done after 2s");
+    }
+
+    #[actix_web::test]
+    async fn only_the_wrapped_route_gets_longer() {
+        let app = test::init_service(
+            App::new()
+                .app_data(one_second())
+                .wrap(middleware::from_fn(request_timeout))
+                .service(
+                    web::resource("/generous")
+                        .wrap(middleware::from_fn(route_timeout::<3>))
+                        .to(|| sleeping(1500)),
+                )
+                .route("/default", web::get().to(|| sleeping(1500))),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/generous").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/default").to_request();
+        let err = test::try_call_service(&app, req).await.unwrap_err();
+        assert_eq!(err.error_response().status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[actix_web::test]
+    async fn a_shorter_route_limit_fails_fast() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Config::default()))
+                .wrap(middleware::from_fn(request_timeout))
+                .service(
+                    web::resource("/impatient")
+                        .wrap(middleware::from_fn(route_timeout::<1>))
+                        .to(|| sleeping(1500)),
+                ),
+        )
+        .await;
+
+        let started = Instant::now();
+        let req = test::TestRequest::get().uri("/impatient").to_request();
+        let err = test::try_call_service(&app, req).await.unwrap_err();
+
+        assert_eq!(err.error_response().status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(started.elapsed() < Duration::from_millis(1500));
+    }
+}
@@ -0,0 +1,270 @@
+/*
+   TRACING SPANS PER REQUEST
+    every request runs inside a `tracing` span named `request` with its request id (see
+     request_id.rs), method, path and trace id. the logger (tracing-subscriber, see logging.rs)
+     prints the fields of the current span in front of every line, so whatever a handler logs,
+     with log:: or tracing::, can be matched to its request:
+
+        INFO request{request_id=6f2c... method=GET path=/proxy/weather trace_id=4bf9...}:
+             actix_web::weather: weather upstream unavailable: ...
+
+    TRACE CONTEXT (W3C `traceparent`)
+     a request that came in with a valid `traceparent` header keeps its trace id, so our part
+     shows up in the same trace as the caller's. otherwise a new trace starts here. either way
+     this request gets a span id of its own, and calls to other services (the weather upstream,
+     the reverse proxy) send
+
+        traceparent: 00-<trace id>-<span id of this request>-<flags>
+
+     so THEY can continue the trace. handlers get it with a TraceContext parameter.
+
+    it sits inside request_id.rs, which has to have run for the span to carry the id.
+*/
+
+use std::future::{ready, Ready};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::header::{HeaderMap, HeaderName},
+    middleware::Next,
+    Error, FromRequest, HttpMessage, HttpRequest,
+};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::request_id::RequestId;
+
+pub const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+
+#[derive(Clone, Debug)]
+pub struct TraceContext {
+    // 32 lowercase hex digits, shared by every service in the trace
+    pub trace_id: String,
+    // 16 lowercase hex digits, this request's own
+    pub span_id: String,
+    flags: String,
+}
+
+fn is_hex_id(id: &str, len: usize) -> bool {
+    id.len() == len
+        && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        && id.bytes().any(|b| b != b'0')
+}
+
+fn new_span_id() -> String {
+    Uuid::new_v4().simple().to_string()[..16].to_owned()
+}
+
+impl TraceContext {
+    // "00-<trace id>-<parent id>-<flags>", None for anything else
+    fn from_traceparent(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split('-');
+        let (version, trace_id, parent_id, flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        let valid = version == "00"
+            && parts.next().is_none()
+            && is_hex_id(trace_id, 32)
+            && is_hex_id(parent_id, 16)
+            && flags.len() == 2
+            && flags.bytes().all(|b| b.is_ascii_hexdigit());
+
+        valid.then(|| Self {
+            trace_id: trace_id.to_owned(),
+            span_id: new_span_id(),
+            flags: flags.to_ascii_lowercase(),
+        })
+    }
+
+    fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get(&TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::from_traceparent)
+            .unwrap_or_else(|| Self {
+                trace_id: Uuid::new_v4().simple().to_string(),
+                span_id: new_span_id(),
+                flags: "01".to_owned(),
+            })
+    }
+
+    // for requests to other services, with this request as their parent
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{}", self.trace_id, self.span_id, self.flags)
+    }
+}
+
+// without the middleware (eg: in a bare app) a handler still gets a fresh context
+impl FromRequest for TraceContext {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let context = req
+            .extensions()
+            .get::<TraceContext>()
+            .cloned()
+            .unwrap_or_else(|| TraceContext::from_headers(req.headers()));
+        ready(Ok(context))
+    }
+}
+
+pub async fn trace_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let context = TraceContext::from_headers(req.headers());
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .map(|RequestId(id)| id.clone())
+        .unwrap_or_default();
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+        trace_id = %context.trace_id,
+    );
+    req.extensions_mut().insert(context);
+
+    next.call(req).instrument(span).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fmt::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use actix_web::{middleware, test, web, App, HttpResponse};
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    use super::*;
+    use crate::request_id::{self, REQUEST_ID_HEADER};
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    // "name{field=value ...}" for every span opened, "event in name" for every event in one
+    #[derive(Clone, Default)]
+    struct Recorded(Arc<Mutex<Vec<String>>>);
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let _ = write!(self.0, " {}={value:?}", field.name());
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            let _ = write!(self.0, " {}={value}", field.name());
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Recorded {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, _: &span::Id, _: Context<'_, S>) {
+            let mut fields = Fields(String::new());
+            attrs.record(&mut fields);
+            let line = format!("{}{{{}}}", attrs.metadata().name(), fields.0.trim_start());
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            if let Some(span) = ctx.event_span(event) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("event in {}", span.name()));
+            }
+        }
+    }
+
+    async fn traced(context: TraceContext) -> HttpResponse {
+        tracing::info!("handling it");
+        HttpResponse::Ok().body(context.traceparent())
+    }
+
+    #[actix_web::test]
+    async fn every_request_gets_a_span_with_its_fields() {
+        let recorded = Recorded::default();
+        let _default = tracing_subscriber::registry()
+            .with(recorded.clone())
+            .set_default();
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(trace_request))
+                .wrap(middleware::from_fn(request_id::request_id))
+                .route("/traced", web::get().to(traced)),
+        )
+        .await;
+
+        for id in ["trace-1", "trace-2"] {
+            let req = test::TestRequest::get()
+                .uri("/traced")
+                .insert_header((REQUEST_ID_HEADER, id))
+                .insert_header((TRACEPARENT, PARENT))
+                .to_request();
+            test::call_service(&app, req).await;
+        }
+
+        let recorded = recorded.0.lock().unwrap();
+        assert_eq!(
+            *recorded,
+            [
+                "request{request_id=trace-1 method=GET path=/traced \
+                 trace_id=4bf92f3577b34da6a3ce929d0e0e4736}",
+                "event in request",
+                "request{request_id=trace-2 method=GET path=/traced \
+                 trace_id=4bf92f3577b34da6a3ce929d0e0e4736}",
+                "event in request",
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn calls_onward_continue_the_trace_with_a_span_of_their_own() {
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(trace_request))
+                .route("/traced", web::get().to(traced)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/traced")
+            .insert_header((TRACEPARENT, PARENT))
+            .to_request();
+        let onward = test::call_and_read_body(&app, req).await;
+        let onward = std::str::from_utf8(&onward).unwrap();
+
+        let parts: Vec<&str> = onward.split('-').collect();
+        assert_eq!(parts[0], "00");
+        assert_eq!(parts[1], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert!(is_hex_id(parts[2], 16) && parts[2] != "00f067aa0ba902b7");
+        assert_eq!(parts[3], "01");
+    }
+
+    #[actix_web::test]
+    async fn an_invalid_traceparent_starts_a_new_trace() {
+        for value in [
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+        ] {
+            assert!(TraceContext::from_traceparent(value).is_none(), "{value}");
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(TRACEPARENT, "garbage".parse().unwrap());
+        let context = TraceContext::from_headers(&headers);
+        assert!(is_hex_id(&context.trace_id, 32));
+        assert!(context.traceparent().ends_with("-01"));
+    }
+}
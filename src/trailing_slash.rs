@@ -0,0 +1,142 @@
+/*
+   TRAILING SLASHES
+    `/users/` and `/users` are different paths to the router, so without help the first one is
+     a `404`. this middleware normalizes the path BEFORE routing: repeated slashes are merged
+     (`//users///1` -> `/users/1`) and a trailing slash is dropped, except for the root `/`.
+
+    what happens to a path that changed is `trailing_slash` (env APP_TRAILING_SLASH, config.rs):
+     - `trim` (default)  -> the request is rewritten and handled as if it came in normalized,
+                             the client never knows
+     - `redirect`        -> `308 Permanent Redirect` to the normalized path (query kept), so
+                             clients and crawlers learn the one canonical url. 308 and not 301:
+                             a POST stays a POST with its body
+
+    it sits outside every middleware that looks at the path (metrics, rate limits, the write
+     locks, ...), so they all see the normalized one. the reverse proxy's paths are left alone:
+     the upstream may well tell `/x/` and `/x` apart.
+*/
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header, uri::PathAndQuery, Uri},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+
+use crate::{config::Config, reverse_proxy};
+
+fn normalized(path: &str) -> String {
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+fn with_query(path: String, req: &ServiceRequest) -> String {
+    match req.query_string() {
+        "" => path,
+        query => format!("{path}?{query}"),
+    }
+}
+
+pub async fn normalize_path(
+    config: web::Data<Config>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let path = normalized(req.path());
+    if path == req.path() || req.path().starts_with(reverse_proxy::SCOPE) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    }
+
+    let path_and_query = with_query(path, &req);
+    if config.trailing_slash == "redirect" {
+        let res = HttpResponse::PermanentRedirect()
+            .insert_header((header::LOCATION, path_and_query))
+            .finish();
+        return Ok(req.into_response(res));
+    }
+
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = PathAndQuery::try_from(path_and_query).ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        req.match_info_mut().get_mut().update(&uri);
+        req.head_mut().uri = uri;
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing;
+
+    fn redirecting() -> Config {
+        Config {
+            trailing_slash: "redirect".to_owned(),
+            ..Config::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn with_and_without_a_slash_reach_the_same_handler() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for uri in ["/users", "/users/", "//users///", "/users/?limit=2"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK, "{uri}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn the_root_is_left_alone() {
+        for config in [Config::default(), redirecting()] {
+            let app = test::init_service(testing::builder_with(config).await.build()).await;
+
+            let req = test::TestRequest::get().uri("/").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+    }
+
+    #[actix_web::test]
+    async fn redirect_mode_sends_a_308_to_the_canonical_path() {
+        let app = test::init_service(testing::builder_with(redirecting()).await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("//users/?notify=1")
+            .set_json(json!({ "name": "Abebe", "email": "abebe@example.com" }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            res.headers().get(header::LOCATION).unwrap(),
+            "/users?notify=1"
+        );
+
+        let req = test::TestRequest::get().uri("/users").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn segments_are_merged_and_trimmed() {
+        assert_eq!(normalized("/users/"), "/users");
+        assert_eq!(normalized("//users///1//"), "/users/1");
+        assert_eq!(normalized("/"), "/");
+        assert_eq!(normalized("///"), "/");
+    }
+}
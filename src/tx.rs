@@ -0,0 +1,316 @@
+/*
+   ONE TRANSACTION PER REQUEST
+    a handler that writes several times asks for a Tx and runs every query on it:
+
+        async fn handler(tx: Tx, ...) -> ... {
+            users::insert(&mut *tx.conn().await, first).await?;
+            users::insert(&mut *tx.conn().await, second).await?;
+        }
+
+    the handler never commits. finish_transactions(), the middleware around it, does that once
+     the answer is known:
+     - 2xx                                  -> COMMIT (a failing commit turns into a `500`)
+     - anything else (an error, 4xx, 5xx)   -> ROLLBACK
+    so a handler that fails halfway, whether by returning an error or answering 4xx/5xx,
+     leaves nothing of what it wrote behind.
+
+    - the transaction begins when the extractor runs, right before the handler; requests
+       without a Tx parameter never open one
+    - several Tx parameters (or extractors built on it) share the same transaction
+    - a handler that timed out (see timeout.rs) is dropped, and sqlx rolls back a transaction
+       that is dropped without a commit
+
+    `POST /users/pair` shows it: `{ "first": <user>, "second": <user> }` inserts `first`, THEN
+     checks `second`. an invalid `second` is a `400`, and `first` is rolled back with it.
+*/
+
+use std::rc::Rc;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error,
+    middleware::Next,
+    post, web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{Sqlite, SqliteConnection, SqlitePool, Transaction};
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
+use validator::Validate;
+
+use crate::users::{self, NewUser};
+
+// put in the request extensions by the middleware, filled by the first Tx extracted
+#[derive(Clone, Default)]
+struct TxSlot(Rc<Mutex<Option<Transaction<'static, Sqlite>>>>);
+
+pub struct Tx(TxSlot);
+
+impl Tx {
+    // the connection the transaction runs on, for one query (or several) at a time
+    pub async fn conn(&self) -> MappedMutexGuard<'_, SqliteConnection> {
+        MutexGuard::map(self.0 .0.lock().await, |tx| {
+            let tx = tx
+                .as_mut()
+                .expect("the transaction is only taken once the handler is done");
+            &mut **tx
+        })
+    }
+}
+
+impl FromRequest for Tx {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let slot = req.extensions().get::<TxSlot>().cloned();
+        let pool = req.app_data::<web::Data<SqlitePool>>().cloned();
+
+        Box::pin(async move {
+            let slot = slot.ok_or_else(|| {
+                error::ErrorInternalServerError("finish_transactions is not wrapped around Tx")
+            })?;
+            let pool = pool.ok_or_else(|| {
+                error::ErrorInternalServerError("SqlitePool is not registered as app data")
+            })?;
+
+            let mut tx = slot.0.lock().await;
+            if tx.is_none() {
+                *tx = Some(
+                    pool.begin()
+                        .await
+                        .map_err(error::ErrorInternalServerError)?,
+                );
+            }
+            drop(tx);
+            Ok(Tx(slot))
+        })
+    }
+}
+
+pub async fn finish_transactions(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let slot = TxSlot::default();
+    req.extensions_mut().insert(slot.clone());
+
+    let res = next.call(req).await;
+    let Some(tx) = slot.0.lock().await.take() else {
+        return res;
+    };
+
+    match res {
+        Ok(res) if res.status().is_success() => {
+            tx.commit().await.map_err(|err| {
+                log::error!("can't commit the request's transaction: {err}");
+                error::ErrorInternalServerError("the changes could not be saved")
+            })?;
+            Ok(res)
+        }
+        res => {
+            if let Err(err) = tx.rollback().await {
+                log::error!("can't roll back the request's transaction: {err}");
+            }
+            res
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UserPair {
+    first: NewUser,
+    second: NewUser,
+}
+
+#[post("/users/pair")]
+pub async fn create_user_pair(
+    tx: Tx,
+    body: web::Json<UserPair>,
+) -> actix_web::Result<HttpResponse> {
+    let UserPair { first, second } = body.into_inner();
+    if let Err(errors) = first.validate() {
+        return Ok(users::validation_response(&errors));
+    }
+    let first = users::insert(&mut *tx.conn().await, first)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    // checked only now, so that a bad `second` has something to roll back
+    if let Err(errors) = second.validate() {
+        return Ok(users::validation_response(&errors));
+    }
+    let second = users::insert(&mut *tx.conn().await, second)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Created().json(json!({ "users": [first, second] })))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        dev::{ServiceFactory, ServiceResponse},
+        http::StatusCode,
+        middleware, test, App,
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    async fn database() -> SqlitePool {
+        let url = format!(
+            "sqlite:file:{}?mode=memory&cache=shared",
+            uuid::Uuid::new_v4()
+        );
+        crate::db::connect(&url).await.unwrap()
+    }
+
+    async fn user_count(pool: &SqlitePool) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn write_one(tx: &Tx, n: u32) {
+        let new_user = serde_json::from_value(
+            json!({ "name": "written", "email": format!("w{n}@example.com") }),
+        )
+        .unwrap();
+        users::insert(&mut *tx.conn().await, new_user)
+            .await
+            .unwrap();
+    }
+
+    // every handler writes a user, then answers as its path says
+    fn tx_app(
+        pool: &SqlitePool,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = Error,
+            InitError = (),
+        >,
+    > {
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .wrap(middleware::from_fn(finish_transactions))
+            .route(
+                "/ok",
+                web::post().to(|tx: Tx| async move {
+                    write_one(&tx, 1).await;
+                    write_one(&tx, 2).await;
+                    HttpResponse::Ok().finish()
+                }),
+            )
+            .route(
+                "/error",
+                web::post().to(|tx: Tx| async move {
+                    write_one(&tx, 1).await;
+                    Err::<HttpResponse, _>(error::ErrorBadGateway("the upstream is down"))
+                }),
+            )
+            .route(
+                "/conflict",
+                web::post().to(|tx: Tx| async move {
+                    write_one(&tx, 1).await;
+                    HttpResponse::Conflict().finish()
+                }),
+            )
+            .route(
+                "/shared",
+                web::post().to(|first: Tx, second: Tx| async move {
+                    write_one(&first, 1).await;
+                    write_one(&second, 2).await;
+                    HttpResponse::InternalServerError().finish()
+                }),
+            )
+    }
+
+    fn post(uri: &str) -> test::TestRequest {
+        test::TestRequest::post().uri(uri)
+    }
+
+    #[actix_web::test]
+    async fn a_successful_answer_commits_every_write() {
+        let pool = database().await;
+        let app = test::init_service(tx_app(&pool)).await;
+
+        let res = test::call_service(&app, post("/ok").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(user_count(&pool).await, 2);
+    }
+
+    #[actix_web::test]
+    async fn an_error_after_a_write_rolls_it_back() {
+        let pool = database().await;
+        let app = test::init_service(tx_app(&pool)).await;
+
+        let res = test::call_service(&app, post("/error").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(user_count(&pool).await, 0);
+    }
+
+    #[actix_web::test]
+    async fn a_4xx_or_5xx_answer_rolls_back_too() {
+        let pool = database().await;
+        let app = test::init_service(tx_app(&pool)).await;
+
+        let res = test::call_service(&app, post("/conflict").to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        // two Tx parameters, one transaction: both writes go
+        let res = test::call_service(&app, post("/shared").to_request()).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        assert_eq!(user_count(&pool).await, 0);
+    }
+
+    #[actix_web::test]
+    async fn a_tx_without_the_middleware_is_a_500() {
+        let pool = database().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/bare", web::post().to(|_tx: Tx| async { "unreachable" })),
+        )
+        .await;
+
+        let res = test::call_service(&app, post("/bare").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[actix_web::test]
+    async fn an_invalid_second_user_takes_the_first_with_it() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let pair = |second_email: &str| {
+            post("/users/pair")
+                .set_json(json!({
+                    "first": { "name": "First", "email": "first@example.com" },
+                    "second": { "name": "Second", "email": second_email },
+                }))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, pair("not an email")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let req = test::TestRequest::get().uri("/users").to_request();
+        let listed: Value = test::call_and_read_body_json(&app, req).await;
+        assert!(listed["users"].as_array().unwrap().is_empty());
+
+        let res = test::call_service(&app, pair("second@example.com")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        // another query than above, the cached answer for `/users` lives for its ttl
+        let req = test::TestRequest::get().uri("/users?limit=10").to_request();
+        let listed: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(listed["users"].as_array().unwrap().len(), 2);
+    }
+}
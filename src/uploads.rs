@@ -0,0 +1,353 @@
+/*
+   TEXT UPLOADS WITH CHARSET DETECTION
+    `POST /uploads` takes a raw text body, `GET /uploads/{id}` gives it back.
+
+    clients don't reliably say which charset their text is in (and often say the wrong one), so
+     the bytes themselves decide:
+     - a UTF-8 byte order mark (EF BB BF)         -> UTF-8, the BOM is dropped
+     - valid UTF-8                                -> UTF-8
+     - anything else made of printable bytes      -> Latin-1 (ISO-8859-1)
+     - NUL or other control bytes (not tab/newline/carriage return/form feed)
+                                                  -> not text, `415 Unsupported Media Type`
+
+    every Latin-1 byte is exactly one char (U+0000..U+00FF), which is why any non binary byte
+     sequence can be read as Latin-1: it is the fallback, not something we can positively detect.
+    the text is always STORED as UTF-8 and served back as `text/plain; charset=utf-8`; the
+     original charset is kept next to it and sent in the `X-Original-Charset` header.
+
+    uploads live in memory, so the store is bounded: at most MAX_UPLOADS uploads and
+     MAX_STORED_BYTES of text. an upload is kept for UPLOAD_TTL (then it is `404`, and swept every
+     SWEEP_INTERVAL, see AppBuilder::start_background_tasks()); when a new one doesn't fit, expired
+     uploads go first and then the OLDEST ones. a single upload bigger than MAX_STORED_BYTES is
+     `413`.
+*/
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    get,
+    http::header::{self, ContentType},
+    post, rt,
+    web::{self, Bytes},
+    HttpResponse, Responder,
+};
+use serde::Serialize;
+use serde_json::json;
+use tokio::time;
+use uuid::Uuid;
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+
+pub const MAX_UPLOADS: usize = 1000;
+pub const MAX_STORED_BYTES: usize = 64 * 1024 * 1024;
+pub const UPLOAD_TTL: Duration = Duration::from_secs(60 * 60);
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Serialize)]
+pub enum Charset {
+    #[serde(rename = "utf-8")]
+    Utf8,
+    #[serde(rename = "iso-8859-1")]
+    Latin1,
+}
+
+impl Charset {
+    fn as_str(self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            Charset::Latin1 => "iso-8859-1",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Upload {
+    text: String,
+    original_charset: Charset,
+    stored_at: Instant,
+}
+
+#[derive(Default)]
+struct Uploads {
+    by_id: HashMap<Uuid, Upload>,
+    // the text bytes of all of them
+    bytes: usize,
+}
+
+impl Uploads {
+    fn remove(&mut self, id: &Uuid) {
+        if let Some(upload) = self.by_id.remove(id) {
+            self.bytes -= upload.text.len();
+        }
+    }
+
+    fn remove_expired(&mut self) {
+        let expired: Vec<Uuid> = self
+            .by_id
+            .iter()
+            .filter(|(_, upload)| upload.stored_at.elapsed() >= UPLOAD_TTL)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            self.remove(id);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct UploadStore {
+    uploads: Mutex<Uploads>,
+}
+
+impl UploadStore {
+    // None when the upload is bigger than the whole store may hold
+    fn insert(&self, upload: Upload) -> Option<Uuid> {
+        let size = upload.text.len();
+        if size > MAX_STORED_BYTES {
+            return None;
+        }
+
+        let mut uploads = self.uploads.lock().unwrap();
+        let fits = |uploads: &Uploads| {
+            uploads.by_id.len() < MAX_UPLOADS && uploads.bytes + size <= MAX_STORED_BYTES
+        };
+        if !fits(&uploads) {
+            uploads.remove_expired();
+        }
+        while !fits(&uploads) {
+            let Some(oldest) = uploads
+                .by_id
+                .iter()
+                .min_by_key(|(_, upload)| upload.stored_at)
+                .map(|(id, _)| *id)
+            else {
+                break;
+            };
+            uploads.remove(&oldest);
+        }
+
+        let id = Uuid::new_v4();
+        uploads.bytes += size;
+        uploads.by_id.insert(id, upload);
+        Some(id)
+    }
+
+    fn get(&self, id: &Uuid) -> Option<Upload> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .by_id
+            .get(id)
+            .filter(|upload| upload.stored_at.elapsed() < UPLOAD_TTL)
+            .cloned()
+    }
+
+    fn sweep(&self) {
+        self.uploads.lock().unwrap().remove_expired();
+    }
+}
+
+pub fn spawn_sweeper(store: web::Data<UploadStore>) {
+    rt::spawn(async move {
+        let mut ticks = time::interval(SWEEP_INTERVAL);
+        loop {
+            ticks.tick().await;
+            store.sweep();
+        }
+    });
+}
+
+// control bytes never show up in text, in any charset
+fn is_binary_byte(byte: u8) -> bool {
+    matches!(byte, 0x00..=0x08 | 0x0B | 0x0E..=0x1F | 0x7F)
+}
+
+// None when the bytes don't look like text at all
+fn decode_text(bytes: &[u8]) -> Option<(String, Charset)> {
+    if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+        return String::from_utf8(rest.to_vec())
+            .ok()
+            .filter(|text| !text.bytes().any(is_binary_byte))
+            .map(|text| (text, Charset::Utf8));
+    }
+
+    if bytes.iter().copied().any(is_binary_byte) {
+        return None;
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Some((text.to_owned(), Charset::Utf8)),
+        // 0x80..0x9F are control chars in Latin-1 (valid UTF-8 uses them as continuation bytes)
+        Err(_) if bytes.iter().any(|b| (0x80..=0x9F).contains(b)) => None,
+        Err(_) => Some((
+            bytes.iter().map(|&b| char::from(b)).collect(),
+            Charset::Latin1,
+        )),
+    }
+}
+
+#[post("/uploads")]
+pub async fn create_upload(store: web::Data<UploadStore>, body: Bytes) -> impl Responder {
+    let Some((text, original_charset)) = decode_text(&body) else {
+        return HttpResponse::UnsupportedMediaType()
+            .body("the upload is not UTF-8 or Latin-1 text");
+    };
+
+    let size = text.len();
+    let upload = Upload {
+        text,
+        original_charset,
+        stored_at: Instant::now(),
+    };
+    let Some(id) = store.insert(upload) else {
+        return HttpResponse::PayloadTooLarge().body(format!(
+            "an upload can be at most {MAX_STORED_BYTES} bytes of text"
+        ));
+    };
+
+    let location = format!("/uploads/{id}");
+    HttpResponse::Created()
+        .insert_header((header::LOCATION, location.clone()))
+        .json(json!({
+            "id": id,
+            "location": location,
+            "original_charset": original_charset,
+            "utf8_bytes": size,
+        }))
+}
+
+#[get("/uploads/{id}")]
+pub async fn show_upload(store: web::Data<UploadStore>, id: web::Path<Uuid>) -> impl Responder {
+    match store.get(&id) {
+        Some(upload) => HttpResponse::Ok()
+            .insert_header(ContentType::plaintext()) // text/plain; charset=utf-8
+            .insert_header(("X-Original-Charset", upload.original_charset.as_str()))
+            .body(upload.text),
+        None => HttpResponse::NotFound().body("no such upload"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    fn upload(body: &[u8]) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/uploads")
+            .set_payload(body.to_vec())
+    }
+
+    fn stored(text: &str, stored_at: Instant) -> Upload {
+        Upload {
+            text: text.to_owned(),
+            original_charset: Charset::Utf8,
+            stored_at,
+        }
+    }
+
+    #[actix_web::test]
+    async fn text_is_stored_as_utf8_with_its_original_charset() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let cases: [(&[u8], &str, &str); 3] = [
+            ("héllo wörld".as_bytes(), "héllo wörld", "utf-8"),
+            (b"\xEF\xBB\xBFwith a bom", "with a bom", "utf-8"),
+            (b"caf\xE9 cr\xE8me", "café crème", "iso-8859-1"),
+        ];
+        for (body, text, charset) in cases {
+            let res = test::call_service(&app, upload(body).to_request()).await;
+            assert_eq!(res.status(), StatusCode::CREATED);
+            let created: Value = test::read_body_json(res).await;
+            assert_eq!(created["original_charset"], charset);
+            assert_eq!(created["utf8_bytes"], text.len());
+
+            let req = test::TestRequest::get()
+                .uri(created["location"].as_str().unwrap())
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(
+                res.headers().get(header::CONTENT_TYPE).unwrap(),
+                "text/plain; charset=utf-8"
+            );
+            assert_eq!(res.headers().get("X-Original-Charset").unwrap(), charset);
+            assert_eq!(test::read_body(res).await, text);
+        }
+    }
+
+    #[actix_web::test]
+    async fn binary_is_not_text() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        for body in [
+            &b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"[..],
+            b"\xEF\xBB\xBFnul\0",
+            b"\x81\x9F",
+        ] {
+            let res = test::call_service(&app, upload(body).to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+    }
+
+    #[actix_web::test]
+    async fn an_unknown_upload_is_404() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/uploads/{}", Uuid::new_v4()))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_web::test]
+    async fn a_full_store_drops_the_oldest_upload() {
+        let store = UploadStore::default();
+        let now = Instant::now();
+
+        let oldest = store.insert(stored("first", now)).unwrap();
+        for _ in 1..MAX_UPLOADS {
+            store.insert(stored("later", now + Duration::from_millis(1)));
+        }
+        let newest = store.insert(stored("newest", now + Duration::from_millis(2)));
+
+        assert!(store.get(&oldest).is_none());
+        assert!(store.get(&newest.unwrap()).is_some());
+        assert_eq!(store.uploads.lock().unwrap().by_id.len(), MAX_UPLOADS);
+    }
+
+    #[actix_web::test]
+    async fn expired_uploads_are_gone() {
+        let store = UploadStore::default();
+        let expired_at = Instant::now().checked_sub(UPLOAD_TTL).unwrap();
+
+        let expired = store.insert(stored("old", expired_at)).unwrap();
+        let fresh = store.insert(stored("new", Instant::now())).unwrap();
+        assert!(store.get(&expired).is_none());
+
+        store.sweep();
+        let uploads = store.uploads.lock().unwrap();
+        assert!(!uploads.by_id.contains_key(&expired));
+        assert!(uploads.by_id.contains_key(&fresh));
+        assert_eq!(uploads.bytes, "new".len());
+    }
+
+    #[actix_web::test]
+    async fn an_upload_bigger_than_the_store_is_refused() {
+        let store = UploadStore::default();
+        let huge = "x".repeat(MAX_STORED_BYTES + 1);
+
+        assert!(store.insert(stored(&huge, Instant::now())).is_none());
+        assert_eq!(store.uploads.lock().unwrap().bytes, 0);
+    }
+}
@@ -0,0 +1,836 @@
+/*
+   USERS
+    `GET /users?after=<id>&limit=<n>` pages through the users table with KEYSET pagination:
+
+        SELECT ... WHERE id > ? ORDER BY id LIMIT ?
+
+    with OFFSET the database still walks over every skipped row, so page 10 000 is slow. with a
+     cursor (the last id of the previous page) the index on `id` jumps straight to the next row,
+     so every page costs the same however deep it is. rows added or removed between two requests
+     also don't shift the pages around.
+
+    the response carries `next_cursor`: pass it as `after` to get the next page. it is null on
+     the last page. one extra row is fetched to know whether there is a next page at all.
+
+    `GET /users/stream` is EVERY user as one JSON array, sent while it is being read: batches of
+     STREAM_BATCH rows (the same keyset query) become array elements one by one, so memory
+     stays flat however big the table is. `[`, the commas and `]` are written by us, like in
+     sources.rs. a database error halfway can't be turned into a status anymore (`200` is long
+     gone), so the connection is cut instead: the client sees a truncated body, never an array
+     that looks complete.
+
+    `POST /users` creates a user. the rules live on NewUser itself (validator's derive) and
+     validate() checks ALL of them, so a `400` lists every invalid field at once:
+
+        { "errors": { "email": ["email must be a valid address"], "name": ["..."] } }
+
+     instead of making the client fix one field per round trip.
+
+    `GET /users/{id}` is one user, `404` when there is none, with an ETag (see conditional.rs).
+     `PUT /users/{id}` replaces its name and email, but only with the ETag the client read in
+     `If-Match` (`428` without one, `412` when the user has changed since). the UPDATE itself
+     also checks the old values, so of two updates with the same tag exactly one wins even if
+     they race. the answer is the updated user with its new ETag.
+
+    the handlers carry their own OpenAPI description (#[utoipa::path], ToSchema on the types),
+     right next to the code it describes, see openapi.rs for where it is served.
+
+    `POST /users/bulk` takes a JSON array of up to MAX_BULK users and answers `207 Multi-Status`:
+     one result per item, in order, with the status that item would have had on its own
+     (201 + the user, 400 + its field errors, 500 for a database error), so one bad record
+     doesn't reject the whole batch. all inserts run in ONE transaction, and the query decides
+     what a failure does to it:
+     - default           -> the successful items are committed, the failed ones are skipped
+     - `?atomic=true`    -> any failure rolls everything back; the items that had succeeded
+                             are then reported as `424 Failed Dependency`
+     `committed` in the answer says whether anything was written at all.
+    the array is read as it arrives (see json_array.rs): item MAX_BULK + 1 ends the request with
+     `413` right away, before the rest of a huge array is even received, and nothing is written.
+*/
+
+use std::{borrow::Cow, collections::BTreeMap, future::ready};
+
+use actix_web::{
+    error, get,
+    http::header::{ContentType, ETag, EntityTag},
+    post, put,
+    web::{self, Bytes},
+    HttpRequest, HttpResponse,
+};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{SqliteExecutor, SqlitePool};
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationErrors};
+
+use crate::{
+    conditional::{self, Precondition},
+    json_array,
+};
+
+pub const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
+const MAX_BULK: usize = 1000;
+const STREAM_BATCH: u32 = 500;
+
+#[derive(Serialize, sqlx::FromRow, ToSchema)]
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Deserialize, Validate, ToSchema)]
+pub struct NewUser {
+    #[validate(length(min = 1, max = 50, message = "name must be 1 to 50 characters"))]
+    #[schema(min_length = 1, max_length = 50)]
+    name: String,
+    #[validate(
+        email(message = "email must be a valid address"),
+        length(max = 254, message = "email must be at most 254 characters")
+    )]
+    #[schema(format = Email, max_length = 254)]
+    email: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct PageParams {
+    /// the `next_cursor` of the previous page
+    pub after: Option<i64>,
+    /// page size, 1 to 100 (default 20)
+    limit: Option<u32>,
+}
+
+impl PageParams {
+    pub fn limit(&self) -> u32 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserPage {
+    pub users: Vec<User>,
+    pub next_cursor: Option<i64>,
+}
+
+pub async fn page_after(
+    pool: &SqlitePool,
+    after: Option<i64>,
+    limit: u32,
+) -> Result<UserPage, sqlx::Error> {
+    let mut users: Vec<User> =
+        sqlx::query_as("SELECT id, name, email FROM users WHERE id > ? ORDER BY id LIMIT ?")
+            .bind(after.unwrap_or(0))
+            .bind(i64::from(limit) + 1)
+            .fetch_all(pool)
+            .await?;
+
+    let has_more = users.len() > limit as usize;
+    users.truncate(limit as usize);
+    let next_cursor = if has_more {
+        users.last().map(|user| user.id)
+    } else {
+        None
+    };
+
+    Ok(UserPage { users, next_cursor })
+}
+
+// takes a pool or a transaction
+pub async fn insert(
+    executor: impl SqliteExecutor<'_>,
+    new_user: NewUser,
+) -> Result<User, sqlx::Error> {
+    sqlx::query_as("INSERT INTO users (name, email) VALUES (?, ?) RETURNING id, name, email")
+        .bind(new_user.name)
+        .bind(new_user.email)
+        .fetch_one(executor)
+        .await
+}
+
+pub async fn find(pool: &SqlitePool, id: i64) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as("SELECT id, name, email FROM users WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+// None when the row is gone or no longer holds `current`
+async fn update_if_unchanged(
+    pool: &SqlitePool,
+    current: &User,
+    new_user: NewUser,
+) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as(
+        "UPDATE users SET name = ?, email = ? WHERE id = ? AND name = ? AND email = ? \
+         RETURNING id, name, email",
+    )
+    .bind(new_user.name)
+    .bind(new_user.email)
+    .bind(current.id)
+    .bind(&current.name)
+    .bind(&current.email)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn find_by_email(pool: &SqlitePool, email: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as("SELECT id, name, email FROM users WHERE email = ?")
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+}
+
+#[utoipa::path(
+    tag = "users",
+    params(PageParams),
+    responses((status = 200, description = "one page of users", body = UserPage))
+)]
+#[get("/users")]
+pub async fn list_users(
+    pool: web::Data<SqlitePool>,
+    params: web::Query<PageParams>,
+) -> actix_web::Result<HttpResponse> {
+    let page = page_after(&pool, params.after, params.limit())
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(page))
+}
+
+#[get("/users/stream")]
+pub async fn stream_users(pool: web::Data<SqlitePool>) -> HttpResponse {
+    // state: the cursor of the next batch, None once the last one was read
+    let batches = stream::try_unfold(Some(0), move |after| {
+        let pool = pool.clone();
+        async move {
+            let Some(after) = after else {
+                return Ok(None);
+            };
+            let page = page_after(&pool, Some(after), STREAM_BATCH).await?;
+            Ok::<_, sqlx::Error>(Some((page.users, page.next_cursor)))
+        }
+    });
+    let users = batches
+        .map_ok(|users| stream::iter(users.into_iter().map(Ok::<_, sqlx::Error>)))
+        .try_flatten();
+
+    let mut first = true;
+    let elements = users.map(move |user| {
+        let user = user.map_err(|err| {
+            log::error!("streaming users failed, cutting the response: {err}");
+            error::ErrorInternalServerError(err)
+        })?;
+        let mut chunk = if first { Vec::new() } else { b",".to_vec() };
+        first = false;
+        serde_json::to_writer(&mut chunk, &user)?;
+        Ok::<_, actix_web::Error>(Bytes::from(chunk))
+    });
+
+    let body = stream::once(ready(Ok(Bytes::from_static(b"["))))
+        .chain(elements)
+        .chain(stream::once(ready(Ok(Bytes::from_static(b"]")))));
+
+    HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .streaming(body)
+}
+
+// field -> every message for it, sorted by field so the output is stable
+fn field_errors(errors: &ValidationErrors) -> BTreeMap<Cow<'_, str>, Vec<String>> {
+    errors
+        .field_errors()
+        .into_iter()
+        .map(|(field, errors)| {
+            let messages = errors
+                .iter()
+                .map(|error| match &error.message {
+                    Some(message) => message.to_string(),
+                    None => format!("{field} is invalid ({})", error.code),
+                })
+                .collect();
+            (field, messages)
+        })
+        .collect()
+}
+
+// the `400` of POST /users, as it appears in the OpenAPI document
+#[derive(Serialize, ToSchema)]
+pub struct ValidationErrorBody {
+    /// field -> every message for it
+    errors: BTreeMap<String, Vec<String>>,
+}
+
+pub fn validation_response(errors: &ValidationErrors) -> HttpResponse {
+    let errors = field_errors(errors)
+        .into_iter()
+        .map(|(field, messages)| (field.into_owned(), messages))
+        .collect();
+    HttpResponse::BadRequest().json(ValidationErrorBody { errors })
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = NewUser,
+    responses(
+        (status = 201, description = "the created user", body = User),
+        (status = 400, description = "every invalid field", body = ValidationErrorBody),
+    )
+)]
+#[post("/users")]
+pub async fn create_user(
+    pool: web::Data<SqlitePool>,
+    body: web::Json<NewUser>,
+) -> actix_web::Result<HttpResponse> {
+    let new_user = body.into_inner();
+    if let Err(errors) = new_user.validate() {
+        return Ok(validation_response(&errors));
+    }
+
+    let user = insert(pool.get_ref(), new_user)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Created().json(user))
+}
+
+#[utoipa::path(
+    tag = "users",
+    params(("id" = i64, Path, description = "user id")),
+    responses(
+        (status = 200, description = "the user", body = User),
+        (status = 404, description = "no user with this id"),
+    )
+)]
+#[get("/users/{id}")]
+pub async fn show_user(
+    req: HttpRequest,
+    pool: web::Data<SqlitePool>,
+    id: web::Path<i64>,
+) -> actix_web::Result<HttpResponse> {
+    let user = find(&pool, id.into_inner())
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(match user {
+        Some(user) => conditional::json_with_etag(&req, &user),
+        None => HttpResponse::NotFound().body("no such user"),
+    })
+}
+
+fn etag_of(user: &User) -> actix_web::Result<EntityTag> {
+    conditional::json_etag(user).map_err(error::ErrorInternalServerError)
+}
+
+#[utoipa::path(
+    tag = "users",
+    params(
+        ("id" = i64, Path, description = "user id"),
+        ("If-Match" = String, Header, description = "the ETag of the user as last read"),
+    ),
+    request_body = NewUser,
+    responses(
+        (status = 200, description = "the updated user, with its new ETag", body = User),
+        (status = 400, description = "every invalid field", body = ValidationErrorBody),
+        (status = 404, description = "no user with this id"),
+        (status = 412, description = "the user has changed since that ETag"),
+        (status = 428, description = "no If-Match header"),
+    )
+)]
+#[put("/users/{id}")]
+pub async fn update_user(
+    req: HttpRequest,
+    pool: web::Data<SqlitePool>,
+    id: web::Path<i64>,
+    body: web::Json<NewUser>,
+) -> actix_web::Result<HttpResponse> {
+    let new_user = body.into_inner();
+    if let Err(errors) = new_user.validate() {
+        return Ok(validation_response(&errors));
+    }
+
+    let Some(current) = find(&pool, id.into_inner())
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    else {
+        return Ok(HttpResponse::NotFound().body("no such user"));
+    };
+
+    let current_etag = etag_of(&current)?;
+    match conditional::if_match(&req, &current_etag) {
+        Precondition::Matches => {}
+        Precondition::Missing => {
+            return Ok(HttpResponse::PreconditionRequired()
+                .body("send the user's ETag in If-Match, GET /users/{id} has it"))
+        }
+        Precondition::Fails => {
+            return Ok(HttpResponse::PreconditionFailed()
+                .insert_header(ETag(current_etag))
+                .body("the user has changed since, read it again"))
+        }
+    }
+
+    let Some(updated) = update_if_unchanged(&pool, &current, new_user)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    else {
+        // changed between our read and our write
+        return Ok(
+            HttpResponse::PreconditionFailed().body("the user has changed since, read it again")
+        );
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag_of(&updated)?))
+        .json(updated))
+}
+
+#[derive(Deserialize)]
+pub struct BulkParams {
+    #[serde(default)]
+    atomic: bool,
+}
+
+#[post("/users/bulk")]
+pub async fn create_users_bulk(
+    pool: web::Data<SqlitePool>,
+    params: web::Query<BulkParams>,
+    req: HttpRequest,
+    body: web::Payload,
+) -> actix_web::Result<HttpResponse> {
+    // items are turned into users one by one, so one that isn't a user is just a failed item
+    let items = json_array::read_array(&req, body, MAX_BULK).await?;
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let mut results = Vec::with_capacity(items.len());
+    let mut failed = false;
+
+    for (index, item) in items.into_iter().enumerate() {
+        let result = match serde_json::from_value::<NewUser>(item) {
+            Err(err) => json!({ "index": index, "status": 400, "error": err.to_string() }),
+            Ok(new_user) => match new_user.validate() {
+                Err(errors) => {
+                    json!({ "index": index, "status": 400, "errors": field_errors(&errors) })
+                }
+                Ok(()) => match insert(&mut *tx, new_user).await {
+                    Ok(user) => json!({ "index": index, "status": 201, "user": user }),
+                    Err(err) => json!({ "index": index, "status": 500, "error": err.to_string() }),
+                },
+            },
+        };
+        failed |= result["status"] != 201;
+        results.push(result);
+    }
+
+    let rollback = failed && params.atomic;
+    if rollback {
+        tx.rollback()
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+        for result in results.iter_mut().filter(|result| result["status"] == 201) {
+            let index = result["index"].clone();
+            *result = json!({
+                "index": index,
+                "status": 424,
+                "error": "rolled back because another item failed",
+            });
+        }
+    } else {
+        tx.commit().await.map_err(error::ErrorInternalServerError)?;
+    }
+
+    let committed = !rollback && results.iter().any(|result| result["status"] == 201);
+    Ok(HttpResponse::MultiStatus().json(json!({
+        "committed": committed,
+        "results": results,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::poll_fn, pin::Pin};
+
+    use actix_web::{
+        body::{BodySize, MessageBody},
+        dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+        http::{header, StatusCode},
+        test, App,
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing;
+
+    fn new_user(n: usize) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "name": format!("user {n}"), "email": format!("u{n}@example.com") }))
+    }
+
+    fn get(uri: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(uri)
+    }
+
+    #[actix_web::test]
+    async fn pages_follow_the_cursor_to_the_end() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        for n in 0..5 {
+            test::call_service(&app, new_user(n).to_request()).await;
+        }
+
+        let first: Value =
+            test::call_and_read_body_json(&app, get("/users?limit=2").to_request()).await;
+        assert_eq!(first["users"].as_array().unwrap().len(), 2);
+        assert_eq!(first["next_cursor"], 2);
+
+        let second: Value =
+            test::call_and_read_body_json(&app, get("/users?limit=2&after=2").to_request()).await;
+        assert_eq!(second["users"][0]["id"], 3);
+        assert_eq!(second["next_cursor"], 4);
+
+        let last: Value =
+            test::call_and_read_body_json(&app, get("/users?limit=2&after=4").to_request()).await;
+        assert_eq!(last["users"].as_array().unwrap().len(), 1);
+        assert!(last["next_cursor"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn the_page_size_is_clamped() {
+        let limit = |limit| PageParams { after: None, limit };
+
+        assert_eq!(limit(None).limit(), DEFAULT_PAGE_SIZE);
+        assert_eq!(limit(Some(0)).limit(), 1);
+        assert_eq!(limit(Some(1000)).limit(), MAX_PAGE_SIZE);
+    }
+
+    #[actix_web::test]
+    async fn an_exact_last_page_has_no_cursor() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        for n in 0..2 {
+            test::call_service(&app, new_user(n).to_request()).await;
+        }
+
+        let page: Value =
+            test::call_and_read_body_json(&app, get("/users?limit=2").to_request()).await;
+
+        assert_eq!(page["users"].as_array().unwrap().len(), 2);
+        assert!(page["next_cursor"].is_null());
+        let res = test::call_service(&app, get("/users?after=x").to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn every_invalid_field_is_reported_at_once() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "name": "", "email": "not an email" }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["errors"]["name"][0], "name must be 1 to 50 characters");
+        assert_eq!(body["errors"]["email"][0], "email must be a valid address");
+
+        let users: Value = test::call_and_read_body_json(&app, get("/users").to_request()).await;
+        assert!(users["users"].as_array().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn a_valid_user_is_created() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, new_user(1).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let user: Value = test::read_body_json(res).await;
+        assert_eq!(user["name"], "user 1");
+        assert_eq!(user["email"], "u1@example.com");
+    }
+
+    fn bulk(uri: &str, users: Value) -> test::TestRequest {
+        test::TestRequest::post().uri(uri).set_json(users)
+    }
+
+    fn users(range: std::ops::Range<usize>) -> Vec<Value> {
+        range
+            .map(|n| json!({ "name": format!("user {n}"), "email": format!("u{n}@example.com") }))
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn a_valid_batch_is_all_created() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res =
+            test::call_service(&app, bulk("/users/bulk", json!(users(0..3))).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["committed"], true);
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        for (index, result) in results.iter().enumerate() {
+            assert_eq!(result["index"], index);
+            assert_eq!(result["status"], 201);
+            assert_eq!(result["user"]["name"], format!("user {index}"));
+        }
+
+        let listed: Value = test::call_and_read_body_json(&app, get("/users").to_request()).await;
+        assert_eq!(listed["users"].as_array().unwrap().len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn a_bad_item_fails_alone() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let mut batch = users(0..3);
+        batch[1] = json!({ "name": "", "email": "not an email" });
+        batch.push(json!("not a user"));
+
+        let res = test::call_service(&app, bulk("/users/bulk", json!(batch)).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["committed"], true);
+        let statuses: Vec<_> = body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, [201, 400, 201, 400]);
+        assert_eq!(
+            body["results"][1]["errors"]["email"][0],
+            "email must be a valid address"
+        );
+
+        let listed: Value = test::call_and_read_body_json(&app, get("/users").to_request()).await;
+        assert_eq!(listed["users"].as_array().unwrap().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn an_atomic_batch_rolls_back_on_a_bad_item() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let mut batch = users(0..3);
+        batch[2] = json!({ "name": "", "email": "u2@example.com" });
+
+        let req = bulk("/users/bulk?atomic=true", json!(batch)).to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["committed"], false);
+        let statuses: Vec<_> = body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, [424, 424, 400]);
+
+        let listed: Value = test::call_and_read_body_json(&app, get("/users").to_request()).await;
+        assert!(listed["users"].as_array().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn a_batch_over_the_cap_is_refused_whole() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = bulk("/users/bulk", json!(users(0..MAX_BULK + 1))).to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let listed: Value = test::call_and_read_body_json(&app, get("/users").to_request()).await;
+        assert!(listed["users"].as_array().unwrap().is_empty());
+    }
+
+    fn put(id: &str, if_match: Option<&str>, n: usize) -> test::TestRequest {
+        let req = test::TestRequest::put()
+            .uri(&format!("/users/{id}"))
+            .set_json(
+                json!({ "name": format!("renamed {n}"), "email": format!("r{n}@example.com") }),
+            );
+        match if_match {
+            Some(tag) => req.insert_header((header::IF_MATCH, tag)),
+            None => req,
+        }
+    }
+
+    fn etag<B>(res: &ServiceResponse<B>) -> String {
+        let etag = res.headers().get(header::ETAG).unwrap();
+        etag.to_str().unwrap().to_owned()
+    }
+
+    #[actix_web::test]
+    async fn an_update_with_the_current_etag_applies() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let created: Value = test::call_and_read_body_json(&app, new_user(1).to_request()).await;
+        let id = created["id"].to_string();
+        let read = test::call_service(&app, get(&format!("/users/{id}")).to_request()).await;
+        let read_tag = etag(&read);
+
+        let res = test::call_service(&app, put(&id, Some(&read_tag), 1).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let new_tag = etag(&res);
+        assert_ne!(new_tag, read_tag);
+        let updated: Value = test::read_body_json(res).await;
+        assert_eq!(updated["name"], "renamed 1");
+        // the new tag is the one a read now gives
+        let read = test::call_service(&app, get(&format!("/users/{id}")).to_request()).await;
+        assert_eq!(etag(&read), new_tag);
+    }
+
+    #[actix_web::test]
+    async fn an_update_with_a_stale_etag_is_refused() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let created: Value = test::call_and_read_body_json(&app, new_user(1).to_request()).await;
+        let id = created["id"].to_string();
+        let read = test::call_service(&app, get(&format!("/users/{id}")).to_request()).await;
+        let stale = etag(&read);
+        let first = test::call_service(&app, put(&id, Some(&stale), 1).to_request()).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let current = etag(&first);
+
+        let res = test::call_service(&app, put(&id, Some(&stale), 2).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(etag(&res), current);
+        let kept: Value =
+            test::call_and_read_body_json(&app, get(&format!("/users/{id}")).to_request()).await;
+        assert_eq!(kept["name"], "renamed 1");
+    }
+
+    #[actix_web::test]
+    async fn an_update_without_if_match_is_refused() {
+        let app = test::init_service(testing::builder().await.build()).await;
+        let created: Value = test::call_and_read_body_json(&app, new_user(1).to_request()).await;
+        let id = created["id"].to_string();
+
+        let res = test::call_service(&app, put(&id, None, 1).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::PRECONDITION_REQUIRED);
+        let kept: Value =
+            test::call_and_read_body_json(&app, get(&format!("/users/{id}")).to_request()).await;
+        assert_eq!(kept["name"], "user 1");
+    }
+
+    #[actix_web::test]
+    async fn an_update_of_a_missing_user_is_404() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let res = test::call_service(&app, put("404", Some("\"x\""), 1).to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    // a database of its own, that a test can break
+    async fn users_db(users: usize) -> SqlitePool {
+        let url = format!(
+            "sqlite:file:{}?mode=memory&cache=shared",
+            uuid::Uuid::new_v4()
+        );
+        let pool = crate::db::connect(&url).await.unwrap();
+        for n in 0..users {
+            let new_user = NewUser {
+                name: format!("user {n}"),
+                email: format!("u{n}@example.com"),
+            };
+            insert(&pool, new_user).await.unwrap();
+        }
+        pool
+    }
+
+    fn stream_app(
+        pool: &SqlitePool,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    > {
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .service(stream_users)
+    }
+
+    #[actix_web::test]
+    async fn an_empty_table_streams_an_empty_array() {
+        let app = test::init_service(stream_app(&users_db(0).await)).await;
+
+        let body = test::call_and_read_body(&app, get("/users/stream").to_request()).await;
+
+        assert_eq!(body, "[]");
+    }
+
+    #[actix_web::test]
+    async fn every_user_streams_as_one_valid_array() {
+        let count = STREAM_BATCH as usize * 2 + 3;
+        let app = test::init_service(stream_app(&users_db(count).await)).await;
+
+        let res = test::call_service(&app, get("/users/stream").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        // sent as it is read: no length up front, one chunk per element
+        let mut body = res.into_body();
+        assert!(matches!(body.size(), BodySize::Stream));
+        let mut chunks = Vec::new();
+        while let Some(chunk) = testing::next_chunk(&mut body).await {
+            chunks.push(chunk);
+        }
+        assert_eq!(chunks.len(), count + 2);
+
+        let users: Vec<Value> = serde_json::from_slice(&chunks.concat()).unwrap();
+        assert_eq!(users.len(), count);
+        let ids: Vec<i64> = users
+            .iter()
+            .map(|user| user["id"].as_i64().unwrap())
+            .collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(
+            users[count - 1]["email"],
+            format!("u{}@example.com", count - 1)
+        );
+    }
+
+    #[actix_web::test]
+    async fn a_database_error_midway_cuts_the_body_short() {
+        let pool = users_db(STREAM_BATCH as usize + 10).await;
+        let app = test::init_service(stream_app(&pool)).await;
+        let res = test::call_service(&app, get("/users/stream").to_request()).await;
+        let mut body = res.into_body();
+        // "[" and the first user: the first batch has been read
+        let mut sent = testing::next_chunk(&mut body).await.unwrap().to_vec();
+        sent.extend(testing::next_chunk(&mut body).await.unwrap());
+
+        sqlx::query("DROP TABLE users")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let failed = loop {
+            match poll_fn(|cx| Pin::new(&mut body).poll_next(cx)).await {
+                Some(Ok(chunk)) => sent.extend(chunk),
+                Some(Err(_)) => break true,
+                None => break false,
+            }
+        };
+        assert!(failed, "the body ended as if it was complete");
+        assert!(!sent.ends_with(b"]"));
+        assert!(serde_json::from_slice::<Value>(&sent).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+/*
+   BUILD METADATA
+    `GET /version` tells which build is running:
+
+        { "version": "0.1.0", "git_sha": "3f807f2...", "built_at": 1760000000 }
+
+    all three are fixed at COMPILE time: env!() / option_env!() read environment variables of
+     the compiler, not of the running server. the crate version comes from cargo itself, the
+     commit and the build time (unix seconds) from build.rs. a build without git (eg: from a
+     tarball) has no GIT_SHA, option_env!() is then None and the sha shows as "unknown"
+     instead of breaking the build.
+
+    build.rs only re-runs when the checked out commit changes, so `built_at` is when the current
+     commit was first built, not the latest recompile: edits without a commit keep the old time.
+*/
+
+use actix_web::{get, HttpResponse};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct BuildInfo {
+    version: &'static str,
+    git_sha: &'static str,
+    built_at: u64,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: option_env!("GIT_SHA").unwrap_or("unknown"),
+            built_at: env!("BUILD_TIMESTAMP").parse().unwrap_or(0),
+        }
+    }
+}
+
+#[get("/version")]
+pub async fn version() -> HttpResponse {
+    HttpResponse::Ok().json(BuildInfo::current())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+    use serde_json::Value;
+
+    use crate::testing;
+
+    #[actix_web::test]
+    async fn version_is_the_crate_version() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::get().uri("/version").to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        let git_sha = body["git_sha"].as_str().unwrap();
+        assert!(git_sha == "unknown" || git_sha.chars().all(|c| c.is_ascii_hexdigit()));
+        assert!(body["built_at"].as_u64().unwrap() > 0);
+    }
+}
@@ -0,0 +1,231 @@
+/*
+   GRACEFUL DEGRADATION: GET /proxy/weather
+    the weather comes from an upstream HTTP API (WEATHER_UPSTREAM_URL, by default
+     http://127.0.0.1:9000/weather), fetched with awc, the actix HTTP client.
+
+    when the upstream is down the endpoint should still be useful, so every good answer is kept
+     as the LAST KNOWN GOOD value:
+     - upstream answers 2xx JSON            -> `200` with it (and it is remembered)
+     - timeout (2s), connection error, 5xx  -> `200` with the remembered value, marked with a
+                                               `Warning: 110 - "Response is Stale"` header
+     - same, but nothing remembered yet     -> `503 Service Unavailable`
+     - upstream answers 4xx                 -> `502 Bad Gateway`: that is our request being
+                                               wrong, an old value would only hide the bug
+
+    the upstream request carries the `traceparent` of ours (see trace.rs).
+
+    a failed fetch is first retried with backoff (see retry.rs), all attempts within
+     RETRY_DEADLINE; only when they all fail does the fallback above kick in.
+*/
+
+use std::{env, sync::RwLock, time::Duration};
+
+use actix_web::{
+    get,
+    http::{header, Method},
+    web, HttpResponse,
+};
+use serde_json::Value;
+
+use crate::{
+    retry::{self, RetryPolicy},
+    trace::{TraceContext, TRACEPARENT},
+};
+
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+const RETRY_DEADLINE: Duration = Duration::from_secs(5);
+const DEFAULT_UPSTREAM_URL: &str = "http://127.0.0.1:9000/weather";
+
+pub struct WeatherProxy {
+    upstream_url: String,
+    retry: RetryPolicy,
+    last_good: RwLock<Option<Value>>,
+}
+
+enum UpstreamError {
+    // timeouts, connection errors, 5xx, unreadable bodies: worth falling back
+    Unavailable(String),
+    // 4xx: not worth falling back
+    Rejected(u16),
+}
+
+impl WeatherProxy {
+    pub fn from_env(max_attempts: u32) -> Self {
+        let upstream_url =
+            env::var("WEATHER_UPSTREAM_URL").unwrap_or_else(|_| DEFAULT_UPSTREAM_URL.to_owned());
+        Self::new(upstream_url, max_attempts)
+    }
+
+    pub fn new(upstream_url: String, max_attempts: u32) -> Self {
+        Self {
+            upstream_url,
+            retry: RetryPolicy {
+                max_attempts,
+                deadline: RETRY_DEADLINE,
+            },
+            last_good: RwLock::new(None),
+        }
+    }
+
+    async fn fetch(&self, trace: &TraceContext) -> Result<Value, UpstreamError> {
+        let client = awc::Client::builder().timeout(UPSTREAM_TIMEOUT).finish();
+
+        let retried = retry::send_with_retry(self.retry, &Method::GET, || {
+            client
+                .get(&self.upstream_url)
+                .insert_header((header::ACCEPT, "application/json"))
+                .insert_header((TRACEPARENT, trace.traceparent()))
+                .send()
+        })
+        .await;
+        if retried.attempts > 1 {
+            log::info!("weather upstream: {} attempts", retried.attempts);
+        }
+        let mut res = retried
+            .result
+            .map_err(|err| UpstreamError::Unavailable(err.to_string()))?;
+
+        let status = res.status();
+        if status.is_client_error() {
+            return Err(UpstreamError::Rejected(status.as_u16()));
+        }
+        if !status.is_success() {
+            return Err(UpstreamError::Unavailable(format!(
+                "upstream answered {status}"
+            )));
+        }
+
+        res.json::<Value>()
+            .await
+            .map_err(|err| UpstreamError::Unavailable(err.to_string()))
+    }
+}
+
+#[get("/proxy/weather")]
+pub async fn weather(proxy: web::Data<WeatherProxy>, trace: TraceContext) -> HttpResponse {
+    match proxy.fetch(&trace).await {
+        Ok(weather) => {
+            *proxy.last_good.write().unwrap() = Some(weather.clone());
+            HttpResponse::Ok().json(weather)
+        }
+        Err(UpstreamError::Rejected(status)) => {
+            HttpResponse::BadGateway().body(format!("the weather upstream answered {status}"))
+        }
+        Err(UpstreamError::Unavailable(reason)) => {
+            log::warn!("weather upstream unavailable: {reason}");
+            match proxy.last_good.read().unwrap().clone() {
+                Some(weather) => HttpResponse::Ok()
+                    .insert_header((header::WARNING, "110 - \"Response is Stale\""))
+                    .json(weather),
+                None => HttpResponse::ServiceUnavailable()
+                    .insert_header(("Retry-After", "5"))
+                    .body("the weather is unavailable right now"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, TcpListener},
+        sync::{
+            atomic::{AtomicU8, Ordering},
+            Arc,
+        },
+    };
+
+    use actix_web::{http::StatusCode, rt, test, App, HttpServer};
+    use serde_json::json;
+
+    use super::*;
+
+    const UP: u8 = 0;
+    const SLOW: u8 = 1;
+    const FAILING: u8 = 2;
+    const REJECTING: u8 = 3;
+
+    // an upstream that behaves as `mode` says at the moment of each request
+    fn mock_upstream(mode: Arc<AtomicU8>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = HttpServer::new(move || {
+            let mode = mode.clone();
+            App::new().route(
+                "/weather",
+                web::get().to(move || {
+                    let mode = mode.load(Ordering::SeqCst);
+                    async move {
+                        match mode {
+                            UP => HttpResponse::Ok().json(json!({ "temp_c": 21 })),
+                            SLOW => {
+                                rt::time::sleep(UPSTREAM_TIMEOUT * 2).await;
+                                HttpResponse::Ok().json(json!({ "temp_c": 99 }))
+                            }
+                            FAILING => HttpResponse::InternalServerError().finish(),
+                            _ => HttpResponse::NotFound().finish(),
+                        }
+                    }
+                }),
+            )
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .unwrap()
+        .run();
+        rt::spawn(server);
+        addr
+    }
+
+    fn weather_request() -> test::TestRequest {
+        test::TestRequest::get().uri("/proxy/weather")
+    }
+
+    #[actix_web::test]
+    async fn falls_back_to_the_last_good_value() {
+        let mode = Arc::new(AtomicU8::new(FAILING));
+        let addr = mock_upstream(mode.clone());
+        let proxy = WeatherProxy::new(format!("http://{addr}/weather"), 1);
+        let app =
+            test::init_service(App::new().app_data(web::Data::new(proxy)).service(weather)).await;
+
+        // nothing to fall back to yet
+        let res = test::call_service(&app, weather_request().to_request()).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        mode.store(UP, Ordering::SeqCst);
+        let res = test::call_service(&app, weather_request().to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key(header::WARNING));
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["temp_c"], 21);
+
+        for down in [FAILING, SLOW] {
+            mode.store(down, Ordering::SeqCst);
+            let res = test::call_service(&app, weather_request().to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(
+                res.headers().get(header::WARNING).unwrap(),
+                "110 - \"Response is Stale\""
+            );
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["temp_c"], 21);
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_4xx_upstream_is_502_not_the_fallback() {
+        let mode = Arc::new(AtomicU8::new(UP));
+        let addr = mock_upstream(mode.clone());
+        let proxy = WeatherProxy::new(format!("http://{addr}/weather"), 1);
+        let app =
+            test::init_service(App::new().app_data(web::Data::new(proxy)).service(weather)).await;
+        test::call_service(&app, weather_request().to_request()).await;
+
+        mode.store(REJECTING, Ordering::SeqCst);
+        let res = test::call_service(&app, weather_request().to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+}
@@ -0,0 +1,87 @@
+/*
+   COMPOSED GUARDS: POST /webhook
+    guards decide whether a route matches at all (see the guards section above). guard::All
+     combines several, the route matches only when EVERY one of them does:
+
+        guard::All(guard::Post()).and(guard::Header("content-type", "application/json"))
+
+    so only a JSON POST reaches `receive`. anything else sent to `/webhook` (a GET, a POST with
+     a form body, ...) matches no route of the resource and falls through to its default
+     service, which answers `415 Unsupported Media Type`.
+
+    note: guard::Header compares the whole value, `application/json; charset=utf-8` doesn't
+     match it.
+*/
+
+use actix_web::{guard, http::header, web, HttpResponse, Responder};
+use serde_json::{json, Value};
+
+async fn receive(event: web::Json<Value>) -> impl Responder {
+    log::info!("webhook received: {event}");
+    HttpResponse::Accepted().json(json!({ "received": true }))
+}
+
+async fn unsupported() -> impl Responder {
+    HttpResponse::UnsupportedMediaType()
+        .insert_header((header::ACCEPT, "application/json"))
+        .body("/webhook only accepts POST with Content-Type: application/json")
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/webhook")
+            .route(
+                web::route()
+                    .guard(
+                        guard::All(guard::Post())
+                            .and(guard::Header("content-type", "application/json")),
+                    )
+                    .to(receive),
+            )
+            .default_service(web::to(unsupported)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use super::*;
+    use crate::testing;
+
+    #[actix_web::test]
+    async fn a_json_post_is_received() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/webhook")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload(r#"{"event":"push"}"#)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body, json!({ "received": true }));
+    }
+
+    #[actix_web::test]
+    async fn anything_else_is_415() {
+        let app = test::init_service(testing::builder().await.build()).await;
+
+        let form_post = test::TestRequest::post()
+            .uri("/webhook")
+            .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+            .set_payload("event=push");
+        let get = test::TestRequest::get().uri("/webhook");
+
+        for req in [form_post, get] {
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            assert_eq!(
+                res.headers().get(header::ACCEPT).unwrap(),
+                "application/json"
+            );
+        }
+    }
+}
@@ -0,0 +1,221 @@
+/*
+   ONE WRITE AT A TIME PER RESOURCE
+    two PUTs to `/users/7` arriving together can interleave (both read, both modify, the last
+     write wins and the other one is silently lost). this middleware gives every RESOURCE its own
+     lock, so unsafe requests (POST, PUT, PATCH, DELETE) to the same resource run one after the
+     other, while writes to other resources and all reads aren't slowed down at all.
+
+    a resource is what a route with an id in its pattern addresses: the route `/users/{id}` with
+     the id 7 (`/users/{id}` + `/users/7`), `/kv/{key}` with a key. writes to routes without one
+     (`POST /users`, `/contact`, `/ingest`, `/jobs`, ...) create something new or act on nothing
+     in particular, so there is nothing to take turns on and they are never locked. the pattern
+     is looked up in the app's resource map BEFORE routing (this runs outside it), with
+     ResourceMap::match_pattern().
+
+    a request waits up to LOCK_WAIT for the resource; if the write in front of it still holds the
+     lock by then it gets `409 Conflict` instead of queueing forever.
+
+    the lock is held until the handler produced the response, a streamed body is sent after
+     the lock is released.
+    the map keeps only WEAK references, so a path's lock is freed together with the last request
+     holding or waiting for it, and the map doesn't grow with every path ever written to.
+*/
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::Method,
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use tokio::time;
+
+// how long a write may wait for the one in front of it
+pub const LOCK_WAIT: Duration = Duration::from_secs(2);
+
+type ResourceLock = tokio::sync::Mutex<()>;
+
+pub struct ResourceLocks {
+    wait: Duration,
+    locks: Mutex<HashMap<String, Weak<ResourceLock>>>,
+}
+
+impl ResourceLocks {
+    pub fn new(wait: Duration) -> Self {
+        Self {
+            wait,
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock_for(&self, resource: String) -> Arc<ResourceLock> {
+        let mut locks = self.locks.lock().unwrap();
+        locks.retain(|_, lock| lock.strong_count() > 0);
+
+        if let Some(lock) = locks.get(&resource).and_then(Weak::upgrade) {
+            return lock;
+        }
+        let lock = Arc::new(ResourceLock::new(()));
+        locks.insert(resource, Arc::downgrade(&lock));
+        lock
+    }
+}
+
+// `/users/{id} /users/7` for a route with an id (any `{...}` segment), None for the others
+fn resource_key(req: &ServiceRequest) -> Option<String> {
+    let pattern = req.resource_map().match_pattern(req.path())?;
+    pattern
+        .contains('{')
+        .then(|| format!("{pattern} {}", req.path()))
+}
+
+fn is_write(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    )
+}
+
+pub async fn serialize_writes(
+    locks: web::Data<ResourceLocks>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let resource = is_write(req.method()).then(|| resource_key(&req)).flatten();
+    let Some(resource) = resource else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    };
+
+    let lock = locks.lock_for(resource);
+    let Ok(_guard) = time::timeout(locks.wait, lock.lock_owned()).await else {
+        let res = HttpResponse::Conflict()
+            .body("another write to this resource is in progress, try again");
+        return Ok(req.into_response(res));
+    };
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use actix_web::{http::StatusCode, middleware, test, App, HttpResponse};
+    use futures_util::future::join_all;
+
+    use super::*;
+
+    // how many handlers run at once, and the most there ever were
+    #[derive(Default)]
+    struct Running {
+        now: AtomicUsize,
+        most: AtomicUsize,
+    }
+
+    async fn slow_write(running: web::Data<Running>, hold: web::Data<Duration>) -> HttpResponse {
+        let now = running.now.fetch_add(1, Ordering::SeqCst) + 1;
+        running.most.fetch_max(now, Ordering::SeqCst);
+        time::sleep(**hold).await;
+        running.now.fetch_sub(1, Ordering::SeqCst);
+        HttpResponse::Ok().finish()
+    }
+
+    async fn statuses(
+        wait: Duration,
+        hold: Duration,
+        requests: Vec<test::TestRequest>,
+    ) -> (Vec<StatusCode>, usize) {
+        let running = web::Data::new(Running::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(ResourceLocks::new(wait)))
+                .app_data(running.clone())
+                .app_data(web::Data::new(hold))
+                .wrap(middleware::from_fn(serialize_writes))
+                .route("/items/{id}", web::route().to(slow_write)),
+        )
+        .await;
+
+        let calls = requests
+            .into_iter()
+            .map(|req| test::call_service(&app, req.to_request()));
+        let statuses = join_all(calls)
+            .await
+            .iter()
+            .map(|res| res.status())
+            .collect();
+        (statuses, running.most.load(Ordering::SeqCst))
+    }
+
+    fn put(uri: &str) -> test::TestRequest {
+        test::TestRequest::put().uri(uri)
+    }
+
+    #[actix_web::test]
+    async fn writes_to_one_resource_take_turns() {
+        let writes = vec![put("/items/7"), put("/items/7"), put("/items/7")];
+        let (statuses, most) =
+            statuses(Duration::from_secs(2), Duration::from_millis(20), writes).await;
+
+        assert_eq!(statuses, [StatusCode::OK; 3]);
+        assert_eq!(most, 1);
+    }
+
+    #[actix_web::test]
+    async fn writes_to_other_resources_and_reads_run_together() {
+        let hold = Duration::from_millis(20);
+
+        let writes = vec![put("/items/1"), put("/items/2"), put("/items/3")];
+        let (_, most) = statuses(Duration::from_secs(2), hold, writes).await;
+        assert_eq!(most, 3);
+
+        let reads = (0..3)
+            .map(|_| test::TestRequest::get().uri("/items/7"))
+            .collect();
+        let (_, most) = statuses(Duration::from_secs(2), hold, reads).await;
+        assert_eq!(most, 3);
+    }
+
+    #[actix_web::test]
+    async fn a_write_waiting_too_long_is_409() {
+        let writes = vec![put("/items/7"), put("/items/7")];
+        let (statuses, _) = statuses(
+            Duration::from_millis(20),
+            Duration::from_millis(200),
+            writes,
+        )
+        .await;
+
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::CONFLICT]);
+    }
+
+    #[actix_web::test]
+    async fn a_lock_is_freed_with_its_last_request() {
+        let locks = ResourceLocks::new(LOCK_WAIT);
+
+        let lock = locks.lock_for("/items/{id} /items/7".to_owned());
+        assert!(Arc::ptr_eq(
+            &lock,
+            &locks.lock_for("/items/{id} /items/7".to_owned())
+        ));
+        drop(lock);
+
+        locks.lock_for("/items/{id} /items/8".to_owned());
+        assert!(!locks
+            .locks
+            .lock()
+            .unwrap()
+            .contains_key("/items/{id} /items/7"));
+    }
+}